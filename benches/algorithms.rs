@@ -0,0 +1,54 @@
+//! Times every algorithm through [`ditherer::dither`] at a few resolutions, reporting throughput
+//! in pixels per second. Criterion keeps the last run as a baseline, so running it again between
+//! releases reports any change:
+//!
+//! ```bash
+//! cargo bench --bench algorithms
+//! ```
+//!
+//! The palette-based algorithms (`knoll`, `yliluoma1`, `yliluoma2`) are timed with the 4-color
+//! `cga0` palette, as they fall back to plain Bayer dithering without one.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ditherer::{dither, Algorithm, DitherOptions, Palette};
+use image::{DynamicImage, Rgb, RgbImage};
+
+const RESOLUTIONS: [(u32, u32); 3] = [(320, 240), (1280, 720), (1920, 1080)];
+
+fn algorithms(c: &mut Criterion) {
+    let palette = Palette::preset("cga0").unwrap();
+    let mut group = c.benchmark_group("algorithms");
+    group.sample_size(10);
+
+    for (width, height) in RESOLUTIONS {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            Rgb([
+                (x * 255 / width) as u8,
+                (y * 255 / height) as u8,
+                ((x + y) % 256) as u8,
+            ])
+        }));
+        let resolution = format!("{}x{}", width, height);
+        group.throughput(Throughput::Elements((width * height) as u64));
+
+        for algorithm in Algorithm::ALL {
+            let mut options = DitherOptions::new().algorithm(algorithm.clone());
+            if matches!(
+                algorithm,
+                Algorithm::Knoll | Algorithm::Yliluoma1 | Algorithm::Yliluoma2
+            ) {
+                options = options.palette(palette.clone());
+            }
+
+            group.bench_with_input(
+                BenchmarkId::new(algorithm.to_string(), &resolution),
+                &options,
+                |b, options| b.iter(|| dither(black_box(&image), options)),
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, algorithms);
+criterion_main!(benches);
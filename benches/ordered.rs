@@ -0,0 +1,51 @@
+//! Times ordered dithering of a 4K frame. Run with and without the `simd` feature to compare:
+//!
+//! ```bash
+//! cargo bench --bench ordered
+//! cargo bench --bench ordered --features simd
+//! ```
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ditherer::{
+    apply_bayer_dithering_grayscale, build_tone_curve, BayerMatrixOption, ThresholdMatrix,
+};
+use image::{DynamicImage, GrayImage, Luma};
+
+const WIDTH: u32 = 3840;
+const HEIGHT: u32 = 2160;
+
+fn ordered(c: &mut Criterion) {
+    let image = DynamicImage::ImageLuma8(GrayImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        Luma([((x + y) % 256) as u8])
+    }));
+    let tone_curve = build_tone_curve(0.0, true);
+    let resolution = format!("{}x{}", WIDTH, HEIGHT);
+
+    // The same IDs with and without `simd`, so the second run reports the change.
+    let mut group = c.benchmark_group("ordered");
+    group.throughput(Throughput::Elements((WIDTH * HEIGHT) as u64));
+    group.sample_size(20);
+
+    for (name, threshold_matrix) in [
+        ("bayer-m8", ThresholdMatrix::bayer(&BayerMatrixOption::M8)),
+        ("blue-noise-64", ThresholdMatrix::blue_noise(64)),
+    ] {
+        group.bench_with_input(
+            BenchmarkId::new(name, &resolution),
+            &threshold_matrix,
+            |b, threshold_matrix| {
+                b.iter(|| {
+                    apply_bayer_dithering_grayscale(
+                        black_box(&image),
+                        threshold_matrix,
+                        &tone_curve,
+                    )
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, ordered);
+criterion_main!(benches);
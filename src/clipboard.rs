@@ -0,0 +1,55 @@
+//! System clipboard access for `--clipboard-in` and `--clipboard-out`, enabled with the
+//! `clipboard` feature.
+//!
+//! The clipboard holds raw RGBA pixels; they are converted from and to PNG here, so the rest of
+//! the CLI reads and writes the clipboard like any other encoded image.
+
+use crate::CliError;
+use arboard::{Clipboard, ImageData};
+use image::{DynamicImage, ImageBuffer, ImageFormat, Rgba};
+use std::borrow::Cow;
+use std::error::Error;
+use std::io::Cursor;
+
+/// Returns the image on the system clipboard, encoded as PNG.
+pub fn paste() -> Result<Vec<u8>, Box<dyn Error>> {
+    let image = Clipboard::new()
+        .map_err(clipboard_error)?
+        .get_image()
+        .map_err(|error| match error {
+            arboard::Error::ContentNotAvailable => {
+                CliError::Io("the clipboard does not hold an image".to_string())
+            }
+            error => clipboard_error(error),
+        })?;
+    let pixels = ImageBuffer::<Rgba<u8>, _>::from_raw(
+        image.width as u32,
+        image.height as u32,
+        image.bytes.into_owned(),
+    )
+    .ok_or("the clipboard image has an unexpected size")?;
+
+    let mut png_bytes = Vec::new();
+    DynamicImage::ImageRgba8(pixels)
+        .write_to(&mut Cursor::new(&mut png_bytes), ImageFormat::Png)?;
+    Ok(png_bytes)
+}
+
+/// Puts the image encoded in `png_bytes` on the system clipboard.
+pub fn copy(png_bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+    let image = image::load_from_memory_with_format(png_bytes, ImageFormat::Png)?.to_rgba8();
+    let (width, height) = image.dimensions();
+    Clipboard::new()
+        .map_err(clipboard_error)?
+        .set_image(ImageData {
+            width: width as usize,
+            height: height as usize,
+            bytes: Cow::Owned(image.into_raw()),
+        })
+        .map_err(clipboard_error)?;
+    Ok(())
+}
+
+fn clipboard_error(error: arboard::Error) -> CliError {
+    CliError::Io(format!("clipboard: {}", error))
+}
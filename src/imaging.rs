@@ -0,0 +1,4404 @@
+//! Dithering of [`image`] buffers, the part of the library that needs the `std` feature.
+
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GrayImage, ImageBuffer, Luma, Pixel, Rgba};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+use crate::raw::{BAYER_MATRIX_2X2, BAYER_MATRIX_4X4, BAYER_MATRIX_8X8};
+
+#[cfg(feature = "simd")]
+use crate::simd;
+
+#[derive(Clone, Debug)]
+pub enum PreserveOrder {
+    Dark,
+    Light,
+}
+
+impl FromStr for PreserveOrder {
+    type Err = PreserveOrderParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "dark" => Ok(PreserveOrder::Dark),
+            "light" => Ok(PreserveOrder::Light),
+            _ => Err(PreserveOrderParseError),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PreserveOrderParseError;
+
+impl fmt::Display for PreserveOrderParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid preserve order option. Choose from: dark, light."
+        )
+    }
+}
+
+impl Error for PreserveOrderParseError {}
+
+#[derive(Clone, Debug)]
+pub enum BayerMatrixOption {
+    M2,
+    M4,
+    M8,
+    Generated(u32),
+}
+
+impl FromStr for BayerMatrixOption {
+    type Err = BayerMatrixParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "m2" => Ok(BayerMatrixOption::M2),
+            "m4" => Ok(BayerMatrixOption::M4),
+            "m8" => Ok(BayerMatrixOption::M8),
+            size => match size
+                .strip_prefix('m')
+                .and_then(|size| size.parse::<u32>().ok())
+            {
+                Some(size) if size.is_power_of_two() && size <= BAYER_MAX_SIZE => {
+                    Ok(BayerMatrixOption::Generated(size))
+                }
+                _ => Err(BayerMatrixParseError),
+            },
+        }
+    }
+}
+
+impl fmt::Display for BayerMatrixOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BayerMatrixOption::M2 => write!(f, "m2"),
+            BayerMatrixOption::M4 => write!(f, "m4"),
+            BayerMatrixOption::M8 => write!(f, "m8"),
+            BayerMatrixOption::Generated(size) => write!(f, "m{}", size),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BayerMatrixParseError;
+
+impl fmt::Display for BayerMatrixParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid Bayer Matrix option. Choose from: m2, m4, m8, m16, m32, m64, m128, m256."
+        )
+    }
+}
+
+impl Error for BayerMatrixParseError {}
+
+#[derive(Clone, Debug)]
+pub struct MatrixPair {
+    pub a: BayerMatrixOption,
+    pub b: BayerMatrixOption,
+}
+
+impl FromStr for MatrixPair {
+    type Err = MatrixPairParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (a, b) = input.split_once(':').ok_or(MatrixPairParseError)?;
+        Ok(MatrixPair {
+            a: a.parse().map_err(|_| MatrixPairParseError)?,
+            b: b.parse().map_err(|_| MatrixPairParseError)?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct MatrixPairParseError;
+
+impl fmt::Display for MatrixPairParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid matrix pair. Expected two matrix sizes separated by ':', e.g. m2:m8."
+        )
+    }
+}
+
+impl Error for MatrixPairParseError {}
+
+/// Two ways of dithering the same image, compared by `--diff`.
+#[derive(Clone, Debug)]
+pub struct AlgorithmPair {
+    pub a: (Algorithm, Option<BayerMatrixOption>),
+    pub b: (Algorithm, Option<BayerMatrixOption>),
+}
+
+impl FromStr for AlgorithmPair {
+    type Err = AlgorithmPairParseError;
+
+    /// Parses `:`-separated algorithms, each optionally followed by a matrix size, such as
+    /// `bayer:m2:floyd-steinberg`. A matrix size on its own stands for `bayer`, so `m2:m8` compares
+    /// two Bayer matrices.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut sides: Vec<(Algorithm, Option<BayerMatrixOption>)> = Vec::new();
+        for part in input.split(':') {
+            if let Ok(algorithm) = part.parse::<Algorithm>() {
+                sides.push((algorithm, None));
+                continue;
+            }
+            let matrix = part.parse().map_err(|_| AlgorithmPairParseError)?;
+            match sides.last_mut() {
+                Some((_, last_matrix @ None)) => *last_matrix = Some(matrix),
+                _ => sides.push((Algorithm::Bayer, Some(matrix))),
+            }
+        }
+
+        match <[_; 2]>::try_from(sides) {
+            Ok([a, b]) => Ok(AlgorithmPair { a, b }),
+            Err(_) => Err(AlgorithmPairParseError),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AlgorithmPairParseError;
+
+impl fmt::Display for AlgorithmPairParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid algorithm pair. Expected two algorithms, each optionally followed by a matrix size, separated by ':', e.g. bayer:floyd-steinberg or m2:m8."
+        )
+    }
+}
+
+impl Error for AlgorithmPairParseError {}
+
+#[derive(Clone, Debug)]
+pub enum Algorithm {
+    Bayer,
+    Interference,
+    BlueNoise,
+    Halftone,
+    Random,
+    Ign,
+    FloydSteinberg,
+    Atkinson,
+    Sierra,
+    SierraLite,
+    Stucki,
+    Burkes,
+    Jjn,
+    Riemersma,
+    Ostromoukhov,
+    Knoll,
+    Yliluoma1,
+    Yliluoma2,
+    Fm,
+}
+
+impl FromStr for Algorithm {
+    type Err = AlgorithmParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "bayer" => Ok(Algorithm::Bayer),
+            "interference" => Ok(Algorithm::Interference),
+            "blue-noise" => Ok(Algorithm::BlueNoise),
+            "halftone" => Ok(Algorithm::Halftone),
+            "random" => Ok(Algorithm::Random),
+            "ign" => Ok(Algorithm::Ign),
+            "floyd-steinberg" => Ok(Algorithm::FloydSteinberg),
+            "atkinson" => Ok(Algorithm::Atkinson),
+            "sierra" => Ok(Algorithm::Sierra),
+            "sierra-lite" => Ok(Algorithm::SierraLite),
+            "stucki" => Ok(Algorithm::Stucki),
+            "burkes" => Ok(Algorithm::Burkes),
+            "jjn" => Ok(Algorithm::Jjn),
+            "riemersma" => Ok(Algorithm::Riemersma),
+            "ostromoukhov" => Ok(Algorithm::Ostromoukhov),
+            "knoll" => Ok(Algorithm::Knoll),
+            "yliluoma1" => Ok(Algorithm::Yliluoma1),
+            "yliluoma2" => Ok(Algorithm::Yliluoma2),
+            "fm" => Ok(Algorithm::Fm),
+            _ => Err(AlgorithmParseError),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Algorithm::Bayer => "bayer",
+            Algorithm::Interference => "interference",
+            Algorithm::BlueNoise => "blue-noise",
+            Algorithm::Halftone => "halftone",
+            Algorithm::Random => "random",
+            Algorithm::Ign => "ign",
+            Algorithm::FloydSteinberg => "floyd-steinberg",
+            Algorithm::Atkinson => "atkinson",
+            Algorithm::Sierra => "sierra",
+            Algorithm::SierraLite => "sierra-lite",
+            Algorithm::Stucki => "stucki",
+            Algorithm::Burkes => "burkes",
+            Algorithm::Jjn => "jjn",
+            Algorithm::Riemersma => "riemersma",
+            Algorithm::Ostromoukhov => "ostromoukhov",
+            Algorithm::Knoll => "knoll",
+            Algorithm::Yliluoma1 => "yliluoma1",
+            Algorithm::Yliluoma2 => "yliluoma2",
+            Algorithm::Fm => "fm",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug)]
+pub struct AlgorithmParseError;
+
+impl fmt::Display for AlgorithmParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid algorithm. Choose from: bayer, interference, blue-noise, halftone, random, ign, floyd-steinberg, atkinson, sierra, sierra-lite, stucki, burkes, jjn, riemersma, ostromoukhov, knoll, yliluoma1, yliluoma2, fm."
+        )
+    }
+}
+
+impl Error for AlgorithmParseError {}
+
+impl Algorithm {
+    /// Every algorithm, in the order the CLI lists them.
+    pub const ALL: [Algorithm; 19] = [
+        Algorithm::Bayer,
+        Algorithm::Interference,
+        Algorithm::BlueNoise,
+        Algorithm::Halftone,
+        Algorithm::Random,
+        Algorithm::Ign,
+        Algorithm::FloydSteinberg,
+        Algorithm::Atkinson,
+        Algorithm::Sierra,
+        Algorithm::SierraLite,
+        Algorithm::Stucki,
+        Algorithm::Burkes,
+        Algorithm::Jjn,
+        Algorithm::Riemersma,
+        Algorithm::Ostromoukhov,
+        Algorithm::Knoll,
+        Algorithm::Yliluoma1,
+        Algorithm::Yliluoma2,
+        Algorithm::Fm,
+    ];
+
+    pub fn diffusion_kernel(&self) -> Option<&'static DiffusionKernel> {
+        match self {
+            Algorithm::Bayer
+            | Algorithm::Interference
+            | Algorithm::BlueNoise
+            | Algorithm::Halftone
+            | Algorithm::Random
+            | Algorithm::Ign
+            | Algorithm::Knoll
+            | Algorithm::Yliluoma1
+            | Algorithm::Yliluoma2
+            | Algorithm::Fm => None,
+            Algorithm::FloydSteinberg => Some(&FLOYD_STEINBERG),
+            Algorithm::Atkinson => Some(&ATKINSON),
+            Algorithm::Sierra => Some(&SIERRA),
+            Algorithm::SierraLite => Some(&SIERRA_LITE),
+            Algorithm::Stucki => Some(&STUCKI),
+            Algorithm::Burkes => Some(&BURKES),
+            Algorithm::Jjn => Some(&JJN),
+            Algorithm::Riemersma => Some(&RIEMERSMA),
+            Algorithm::Ostromoukhov => Some(&OSTROMOUKHOV),
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Algorithm::Bayer => {
+                "bayer: ordered dithering with a recursive Bayer threshold matrix.\n\
+                 Speed: very fast, every pixel is independent.\n\
+                 Artifacts: regular cross-hatch pattern, more visible with small matrices.\n\
+                 Best for: retro looks, animation (the pattern is stable between frames)."
+            }
+            Algorithm::Interference => {
+                "interference: ordered dithering with two Bayer matrices blended per pixel.\n\
+                 Speed: very fast, same as bayer.\n\
+                 Artifacts: deliberate moire and interference patterns.\n\
+                 Best for: generative and stylized effects rather than faithful reproduction."
+            }
+            Algorithm::BlueNoise => {
+                "blue-noise: ordered dithering with a void-and-cluster blue-noise threshold texture.\n\
+                 Speed: very fast per pixel; the texture is generated once, which takes a moment for large sizes.\n\
+                 Artifacts: fine, even grain without the cross-hatch of bayer or the worms of error diffusion.\n\
+                 Best for: natural-looking stills and animation, since the pattern is stable between frames."
+            }
+            Algorithm::Halftone => {
+                "halftone: clustered-dot ordered dithering on a rotated screen, like newspaper print.\n\
+                 Speed: very fast, same as bayer.\n\
+                 Artifacts: visible round dots, coarse detail; moire when screens overlap.\n\
+                 Best for: print looks and CMYK-style separations with per-channel screen angles."
+            }
+            Algorithm::Random => {
+                "random: thresholds from seeded white noise, a different random value for every pixel.\n\
+                 Speed: very fast; the same --seed always gives the same pattern.\n\
+                 Artifacts: coarse, clumpy grain with no structure at all.\n\
+                 Best for: a noisy, film-grain look and as a baseline to compare other methods against."
+            }
+            Algorithm::Ign => {
+                "ign: Interleaved Gradient Noise, a per-pixel formula from real-time rendering.\n\
+                 Speed: very fast, nothing to precompute.\n\
+                 Artifacts: faint diagonal structure, finer and more even than white noise.\n\
+                 Best for: matching the dither used by game and shader pipelines."
+            }
+            Algorithm::FloydSteinberg => {
+                "floyd-steinberg: error diffusion, each pixel's rounding error is pushed to its neighbors.\n\
+                 Speed: fast, but pixels must be processed in order.\n\
+                 Artifacts: worm-like textures in flat areas, no regular pattern.\n\
+                 Best for: photos and faithful tone reproduction in still images."
+            }
+            Algorithm::Atkinson => {
+                "atkinson: error diffusion that passes on only 6/8 of the error (classic Macintosh).\n\
+                 Speed: fast, slightly slower than floyd-steinberg.\n\
+                 Artifacts: blown-out highlights and crushed shadows, crisp mid-tone texture.\n\
+                 Best for: high-contrast images, line art and a retro Mac look."
+            }
+            Algorithm::Sierra => {
+                "sierra: error diffusion over three rows (Sierra-3).\n\
+                 Speed: moderate, 10 neighbors per pixel.\n\
+                 Artifacts: few worms, smooth gradients.\n\
+                 Best for: photos when floyd-steinberg looks too wormy."
+            }
+            Algorithm::SierraLite => {
+                "sierra-lite: error diffusion over two neighbors below and one to the right.\n\
+                 Speed: very fast, the cheapest error diffusion.\n\
+                 Artifacts: similar to floyd-steinberg, a little more structured.\n\
+                 Best for: large images where speed matters."
+            }
+            Algorithm::Stucki => {
+                "stucki: error diffusion over three rows with weights favoring near neighbors.\n\
+                 Speed: slow, 12 neighbors per pixel.\n\
+                 Artifacts: clean and sharp, little visible texture.\n\
+                 Best for: detailed photos and print."
+            }
+            Algorithm::Burkes => {
+                "burkes: two-row simplification of stucki.\n\
+                 Speed: moderate, 7 neighbors per pixel.\n\
+                 Artifacts: close to stucki with slightly more texture.\n\
+                 Best for: a good quality/speed compromise."
+            }
+            Algorithm::Jjn => {
+                "jjn: Jarvis, Judice and Ninke error diffusion over three rows.\n\
+                 Speed: slow, 12 neighbors per pixel.\n\
+                 Artifacts: very smooth, coarser grain than floyd-steinberg.\n\
+                 Best for: smooth gradients and soft photos."
+            }
+            Algorithm::Riemersma => {
+                "riemersma: error diffusion along a Hilbert curve with a decaying history of the last 16 errors.\n\
+                 Speed: fast, a little overhead for walking the curve.\n\
+                 Artifacts: organic, slightly clustered texture with no directional worms.\n\
+                 Best for: illustrations and photos where raster-scan artifacts are distracting."
+            }
+            Algorithm::Ostromoukhov => {
+                "ostromoukhov: serpentine error diffusion with weights that vary with each pixel's intensity.\n\
+                 Speed: fast, three neighbors per pixel plus a table lookup.\n\
+                 Artifacts: very few; avoids the regular patterns floyd-steinberg shows in highlights and shadows.\n\
+                 Best for: photos with large light or dark areas and smooth gradients."
+            }
+            Algorithm::Knoll => {
+                "knoll: Thomas Knoll's pattern dithering (Photoshop), for --palette and --colors.\n\
+                 Each pixel picks a mix of palette colors averaging to its color, sorted by luminance and indexed by the Bayer matrix.\n\
+                 Speed: moderate, one palette search per matrix cell per pixel.\n\
+                 Artifacts: regular Bayer-like pattern, but mixes any colors rather than only neighbors in RGB.\n\
+                 Best for: pixel art with small hand-picked palettes and animation (the pattern is stable between frames).\n\
+                 Without a palette it is the same as bayer."
+            }
+            Algorithm::Yliluoma1 => {
+                "yliluoma1: Joel Yliluoma's positional dithering algorithm 1, for --palette and --colors.\n\
+                 Each color is drawn as the best mix of two palette colors in the ratios the Bayer matrix allows, mixed in linear light.\n\
+                 Speed: slow for many distinct colors, every pair of palette colors is tried; results are cached per color.\n\
+                 Artifacts: clean two-color patterns; pairs of very different colors are penalized.\n\
+                 Best for: pixel art and flat illustrations with retro palettes.\n\
+                 Without a palette it is the same as bayer."
+            }
+            Algorithm::Yliluoma2 => {
+                "yliluoma2: Joel Yliluoma's positional dithering algorithm 2, for --palette and --colors.\n\
+                 Each color is drawn as a mix of up to one palette color per Bayer matrix cell, built greedily in linear light and sorted by luminance.\n\
+                 Speed: faster than yliluoma1 with large palettes; results are cached per color.\n\
+                 Artifacts: smooth, accurate mixes of more than two colors.\n\
+                 Best for: gradients in pixel art and photos reduced to retro palettes.\n\
+                 Without a palette it is the same as bayer."
+            }
+            Algorithm::Fm => {
+                "fm: stochastic (FM) screening, dot density follows the average intensity of each --cell-size cell.\n\
+                 Every cell gets exactly as many white pixels as its tone needs, placed in blue-noise order where the image is brightest.\n\
+                 Speed: fast, one small sort per cell; the blue-noise texture is generated once.\n\
+                 Artifacts: fine, even, print-like grain; detail smaller than a cell is softened.\n\
+                 Best for: print, where fixed-size dots reproduce tones more reliably than per-pixel thresholding.\n\
+                 With --color, --rgb, --palette or --levels it thresholds against the blue-noise texture like blue-noise."
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum InterferenceBlend {
+    Average,
+    Difference,
+}
+
+impl FromStr for InterferenceBlend {
+    type Err = InterferenceBlendParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "average" => Ok(InterferenceBlend::Average),
+            "difference" => Ok(InterferenceBlend::Difference),
+            _ => Err(InterferenceBlendParseError),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InterferenceBlendParseError;
+
+impl fmt::Display for InterferenceBlendParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid interference blend. Choose from: average, difference."
+        )
+    }
+}
+
+impl Error for InterferenceBlendParseError {}
+
+#[derive(Clone, Debug)]
+pub enum GradientDirection {
+    Horizontal,
+    Vertical,
+    Radial,
+}
+
+impl FromStr for GradientDirection {
+    type Err = GradientDirectionParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "horizontal" => Ok(GradientDirection::Horizontal),
+            "vertical" => Ok(GradientDirection::Vertical),
+            "radial" => Ok(GradientDirection::Radial),
+            _ => Err(GradientDirectionParseError),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GradientDirectionParseError;
+
+impl fmt::Display for GradientDirectionParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid gradient direction. Choose from: horizontal, vertical, radial."
+        )
+    }
+}
+
+impl Error for GradientDirectionParseError {}
+
+/// Shape of the dots that [`ThresholdMatrix::halftone_with_shape`] grows in each screen cell.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DotShape {
+    /// Circular dots, the classic newspaper screen.
+    Round,
+    /// Square dots, which meet at their corners at 50% gray.
+    Square,
+    /// Diamond dots, which join into a checkerboard at 50% gray.
+    Diamond,
+    /// Parallel lines along the screen angle instead of dots.
+    Line,
+}
+
+impl FromStr for DotShape {
+    type Err = DotShapeParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "round" => Ok(DotShape::Round),
+            "square" => Ok(DotShape::Square),
+            "diamond" => Ok(DotShape::Diamond),
+            "line" => Ok(DotShape::Line),
+            _ => Err(DotShapeParseError),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DotShapeParseError;
+
+impl fmt::Display for DotShapeParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid dot shape. Choose from: round, square, diamond, line."
+        )
+    }
+}
+
+impl Error for DotShapeParseError {}
+
+/// How [`tone_map`] compresses high-dynamic-range input into the displayable range.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ToneMap {
+    /// Clips everything above white, keeping the exposure as is.
+    Clamp,
+    /// Reinhard's global operator on luminance, `L / (1 + L)`, which keeps hues.
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve per channel, with a toe and a soft shoulder.
+    Aces,
+}
+
+impl FromStr for ToneMap {
+    type Err = ToneMapParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "clamp" => Ok(ToneMap::Clamp),
+            "reinhard" => Ok(ToneMap::Reinhard),
+            "aces" => Ok(ToneMap::Aces),
+            _ => Err(ToneMapParseError),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ToneMapParseError;
+
+impl fmt::Display for ToneMapParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid tone map. Choose from: clamp, reinhard, aces.")
+    }
+}
+
+impl Error for ToneMapParseError {}
+
+#[derive(Clone, Debug)]
+pub struct ChannelSwap {
+    sources: [usize; 3],
+}
+
+impl FromStr for ChannelSwap {
+    type Err = ChannelSwapParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.to_lowercase();
+        let (from, to) = input.split_once("->").ok_or(ChannelSwapParseError)?;
+        let is_permutation = |channels: &str| {
+            let mut sorted: Vec<char> = channels.chars().collect();
+            sorted.sort_unstable();
+            sorted == ['b', 'g', 'r']
+        };
+        if !is_permutation(from) || !is_permutation(to) {
+            return Err(ChannelSwapParseError);
+        }
+
+        let mut sources = [0; 3];
+        for (source, channel) in sources.iter_mut().zip(to.chars()) {
+            *source = from.find(channel).ok_or(ChannelSwapParseError)?;
+        }
+        Ok(ChannelSwap { sources })
+    }
+}
+
+#[derive(Debug)]
+pub struct ChannelSwapParseError;
+
+impl fmt::Display for ChannelSwapParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid channel swap. Expected two orderings of r, g and b separated by '->', e.g. rgb->gbr."
+        )
+    }
+}
+
+impl Error for ChannelSwapParseError {}
+
+#[derive(Clone, Debug)]
+pub enum ColorSpace {
+    Rgb,
+    Lab,
+    Oklab,
+}
+
+impl FromStr for ColorSpace {
+    type Err = ColorSpaceParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "rgb" => Ok(ColorSpace::Rgb),
+            "lab" => Ok(ColorSpace::Lab),
+            "oklab" => Ok(ColorSpace::Oklab),
+            _ => Err(ColorSpaceParseError),
+        }
+    }
+}
+
+impl ColorSpace {
+    /// Converts an RGB color in `0.0..=1.0`, sRGB-encoded or with `linear` in linear light, to
+    /// coordinates in this space. Lab is scaled down by 100 so all spaces have similar ranges.
+    pub fn convert(&self, color: [f32; 3], linear: bool) -> [f32; 3] {
+        let [r, g, b] = if linear {
+            color
+        } else {
+            color.map(srgb_to_linear)
+        };
+        match self {
+            ColorSpace::Rgb => color,
+            ColorSpace::Lab => {
+                let f = |t: f32| {
+                    if t > 216.0 / 24389.0 {
+                        t.cbrt()
+                    } else {
+                        t * 841.0 / 108.0 + 4.0 / 29.0
+                    }
+                };
+                let x = f((0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047);
+                let y = f(0.2126 * r + 0.7152 * g + 0.0722 * b);
+                let z = f((0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883);
+                [1.16 * y - 0.16, 5.0 * (x - y), 2.0 * (y - z)]
+            }
+            ColorSpace::Oklab => {
+                let l = (0.4122215 * r + 0.5363325 * g + 0.051446 * b).cbrt();
+                let m = (0.2119035 * r + 0.6806995 * g + 0.107397 * b).cbrt();
+                let s = (0.0883025 * r + 0.2817188 * g + 0.6299787 * b).cbrt();
+                [
+                    0.2104543 * l + 0.7936178 * m - 0.004072 * s,
+                    1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+                    0.025904 * l + 0.7827718 * m - 0.8086758 * s,
+                ]
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ColorSpaceParseError;
+
+impl fmt::Display for ColorSpaceParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid color space. Choose from: rgb, lab, oklab.")
+    }
+}
+
+impl Error for ColorSpaceParseError {}
+
+#[derive(Clone, Debug)]
+pub enum GrayscaleMethod {
+    Luma,
+    Luminosity,
+    Lightness,
+    Average,
+    Desaturate,
+    SingleChannel(usize),
+}
+
+impl FromStr for GrayscaleMethod {
+    type Err = GrayscaleMethodParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "luma" => Ok(GrayscaleMethod::Luma),
+            "luminosity" => Ok(GrayscaleMethod::Luminosity),
+            "lightness" => Ok(GrayscaleMethod::Lightness),
+            "average" => Ok(GrayscaleMethod::Average),
+            "desaturate" | "max" => Ok(GrayscaleMethod::Desaturate),
+            "single-channel:r" => Ok(GrayscaleMethod::SingleChannel(0)),
+            "single-channel:g" => Ok(GrayscaleMethod::SingleChannel(1)),
+            "single-channel:b" => Ok(GrayscaleMethod::SingleChannel(2)),
+            _ => Err(GrayscaleMethodParseError),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GrayscaleMethodParseError;
+
+impl fmt::Display for GrayscaleMethodParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid grayscale method. Choose from: luma, luminosity, lightness, average, desaturate, single-channel:r, single-channel:g, single-channel:b."
+        )
+    }
+}
+
+impl Error for GrayscaleMethodParseError {}
+
+/// Channel of each pixel that color-mode dithering thresholds.
+#[derive(Clone, Debug)]
+pub enum ThresholdChannel {
+    /// Rec. 601 luminance.
+    Luminance,
+    /// HSL lightness, the mean of the largest and smallest component.
+    Lightness,
+    /// HSV value, the largest component.
+    Value,
+    /// HSV saturation, so gray pixels count as dark and vivid ones as light.
+    Saturation,
+}
+
+impl FromStr for ThresholdChannel {
+    type Err = ThresholdChannelParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "luminance" => Ok(ThresholdChannel::Luminance),
+            "lightness" => Ok(ThresholdChannel::Lightness),
+            "value" => Ok(ThresholdChannel::Value),
+            "saturation" => Ok(ThresholdChannel::Saturation),
+            _ => Err(ThresholdChannelParseError),
+        }
+    }
+}
+
+impl ThresholdChannel {
+    pub fn extract(&self, pixel: &[u8; 3]) -> u8 {
+        let max = pixel[0].max(pixel[1]).max(pixel[2]);
+        let min = pixel[0].min(pixel[1]).min(pixel[2]);
+        match self {
+            ThresholdChannel::Luminance => compute_luminance(pixel),
+            ThresholdChannel::Lightness => (max as u16 + min as u16).div_ceil(2) as u8,
+            ThresholdChannel::Value => max,
+            ThresholdChannel::Saturation if max == 0 => 0,
+            ThresholdChannel::Saturation => {
+                (((max - min) as u32 * 255 + max as u32 / 2) / max as u32) as u8
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ThresholdChannelParseError;
+
+impl fmt::Display for ThresholdChannelParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid channel. Choose from: luminance, lightness, value, saturation."
+        )
+    }
+}
+
+impl Error for ThresholdChannelParseError {}
+
+/// What happens to the alpha channel of a transparent input.
+#[derive(Clone, Debug)]
+pub enum AlphaPolicy {
+    Keep,
+    Premultiply,
+    Matte([u8; 3]),
+    Dither,
+}
+
+impl FromStr for AlphaPolicy {
+    type Err = AlphaPolicyParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.to_lowercase();
+        match input.as_str() {
+            "keep" => Ok(AlphaPolicy::Keep),
+            "premultiply" => Ok(AlphaPolicy::Premultiply),
+            "matte" => Ok(AlphaPolicy::Matte([255, 255, 255])),
+            "dither" => Ok(AlphaPolicy::Dither),
+            _ => match input.strip_prefix("matte:") {
+                Some(color) => parse_hex_color(color)
+                    .map(AlphaPolicy::Matte)
+                    .map_err(|_| AlphaPolicyParseError),
+                None => Err(AlphaPolicyParseError),
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AlphaPolicyParseError;
+
+impl fmt::Display for AlphaPolicyParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid alpha policy. Choose from: keep, premultiply, matte, matte:<hex color>, dither."
+        )
+    }
+}
+
+impl Error for AlphaPolicyParseError {}
+
+const BAYER_MAX_SIZE: u32 = 256;
+/// Error-diffusion weights as `(dx, dy, weight)` offsets from the current pixel.
+#[derive(Clone, Debug)]
+pub struct DiffusionKernel {
+    pub weights: &'static [(i32, i32, f32)],
+    pub divisor: f32,
+    pub scan: ScanOrder,
+    /// Per-intensity weights replacing those of `weights`, indexed by the pixel's input level.
+    pub coefficients: Option<&'static [[u16; 3]; 128]>,
+    /// Eschbach–Knox edge enhancement: how far each pixel's own input moves its threshold.
+    pub edge_enhance: f32,
+}
+
+/// The order in which error diffusion visits pixels.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScanOrder {
+    Raster,
+    /// Odd rows right to left, mirroring the weights, to break up directional worms.
+    Serpentine,
+    /// Along a Hilbert curve, spreading error through a decaying history (Riemersma) instead of
+    /// the kernel weights.
+    Hilbert,
+}
+
+impl DiffusionKernel {
+    pub fn with_strength(&self, strength: f32) -> Self {
+        DiffusionKernel {
+            divisor: self.divisor / strength,
+            ..self.clone()
+        }
+    }
+
+    pub fn with_serpentine(mut self, serpentine: bool) -> Self {
+        if serpentine && self.scan == ScanOrder::Raster {
+            self.scan = ScanOrder::Serpentine;
+        }
+        self
+    }
+
+    /// Lowers the threshold for light input and raises it for dark input by `edge_enhance`
+    /// times the input's distance from mid-gray. The diffused error still comes from the
+    /// unmodified value, so average tones are kept while edges and fine lines are sharpened.
+    pub fn with_edge_enhance(mut self, edge_enhance: f32) -> Self {
+        self.edge_enhance = edge_enhance;
+        self
+    }
+
+    /// Whether the diffusion needs each pixel's undiffused input next to its accumulated value.
+    fn uses_originals(&self) -> bool {
+        self.coefficients.is_some() || self.edge_enhance != 0.0
+    }
+
+    /// Decides whether a pixel with accumulated value `intensity` and input `original` is white.
+    fn is_on(&self, intensity: f32, original: f32) -> bool {
+        intensity + self.edge_enhance * (original - 0.5) > 0.5
+    }
+
+    /// Returns the share of the error passed on by tap `tap` for a pixel of input `intensity`.
+    fn tap_weight(&self, tap: usize, weight: f32, intensity: f32) -> f32 {
+        match self.coefficients {
+            Some(coefficients) => {
+                let level = (intensity.clamp(0.0, 1.0) * 255.0).round() as usize;
+                let row = coefficients[level.min(255 - level)];
+                let sum: u16 = row.iter().sum();
+                row[tap] as f32 / (sum as f32 * self.divisor)
+            }
+            None => weight / self.divisor,
+        }
+    }
+
+    fn scan_direction(&self, y: i32) -> i32 {
+        if self.scan == ScanOrder::Serpentine && y % 2 == 1 {
+            -1
+        } else {
+            1
+        }
+    }
+}
+
+pub const FLOYD_STEINBERG: DiffusionKernel = DiffusionKernel {
+    weights: &[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)],
+    divisor: 16.0,
+    scan: ScanOrder::Raster,
+    coefficients: None,
+    edge_enhance: 0.0,
+};
+pub const ATKINSON: DiffusionKernel = DiffusionKernel {
+    weights: &[
+        (1, 0, 1.0),
+        (2, 0, 1.0),
+        (-1, 1, 1.0),
+        (0, 1, 1.0),
+        (1, 1, 1.0),
+        (0, 2, 1.0),
+    ],
+    divisor: 8.0,
+    scan: ScanOrder::Raster,
+    coefficients: None,
+    edge_enhance: 0.0,
+};
+pub const SIERRA: DiffusionKernel = DiffusionKernel {
+    weights: &[
+        (1, 0, 5.0),
+        (2, 0, 3.0),
+        (-2, 1, 2.0),
+        (-1, 1, 4.0),
+        (0, 1, 5.0),
+        (1, 1, 4.0),
+        (2, 1, 2.0),
+        (-1, 2, 2.0),
+        (0, 2, 3.0),
+        (1, 2, 2.0),
+    ],
+    divisor: 32.0,
+    scan: ScanOrder::Raster,
+    coefficients: None,
+    edge_enhance: 0.0,
+};
+pub const SIERRA_LITE: DiffusionKernel = DiffusionKernel {
+    weights: &[(1, 0, 2.0), (-1, 1, 1.0), (0, 1, 1.0)],
+    divisor: 4.0,
+    scan: ScanOrder::Raster,
+    coefficients: None,
+    edge_enhance: 0.0,
+};
+pub const STUCKI: DiffusionKernel = DiffusionKernel {
+    weights: &[
+        (1, 0, 8.0),
+        (2, 0, 4.0),
+        (-2, 1, 2.0),
+        (-1, 1, 4.0),
+        (0, 1, 8.0),
+        (1, 1, 4.0),
+        (2, 1, 2.0),
+        (-2, 2, 1.0),
+        (-1, 2, 2.0),
+        (0, 2, 4.0),
+        (1, 2, 2.0),
+        (2, 2, 1.0),
+    ],
+    divisor: 42.0,
+    scan: ScanOrder::Raster,
+    coefficients: None,
+    edge_enhance: 0.0,
+};
+pub const BURKES: DiffusionKernel = DiffusionKernel {
+    weights: &[
+        (1, 0, 8.0),
+        (2, 0, 4.0),
+        (-2, 1, 2.0),
+        (-1, 1, 4.0),
+        (0, 1, 8.0),
+        (1, 1, 4.0),
+        (2, 1, 2.0),
+    ],
+    divisor: 32.0,
+    scan: ScanOrder::Raster,
+    coefficients: None,
+    edge_enhance: 0.0,
+};
+pub const RIEMERSMA: DiffusionKernel = DiffusionKernel {
+    weights: &[],
+    divisor: RIEMERSMA_WEIGHT_RATIO,
+    scan: ScanOrder::Hilbert,
+    coefficients: None,
+    edge_enhance: 0.0,
+};
+pub const OSTROMOUKHOV: DiffusionKernel = DiffusionKernel {
+    weights: &[(1, 0, 1.0), (-1, 1, 1.0), (0, 1, 1.0)],
+    divisor: 1.0,
+    scan: ScanOrder::Serpentine,
+    coefficients: Some(&OSTROMOUKHOV_COEFFICIENTS),
+    edge_enhance: 0.0,
+};
+pub const JJN: DiffusionKernel = DiffusionKernel {
+    weights: &[
+        (1, 0, 7.0),
+        (2, 0, 5.0),
+        (-2, 1, 3.0),
+        (-1, 1, 5.0),
+        (0, 1, 7.0),
+        (1, 1, 5.0),
+        (2, 1, 3.0),
+        (-2, 2, 1.0),
+        (-1, 2, 3.0),
+        (0, 2, 5.0),
+        (1, 2, 3.0),
+        (2, 2, 1.0),
+    ],
+    divisor: 48.0,
+    scan: ScanOrder::Raster,
+    coefficients: None,
+    edge_enhance: 0.0,
+};
+
+const BLUE_NOISE_SIGMA: f32 = 1.5;
+/// Ostromoukhov's right, down-left and down weights for intensity levels 0-127; lighter levels
+/// mirror these (level `255 - i` uses row `i`).
+const OSTROMOUKHOV_COEFFICIENTS: [[u16; 3]; 128] = [
+    [13, 0, 5],
+    [13, 0, 5],
+    [21, 0, 10],
+    [7, 0, 4],
+    [8, 0, 5],
+    [47, 3, 28],
+    [23, 3, 13],
+    [15, 3, 8],
+    [22, 6, 11],
+    [43, 15, 20],
+    [7, 3, 3],
+    [501, 224, 211],
+    [249, 116, 103],
+    [165, 80, 67],
+    [123, 62, 49],
+    [489, 256, 191],
+    [81, 44, 31],
+    [483, 272, 181],
+    [60, 35, 22],
+    [53, 32, 19],
+    [237, 148, 83],
+    [471, 304, 161],
+    [3, 2, 1],
+    [481, 314, 185],
+    [354, 226, 155],
+    [1389, 866, 685],
+    [227, 138, 125],
+    [267, 158, 163],
+    [327, 188, 220],
+    [61, 34, 45],
+    [627, 338, 505],
+    [1227, 638, 1075],
+    [20, 10, 19],
+    [1937, 1000, 1767],
+    [977, 520, 855],
+    [657, 360, 551],
+    [71, 40, 57],
+    [2005, 1160, 1539],
+    [337, 200, 247],
+    [2039, 1240, 1425],
+    [257, 160, 171],
+    [691, 440, 437],
+    [1045, 680, 627],
+    [301, 200, 171],
+    [177, 120, 95],
+    [2141, 1480, 1083],
+    [1079, 760, 513],
+    [725, 520, 323],
+    [137, 100, 57],
+    [2209, 1640, 855],
+    [53, 40, 19],
+    [2243, 1720, 741],
+    [565, 440, 171],
+    [759, 600, 209],
+    [1147, 920, 285],
+    [2311, 1880, 513],
+    [97, 80, 19],
+    [335, 280, 57],
+    [1181, 1000, 171],
+    [793, 680, 95],
+    [599, 520, 57],
+    [2413, 2120, 171],
+    [405, 360, 19],
+    [2447, 2200, 57],
+    [11, 10, 0],
+    [158, 151, 3],
+    [178, 179, 7],
+    [1030, 1091, 63],
+    [248, 277, 21],
+    [318, 375, 35],
+    [458, 571, 63],
+    [878, 1159, 147],
+    [5, 7, 1],
+    [172, 181, 37],
+    [97, 76, 22],
+    [72, 41, 17],
+    [119, 47, 29],
+    [4, 1, 1],
+    [4, 1, 1],
+    [4, 1, 1],
+    [4, 1, 1],
+    [4, 1, 1],
+    [4, 1, 1],
+    [4, 1, 1],
+    [4, 1, 1],
+    [4, 1, 1],
+    [65, 18, 17],
+    [95, 29, 26],
+    [185, 62, 53],
+    [30, 11, 9],
+    [35, 14, 11],
+    [85, 37, 28],
+    [55, 26, 19],
+    [80, 41, 29],
+    [155, 86, 59],
+    [5, 3, 2],
+    [5, 3, 2],
+    [5, 3, 2],
+    [5, 3, 2],
+    [5, 3, 2],
+    [5, 3, 2],
+    [5, 3, 2],
+    [5, 3, 2],
+    [5, 3, 2],
+    [5, 3, 2],
+    [5, 3, 2],
+    [5, 3, 2],
+    [5, 3, 2],
+    [305, 176, 119],
+    [155, 86, 59],
+    [105, 56, 39],
+    [80, 41, 29],
+    [65, 32, 23],
+    [55, 26, 19],
+    [335, 152, 113],
+    [85, 37, 28],
+    [115, 48, 37],
+    [35, 14, 11],
+    [355, 136, 109],
+    [30, 11, 9],
+    [365, 128, 107],
+    [185, 62, 53],
+    [25, 8, 7],
+    [95, 29, 26],
+    [385, 112, 103],
+    [65, 18, 17],
+    [395, 104, 101],
+    [4, 1, 1],
+];
+const MEDIAN_CUT_MAX_SAMPLES: usize = 1 << 16;
+const RIEMERSMA_HISTORY: usize = 16;
+const RIEMERSMA_WEIGHT_RATIO: f32 = 16.0;
+const PATTERN_MAX_MIX: usize = 64;
+const DOT_GAIN_SAMPLES: usize = 64;
+/// Per-frame steps of the R2 sequence, the reciprocals of the plastic number and its square.
+const R2_STEPS: (f64, f64) = (0.754_877_666_246_692_8, 0.569_840_290_998_053_3);
+/// Mixed into the seed of [`ThresholdMatrix::with_jitter`], so that jitter stays independent of a
+/// [`ThresholdMatrix::white_noise`] pattern made from the same seed.
+const JITTER_STREAM: u64 = 0x6a09_e667_f3bc_c908;
+/// Defaults of the CLI's `--noise-size`, `--dot-size`, `--angle` and `--cell-size`, used by
+/// [`dither`].
+const BLUE_NOISE_SIZE: u32 = 64;
+const HALFTONE_DOT_SIZE: u32 = 8;
+const HALFTONE_ANGLE: f64 = 45.0;
+const FM_CELL_SIZE: u32 = 8;
+
+/// Built-in palettes by name, with a short description.
+const PALETTE_PRESETS: [(&str, &str, &[[u8; 3]]); 8] = [
+    ("bw", "black and white", &[[0, 0, 0], [255, 255, 255]]),
+    (
+        "rgb8",
+        "the eight corners of the RGB cube",
+        &[
+            [0, 0, 0],
+            [255, 0, 0],
+            [0, 255, 0],
+            [0, 0, 255],
+            [255, 255, 0],
+            [255, 0, 255],
+            [0, 255, 255],
+            [255, 255, 255],
+        ],
+    ),
+    (
+        "gameboy",
+        "the four greens of the original Game Boy (DMG) screen",
+        &[[15, 56, 15], [48, 98, 48], [139, 172, 15], [155, 188, 15]],
+    ),
+    (
+        "nes",
+        "the 55 distinct colors of the NES (2C02) PPU",
+        &NES_COLORS,
+    ),
+    (
+        "cga0",
+        "CGA mode 4 palette 0: black, green, red, brown",
+        &[[0, 0, 0], [0, 170, 0], [170, 0, 0], [170, 85, 0]],
+    ),
+    (
+        "cga1",
+        "CGA mode 4 palette 1: black, cyan, magenta, light gray",
+        &[[0, 0, 0], [0, 170, 170], [170, 0, 170], [170, 170, 170]],
+    ),
+    (
+        "pico8",
+        "the 16 colors of the PICO-8 fantasy console",
+        &[
+            [0, 0, 0],
+            [29, 43, 83],
+            [126, 37, 83],
+            [0, 135, 81],
+            [171, 82, 54],
+            [95, 87, 79],
+            [194, 195, 199],
+            [255, 241, 232],
+            [255, 0, 77],
+            [255, 163, 0],
+            [255, 236, 39],
+            [0, 228, 54],
+            [41, 173, 255],
+            [131, 118, 156],
+            [255, 119, 168],
+            [255, 204, 170],
+        ],
+    ),
+    (
+        "zx",
+        "the 15 colors of the ZX Spectrum, normal and bright",
+        &[
+            [0, 0, 0],
+            [0, 0, 215],
+            [215, 0, 0],
+            [215, 0, 215],
+            [0, 215, 0],
+            [0, 215, 215],
+            [215, 215, 0],
+            [215, 215, 215],
+            [0, 0, 255],
+            [255, 0, 0],
+            [255, 0, 255],
+            [0, 255, 0],
+            [0, 255, 255],
+            [255, 255, 0],
+            [255, 255, 255],
+        ],
+    ),
+];
+
+const NES_COLORS: [[u8; 3]; 55] = [
+    [0x7c, 0x7c, 0x7c],
+    [0x00, 0x00, 0xfc],
+    [0x00, 0x00, 0xbc],
+    [0x44, 0x28, 0xbc],
+    [0x94, 0x00, 0x84],
+    [0xa8, 0x00, 0x20],
+    [0xa8, 0x10, 0x00],
+    [0x88, 0x14, 0x00],
+    [0x50, 0x30, 0x00],
+    [0x00, 0x78, 0x00],
+    [0x00, 0x68, 0x00],
+    [0x00, 0x58, 0x00],
+    [0x00, 0x40, 0x58],
+    [0x00, 0x00, 0x00],
+    [0xbc, 0xbc, 0xbc],
+    [0x00, 0x78, 0xf8],
+    [0x00, 0x58, 0xf8],
+    [0x68, 0x44, 0xfc],
+    [0xd8, 0x00, 0xcc],
+    [0xe4, 0x00, 0x58],
+    [0xf8, 0x38, 0x00],
+    [0xe4, 0x5c, 0x10],
+    [0xac, 0x7c, 0x00],
+    [0x00, 0xb8, 0x00],
+    [0x00, 0xa8, 0x00],
+    [0x00, 0xa8, 0x44],
+    [0x00, 0x88, 0x88],
+    [0xf8, 0xf8, 0xf8],
+    [0x3c, 0xbc, 0xfc],
+    [0x68, 0x88, 0xfc],
+    [0x98, 0x78, 0xf8],
+    [0xf8, 0x78, 0xf8],
+    [0xf8, 0x58, 0x98],
+    [0xf8, 0x78, 0x58],
+    [0xfc, 0xa0, 0x44],
+    [0xf8, 0xb8, 0x00],
+    [0xb8, 0xf8, 0x18],
+    [0x58, 0xd8, 0x54],
+    [0x58, 0xf8, 0x98],
+    [0x00, 0xe8, 0xd8],
+    [0x78, 0x78, 0x78],
+    [0xfc, 0xfc, 0xfc],
+    [0xa4, 0xe4, 0xfc],
+    [0xb8, 0xb8, 0xf8],
+    [0xd8, 0xb8, 0xf8],
+    [0xf8, 0xb8, 0xf8],
+    [0xf8, 0xa4, 0xc0],
+    [0xf0, 0xd0, 0xb0],
+    [0xfc, 0xe0, 0xa8],
+    [0xf8, 0xd8, 0x78],
+    [0xd8, 0xf8, 0x78],
+    [0xb8, 0xf8, 0xb8],
+    [0xb8, 0xf8, 0xd8],
+    [0x00, 0xfc, 0xfc],
+    [0xf8, 0xd8, 0xf8],
+];
+const YLILUOMA_CONTRAST_PENALTY: f32 = 0.1;
+
+#[derive(Debug)]
+pub struct ThresholdMapParseError;
+
+impl fmt::Display for ThresholdMapParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid threshold map. Expected rows of equally many values between 0.0 and 1.0."
+        )
+    }
+}
+
+impl Error for ThresholdMapParseError {}
+
+/// A matrix of thresholds between `0.0` and `1.0`, tiled across the image.
+#[derive(Clone, Debug)]
+pub struct ThresholdMatrix {
+    values: Vec<f32>,
+    width: u32,
+    height: u32,
+}
+
+impl ThresholdMatrix {
+    pub fn from_text(input: &str) -> Result<Self, ThresholdMapParseError> {
+        let mut values = Vec::new();
+        let mut width = 0;
+        let mut height = 0;
+
+        for line in input.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let row = line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|value| !value.is_empty())
+                .map(|value| value.parse::<f32>().map_err(|_| ThresholdMapParseError))
+                .collect::<Result<Vec<f32>, _>>()?;
+
+            if height == 0 {
+                width = row.len();
+            } else if row.len() != width {
+                return Err(ThresholdMapParseError);
+            }
+            if row.iter().any(|value| !(0.0..=1.0).contains(value)) {
+                return Err(ThresholdMapParseError);
+            }
+
+            values.extend(row);
+            height += 1;
+        }
+
+        if values.is_empty() {
+            return Err(ThresholdMapParseError);
+        }
+
+        Ok(ThresholdMatrix {
+            values,
+            width: width as u32,
+            height,
+        })
+    }
+
+    /// Uses the luma of `image` as a tiled threshold texture, black being 0.0 and white 1.0.
+    pub fn from_image(image: &DynamicImage) -> Self {
+        let gray_image = image.to_luma16();
+
+        ThresholdMatrix {
+            values: gray_image
+                .pixels()
+                .map(|pixel| pixel[0] as f32 / u16::MAX as f32)
+                .collect(),
+            width: gray_image.width(),
+            height: gray_image.height(),
+        }
+    }
+
+    pub fn bayer(option: &BayerMatrixOption) -> Self {
+        let (values, size): (&[u8], u32) = match option {
+            BayerMatrixOption::M2 => (&BAYER_MATRIX_2X2, 2),
+            BayerMatrixOption::M4 => (&BAYER_MATRIX_4X4, 4),
+            BayerMatrixOption::M8 => (&BAYER_MATRIX_8X8, 8),
+            BayerMatrixOption::Generated(size) => return Self::generated_bayer(*size),
+        };
+
+        ThresholdMatrix {
+            values: values.iter().map(|&value| value as f32 / 255.0).collect(),
+            width: size,
+            height: size,
+        }
+    }
+
+    /// Builds a `size`x`size` Bayer matrix by recursively tiling the 2x2 pattern.
+    pub fn generated_bayer(size: u32) -> Self {
+        let mut ranks = vec![0u32];
+        let mut side = 1;
+
+        while side < size as usize {
+            let next_side = side * 2;
+            let mut next = vec![0u32; next_side * next_side];
+            for y in 0..side {
+                for x in 0..side {
+                    let rank = 4 * ranks[y * side + x];
+                    next[y * next_side + x] = rank;
+                    next[y * next_side + x + side] = rank + 2;
+                    next[(y + side) * next_side + x] = rank + 3;
+                    next[(y + side) * next_side + x + side] = rank + 1;
+                }
+            }
+            ranks = next;
+            side = next_side;
+        }
+
+        let count = ranks.len() as f32;
+        ThresholdMatrix {
+            values: ranks.iter().map(|&rank| rank as f32 / count).collect(),
+            width: side as u32,
+            height: side as u32,
+        }
+    }
+
+    /// Builds a clustered-dot screen with cells of about `dot_size` pixels rotated by `angle`
+    /// degrees. The angle is snapped to the nearest rational tangent so the tile repeats seamlessly.
+    pub fn halftone(dot_size: u32, angle: f64) -> Self {
+        ThresholdMatrix::halftone_with_shape(dot_size, angle, &DotShape::Round)
+    }
+
+    /// Like [`ThresholdMatrix::halftone`], with dots that grow in the given `shape`. Pixels at
+    /// the same distance from the cell center are ordered by their round distance, so square,
+    /// diamond and line dots still fill in from the middle.
+    pub fn halftone_with_shape(dot_size: u32, angle: f64, shape: &DotShape) -> Self {
+        let radians = angle.to_radians();
+        let a = (dot_size as f64 * radians.cos()).round() as i64;
+        let b = (dot_size as f64 * radians.sin()).round() as i64;
+        let (a, b) = if a == 0 && b == 0 { (1, 0) } else { (a, b) };
+        let side = (a * a + b * b) as usize;
+
+        let spot = |x: usize, y: usize| {
+            let (x, y) = (x as f64 + 0.5, y as f64 + 0.5);
+            let u = (x * a as f64 + y * b as f64) / side as f64;
+            let v = (y * a as f64 - x * b as f64) / side as f64;
+            let (dx, dy) = (u - u.floor() - 0.5, v - v.floor() - 0.5);
+            let round = dx * dx + dy * dy;
+            let distance = match shape {
+                DotShape::Round => round,
+                DotShape::Square => dx.abs().max(dy.abs()),
+                DotShape::Diamond => dx.abs() + dy.abs(),
+                DotShape::Line => dy.abs(),
+            };
+            (distance, round)
+        };
+
+        let mut order: Vec<usize> = (0..side * side).collect();
+        let spots: Vec<(f64, f64)> = order.iter().map(|&i| spot(i % side, i / side)).collect();
+        order.sort_by(|&i, &j| {
+            spots[j]
+                .0
+                .total_cmp(&spots[i].0)
+                .then(spots[j].1.total_cmp(&spots[i].1))
+        });
+
+        let mut values = vec![0.0f32; side * side];
+        for (rank, &index) in order.iter().enumerate() {
+            values[index] = rank as f32 / order.len() as f32;
+        }
+
+        ThresholdMatrix {
+            values,
+            width: side as u32,
+            height: side as u32,
+        }
+    }
+
+    pub fn blue_noise(size: u32) -> Self {
+        let side = size as usize;
+        let count = side * side;
+
+        let mut gaussian = vec![0.0f32; count];
+        for dy in 0..side {
+            for dx in 0..side {
+                let x = dx.min(side - dx) as f32;
+                let y = dy.min(side - dy) as f32;
+                gaussian[dy * side + dx] =
+                    (-(x * x + y * y) / (2.0 * BLUE_NOISE_SIGMA * BLUE_NOISE_SIGMA)).exp();
+            }
+        }
+
+        let update = |energy: &mut [f32], index: usize, sign: f32| {
+            let (px, py) = (index % side, index / side);
+            for y in 0..side {
+                let dy = (y + side - py) % side;
+                for x in 0..side {
+                    let dx = (x + side - px) % side;
+                    energy[y * side + x] += sign * gaussian[dy * side + dx];
+                }
+            }
+        };
+        let tightest_cluster = |pattern: &[bool], energy: &[f32]| {
+            (0..count)
+                .filter(|&index| pattern[index])
+                .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+                .unwrap()
+        };
+        let largest_void = |pattern: &[bool], energy: &[f32]| {
+            (0..count)
+                .filter(|&index| !pattern[index])
+                .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+                .unwrap()
+        };
+
+        let mut pattern = vec![false; count];
+        let mut energy = vec![0.0f32; count];
+        let initial = (count / 10).max(1);
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut placed = 0;
+        while placed < initial {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let index = (state % count as u64) as usize;
+            if !pattern[index] {
+                pattern[index] = true;
+                update(&mut energy, index, 1.0);
+                placed += 1;
+            }
+        }
+
+        for _ in 0..count {
+            let cluster = tightest_cluster(&pattern, &energy);
+            pattern[cluster] = false;
+            update(&mut energy, cluster, -1.0);
+
+            let void = largest_void(&pattern, &energy);
+            pattern[void] = true;
+            update(&mut energy, void, 1.0);
+            if void == cluster {
+                break;
+            }
+        }
+
+        let mut ranks = vec![0; count];
+
+        let mut removed_pattern = pattern.clone();
+        let mut removed_energy = energy.clone();
+        for rank in (0..initial).rev() {
+            let cluster = tightest_cluster(&removed_pattern, &removed_energy);
+            removed_pattern[cluster] = false;
+            update(&mut removed_energy, cluster, -1.0);
+            ranks[cluster] = rank;
+        }
+
+        for rank in initial..count {
+            let void = largest_void(&pattern, &energy);
+            pattern[void] = true;
+            update(&mut energy, void, 1.0);
+            ranks[void] = rank;
+        }
+
+        ThresholdMatrix {
+            values: ranks
+                .into_iter()
+                .map(|rank| rank as f32 / count as f32)
+                .collect(),
+            width: size,
+            height: size,
+        }
+    }
+
+    pub fn interference(
+        a: &ThresholdMatrix,
+        b: &ThresholdMatrix,
+        blend: &InterferenceBlend,
+    ) -> Self {
+        let width = lcm(a.width, b.width);
+        let height = lcm(a.height, b.height);
+        let mut values = Vec::with_capacity((width * height) as usize);
+
+        for y in 0..height {
+            for x in 0..width {
+                let threshold_a = a.threshold(x, y);
+                let threshold_b = b.threshold(x, y);
+                values.push(match blend {
+                    InterferenceBlend::Average => (threshold_a + threshold_b) / 2.0,
+                    InterferenceBlend::Difference => (threshold_a - threshold_b).abs(),
+                });
+            }
+        }
+
+        ThresholdMatrix {
+            values,
+            width,
+            height,
+        }
+    }
+
+    pub fn uniform(threshold: f32) -> Self {
+        ThresholdMatrix {
+            values: vec![threshold],
+            width: 1,
+            height: 1,
+        }
+    }
+
+    pub fn gradient(
+        a: &ThresholdMatrix,
+        b: &ThresholdMatrix,
+        direction: &GradientDirection,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let mut values = Vec::with_capacity(width as usize * height as usize);
+        let center_x = (width as f64 - 1.0) / 2.0;
+        let center_y = (height as f64 - 1.0) / 2.0;
+        let max_radius = (center_x * center_x + center_y * center_y).sqrt().max(1.0);
+
+        for y in 0..height {
+            for x in 0..width {
+                let weight = match direction {
+                    GradientDirection::Horizontal => x as f64 / (width as f64 - 1.0).max(1.0),
+                    GradientDirection::Vertical => y as f64 / (height as f64 - 1.0).max(1.0),
+                    GradientDirection::Radial => {
+                        let dx = x as f64 - center_x;
+                        let dy = y as f64 - center_y;
+                        (dx * dx + dy * dy).sqrt() / max_radius
+                    }
+                };
+
+                let weight = weight as f32;
+                values.push((1.0 - weight) * a.threshold(x, y) + weight * b.threshold(x, y));
+            }
+        }
+
+        ThresholdMatrix {
+            values,
+            width: width.max(1),
+            height: height.max(1),
+        }
+    }
+
+    /// Builds a `width`x`height` matrix of white noise. The values depend only on the
+    /// position and `seed`, so the same seed always gives the same pattern.
+    pub fn white_noise(width: u32, height: u32, seed: u64) -> Self {
+        let (width, height) = (width.max(1), height.max(1));
+        let mut values = Vec::with_capacity(width as usize * height as usize);
+        for y in 0..height {
+            for x in 0..width {
+                values.push(noise_value(splitmix(seed, x, y)));
+            }
+        }
+
+        ThresholdMatrix {
+            values,
+            width,
+            height,
+        }
+    }
+
+    /// Builds a `width`x`height` matrix of white noise in which every `grid`x`grid` tile restarts
+    /// the generator from a seed derived from `seed` and the tile's column and row. A tile
+    /// therefore comes out the same whatever the image size, and [`ThresholdMatrix::white_noise_tile`]
+    /// rebuilds any one of them on its own.
+    pub fn white_noise_grid(width: u32, height: u32, seed: u64, grid: u32) -> Self {
+        let (width, height, grid) = (width.max(1), height.max(1), grid.max(1));
+        let mut values = Vec::with_capacity(width as usize * height as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let tile_seed = splitmix(seed, x / grid, y / grid);
+                values.push(noise_value(splitmix(tile_seed, x % grid, y % grid)));
+            }
+        }
+
+        ThresholdMatrix {
+            values,
+            width,
+            height,
+        }
+    }
+
+    /// Builds the `size`x`size` tile at column `tile_x` and row `tile_y` of
+    /// [`ThresholdMatrix::white_noise_grid`] with the same `seed` and a `grid` of `size`.
+    pub fn white_noise_tile(size: u32, seed: u64, tile_x: u32, tile_y: u32) -> Self {
+        let size = size.max(1);
+        let tile_seed = splitmix(seed, tile_x, tile_y);
+        let mut values = Vec::with_capacity(size as usize * size as usize);
+        for y in 0..size {
+            for x in 0..size {
+                values.push(noise_value(splitmix(tile_seed, x, y)));
+            }
+        }
+
+        ThresholdMatrix {
+            values,
+            width: size,
+            height: size,
+        }
+    }
+
+    /// Builds a `width`x`height` matrix of Interleaved Gradient Noise (Jimenez 2014).
+    pub fn interleaved_gradient_noise(width: u32, height: u32) -> Self {
+        let mut values = Vec::with_capacity(width as usize * height as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let gradient = (0.06711056 * x as f64 + 0.00583715 * y as f64).fract();
+                values.push((52.9829189 * gradient).fract() as f32);
+            }
+        }
+
+        ThresholdMatrix {
+            values,
+            width: width.max(1),
+            height: height.max(1),
+        }
+    }
+
+    pub fn with_strength(mut self, strength: f32) -> Self {
+        for value in &mut self.values {
+            *value = 0.5 + (*value - 0.5) * strength;
+        }
+        self
+    }
+
+    pub fn with_display_gamma(mut self, gamma: f64) -> Self {
+        let exponent = (1.0 / gamma) as f32;
+        for value in &mut self.values {
+            *value = value.powf(exponent);
+        }
+        self
+    }
+
+    /// Shifts the matrix up by `offset` rows, so a strip starting at row `offset` of a larger
+    /// image lines up with the thresholds the whole image would have used.
+    pub fn with_row_offset(mut self, offset: u32) -> Self {
+        let shift = (offset % self.height) * self.width;
+        self.values.rotate_left(shift as usize);
+        self
+    }
+
+    /// Resamples the matrix for a `width`x`height` image whose pattern is enlarged by `scale` and
+    /// rotated by `angle` degrees, interpolating bilinearly between the tiled cells.
+    pub fn transformed(&self, scale: f64, angle: f64, width: u32, height: u32) -> Self {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let cell = |x: i64, y: i64| {
+            self.threshold(
+                x.rem_euclid(self.width as i64) as u32,
+                y.rem_euclid(self.height as i64) as u32,
+            )
+        };
+
+        let mut values = Vec::with_capacity(width as usize * height as usize);
+        for y in 0..height {
+            for x in 0..width {
+                // Pixel centers are mapped back into matrix space, where cell centers sit at n + 0.5.
+                let (center_x, center_y) = (x as f64 + 0.5, y as f64 + 0.5);
+                let u = (cos * center_x + sin * center_y) / scale - 0.5;
+                let v = (cos * center_y - sin * center_x) / scale - 0.5;
+                let (left, top) = (u.floor(), v.floor());
+                let (fraction_x, fraction_y) = ((u - left) as f32, (v - top) as f32);
+                let (left, top) = (left as i64, top as i64);
+
+                let upper = cell(left, top) * (1.0 - fraction_x) + cell(left + 1, top) * fraction_x;
+                let lower =
+                    cell(left, top + 1) * (1.0 - fraction_x) + cell(left + 1, top + 1) * fraction_x;
+                values.push(upper * (1.0 - fraction_y) + lower * fraction_y);
+            }
+        }
+
+        ThresholdMatrix {
+            values,
+            width: width.max(1),
+            height: height.max(1),
+        }
+    }
+
+    /// Shifts the matrix by a different offset for every animation `frame`, following the R2
+    /// low-discrepancy sequence, so consecutive frames get different but equally dense patterns.
+    pub fn with_frame_offset(self, frame: u64) -> Self {
+        let (x_step, y_step) = R2_STEPS;
+        let offset_x = ((frame as f64 * x_step).fract() * self.width as f64) as u32;
+        let offset_y = ((frame as f64 * y_step).fract() * self.height as f64) as u32;
+        let values = (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+            .map(|(x, y)| self.threshold(x + offset_x, y + offset_y))
+            .collect();
+        ThresholdMatrix { values, ..self }
+    }
+
+    /// Perturbs every threshold of a `width`x`height` image by seeded white noise spanning
+    /// `amount`, breaking up the regular structure of the matrix. The thresholds stay within
+    /// `[0, 1)`, so black and white input are unaffected.
+    pub fn with_jitter(&self, amount: f32, seed: u64, width: u32, height: u32) -> Self {
+        let noise = ThresholdMatrix::white_noise(width, height, seed ^ JITTER_STREAM);
+        let values = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let jitter = amount * (noise.threshold(x, y) - 0.5);
+                (self.threshold(x, y) + jitter).clamp(0.0, 1.0 - f32::EPSILON)
+            })
+            .collect();
+
+        ThresholdMatrix {
+            values,
+            width: width.max(1),
+            height: height.max(1),
+        }
+    }
+
+    /// Renders the thresholds as a 16-bit grayscale image, one pixel per matrix cell.
+    pub fn to_image(&self) -> ImageBuffer<Luma<u16>, Vec<u16>> {
+        ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            Luma([(self.threshold(x, y) * u16::MAX as f32).round() as u16])
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn threshold(&self, x: u32, y: u32) -> f32 {
+        self.values[((y % self.height) * self.width + (x % self.width)) as usize]
+    }
+}
+
+#[derive(Debug)]
+pub struct PaletteParseError;
+
+impl fmt::Display for PaletteParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid palette. Expected hex colors like #ff8800 or a GIMP .gpl palette."
+        )
+    }
+}
+
+impl Error for PaletteParseError {}
+
+/// A fixed set of output colors.
+#[derive(Clone, Debug)]
+pub struct Palette {
+    colors: Vec<[u8; 3]>,
+    encoded: Vec<[f32; 3]>,
+    linear: Vec<[f32; 3]>,
+}
+
+impl Palette {
+    pub fn from_text(input: &str) -> Result<Self, PaletteParseError> {
+        let mut lines = input.lines().map(str::trim).peekable();
+        let is_gpl = lines.peek() == Some(&"GIMP Palette");
+        let mut colors = Vec::new();
+
+        for line in lines {
+            if line.is_empty() || (is_gpl && line.starts_with('#')) || line.starts_with(';') {
+                continue;
+            }
+
+            if is_gpl {
+                if line == "GIMP Palette" || line.contains(':') {
+                    continue;
+                }
+                let channels = line
+                    .split_whitespace()
+                    .take(3)
+                    .map(|value| value.parse::<u8>().map_err(|_| PaletteParseError))
+                    .collect::<Result<Vec<u8>, _>>()?;
+                colors.push(channels.try_into().map_err(|_| PaletteParseError)?);
+            } else {
+                for value in line
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|value| !value.is_empty())
+                {
+                    colors.push(parse_hex_color(value)?);
+                }
+            }
+        }
+
+        if colors.is_empty() {
+            return Err(PaletteParseError);
+        }
+
+        Ok(Palette::new(colors))
+    }
+
+    pub fn new(colors: Vec<[u8; 3]>) -> Self {
+        let encoded: Vec<[f32; 3]> = colors
+            .iter()
+            .map(|color| color.map(|value| value as f32 / 255.0))
+            .collect();
+        let linear = encoded
+            .iter()
+            .map(|color| color.map(srgb_to_linear))
+            .collect();
+        Palette {
+            colors,
+            encoded,
+            linear,
+        }
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        PALETTE_PRESETS
+            .iter()
+            .find(|(preset, _, _)| *preset == name)
+            .map(|(_, _, colors)| Palette::new(colors.to_vec()))
+    }
+
+    /// Names and descriptions of the palettes accepted by [`Palette::preset`].
+    pub fn presets() -> impl Iterator<Item = (&'static str, &'static str)> {
+        PALETTE_PRESETS
+            .iter()
+            .map(|&(name, description, _)| (name, description))
+    }
+
+    /// Derives a palette of up to `count` colors from `image` by median cut: the box of colors
+    /// with the widest channel range is split at its median until there are `count` boxes, and
+    /// each box contributes its average color.
+    pub fn median_cut(image: &DynamicImage, count: usize) -> Self {
+        let rgb_image = image.to_rgb8();
+        let step = (rgb_image.len() / 3 / MEDIAN_CUT_MAX_SAMPLES).max(1);
+        let samples: Vec<[u8; 3]> = rgb_image
+            .pixels()
+            .step_by(step)
+            .map(|pixel| pixel.0)
+            .collect();
+
+        let widest_channel = |colors: &[[u8; 3]]| {
+            (0..3)
+                .map(|channel| {
+                    let values = colors.iter().map(|color| color[channel]);
+                    let range = values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+                    (range, channel)
+                })
+                .max()
+                .unwrap()
+        };
+
+        let mut boxes = vec![samples];
+        while boxes.len() < count {
+            let Some((index, (range, channel))) = boxes
+                .iter()
+                .enumerate()
+                .filter(|(_, colors)| colors.len() > 1)
+                .map(|(index, colors)| (index, widest_channel(colors)))
+                .max_by_key(|&(_, (range, _))| range)
+            else {
+                break;
+            };
+            if range == 0 {
+                break;
+            }
+
+            let mut colors = boxes.swap_remove(index);
+            colors.sort_unstable_by_key(|color| color[channel]);
+            let upper = colors.split_off(colors.len() / 2);
+            boxes.push(colors);
+            boxes.push(upper);
+        }
+
+        let mut colors: Vec<[u8; 3]> = boxes
+            .iter()
+            .filter(|colors| !colors.is_empty())
+            .map(|colors| {
+                [0, 1, 2].map(|channel| {
+                    let sum: u64 = colors.iter().map(|color| color[channel] as u64).sum();
+                    (sum / colors.len() as u64) as u8
+                })
+            })
+            .collect();
+        colors.sort_unstable();
+        colors.dedup();
+        if colors.is_empty() {
+            colors.push([0, 0, 0]);
+        }
+
+        Palette::new(colors)
+    }
+
+    pub fn colors(&self) -> &[[u8; 3]] {
+        &self.colors
+    }
+
+    /// Returns the index of the palette color closest to `color`, given in `0.0..=1.0`
+    /// sRGB-encoded or, with `linear`, linear-light units.
+    pub fn nearest(&self, color: [f32; 3], linear: bool) -> usize {
+        let candidates = if linear { &self.linear } else { &self.encoded };
+        nearest_color(candidates, color)
+    }
+
+    fn working_colors(&self, linear: bool, color_space: &ColorSpace) -> Vec<[f32; 3]> {
+        let colors = if linear { &self.linear } else { &self.encoded };
+        colors
+            .iter()
+            .map(|&color| color_space.convert(color, linear))
+            .collect()
+    }
+}
+
+fn nearest_color(candidates: &[[f32; 3]], color: [f32; 3]) -> usize {
+    let distance = |candidate: &[f32; 3]| {
+        (0..3)
+            .map(|channel| {
+                let delta = candidate[channel] - color[channel];
+                delta * delta
+            })
+            .sum::<f32>()
+    };
+
+    (0..candidates.len())
+        .min_by(|&a, &b| distance(&candidates[a]).total_cmp(&distance(&candidates[b])))
+        .unwrap()
+}
+
+fn parse_hex_color(input: &str) -> Result<[u8; 3], PaletteParseError> {
+    let hex = input.trim_start_matches('#');
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(PaletteParseError);
+    }
+
+    let channel = |index: usize| {
+        u8::from_str_radix(&hex[index..index + 2], 16).map_err(|_| PaletteParseError)
+    };
+    Ok([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// SplitMix64 of `seed` advanced by the position `(x, y)`.
+fn splitmix(seed: u64, x: u32, y: u32) -> u64 {
+    let position = (y as u64) << 32 | x as u64;
+    let mut z = seed.wrapping_add(position.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn noise_value(z: u64) -> f32 {
+    (z >> 40) as f32 / (1u64 << 24) as f32
+}
+
+fn lcm(a: u32, b: u32) -> u32 {
+    let (mut x, mut y) = (a, b);
+    while y != 0 {
+        (x, y) = (y, x % y);
+    }
+    a / x * b
+}
+
+pub fn count_colors(image: &DynamicImage) -> Vec<([u8; 3], u64)> {
+    let mut counts: HashMap<[u8; 3], u64> = HashMap::new();
+
+    for pixel in image.to_rgb8().pixels() {
+        *counts.entry(pixel.0).or_insert(0) += 1;
+    }
+
+    let mut counts: Vec<([u8; 3], u64)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts
+}
+
+pub fn luma_to_rgba8(luma_img: &ImageBuffer<Luma<u8>, Vec<u8>>) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let (width, height) = luma_img.dimensions();
+    let mut rgba_img = ImageBuffer::new(width, height);
+
+    for (x, y, luma_pixel) in luma_img.enumerate_pixels() {
+        let luma_value = luma_pixel.0[0];
+        rgba_img.put_pixel(x, y, Rgba([luma_value, luma_value, luma_value, 255]));
+    }
+
+    rgba_img
+}
+
+pub fn compute_luminance(pixel: &[u8; 3]) -> u8 {
+    (0.299 * pixel[0] as f64 + 0.587 * pixel[1] as f64 + 0.114 * pixel[2] as f64).clamp(0.0, 255.0)
+        as u8
+}
+
+/// Rec. 709 luma in integer arithmetic, matching `DynamicImage::to_luma8`.
+pub fn compute_luma(pixel: &[u8; 3]) -> u8 {
+    ((2126 * pixel[0] as u32 + 7152 * pixel[1] as u32 + 722 * pixel[2] as u32) / 10000) as u8
+}
+
+pub fn convert_to_grayscale(image: &DynamicImage, method: &GrayscaleMethod) -> GrayImage {
+    let rgb_image = image.to_rgb8();
+    let mut gray_image = GrayImage::new(rgb_image.width(), rgb_image.height());
+
+    for (x, y, pixel) in rgb_image.enumerate_pixels() {
+        let [r, g, b] = pixel.0;
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+
+        let gray = match method {
+            GrayscaleMethod::Luma => compute_luma(&pixel.0),
+            GrayscaleMethod::Luminosity => compute_luminance(&pixel.0),
+            GrayscaleMethod::Lightness => ((max as u16 + min as u16) / 2) as u8,
+            GrayscaleMethod::Average => ((r as u16 + g as u16 + b as u16) / 3) as u8,
+            GrayscaleMethod::Desaturate => max,
+            GrayscaleMethod::SingleChannel(channel) => pixel[*channel],
+        };
+
+        gray_image.put_pixel(x, y, Luma([gray]));
+    }
+
+    gray_image
+}
+
+fn integral_image(image: &GrayImage) -> Vec<u64> {
+    let (width, height) = image.dimensions();
+    let stride = width as usize + 1;
+    let mut integral = vec![0u64; stride * (height as usize + 1)];
+
+    for y in 0..height as usize {
+        let mut row_sum = 0;
+        for x in 0..width as usize {
+            row_sum += image.get_pixel(x as u32, y as u32)[0] as u64;
+            integral[(y + 1) * stride + x + 1] = integral[y * stride + x + 1] + row_sum;
+        }
+    }
+
+    integral
+}
+
+pub fn apply_adaptive_offset(image: &GrayImage, window: u32) -> GrayImage {
+    let (width, height) = image.dimensions();
+    let integral = integral_image(image);
+    let stride = width as usize + 1;
+    let radius = window / 2;
+
+    let mut output_image = GrayImage::new(width, height);
+
+    for y in 0..height {
+        let top = y.saturating_sub(radius) as usize;
+        let bottom = (y + radius + 1).min(height) as usize;
+
+        for x in 0..width {
+            let left = x.saturating_sub(radius) as usize;
+            let right = (x + radius + 1).min(width) as usize;
+
+            let sum = integral[bottom * stride + right] + integral[top * stride + left]
+                - integral[top * stride + right]
+                - integral[bottom * stride + left];
+            let area = ((bottom - top) * (right - left)) as f64;
+            let local_mean = sum as f64 / area;
+
+            let intensity = image.get_pixel(x, y)[0] as f64;
+            let adjusted = (intensity - local_mean + 128.0).round().clamp(0.0, 255.0) as u8;
+            output_image.put_pixel(x, y, Luma([adjusted]));
+        }
+    }
+
+    output_image
+}
+
+fn contrast_curve(value: f64, strength: f64) -> f64 {
+    if strength == 0.0 {
+        return value;
+    }
+
+    let sigmoid = |t: f64| 1.0 / (1.0 + (-strength * (t - 0.5)).exp());
+    (sigmoid(value) - sigmoid(0.0)) / (sigmoid(1.0) - sigmoid(0.0))
+}
+
+/// Builds the table mapping 8-bit values to the intensities that are thresholded, optionally in linear light.
+pub fn build_tone_curve(contrast_strength: f64, linear: bool) -> [f32; 256] {
+    let mut tone_curve = [0.0; 256];
+
+    for (intensity, mapped) in tone_curve.iter_mut().enumerate() {
+        let value = contrast_curve(intensity as f64 / 255.0, contrast_strength).clamp(0.0, 1.0);
+        *mapped = if linear {
+            srgb_to_linear(value as f32)
+        } else {
+            value as f32
+        };
+    }
+
+    tone_curve
+}
+
+#[derive(Debug)]
+pub struct DotGainParseError;
+
+impl fmt::Display for DotGainParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid dot gain curve. Expected lines of 'nominal,printed' ink coverage in percent, rising from 0 to 100."
+        )
+    }
+}
+
+impl Error for DotGainParseError {}
+
+/// How much ink coverage a printing process actually puts on paper for a nominal coverage, as
+/// `(nominal, printed)` pairs between `0.0` and `1.0` that rise from `(0, 0)` to `(1, 1)`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DotGain {
+    points: Vec<(f32, f32)>,
+}
+
+impl DotGain {
+    /// Models a press that prints a nominal 50% tint `percent` points darker (below 50), with
+    /// the gain falling off towards paper white and solid ink.
+    pub fn percent(percent: f32) -> Self {
+        let exponent = (0.5 - percent.clamp(0.0, 49.9) / 100.0).log2() / 0.5f32.log2();
+        let points = (0..=DOT_GAIN_SAMPLES)
+            .map(|step| {
+                let nominal = step as f32 / DOT_GAIN_SAMPLES as f32;
+                (nominal, 1.0 - (1.0 - nominal).powf(exponent))
+            })
+            .collect();
+        DotGain { points }
+    }
+
+    /// Parses measured `nominal,printed` coverage pairs in percent, one per line. `#` starts a
+    /// comment, and the paper white and solid ink end points are added when missing.
+    pub fn from_text(input: &str) -> Result<Self, DotGainParseError> {
+        let mut points = vec![(0.0, 0.0)];
+        for line in input.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (nominal, printed) = line
+                .split_once(|c: char| c == ',' || c == ';' || c.is_whitespace())
+                .ok_or(DotGainParseError)?;
+            let nominal = nominal
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| DotGainParseError)?;
+            let printed = printed
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| DotGainParseError)?;
+            if !(0.0..=100.0).contains(&nominal) || !(0.0..=100.0).contains(&printed) {
+                return Err(DotGainParseError);
+            }
+            points.push((nominal / 100.0, printed / 100.0));
+        }
+        points.push((1.0, 1.0));
+        points.dedup();
+
+        if points.len() < 3
+            || points
+                .windows(2)
+                .any(|pair| pair[0].0 >= pair[1].0 || pair[0].1 > pair[1].1)
+        {
+            return Err(DotGainParseError);
+        }
+        Ok(DotGain { points })
+    }
+
+    /// Returns the nominal coverage that prints as `coverage`.
+    pub fn compensate(&self, coverage: f32) -> f32 {
+        let coverage = coverage.clamp(0.0, 1.0);
+        let segment = self
+            .points
+            .windows(2)
+            .find(|pair| coverage <= pair[1].1)
+            .unwrap_or(&self.points[self.points.len() - 2..]);
+        let ((nominal_start, printed_start), (nominal_end, printed_end)) = (segment[0], segment[1]);
+        if printed_end <= printed_start {
+            return nominal_start;
+        }
+        nominal_start
+            + (coverage - printed_start) / (printed_end - printed_start)
+                * (nominal_end - nominal_start)
+    }
+
+    /// Adjusts a tone curve from [`build_tone_curve`] so the dots come out at the intended
+    /// coverage once the press has spread them. Intensity 1.0 is paper white.
+    pub fn apply_to_tone_curve(&self, tone_curve: &mut [f32; 256]) {
+        for intensity in tone_curve.iter_mut() {
+            *intensity = 1.0 - self.compensate(1.0 - *intensity);
+        }
+    }
+}
+
+/// Converts an sRGB-encoded value between `0.0` and `1.0` to linear light.
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts a linear-light value between `0.0` and `1.0` to sRGB encoding.
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[derive(Debug)]
+pub struct IccProfileParseError;
+
+impl fmt::Display for IccProfileParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Unsupported ICC profile. Only RGB matrix/TRC profiles can be converted."
+        )
+    }
+}
+
+impl Error for IccProfileParseError {}
+
+/// One `rTRC`/`gTRC`/`bTRC` tone response curve, decoding an encoded value to linear light.
+#[derive(Clone, Debug, PartialEq)]
+enum IccCurve {
+    Gamma(f32),
+    Table(Vec<f32>),
+    Parametric(u16, [f32; 7]),
+}
+
+impl IccCurve {
+    fn parse(tag: &[u8]) -> Option<Self> {
+        match tag.get(..4)? {
+            b"curv" => {
+                let count = read_be_u32(tag, 8)? as usize;
+                let entries = (0..count)
+                    .map(|i| Some(read_be_u16(tag, 12 + i * 2)? as f32 / 65535.0))
+                    .collect::<Option<Vec<_>>>()?;
+                match entries.len() {
+                    0 => Some(IccCurve::Gamma(1.0)),
+                    1 => Some(IccCurve::Gamma(read_be_u16(tag, 12)? as f32 / 256.0)),
+                    _ => Some(IccCurve::Table(entries)),
+                }
+            }
+            b"para" => {
+                let function = read_be_u16(tag, 8)?;
+                let count = match function {
+                    0 => 1,
+                    1 => 3,
+                    2 => 4,
+                    3 => 5,
+                    4 => 7,
+                    _ => return None,
+                };
+                let mut parameters = [0.0; 7];
+                for (i, parameter) in parameters.iter_mut().take(count).enumerate() {
+                    *parameter = read_s15_fixed16(tag, 12 + i * 4)?;
+                }
+                Some(IccCurve::Parametric(function, parameters))
+            }
+            _ => None,
+        }
+    }
+
+    fn to_linear(&self, value: f32) -> f32 {
+        let linear = match self {
+            IccCurve::Gamma(gamma) => value.powf(*gamma),
+            IccCurve::Table(entries) => {
+                let position = value * (entries.len() - 1) as f32;
+                let index = (position as usize).min(entries.len() - 2);
+                let fraction = position - index as f32;
+                entries[index] + (entries[index + 1] - entries[index]) * fraction
+            }
+            IccCurve::Parametric(function, [g, a, b, c, d, e, f]) => match function {
+                0 => value.powf(*g),
+                1 if value >= -b / a => (a * value + b).powf(*g),
+                1 => 0.0,
+                2 if value >= -b / a => (a * value + b).powf(*g) + c,
+                2 => *c,
+                3 if value >= *d => (a * value + b).powf(*g),
+                3 => c * value,
+                _ if value >= *d => (a * value + b).powf(*g) + e,
+                _ => c * value + f,
+            },
+        };
+        linear.clamp(0.0, 1.0)
+    }
+}
+
+/// XYZ (D50) to linear sRGB, the inverse of the Bradford-adapted sRGB primaries.
+const XYZ_D50_TO_LINEAR_SRGB: [[f32; 3]; 3] = [
+    [3.133_856, -1.616_867, -0.490_615],
+    [-0.978_768, 1.916_142, 0.033_454],
+    [0.071_945, -0.228_991, 1.405_243],
+];
+/// The `rXYZ`, `gXYZ` and `bXYZ` columns of the standard sRGB ICC profile.
+const SRGB_TO_XYZ_D50: [[f32; 3]; 3] = [
+    [0.436_075, 0.385_065, 0.143_080],
+    [0.222_504, 0.716_879, 0.060_617],
+    [0.013_932, 0.097_105, 0.714_173],
+];
+const ICC_SRGB_TOLERANCE: f32 = 0.002;
+const ICC_ENCODE_STEPS: usize = 4096;
+
+/// An RGB matrix/TRC ICC profile, such as Display P3 or Adobe RGB, that images can be converted
+/// from into sRGB before dithering.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IccProfile {
+    curves: [IccCurve; 3],
+    to_xyz: [[f32; 3]; 3],
+}
+
+impl IccProfile {
+    /// Parses the `rXYZ`/`gXYZ`/`bXYZ` primaries and `rTRC`/`gTRC`/`bTRC` curves of an embedded
+    /// profile. LUT-based, CMYK and grayscale profiles are rejected.
+    pub fn parse(bytes: &[u8]) -> Result<Self, IccProfileParseError> {
+        Self::parse_tags(bytes).ok_or(IccProfileParseError)
+    }
+
+    fn parse_tags(bytes: &[u8]) -> Option<Self> {
+        if bytes.get(16..20)? != b"RGB " || bytes.get(20..24)? != b"XYZ " {
+            return None;
+        }
+
+        let tag = |signature: &[u8; 4]| {
+            let count = read_be_u32(bytes, 128)? as usize;
+            (0..count.min(bytes.len() / 12)).find_map(|i| {
+                let entry = 132 + i * 12;
+                if bytes.get(entry..entry + 4)? != signature {
+                    return None;
+                }
+                let offset = read_be_u32(bytes, entry + 4)? as usize;
+                let size = read_be_u32(bytes, entry + 8)? as usize;
+                bytes.get(offset..offset.checked_add(size)?)
+            })
+        };
+        let primary = |signature: &[u8; 4]| {
+            let tag = tag(signature)?;
+            if tag.get(..4)? != b"XYZ " {
+                return None;
+            }
+            Some([
+                read_s15_fixed16(tag, 8)?,
+                read_s15_fixed16(tag, 12)?,
+                read_s15_fixed16(tag, 16)?,
+            ])
+        };
+
+        let columns = [primary(b"rXYZ")?, primary(b"gXYZ")?, primary(b"bXYZ")?];
+        let mut to_xyz = [[0.0; 3]; 3];
+        for (row, values) in to_xyz.iter_mut().enumerate() {
+            for (column, value) in values.iter_mut().enumerate() {
+                *value = columns[column][row];
+            }
+        }
+
+        Some(IccProfile {
+            curves: [
+                IccCurve::parse(tag(b"rTRC")?)?,
+                IccCurve::parse(tag(b"gTRC")?)?,
+                IccCurve::parse(tag(b"bTRC")?)?,
+            ],
+            to_xyz,
+        })
+    }
+
+    /// Whether the profile describes sRGB closely enough that converting would only add rounding.
+    pub fn is_srgb(&self) -> bool {
+        let same_primaries = self
+            .to_xyz
+            .iter()
+            .flatten()
+            .zip(SRGB_TO_XYZ_D50.iter().flatten())
+            .all(|(a, b)| (a - b).abs() <= ICC_SRGB_TOLERANCE);
+        let same_curves = self.curves.iter().all(|curve| {
+            (0..=16).all(|step| {
+                let value = step as f32 / 16.0;
+                (curve.to_linear(value) - srgb_to_linear(value)).abs() <= ICC_SRGB_TOLERANCE
+            })
+        });
+        same_primaries && same_curves
+    }
+}
+
+/// Converts an image tagged with `profile` to sRGB, keeping the alpha channel.
+pub fn convert_to_srgb(
+    image: &DynamicImage,
+    profile: &IccProfile,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let mut decode = [[0.0f32; 256]; 3];
+    for (curve, lookup) in profile.curves.iter().zip(decode.iter_mut()) {
+        for (value, linear) in lookup.iter_mut().enumerate() {
+            *linear = curve.to_linear(value as f32 / 255.0);
+        }
+    }
+    let encode: Vec<u8> = (0..ICC_ENCODE_STEPS)
+        .map(|step| {
+            let linear = step as f32 / (ICC_ENCODE_STEPS - 1) as f32;
+            (linear_to_srgb(linear) * 255.0).round() as u8
+        })
+        .collect();
+
+    let mut matrix = [[0.0f32; 3]; 3];
+    for (row, values) in matrix.iter_mut().enumerate() {
+        for (column, value) in values.iter_mut().enumerate() {
+            *value = (0..3)
+                .map(|i| XYZ_D50_TO_LINEAR_SRGB[row][i] * profile.to_xyz[i][column])
+                .sum();
+        }
+    }
+
+    map_rgba_rows(image, |_, _, pixel| {
+        let linear = [
+            decode[0][pixel[0] as usize],
+            decode[1][pixel[1] as usize],
+            decode[2][pixel[2] as usize],
+        ];
+        let mut output = [0, 0, 0, pixel[3]];
+        for (channel, row) in matrix.iter().enumerate() {
+            let value = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+            output[channel] =
+                encode[(value.clamp(0.0, 1.0) * (ICC_ENCODE_STEPS - 1) as f32).round() as usize];
+        }
+        output
+    })
+}
+
+fn read_be_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_be_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_s15_fixed16(bytes: &[u8], offset: usize) -> Option<f32> {
+    Some(read_be_u32(bytes, offset)? as i32 as f32 / 65536.0)
+}
+
+fn channel_histograms(image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> [[u32; 256]; 3] {
+    let mut histograms = [[0; 256]; 3];
+
+    for pixel in image.pixels() {
+        for (channel, histogram) in histograms.iter_mut().enumerate() {
+            histogram[pixel[channel] as usize] += 1;
+        }
+    }
+
+    histograms
+}
+
+fn histogram_range(histogram: &[u32; 256]) -> (u8, u8) {
+    let low = histogram.iter().position(|&count| count > 0).unwrap_or(0);
+    let high = histogram
+        .iter()
+        .rposition(|&count| count > 0)
+        .unwrap_or(255);
+    (low as u8, high as u8)
+}
+
+pub fn auto_levels_per_channel(
+    image: &DynamicImage,
+    preserve_luminance: bool,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let mut rgba_image = image.to_rgba8();
+    let ranges = channel_histograms(&rgba_image).map(|histogram| histogram_range(&histogram));
+
+    for pixel in rgba_image.pixels_mut() {
+        let original = [pixel[0], pixel[1], pixel[2]];
+        let mut stretched = [0.0; 3];
+
+        for (channel, &(low, high)) in ranges.iter().enumerate() {
+            stretched[channel] = if high > low {
+                (original[channel].saturating_sub(low)) as f64 * 255.0 / (high - low) as f64
+            } else {
+                original[channel] as f64
+            };
+        }
+
+        if preserve_luminance {
+            let target = compute_luminance(&original) as f64;
+            let current = 0.299 * stretched[0] + 0.587 * stretched[1] + 0.114 * stretched[2];
+            if current > 0.0 {
+                stretched = stretched.map(|value| value * target / current);
+            }
+        }
+
+        for channel in 0..3 {
+            pixel[channel] = stretched[channel].round().clamp(0.0, 255.0) as u8;
+        }
+    }
+
+    rgba_image
+}
+
+/// Applies gamma, then contrast around mid gray, then a brightness offset to every color channel.
+pub fn adjust_tone(
+    image: &DynamicImage,
+    brightness: f32,
+    contrast: f32,
+    gamma: f32,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let mut lookup = [0u8; 256];
+    for (value, mapped) in lookup.iter_mut().enumerate() {
+        let adjusted =
+            ((value as f32 / 255.0).powf(1.0 / gamma) - 0.5) * contrast + 0.5 + brightness;
+        *mapped = (adjusted.clamp(0.0, 1.0) * 255.0).round() as u8;
+    }
+
+    let mut rgba_image = image.to_rgba8();
+    for pixel in rgba_image.pixels_mut() {
+        for channel in 0..3 {
+            pixel[channel] = lookup[pixel[channel] as usize];
+        }
+    }
+
+    rgba_image
+}
+
+/// The mid-gray that [`tone_map`] exposes the log-average luminance of a scene to.
+const TONE_MAP_KEY: f32 = 0.18;
+
+/// Maps a 16-bit or floating-point `image` to 8-bit sRGB in two passes. The first measures the
+/// log-average luminance of the scene, the second exposes it to mid-gray (except for
+/// [`ToneMap::Clamp`]) and compresses the highlights with `operator`. Floating-point pixels are
+/// taken as linear light, as in EXR and Radiance HDR files; integer pixels as sRGB-encoded.
+pub fn tone_map(image: &DynamicImage, operator: &ToneMap) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let linear_input = matches!(
+        image,
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+    );
+    let mut pixels = image.to_rgba32f();
+    for pixel in pixels.pixels_mut() {
+        for channel in 0..3 {
+            let value = pixel[channel].max(0.0);
+            pixel[channel] = if linear_input {
+                value
+            } else {
+                srgb_to_linear(value)
+            };
+        }
+    }
+
+    let luminance = |[r, g, b]: [f32; 3]| 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let exposure = match operator {
+        ToneMap::Clamp => 1.0,
+        ToneMap::Reinhard | ToneMap::Aces => {
+            let count = (pixels.width() as f64 * pixels.height() as f64).max(1.0);
+            let log_sum: f64 = pixels
+                .par_pixels()
+                .map(|pixel| (1e-4 + luminance([pixel[0], pixel[1], pixel[2]]) as f64).ln())
+                .sum();
+            TONE_MAP_KEY / (log_sum / count).exp() as f32
+        }
+    };
+
+    let mut output = ImageBuffer::new(pixels.width(), pixels.height());
+    output.par_pixels_mut().zip(pixels.par_pixels()).for_each(
+        |(output, pixel): (&mut Rgba<u8>, _)| {
+            let rgb = [0, 1, 2].map(|channel| pixel[channel] * exposure);
+            let mapped = match operator {
+                ToneMap::Clamp => rgb,
+                ToneMap::Reinhard => {
+                    let luminance = luminance(rgb);
+                    rgb.map(|value| value / (1.0 + luminance))
+                }
+                ToneMap::Aces => rgb.map(|value| {
+                    (value * (2.51 * value + 0.03)) / (value * (2.43 * value + 0.59) + 0.14)
+                }),
+            };
+            let [r, g, b] =
+                mapped.map(|value| (linear_to_srgb(value.clamp(0.0, 1.0)) * 255.0).round() as u8);
+            let alpha = (pixel[3].clamp(0.0, 1.0) * 255.0).round() as u8;
+            *output = Rgba([r, g, b, alpha]);
+        },
+    );
+
+    output
+}
+
+pub fn swap_channels(
+    image: &DynamicImage,
+    channel_swap: &ChannelSwap,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let mut rgba_image = image.to_rgba8();
+
+    for pixel in rgba_image.pixels_mut() {
+        let original = [pixel[0], pixel[1], pixel[2]];
+        for (channel, &source) in channel_swap.sources.iter().enumerate() {
+            pixel[channel] = original[source];
+        }
+    }
+
+    rgba_image
+}
+
+fn map_rgba_rows<F>(image: &DynamicImage, map_pixel: F) -> ImageBuffer<Rgba<u8>, Vec<u8>>
+where
+    F: Fn(u32, u32, [u8; 4]) -> [u8; 4] + Sync,
+{
+    let rgba_image = image.to_rgba8();
+    let (width, height) = rgba_image.dimensions();
+    let row_length = width.max(1) as usize * 4;
+
+    let mut output_image = ImageBuffer::new(width, height);
+
+    output_image
+        .par_chunks_mut(row_length)
+        .zip(rgba_image.par_chunks(row_length))
+        .enumerate()
+        .for_each(|(y, (output_row, input_row))| {
+            for (x, (output, input)) in output_row
+                .chunks_exact_mut(4)
+                .zip(input_row.chunks_exact(4))
+                .enumerate()
+            {
+                let pixel = [input[0], input[1], input[2], input[3]];
+                output.copy_from_slice(&map_pixel(x as u32, y as u32, pixel));
+            }
+        });
+
+    output_image
+}
+
+/// Makes `image` opaque before dithering: premultiplied, composited onto the matte color,
+/// or with the alpha simply dropped for the policies that restore it afterwards.
+pub fn flatten_alpha(image: &DynamicImage, policy: &AlphaPolicy) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let background = match policy {
+        AlphaPolicy::Premultiply => Some([0, 0, 0]),
+        AlphaPolicy::Matte(color) => Some(*color),
+        AlphaPolicy::Keep | AlphaPolicy::Dither => None,
+    };
+
+    map_rgba_rows(image, |_, _, pixel| {
+        let [r, g, b] = match background {
+            Some(background) => [0, 1, 2].map(|channel| {
+                let alpha = pixel[3] as u32;
+                ((pixel[channel] as u32 * alpha + background[channel] as u32 * (255 - alpha) + 127)
+                    / 255) as u8
+            }),
+            None => [pixel[0], pixel[1], pixel[2]],
+        };
+        [r, g, b, 255]
+    })
+}
+
+/// Carries the alpha of `image` over to the dithered `output`, as is for `Keep` and
+/// `Premultiply` or thresholded to 1 bit for `Dither`. The output's own alpha, which holds
+/// the dither in color mode, is multiplied in rather than replaced.
+pub fn restore_alpha(
+    output: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    image: &DynamicImage,
+    policy: &AlphaPolicy,
+    threshold_matrix: &ThresholdMatrix,
+    kernel: Option<&DiffusionKernel>,
+) {
+    let alpha: Vec<u8> = match policy {
+        AlphaPolicy::Matte(_) => return,
+        AlphaPolicy::Keep | AlphaPolicy::Premultiply => {
+            image.to_rgba8().pixels().map(|pixel| pixel[3]).collect()
+        }
+        AlphaPolicy::Dither => {
+            let rgba_image = image.to_rgba8();
+            let (width, height) = rgba_image.dimensions();
+            match kernel {
+                Some(kernel) => {
+                    let intensities = rgba_image
+                        .pixels()
+                        .map(|pixel| pixel[3] as f32 / 255.0)
+                        .collect();
+                    diffuse_error(intensities, width, height, kernel)
+                        .into_iter()
+                        .map(|on| if on { 255 } else { 0 })
+                        .collect()
+                }
+                None => rgba_image
+                    .enumerate_pixels()
+                    .map(|(x, y, pixel)| {
+                        if pixel[3] as f32 / 255.0 > threshold_matrix.threshold(x, y) {
+                            255
+                        } else {
+                            0
+                        }
+                    })
+                    .collect(),
+            }
+        }
+    };
+
+    for (pixel, alpha) in output.pixels_mut().zip(alpha) {
+        pixel[3] = ((pixel[3] as u32 * alpha as u32 + 127) / 255) as u8;
+    }
+}
+
+/// Keeps the dithered `output` only where `mask` is white and passes `image` through unchanged
+/// elsewhere. A mask of a different size is stretched to fit the output.
+pub fn apply_mask(
+    output: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    image: &DynamicImage,
+    mask: &GrayImage,
+) {
+    let (width, height) = output.dimensions();
+    let resized_mask;
+    let mask = if mask.dimensions() != (width, height) {
+        resized_mask = imageops::resize(mask, width, height, FilterType::Triangle);
+        &resized_mask
+    } else {
+        mask
+    };
+
+    let rgba_image = image.to_rgba8();
+    for ((pixel, original), mask_pixel) in output
+        .pixels_mut()
+        .zip(rgba_image.pixels())
+        .zip(mask.pixels())
+    {
+        if mask_pixel[0] < 128 {
+            *pixel = *original;
+        }
+    }
+}
+
+/// Recolors a grayscale result: black becomes `foreground`, white becomes `background` and the
+/// gray levels of multi-level output are blended in between. Alpha is kept.
+pub fn apply_duotone(
+    output: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    foreground: [u8; 3],
+    background: [u8; 3],
+) {
+    for pixel in output.pixels_mut() {
+        let level = pixel[0] as u32;
+        for channel in 0..3 {
+            pixel[channel] = ((foreground[channel] as u32 * (255 - level)
+                + background[channel] as u32 * level
+                + 127)
+                / 255) as u8;
+        }
+    }
+}
+
+/// Recolors a color-mode result, where the dither is held in the alpha channel: opaque pixels
+/// are painted `foreground` and transparent ones `background`, leaving the output opaque.
+/// Either color can be omitted to keep the source colors or the transparency.
+pub fn apply_duotone_color(
+    output: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    foreground: Option<[u8; 3]>,
+    background: Option<[u8; 3]>,
+) {
+    for pixel in output.pixels_mut() {
+        let color = if pixel[3] == 0 {
+            background
+        } else {
+            foreground
+        };
+        if let Some([r, g, b]) = color {
+            *pixel = Rgba([r, g, b, 255]);
+        }
+    }
+}
+
+/// Dithers the luma of `image` to black and white against `threshold_matrix`. Images with more
+/// than 8 bits per channel are thresholded at their full precision.
+pub fn apply_bayer_dithering_grayscale(
+    image: &DynamicImage,
+    threshold_matrix: &ThresholdMatrix,
+    tone_curve: &[f32; 256],
+) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+    if is_deep(image) {
+        return apply_bayer_dithering_grayscale_deep(image, threshold_matrix, tone_curve);
+    }
+
+    let gray_image = image.to_luma8();
+    let (width, height) = gray_image.dimensions();
+    let row_length = width.max(1) as usize;
+
+    let mut output_image = GrayImage::new(width, height);
+
+    #[cfg(feature = "simd")]
+    if let Some(cutoffs) = simd::Cutoffs::new(threshold_matrix, tone_curve) {
+        output_image
+            .par_chunks_mut(row_length)
+            .zip(gray_image.par_chunks(row_length))
+            .enumerate()
+            .for_each(|(y, (output_row, input_row))| {
+                simd::threshold_row(input_row, &cutoffs.row(y, row_length), output_row);
+            });
+        return output_image;
+    }
+
+    output_image
+        .par_chunks_mut(row_length)
+        .zip(gray_image.par_chunks(row_length))
+        .enumerate()
+        .for_each(|(y, (output_row, input_row))| {
+            for (x, (output, &input)) in output_row.iter_mut().zip(input_row).enumerate() {
+                let intensity = tone_curve[input as usize];
+                let threshold = threshold_matrix.threshold(x as u32, y as u32);
+
+                *output = if intensity > threshold { 255 } else { 0 };
+            }
+        });
+
+    output_image
+}
+
+/// The 16-bit path of [`apply_bayer_dithering_grayscale`], comparing the interpolated intensity
+/// of each 16-bit luma value against the matrix instead of rounding the input to 8 bits first.
+fn apply_bayer_dithering_grayscale_deep(
+    image: &DynamicImage,
+    threshold_matrix: &ThresholdMatrix,
+    tone_curve: &[f32; 256],
+) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+    let gray_image = image.to_luma16();
+    let (width, height) = gray_image.dimensions();
+    let row_length = width.max(1) as usize;
+
+    let mut output_image = GrayImage::new(width, height);
+    output_image
+        .par_chunks_mut(row_length)
+        .zip(gray_image.par_chunks(row_length))
+        .enumerate()
+        .for_each(|(y, (output_row, input_row))| {
+            for (x, (output, &input)) in output_row.iter_mut().zip(input_row).enumerate() {
+                let intensity = deep_intensity(tone_curve, input);
+                let threshold = threshold_matrix.threshold(x as u32, y as u32);
+
+                *output = if intensity > threshold { 255 } else { 0 };
+            }
+        });
+
+    output_image
+}
+
+/// The luma of every pixel of `image` through `tone_curve`, at 16 bits for deep images.
+fn luma_intensities(image: &DynamicImage, tone_curve: &[f32; 256]) -> Vec<f32> {
+    if is_deep(image) {
+        image
+            .to_luma16()
+            .pixels()
+            .map(|pixel| deep_intensity(tone_curve, pixel[0]))
+            .collect()
+    } else {
+        image
+            .to_luma8()
+            .pixels()
+            .map(|pixel| tone_curve[pixel[0] as usize])
+            .collect()
+    }
+}
+
+/// Whether `image` holds more than 8 bits per channel, such as 16-bit PNG and TIFF scans.
+fn is_deep(image: &DynamicImage) -> bool {
+    let color = image.color();
+    color.bytes_per_pixel() > color.channel_count()
+}
+
+/// Looks up a 16-bit `value` in an 8-bit `tone_curve`, interpolating linearly between the two
+/// nearest entries so that levels between 8-bit steps keep their own intensity.
+fn deep_intensity(tone_curve: &[f32; 256], value: u16) -> f32 {
+    let position = value as f32 * 255.0 / 65535.0;
+    let index = (position as usize).min(254);
+    let fraction = position - index as f32;
+    tone_curve[index] + (tone_curve[index + 1] - tone_curve[index]) * fraction
+}
+
+/// Stochastic (FM) screening: every `cell_size`x`cell_size` cell gets as many white pixels as its
+/// average intensity calls for, placed where the pixels are brightest relative to
+/// `threshold_matrix`, typically blue noise. The dots keep one size and vary in density.
+pub fn apply_fm_screening(
+    image: &DynamicImage,
+    threshold_matrix: &ThresholdMatrix,
+    cell_size: u32,
+    tone_curve: &[f32; 256],
+) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+    let gray_image = image.to_luma8();
+    let (width, height) = gray_image.dimensions();
+    let cell_size = cell_size.max(1);
+    let band_length = width.max(1) as usize * cell_size as usize;
+
+    let mut output_image = GrayImage::new(width, height);
+    output_image
+        .par_chunks_mut(band_length)
+        .enumerate()
+        .for_each(|(band, output_rows)| {
+            let top = band as u32 * cell_size;
+            let rows = (output_rows.len() / width.max(1) as usize) as u32;
+            for left in (0..width).step_by(cell_size as usize) {
+                let columns = cell_size.min(width - left);
+                let mut pixels: Vec<(f32, u32, u32)> = (0..rows)
+                    .flat_map(|dy| (0..columns).map(move |dx| (left + dx, top + dy)))
+                    .map(|(x, y)| {
+                        let intensity = tone_curve[gray_image.get_pixel(x, y)[0] as usize];
+                        (intensity - threshold_matrix.threshold(x, y), x, y)
+                    })
+                    .collect();
+                let white = pixels
+                    .iter()
+                    .map(|&(_, x, y)| tone_curve[gray_image.get_pixel(x, y)[0] as usize])
+                    .sum::<f32>()
+                    .round() as usize;
+
+                pixels.sort_by(|a, b| b.0.total_cmp(&a.0));
+                for &(_, x, y) in pixels.iter().take(white) {
+                    output_rows[((y - top) * width + x) as usize] = 255;
+                }
+            }
+        });
+
+    output_image
+}
+
+/// Dithers the luma of `image` to `levels` evenly spaced gray levels, comparing each pixel's
+/// position between the two surrounding levels against `threshold_matrix`. With `linear`, the
+/// levels are placed in linear light to match a `tone_curve` built for linear light.
+///
+/// `preserve_order` picks which way the matrix is read. With [`PreserveOrder::Dark`] a pixel
+/// steps up to the lighter level once its position between the levels is above the threshold,
+/// so the lowest thresholds lighten first and a tie stays on the darker level. With
+/// [`PreserveOrder::Light`] a pixel steps down to the darker level once its distance below the
+/// lighter level is above the threshold, so the lowest thresholds darken first and a tie stays
+/// on the lighter level.
+pub fn apply_bayer_dithering_levels(
+    image: &DynamicImage,
+    threshold_matrix: &ThresholdMatrix,
+    preserve_order: PreserveOrder,
+    tone_curve: &[f32; 256],
+    levels: u32,
+    linear: bool,
+) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+    let steps = levels.max(2) - 1;
+    let level_values: Vec<f32> = (0..=steps)
+        .map(|level| {
+            let value = level as f32 / steps as f32;
+            if linear {
+                srgb_to_linear(value)
+            } else {
+                value
+            }
+        })
+        .collect();
+
+    let gray_image = image.to_luma8();
+    let (width, height) = gray_image.dimensions();
+    let row_length = width.max(1) as usize;
+
+    let mut output_image = GrayImage::new(width, height);
+
+    output_image
+        .par_chunks_mut(row_length)
+        .zip(gray_image.par_chunks(row_length))
+        .enumerate()
+        .for_each(|(y, (output_row, input_row))| {
+            for (x, (output, &input)) in output_row.iter_mut().zip(input_row).enumerate() {
+                let intensity = tone_curve[input as usize];
+                let upper = level_values
+                    .partition_point(|&value| value < intensity)
+                    .clamp(1, steps as usize);
+                let (low, high) = (level_values[upper - 1], level_values[upper]);
+                let fraction = (intensity - low) / (high - low);
+                let threshold = threshold_matrix.threshold(x as u32, y as u32);
+
+                let lighter = match preserve_order {
+                    PreserveOrder::Dark => fraction > threshold,
+                    PreserveOrder::Light => 1.0 - fraction <= threshold,
+                };
+                let level = if lighter { upper } else { upper - 1 };
+                *output = (level as f32 * 255.0 / steps as f32).round() as u8;
+            }
+        });
+
+    output_image
+}
+
+/// Keeps the colors of `image` and dithers its luminance into the alpha channel.
+pub fn apply_bayer_dithering_color(
+    image: &DynamicImage,
+    threshold_matrix: &ThresholdMatrix,
+    preserve_order: PreserveOrder,
+    channel: &ThresholdChannel,
+    tone_curve: &[f32; 256],
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    map_rgba_rows(image, |x, y, pixel| {
+        let intensity = tone_curve[channel.extract(&[pixel[0], pixel[1], pixel[2]]) as usize];
+        let threshold = threshold_matrix.threshold(x, y);
+
+        let new_intensity = match preserve_order {
+            PreserveOrder::Light => {
+                if intensity > threshold {
+                    255
+                } else {
+                    0
+                }
+            }
+            PreserveOrder::Dark => {
+                if intensity > threshold {
+                    0
+                } else {
+                    255
+                }
+            }
+        };
+
+        [pixel[0], pixel[1], pixel[2], new_intensity]
+    })
+}
+
+/// Thresholds the R, G and B channels of `image` independently, giving at most 8 colors.
+pub fn apply_bayer_dithering_rgb(
+    image: &DynamicImage,
+    threshold_matrix: &ThresholdMatrix,
+    tone_curve: &[f32; 256],
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    map_rgba_rows(image, |x, y, pixel| {
+        let threshold = threshold_matrix.threshold(x, y);
+        let [r, g, b] = [0, 1, 2].map(|channel| {
+            if tone_curve[pixel[channel] as usize] > threshold {
+                255
+            } else {
+                0
+            }
+        });
+        [r, g, b, 255]
+    })
+}
+
+/// Error-diffuses the R, G and B channels of `image` independently with `kernel`.
+pub fn apply_error_diffusion_rgb(
+    image: &DynamicImage,
+    kernel: &DiffusionKernel,
+    tone_curve: &[f32; 256],
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let rgb_image = image.to_rgb8();
+    let (width, height) = rgb_image.dimensions();
+    let channels = [0, 1, 2].map(|channel| {
+        let intensities = rgb_image
+            .pixels()
+            .map(|pixel| tone_curve[pixel[channel] as usize])
+            .collect();
+        diffuse_error(intensities, width, height, kernel)
+    });
+
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let index = (y * width + x) as usize;
+        let [r, g, b] = channels
+            .each_ref()
+            .map(|on| if on[index] { 255 } else { 0 });
+        Rgba([r, g, b, 255])
+    })
+}
+
+/// Maps each pixel of `image` to the nearest palette color after an ordered threshold offset.
+pub fn apply_palette_dithering(
+    image: &DynamicImage,
+    palette: &Palette,
+    threshold_matrix: &ThresholdMatrix,
+    tone_curve: &[f32; 256],
+    linear: bool,
+    color_space: &ColorSpace,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let spread = 1.0 / (palette.colors.len() as f32).cbrt();
+    let working_colors = palette.working_colors(linear, color_space);
+
+    map_rgba_rows(image, |x, y, pixel| {
+        let offset = (threshold_matrix.threshold(x, y) - 0.5) * spread;
+        let color = [0, 1, 2].map(|channel| tone_curve[pixel[channel] as usize] - offset);
+        let nearest = nearest_color(&working_colors, color_space.convert(color, linear));
+        let [r, g, b] = palette.colors[nearest];
+        [r, g, b, pixel[3]]
+    })
+}
+
+/// Pattern dithering after Thomas Knoll: every pixel builds a mix of one palette color per
+/// threshold matrix cell (up to 64) by diffusing the error along the mix, sorts the mix by
+/// luminance and outputs the entry the threshold of that pixel points at.
+pub fn apply_pattern_dithering(
+    image: &DynamicImage,
+    palette: &Palette,
+    threshold_matrix: &ThresholdMatrix,
+    tone_curve: &[f32; 256],
+    linear: bool,
+    color_space: &ColorSpace,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let mix_size = pattern_mix_size(threshold_matrix);
+    let working_colors = palette.working_colors(linear, color_space);
+    let colors = if linear {
+        &palette.linear
+    } else {
+        &palette.encoded
+    };
+    let luminances: Vec<f32> = colors.iter().map(|&color| luminance(color)).collect();
+
+    map_rgba_rows(image, |x, y, pixel| {
+        let goal = [0, 1, 2].map(|channel| tone_curve[pixel[channel] as usize]);
+        let mut error = [0.0; 3];
+        let mut mix = [0; PATTERN_MAX_MIX];
+        for entry in &mut mix[..mix_size] {
+            let attempt = [0, 1, 2].map(|channel| goal[channel] + error[channel]);
+            *entry = nearest_color(&working_colors, color_space.convert(attempt, linear));
+            for channel in 0..3 {
+                error[channel] += goal[channel] - colors[*entry][channel];
+            }
+        }
+        mix[..mix_size].sort_unstable_by(|&a, &b| luminances[a].total_cmp(&luminances[b]));
+
+        let index = (threshold_matrix.threshold(x, y) * mix_size as f32) as usize;
+        let [r, g, b] = palette.colors[mix[index.min(mix_size - 1)]];
+        [r, g, b, pixel[3]]
+    })
+}
+
+/// Positional dithering with Joel Yliluoma's algorithm 1: every color is drawn as the mix of two
+/// palette colors, in one of the ratios the threshold matrix can express (up to 64), that best
+/// matches it. Colors are mixed in linear light unless `linear` is false but compared
+/// gamma-encoded (or in `color_space`), and mixes of very different colors are penalized so that
+/// flat areas don't turn into harsh patterns.
+pub fn apply_yliluoma1_dithering(
+    image: &DynamicImage,
+    palette: &Palette,
+    threshold_matrix: &ThresholdMatrix,
+    tone_curve: &[f32; 256],
+    linear: bool,
+    color_space: &ColorSpace,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let mix_size = pattern_mix_size(threshold_matrix);
+    let colors = if linear {
+        &palette.linear
+    } else {
+        &palette.encoded
+    };
+    let perceived_colors: Vec<[f32; 3]> = colors
+        .iter()
+        .map(|&color| perceived(color, linear, color_space))
+        .collect();
+
+    apply_mix_plans(image, palette, threshold_matrix, tone_curve, |goal| {
+        let target = perceived(goal, linear, color_space);
+        let mut best = (f32::INFINITY, 0, 0, 0);
+        for first in 0..colors.len() {
+            for second in first..colors.len() {
+                let contrast = squared_distance(perceived_colors[first], perceived_colors[second]);
+                let ratios = if first == second { 0..=0 } else { 0..=mix_size };
+                for ratio in ratios {
+                    let weight = ratio as f32 / mix_size as f32;
+                    let mixed = [0, 1, 2].map(|channel| {
+                        colors[first][channel]
+                            + (colors[second][channel] - colors[first][channel]) * weight
+                    });
+                    let penalty = squared_distance(target, perceived(mixed, linear, color_space))
+                        + contrast * YLILUOMA_CONTRAST_PENALTY * ((weight - 0.5).abs() + 0.5);
+                    if penalty < best.0 {
+                        best = (penalty, first, second, ratio);
+                    }
+                }
+            }
+        }
+
+        let (_, first, second, ratio) = best;
+        vec![(second, ratio), (first, mix_size - ratio)]
+    })
+}
+
+/// Positional dithering with Joel Yliluoma's algorithm 2: a mix of up to one palette color per
+/// threshold matrix cell (at most 64) is built greedily, each step adding the color, and how many
+/// copies of it, that brings the average of the mix closest to the pixel. The mix is sorted by
+/// luminance and indexed by the threshold, like [`apply_pattern_dithering`]. Colors are averaged
+/// in linear light unless `linear` is false.
+pub fn apply_yliluoma2_dithering(
+    image: &DynamicImage,
+    palette: &Palette,
+    threshold_matrix: &ThresholdMatrix,
+    tone_curve: &[f32; 256],
+    linear: bool,
+    color_space: &ColorSpace,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let mix_size = pattern_mix_size(threshold_matrix);
+    let colors = if linear {
+        &palette.linear
+    } else {
+        &palette.encoded
+    };
+    let mut by_luminance: Vec<usize> = (0..colors.len()).collect();
+    by_luminance.sort_by(|&a, &b| luminance(colors[a]).total_cmp(&luminance(colors[b])));
+
+    apply_mix_plans(image, palette, threshold_matrix, tone_curve, |goal| {
+        let target = perceived(goal, linear, color_space);
+        let mut counts = vec![0; colors.len()];
+        let mut sum = [0.0f32; 3];
+        let mut count = 0;
+        while count < mix_size {
+            let mut best = (f32::INFINITY, 0, 1);
+            for (index, color) in colors.iter().enumerate() {
+                let mut copies = 1;
+                while copies <= count.max(1) {
+                    let total = (count + copies) as f32;
+                    let mixed = [0, 1, 2]
+                        .map(|channel| (sum[channel] + color[channel] * copies as f32) / total);
+                    let penalty = squared_distance(target, perceived(mixed, linear, color_space));
+                    if penalty < best.0 {
+                        best = (penalty, index, copies);
+                    }
+                    copies *= 2;
+                }
+            }
+
+            let (_, index, copies) = best;
+            let copies = copies.min(mix_size - count);
+            counts[index] += copies;
+            count += copies;
+            for channel in 0..3 {
+                sum[channel] += colors[index][channel] * copies as f32;
+            }
+        }
+
+        by_luminance
+            .iter()
+            .filter(|&&index| counts[index] > 0)
+            .map(|&index| (index, counts[index]))
+            .collect()
+    })
+}
+
+fn pattern_mix_size(threshold_matrix: &ThresholdMatrix) -> usize {
+    (threshold_matrix.width() * threshold_matrix.height()).clamp(1, PATTERN_MAX_MIX as u32) as usize
+}
+
+/// Where mixes are compared: like `ColorSpace::convert`, except that plain RGB is compared
+/// gamma-encoded, as linear-light distances overrate differences between dark colors.
+fn perceived(color: [f32; 3], linear: bool, color_space: &ColorSpace) -> [f32; 3] {
+    match color_space {
+        ColorSpace::Rgb if linear => color.map(linear_to_srgb),
+        _ => color_space.convert(color, linear),
+    }
+}
+
+fn luminance(color: [f32; 3]) -> f32 {
+    0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2]
+}
+
+fn squared_distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3)
+        .map(|channel| (a[channel] - b[channel]).powi(2))
+        .sum()
+}
+
+/// Dithers `image` with a mix plan per distinct color: runs of palette indices, in the order the
+/// threshold walks them, whose lengths add up to the mix size. Plans are computed once per color.
+fn apply_mix_plans<F>(
+    image: &DynamicImage,
+    palette: &Palette,
+    threshold_matrix: &ThresholdMatrix,
+    tone_curve: &[f32; 256],
+    plan: F,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>>
+where
+    F: Fn([f32; 3]) -> Vec<(usize, usize)> + Sync,
+{
+    let rgb_image = image.to_rgb8();
+    let mut distinct: Vec<[u8; 3]> = rgb_image.pixels().map(|pixel| pixel.0).collect();
+    distinct.sort_unstable();
+    distinct.dedup();
+    let plans: HashMap<[u8; 3], Vec<(usize, usize)>> = distinct
+        .into_par_iter()
+        .map(|color| {
+            let goal = color.map(|value| tone_curve[value as usize]);
+            (color, plan(goal))
+        })
+        .collect();
+
+    map_rgba_rows(image, |x, y, pixel| {
+        let runs = &plans[&[pixel[0], pixel[1], pixel[2]]];
+        let total: usize = runs.iter().map(|&(_, count)| count).sum();
+        let mut position =
+            ((threshold_matrix.threshold(x, y) * total as f32) as usize).min(total - 1);
+        let mut chosen = runs[0].0;
+        for &(index, count) in runs {
+            chosen = index;
+            if position < count {
+                break;
+            }
+            position -= count;
+        }
+        let [r, g, b] = palette.colors[chosen];
+        [r, g, b, pixel[3]]
+    })
+}
+
+/// Maps each pixel of `image` to the nearest palette color, diffusing the RGB error with `kernel`.
+/// Edge enhancement pushes each pixel's choice away from the palette's mean color by its input's
+/// own distance from it.
+pub fn apply_palette_error_diffusion(
+    image: &DynamicImage,
+    palette: &Palette,
+    kernel: &DiffusionKernel,
+    tone_curve: &[f32; 256],
+    linear: bool,
+    color_space: &ColorSpace,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let rgba_image = image.to_rgba8();
+    let (width, height) = (rgba_image.width() as i32, rgba_image.height() as i32);
+    let working_colors = palette.working_colors(linear, color_space);
+    let mut colors: Vec<[f32; 3]> = rgba_image
+        .pixels()
+        .map(|pixel| {
+            let color = [0, 1, 2].map(|channel| tone_curve[pixel[channel] as usize]);
+            color_space.convert(color, linear)
+        })
+        .collect();
+    let originals = colors.clone();
+    let center: [f32; 3] = std::array::from_fn(|channel| {
+        working_colors
+            .iter()
+            .map(|color| color[channel])
+            .sum::<f32>()
+            / working_colors.len() as f32
+    });
+    let nearest = |index: usize, color: [f32; 3]| {
+        let enhanced = std::array::from_fn(|channel| {
+            color[channel] + kernel.edge_enhance * (originals[index][channel] - center[channel])
+        });
+        nearest_color(&working_colors, enhanced)
+    };
+
+    let mut output_image = ImageBuffer::new(width as u32, height as u32);
+
+    if kernel.scan == ScanOrder::Hilbert {
+        riemersma(
+            &mut colors,
+            width as u32,
+            height as u32,
+            kernel,
+            |index, color| {
+                let nearest = nearest(index, color);
+                let [r, g, b] = palette.colors[nearest];
+                let (x, y) = (index as u32 % width as u32, index as u32 / width as u32);
+                let alpha = rgba_image.get_pixel(x, y)[3];
+                output_image.put_pixel(x, y, Rgba([r, g, b, alpha]));
+                working_colors[nearest]
+            },
+        );
+        return output_image;
+    }
+
+    for y in 0..height {
+        let direction = kernel.scan_direction(y);
+        for step in 0..width {
+            let x = if direction < 0 {
+                width - 1 - step
+            } else {
+                step
+            };
+            let index = (y * width + x) as usize;
+            let color = colors[index];
+            let nearest = nearest(index, color);
+            let working_color = working_colors[nearest];
+            let error = [0, 1, 2].map(|channel| color[channel] - working_color[channel]);
+            let pixel = rgba_image.get_pixel(x as u32, y as u32);
+            let original = (0..3)
+                .map(|channel| tone_curve[pixel[channel] as usize])
+                .sum::<f32>()
+                / 3.0;
+
+            for (tap, &(dx, dy, weight)) in kernel.weights.iter().enumerate() {
+                let (nx, ny) = (x + dx * direction, y + dy);
+                if nx >= 0 && nx < width && ny < height {
+                    let neighbor = &mut colors[(ny * width + nx) as usize];
+                    let weight = kernel.tap_weight(tap, weight, original);
+                    for channel in 0..3 {
+                        neighbor[channel] += error[channel] * weight;
+                    }
+                }
+            }
+
+            let [r, g, b] = palette.colors[nearest];
+            let alpha = pixel[3];
+            output_image.put_pixel(x as u32, y as u32, Rgba([r, g, b, alpha]));
+        }
+    }
+
+    output_image
+}
+
+fn diffuse_error(
+    mut intensities: Vec<f32>,
+    width: u32,
+    height: u32,
+    kernel: &DiffusionKernel,
+) -> Vec<bool> {
+    let mut on = vec![false; intensities.len()];
+
+    if kernel.scan == ScanOrder::Hilbert {
+        let mut values: Vec<[f32; 1]> = intensities.iter().map(|&value| [value]).collect();
+        riemersma(&mut values, width, height, kernel, |index, [value]| {
+            on[index] = kernel.is_on(value, intensities[index]);
+            [if on[index] { 1.0 } else { 0.0 }]
+        });
+        return on;
+    }
+
+    if kernel.scan == ScanOrder::Raster && height > 1 && rayon::current_num_threads() > 1 {
+        return diffuse_error_wavefront(&intensities, width, height, kernel);
+    }
+
+    let originals = kernel.uses_originals().then(|| intensities.clone());
+    diffuse_error_in_place(
+        &mut intensities,
+        originals.as_deref(),
+        width,
+        height,
+        kernel,
+        &mut on,
+    );
+    on
+}
+
+/// The serial scan of [`diffuse_error`], accumulating error in `intensities` and writing the
+/// decisions to `on`. `originals` holds the undiffused intensities for per-level kernels and
+/// edge enhancement.
+fn diffuse_error_in_place(
+    intensities: &mut [f32],
+    originals: Option<&[f32]>,
+    width: u32,
+    height: u32,
+    kernel: &DiffusionKernel,
+    on: &mut [bool],
+) {
+    let (width, height) = (width as i32, height as i32);
+
+    for y in 0..height {
+        let direction = kernel.scan_direction(y);
+        for step in 0..width {
+            let x = if direction < 0 {
+                width - 1 - step
+            } else {
+                step
+            };
+            let index = (y * width + x) as usize;
+            let intensity = intensities[index];
+            let original = originals.map_or(intensity, |originals| originals[index]);
+            on[index] = kernel.is_on(intensity, original);
+            let error = intensity - if on[index] { 1.0 } else { 0.0 };
+
+            for (tap, &(dx, dy, weight)) in kernel.weights.iter().enumerate() {
+                let (nx, ny) = (x + dx * direction, y + dy);
+                if nx >= 0 && nx < width && ny < height {
+                    intensities[(ny * width + nx) as usize] +=
+                        error * kernel.tap_weight(tap, weight, original);
+                }
+            }
+        }
+    }
+}
+
+/// Raster-order error diffusion spread over threads as a wavefront. Rows are claimed in order and
+/// each trails the rows above just far enough that all error reaching a pixel is known. Every
+/// pixel pulls its error from its already dithered neighbors in the order the serial scan would
+/// have pushed it, so the floating-point sums, and the output, match [`diffuse_error`] on one
+/// thread exactly.
+fn diffuse_error_wavefront(
+    originals: &[f32],
+    width: u32,
+    height: u32,
+    kernel: &DiffusionKernel,
+) -> Vec<bool> {
+    let (width, height) = (width as usize, height as usize);
+    let mut taps: Vec<(usize, i32, i32, f32)> = kernel
+        .weights
+        .iter()
+        .enumerate()
+        .map(|(tap, &(dx, dy, weight))| (tap, dx, dy, weight))
+        .collect();
+    taps.sort_by_key(|&(_, dx, dy, _)| (-dy, -dx));
+
+    // How many pixels of the row `dy` above must be done before pixel `x` can start, minus `x`.
+    let max_dy = taps.iter().map(|&(_, _, dy, _)| dy).max().unwrap_or(0) as usize;
+    let mut lead = vec![i32::MIN; max_dy + 1];
+    for &(_, dx, dy, _) in &taps {
+        lead[dy as usize] = lead[dy as usize].max(1 - dx);
+    }
+
+    let errors: Vec<AtomicU32> = (0..width * height).map(|_| AtomicU32::new(0)).collect();
+    let progress: Vec<AtomicUsize> = (0..height).map(|_| AtomicUsize::new(0)).collect();
+    let next_row = AtomicUsize::new(0);
+    let mut on = vec![false; width * height];
+
+    let worker = || {
+        let mut rows = Vec::new();
+        loop {
+            let y = next_row.fetch_add(1, Ordering::Relaxed);
+            if y >= height {
+                return rows;
+            }
+
+            let mut row = vec![false; width];
+            let mut ready = vec![0; max_dy + 1];
+            for (x, pixel_on) in row.iter_mut().enumerate() {
+                for dy in 1..=max_dy.min(y) {
+                    let needed = (x as i64 + lead[dy] as i64).clamp(0, width as i64) as usize;
+                    while ready[dy] < needed {
+                        ready[dy] = progress[y - dy].load(Ordering::Acquire);
+                        if ready[dy] < needed {
+                            thread::yield_now();
+                        }
+                    }
+                }
+
+                let index = y * width + x;
+                let mut intensity = originals[index];
+                for &(tap, dx, dy, weight) in &taps {
+                    let (source_x, source_y) = (x as i64 - dx as i64, y as i64 - dy as i64);
+                    if source_x >= 0 && source_x < width as i64 && source_y >= 0 {
+                        let source = source_y as usize * width + source_x as usize;
+                        let error = f32::from_bits(errors[source].load(Ordering::Relaxed));
+                        intensity += error * kernel.tap_weight(tap, weight, originals[source]);
+                    }
+                }
+
+                *pixel_on = kernel.is_on(intensity, originals[index]);
+                let error = intensity - if *pixel_on { 1.0 } else { 0.0 };
+                errors[index].store(error.to_bits(), Ordering::Relaxed);
+                progress[y].store(x + 1, Ordering::Release);
+            }
+            rows.push((y, row));
+        }
+    };
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..rayon::current_num_threads().min(height))
+            .map(|_| scope.spawn(worker))
+            .collect();
+        for worker in workers {
+            for (y, row) in worker.join().unwrap() {
+                on[y * width..(y + 1) * width].copy_from_slice(&row);
+            }
+        }
+    });
+
+    on
+}
+
+/// Visits the pixels along a Hilbert curve, adding a weighted history of recent errors to each
+/// value before `quantize` maps it to the output value the error is measured against.
+fn riemersma<const N: usize>(
+    values: &mut [[f32; N]],
+    width: u32,
+    height: u32,
+    kernel: &DiffusionKernel,
+    mut quantize: impl FnMut(usize, [f32; N]) -> [f32; N],
+) {
+    let weights: Vec<f32> = (0..RIEMERSMA_HISTORY)
+        .map(|age| RIEMERSMA_WEIGHT_RATIO.powf(age as f32 / (RIEMERSMA_HISTORY - 1) as f32))
+        .collect();
+    let mut history = [[0.0f32; N]; RIEMERSMA_HISTORY];
+    let side = width.max(height).next_power_of_two();
+
+    for distance in 0..side as u64 * side as u64 {
+        let (x, y) = hilbert_point(side, distance);
+        if x >= width || y >= height {
+            continue;
+        }
+
+        let index = (y * width + x) as usize;
+        let original = values[index];
+        let mut adjusted = original;
+        for (errors, weight) in history.iter().zip(&weights) {
+            for channel in 0..N {
+                adjusted[channel] += errors[channel] * weight / kernel.divisor;
+            }
+        }
+        let output = quantize(index, adjusted);
+
+        history.rotate_left(1);
+        history[RIEMERSMA_HISTORY - 1] =
+            std::array::from_fn(|channel| original[channel] - output[channel]);
+    }
+}
+
+/// Maps a distance along a Hilbert curve filling a `side`x`side` square to its coordinates.
+fn hilbert_point(side: u32, distance: u64) -> (u32, u32) {
+    let (mut x, mut y, mut t) = (0u32, 0u32, distance);
+    let mut s = 1;
+
+    while s < side {
+        let rx = (1 & (t / 2)) as u32;
+        let ry = (1 & (t ^ rx as u64)) as u32;
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+
+    (x, y)
+}
+
+/// Dithers the luma of `image` to black and white by diffusing errors with `kernel`. Images with
+/// more than 8 bits per channel are diffused at their full precision.
+pub fn apply_error_diffusion_grayscale(
+    image: &DynamicImage,
+    kernel: &DiffusionKernel,
+    tone_curve: &[f32; 256],
+) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+    let (width, height) = (image.width(), image.height());
+    let on = diffuse_error(luma_intensities(image, tone_curve), width, height, kernel);
+    GrayImage::from_fn(width, height, |x, y| {
+        Luma([if on[(y * width + x) as usize] { 255 } else { 0 }])
+    })
+}
+
+/// Reusable grayscale dithering state for webcam feeds and other streams of frames.
+///
+/// The matrix or kernel and the tone curve are set up once, and the scratch buffers of error
+/// diffusion are kept between calls, so [`Ditherer::dither_into`] does not allocate while the
+/// frame size stays the same. The output matches [`apply_bayer_dithering_grayscale`] and
+/// [`apply_error_diffusion_grayscale`]; error diffusion runs on the calling thread only.
+pub struct Ditherer {
+    threshold_matrix: ThresholdMatrix,
+    kernel: Option<DiffusionKernel>,
+    tone_curve: [f32; 256],
+    intensities: Vec<f32>,
+    originals: Vec<f32>,
+    on: Vec<bool>,
+}
+
+impl Ditherer {
+    /// Ordered dithering against `threshold_matrix`, with rows processed in parallel.
+    pub fn ordered(threshold_matrix: ThresholdMatrix, tone_curve: [f32; 256]) -> Self {
+        Ditherer {
+            threshold_matrix,
+            kernel: None,
+            tone_curve,
+            intensities: Vec::new(),
+            originals: Vec::new(),
+            on: Vec::new(),
+        }
+    }
+
+    /// Error diffusion with `kernel`. The Hilbert scan of Riemersma still allocates per frame.
+    pub fn error_diffusion(kernel: DiffusionKernel, tone_curve: [f32; 256]) -> Self {
+        Ditherer {
+            kernel: Some(kernel),
+            ..Ditherer::ordered(ThresholdMatrix::uniform(0.5), tone_curve)
+        }
+    }
+
+    /// Dithers the luma of `source` to black and white into `destination`. The destination is
+    /// only reallocated if its dimensions differ from the source's.
+    pub fn dither_into<P, C>(&mut self, source: &ImageBuffer<P, C>, destination: &mut GrayImage)
+    where
+        P: Pixel<Subpixel = u8> + Sync,
+        C: Deref<Target = [u8]> + Sync,
+    {
+        let (width, height) = source.dimensions();
+        if destination.dimensions() != (width, height) {
+            *destination = GrayImage::new(width, height);
+        }
+        let row_length = width.max(1) as usize;
+
+        let Some(kernel) = &self.kernel else {
+            destination
+                .par_chunks_mut(row_length)
+                .enumerate()
+                .for_each(|(y, output_row)| {
+                    for (x, output) in output_row.iter_mut().enumerate() {
+                        let (x, y) = (x as u32, y as u32);
+                        let intensity =
+                            self.tone_curve[source.get_pixel(x, y).to_luma()[0] as usize];
+                        let threshold = self.threshold_matrix.threshold(x, y);
+                        *output = if intensity > threshold { 255 } else { 0 };
+                    }
+                });
+            return;
+        };
+
+        self.intensities.clear();
+        self.intensities.extend(
+            source
+                .pixels()
+                .map(|pixel| self.tone_curve[pixel.to_luma()[0] as usize]),
+        );
+        if kernel.scan == ScanOrder::Hilbert {
+            self.on = diffuse_error(self.intensities.clone(), width, height, kernel);
+        } else {
+            self.on.clear();
+            self.on.resize(self.intensities.len(), false);
+            let originals = kernel.uses_originals().then(|| {
+                self.originals.clone_from(&self.intensities);
+                self.originals.as_slice()
+            });
+            diffuse_error_in_place(
+                &mut self.intensities,
+                originals,
+                width,
+                height,
+                kernel,
+                &mut self.on,
+            );
+        }
+
+        for (output, &on) in destination.iter_mut().zip(&self.on) {
+            *output = if on { 255 } else { 0 };
+        }
+    }
+}
+
+/// Settings for [`dither`], built up from the same defaults as the CLI:
+///
+/// ```no_run
+/// use ditherer::{dither, Algorithm, BayerMatrixOption, DitherOptions, Palette};
+///
+/// let image = image::open("input.png").unwrap();
+/// let options = DitherOptions::new()
+///     .algorithm(Algorithm::Bayer)
+///     .matrix_size(BayerMatrixOption::M8)
+///     .strength(0.8)
+///     .palette(Palette::preset("gameboy").unwrap());
+/// dither(&image, &options).save("output.png").unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct DitherOptions {
+    algorithm: Algorithm,
+    matrix_size: BayerMatrixOption,
+    matrix_b: BayerMatrixOption,
+    color: bool,
+    palette: Option<Palette>,
+    color_space: ColorSpace,
+    strength: f32,
+    contrast_curve: f64,
+    linear: bool,
+    serpentine: bool,
+    edge_enhance: f32,
+    seed: u64,
+}
+
+impl DitherOptions {
+    /// Bayer dithering with a 4x4 matrix to black and white, in linear light.
+    pub fn new() -> Self {
+        DitherOptions {
+            algorithm: Algorithm::Bayer,
+            matrix_size: BayerMatrixOption::M4,
+            matrix_b: BayerMatrixOption::M8,
+            color: false,
+            palette: None,
+            color_space: ColorSpace::Rgb,
+            strength: 1.0,
+            contrast_curve: 0.0,
+            linear: true,
+            serpentine: false,
+            edge_enhance: 0.0,
+            seed: 0,
+        }
+    }
+
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// The Bayer matrix of the `bayer`, `knoll` and `yliluoma` algorithms, and the first matrix
+    /// of `interference`.
+    pub fn matrix_size(mut self, matrix_size: BayerMatrixOption) -> Self {
+        self.matrix_size = matrix_size;
+        self
+    }
+
+    /// The second matrix blended in by the `interference` algorithm.
+    pub fn matrix_b(mut self, matrix_size: BayerMatrixOption) -> Self {
+        self.matrix_b = matrix_size;
+        self
+    }
+
+    /// Keeps the colors and dithers the luminance into the alpha channel, as `--color` does.
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn palette(mut self, palette: Palette) -> Self {
+        self.palette = Some(palette);
+        self
+    }
+
+    pub fn color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+
+    /// Between `0.0` (a flat 50% threshold, no diffusion) and `1.0` (the default).
+    pub fn strength(mut self, strength: f32) -> Self {
+        self.strength = strength;
+        self
+    }
+
+    pub fn contrast_curve(mut self, contrast_curve: f64) -> Self {
+        self.contrast_curve = contrast_curve;
+        self
+    }
+
+    /// Thresholds in linear light (the default) or, with `false`, on sRGB-encoded values.
+    pub fn linear(mut self, linear: bool) -> Self {
+        self.linear = linear;
+        self
+    }
+
+    pub fn serpentine(mut self, serpentine: bool) -> Self {
+        self.serpentine = serpentine;
+        self
+    }
+
+    /// See [`DiffusionKernel::with_edge_enhance`].
+    pub fn edge_enhance(mut self, edge_enhance: f32) -> Self {
+        self.edge_enhance = edge_enhance;
+        self
+    }
+
+    /// The seed of the `random` algorithm.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+impl Default for DitherOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Dithers `image` with `options`, returning a grayscale image for black-and-white output and
+/// RGBA otherwise.
+pub fn dither(image: &DynamicImage, options: &DitherOptions) -> DynamicImage {
+    let tone_curve = build_tone_curve(options.contrast_curve, options.linear);
+    let threshold_matrix = match options.algorithm {
+        Algorithm::Bayer | Algorithm::Knoll | Algorithm::Yliluoma1 | Algorithm::Yliluoma2 => {
+            ThresholdMatrix::bayer(&options.matrix_size)
+        }
+        Algorithm::Interference => ThresholdMatrix::interference(
+            &ThresholdMatrix::bayer(&options.matrix_size),
+            &ThresholdMatrix::bayer(&options.matrix_b),
+            &InterferenceBlend::Average,
+        ),
+        Algorithm::BlueNoise | Algorithm::Fm => ThresholdMatrix::blue_noise(BLUE_NOISE_SIZE),
+        Algorithm::Halftone => ThresholdMatrix::halftone(HALFTONE_DOT_SIZE, HALFTONE_ANGLE),
+        Algorithm::Random => {
+            ThresholdMatrix::white_noise(image.width(), image.height(), options.seed)
+        }
+        Algorithm::Ign => {
+            ThresholdMatrix::interleaved_gradient_noise(image.width(), image.height())
+        }
+        _ => ThresholdMatrix::uniform(0.5),
+    }
+    .with_strength(options.strength);
+    let diffusion_kernel = options.algorithm.diffusion_kernel().map(|kernel| {
+        kernel
+            .with_strength(options.strength)
+            .with_serpentine(options.serpentine)
+            .with_edge_enhance(options.edge_enhance)
+    });
+
+    let rgba = match (&options.palette, &diffusion_kernel) {
+        (Some(palette), Some(kernel)) => apply_palette_error_diffusion(
+            image,
+            palette,
+            kernel,
+            &tone_curve,
+            options.linear,
+            &options.color_space,
+        ),
+        (Some(palette), None) if matches!(options.algorithm, Algorithm::Knoll) => {
+            apply_pattern_dithering(
+                image,
+                palette,
+                &threshold_matrix,
+                &tone_curve,
+                options.linear,
+                &options.color_space,
+            )
+        }
+        (Some(palette), None) if matches!(options.algorithm, Algorithm::Yliluoma1) => {
+            apply_yliluoma1_dithering(
+                image,
+                palette,
+                &threshold_matrix,
+                &tone_curve,
+                options.linear,
+                &options.color_space,
+            )
+        }
+        (Some(palette), None) if matches!(options.algorithm, Algorithm::Yliluoma2) => {
+            apply_yliluoma2_dithering(
+                image,
+                palette,
+                &threshold_matrix,
+                &tone_curve,
+                options.linear,
+                &options.color_space,
+            )
+        }
+        (Some(palette), None) => apply_palette_dithering(
+            image,
+            palette,
+            &threshold_matrix,
+            &tone_curve,
+            options.linear,
+            &options.color_space,
+        ),
+        (None, Some(kernel)) if options.color => apply_error_diffusion_color(
+            image,
+            kernel,
+            PreserveOrder::Dark,
+            &ThresholdChannel::Luminance,
+            &tone_curve,
+        ),
+        (None, Some(kernel)) => {
+            return DynamicImage::ImageLuma8(apply_error_diffusion_grayscale(
+                image,
+                kernel,
+                &tone_curve,
+            ))
+        }
+        (None, None) if options.color => apply_bayer_dithering_color(
+            image,
+            &threshold_matrix,
+            PreserveOrder::Dark,
+            &ThresholdChannel::Luminance,
+            &tone_curve,
+        ),
+        (None, None) if matches!(options.algorithm, Algorithm::Fm) => {
+            return DynamicImage::ImageLuma8(apply_fm_screening(
+                image,
+                &threshold_matrix,
+                FM_CELL_SIZE,
+                &tone_curve,
+            ))
+        }
+        (None, None) => {
+            return DynamicImage::ImageLuma8(apply_bayer_dithering_grayscale(
+                image,
+                &threshold_matrix,
+                &tone_curve,
+            ))
+        }
+    };
+    DynamicImage::ImageRgba8(rgba)
+}
+
+/// A dithering method defined outside this crate. Ordered methods implement
+/// [`DitherAlgorithm::threshold`], error-diffusion methods [`DitherAlgorithm::diffuse_row`].
+/// Run one with [`apply_custom_dithering`], or [`register_algorithm`] it to make it available by
+/// name.
+pub trait DitherAlgorithm: Send + Sync {
+    /// The name the algorithm is registered and selected under.
+    fn name(&self) -> &str;
+
+    /// A one-line description for listings.
+    fn description(&self) -> &str {
+        ""
+    }
+
+    /// The threshold between `0.0` and `1.0` that the intensity of pixel (`x`, `y`) must exceed
+    /// to turn white. Returning `None`, the default, selects [`DitherAlgorithm::diffuse_row`]
+    /// instead; an implementation returns `Some` for every pixel or for none.
+    fn threshold(&self, x: u32, y: u32) -> Option<f32> {
+        let _ = (x, y);
+        None
+    }
+
+    /// How many rows below the current one [`DitherAlgorithm::diffuse_row`] passes error to.
+    fn diffusion_rows(&self) -> usize {
+        1
+    }
+
+    /// Dithers row `y`, setting `output[x]` for the pixels that turn white. `row` holds the
+    /// intensities of the row, with the error from the rows above already added, and `below` the
+    /// next [`DitherAlgorithm::diffusion_rows`] rows (fewer at the bottom of the image); error
+    /// is passed on by adding it to pixels of either that are not decided yet. The default
+    /// thresholds at mid-gray without diffusing anything.
+    fn diffuse_row(&self, y: u32, row: &mut [f32], below: &mut [&mut [f32]], output: &mut [bool]) {
+        let _ = (y, below);
+        for (output, &intensity) in output.iter_mut().zip(row.iter()) {
+            *output = intensity > 0.5;
+        }
+    }
+}
+
+static REGISTERED_ALGORITHMS: RwLock<Vec<Arc<dyn DitherAlgorithm>>> = RwLock::new(Vec::new());
+
+/// Makes `algorithm` available under its name, replacing any registered before with that name.
+pub fn register_algorithm(algorithm: Arc<dyn DitherAlgorithm>) {
+    let mut algorithms = REGISTERED_ALGORITHMS.write().unwrap();
+    algorithms.retain(|registered| registered.name() != algorithm.name());
+    algorithms.push(algorithm);
+}
+
+/// The algorithm registered under `name`, if any.
+pub fn registered_algorithm(name: &str) -> Option<Arc<dyn DitherAlgorithm>> {
+    REGISTERED_ALGORITHMS
+        .read()
+        .unwrap()
+        .iter()
+        .find(|algorithm| algorithm.name() == name)
+        .cloned()
+}
+
+/// Every registered algorithm, in the order of registration.
+pub fn registered_algorithms() -> Vec<Arc<dyn DitherAlgorithm>> {
+    REGISTERED_ALGORITHMS.read().unwrap().clone()
+}
+
+/// Dithers the luma of `image` to black and white with a third-party `algorithm`. Ordered
+/// methods run rows in parallel; diffusion methods are handed one row at a time, top to bottom.
+pub fn apply_custom_dithering(
+    image: &DynamicImage,
+    algorithm: &dyn DitherAlgorithm,
+    tone_curve: &[f32; 256],
+) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+    let (width, height) = (image.width(), image.height());
+    let row_length = width.max(1) as usize;
+    let mut intensities = luma_intensities(image, tone_curve);
+    let mut output_image = GrayImage::new(width, height);
+
+    if algorithm.threshold(0, 0).is_some() {
+        output_image
+            .par_chunks_mut(row_length)
+            .zip(intensities.par_chunks(row_length))
+            .enumerate()
+            .for_each(|(y, (output_row, input_row))| {
+                for (x, (output, &intensity)) in output_row.iter_mut().zip(input_row).enumerate() {
+                    let threshold = algorithm.threshold(x as u32, y as u32).unwrap_or(0.5);
+                    *output = if intensity > threshold { 255 } else { 0 };
+                }
+            });
+        return output_image;
+    }
+
+    let mut on = vec![false; row_length];
+    for (y, output_row) in output_image.chunks_mut(row_length).enumerate() {
+        let (row, rest) = intensities[y * row_length..].split_at_mut(row_length);
+        let mut below: Vec<&mut [f32]> = rest
+            .chunks_mut(row_length)
+            .take(algorithm.diffusion_rows())
+            .collect();
+        on.fill(false);
+        algorithm.diffuse_row(y as u32, row, &mut below, &mut on);
+        for (output, &on) in output_row.iter_mut().zip(&on) {
+            *output = if on { 255 } else { 0 };
+        }
+    }
+
+    output_image
+}
+
+/// Keeps the colors of `image` and error-diffuses its luminance into the alpha channel.
+pub fn apply_error_diffusion_color(
+    image: &DynamicImage,
+    kernel: &DiffusionKernel,
+    preserve_order: PreserveOrder,
+    channel: &ThresholdChannel,
+    tone_curve: &[f32; 256],
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let rgba_image = image.to_rgba8();
+    let (width, height) = rgba_image.dimensions();
+    let intensities = rgba_image
+        .pixels()
+        .map(|pixel| tone_curve[channel.extract(&[pixel[0], pixel[1], pixel[2]]) as usize])
+        .collect();
+
+    let on = diffuse_error(intensities, width, height, kernel);
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let pixel = rgba_image.get_pixel(x, y);
+        let opaque = match preserve_order {
+            PreserveOrder::Light => on[(y * width + x) as usize],
+            PreserveOrder::Dark => !on[(y * width + x) as usize],
+        };
+        Rgba([pixel[0], pixel[1], pixel[2], if opaque { 255 } else { 0 }])
+    })
+}
+
+/// Traditional screen angles in degrees for the cyan, magenta, yellow and black plates.
+pub const CMYK_SCREEN_ANGLES: [f64; 4] = [15.0, 75.0, 0.0, 45.0];
+
+/// Separates `image` into cyan, magenta, yellow and black plates with full gray component
+/// replacement and dithers each plate against its own screen. The plates are returned in CMYK
+/// order, black where ink is printed and white where the paper shows.
+pub fn cmyk_separations(
+    image: &DynamicImage,
+    screens: &[ThresholdMatrix; 4],
+    tone_curve: &[f32; 256],
+) -> [GrayImage; 4] {
+    let rgb_image = image.to_rgb8();
+    let (width, height) = rgb_image.dimensions();
+    let mut plates = std::array::from_fn(|_| GrayImage::new(width, height));
+
+    for (x, y, pixel) in rgb_image.enumerate_pixels() {
+        let [r, g, b] = pixel.0.map(|value| value as f32 / 255.0);
+        let key = 1.0 - r.max(g).max(b);
+        let ink = |value: f32| {
+            if key < 1.0 {
+                (1.0 - value - key) / (1.0 - key)
+            } else {
+                0.0
+            }
+        };
+
+        for (plate, (coverage, screen)) in plates
+            .iter_mut()
+            .zip([ink(r), ink(g), ink(b), key].iter().zip(screens))
+        {
+            // The paper left uncovered by the ink is what the tone curve sees as the plate's light.
+            let paper = ((1.0 - coverage) * 255.0).round() as usize;
+            let printed = tone_curve[paper] <= screen.threshold(x, y);
+            plate.put_pixel(x, y, Luma([if printed { 0 } else { 255 }]));
+        }
+    }
+
+    plates
+}
+
+/// Overprints the plates from [`cmyk_separations`] on white paper with ideal inks.
+pub fn composite_separations(plates: &[GrayImage; 4]) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let [cyan, magenta, yellow, black] = plates;
+    ImageBuffer::from_fn(cyan.width(), cyan.height(), |x, y| {
+        let printed = |plate: &GrayImage| plate.get_pixel(x, y)[0] == 0;
+        if printed(black) {
+            return Rgba([0, 0, 0, 255]);
+        }
+        let channel = |plate: &GrayImage| if printed(plate) { 0 } else { 255 };
+        Rgba([channel(cyan), channel(magenta), channel(yellow), 255])
+    })
+}
+
+pub fn diff_dithering_grayscale(
+    image: &DynamicImage,
+    matrix_a: &ThresholdMatrix,
+    matrix_b: &ThresholdMatrix,
+    tone_curve: &[f32; 256],
+) -> (ImageBuffer<Luma<u8>, Vec<u8>>, u64) {
+    diff_bilevel(
+        &apply_bayer_dithering_grayscale(image, matrix_a, tone_curve),
+        &apply_bayer_dithering_grayscale(image, matrix_b, tone_curve),
+    )
+}
+
+/// XORs two equally sized black and white images, returning white where they disagree and the
+/// number of such pixels.
+pub fn diff_bilevel(
+    dithered_a: &ImageBuffer<Luma<u8>, Vec<u8>>,
+    dithered_b: &ImageBuffer<Luma<u8>, Vec<u8>>,
+) -> (ImageBuffer<Luma<u8>, Vec<u8>>, u64) {
+    let (width, height) = dithered_a.dimensions();
+
+    let mut output_image = GrayImage::new(width, height);
+    let mut differing = 0;
+
+    for (x, y, pixel_a) in dithered_a.enumerate_pixels() {
+        let pixel_b = dithered_b.get_pixel(x, y);
+        let value = pixel_a[0] ^ pixel_b[0];
+        if value != 0 {
+            differing += 1;
+        }
+        output_image.put_pixel(x, y, Luma([value]));
+    }
+
+    (output_image, differing)
+}
+
+/// Fidelity of a dithered image to its source, as computed by [`quality_metrics`].
+#[derive(Clone, Debug)]
+pub struct QualityMetrics {
+    /// Peak signal-to-noise ratio in dB; infinite when the images are identical.
+    pub psnr: f64,
+    /// Mean structural similarity index, `1.0` for identical images.
+    pub ssim: f64,
+}
+
+const SSIM_WINDOW_SIGMA: f32 = 1.5;
+const SSIM_C1: f64 = 0.01 * 0.01;
+const SSIM_C2: f64 = 0.03 * 0.03;
+
+/// Compares two equally sized grayscale images the way a viewer would see them.
+///
+/// Both images are blurred in linear light with a Gaussian of `blur_sigma` pixels, modelling the
+/// eye merging dots at a normal viewing distance, before PSNR and SSIM are computed on the
+/// sRGB-encoded result. Without the blur any halftone would score as pure noise.
+pub fn quality_metrics(
+    reference: &GrayImage,
+    dithered: &GrayImage,
+    blur_sigma: f32,
+) -> QualityMetrics {
+    let (width, height) = reference.dimensions();
+    let perceived = |image: &GrayImage| {
+        let linear: Vec<f32> = image
+            .pixels()
+            .map(|pixel| srgb_to_linear(pixel[0] as f32 / 255.0))
+            .collect();
+        gaussian_blur(&linear, width as usize, height as usize, blur_sigma)
+            .into_iter()
+            .map(linear_to_srgb)
+            .collect::<Vec<f32>>()
+    };
+    let a = perceived(reference);
+    let b = perceived(dithered);
+    let count = a.len().max(1) as f64;
+
+    let squared_error: f64 = a
+        .iter()
+        .zip(&b)
+        .map(|(&a, &b)| ((a - b) as f64).powi(2))
+        .sum();
+    let psnr = -10.0 * (squared_error / count).log10();
+
+    let (w, h) = (width as usize, height as usize);
+    let local_mean = |values: &[f32]| gaussian_blur(values, w, h, SSIM_WINDOW_SIGMA);
+    let product =
+        |x: &[f32], y: &[f32]| -> Vec<f32> { x.iter().zip(y).map(|(x, y)| x * y).collect() };
+    let (mean_a, mean_b) = (local_mean(&a), local_mean(&b));
+    let (mean_aa, mean_bb, mean_ab) = (
+        local_mean(&product(&a, &a)),
+        local_mean(&product(&b, &b)),
+        local_mean(&product(&a, &b)),
+    );
+
+    let ssim_sum: f64 = (0..a.len())
+        .map(|i| {
+            let (mu_a, mu_b) = (mean_a[i] as f64, mean_b[i] as f64);
+            let variance_a = mean_aa[i] as f64 - mu_a * mu_a;
+            let variance_b = mean_bb[i] as f64 - mu_b * mu_b;
+            let covariance = mean_ab[i] as f64 - mu_a * mu_b;
+            ((2.0 * mu_a * mu_b + SSIM_C1) * (2.0 * covariance + SSIM_C2))
+                / ((mu_a * mu_a + mu_b * mu_b + SSIM_C1) * (variance_a + variance_b + SSIM_C2))
+        })
+        .sum();
+
+    QualityMetrics {
+        psnr,
+        ssim: ssim_sum / count,
+    }
+}
+
+/// Separable Gaussian blur with edges clamped, so borders are not darkened.
+fn gaussian_blur(values: &[f32], width: usize, height: usize, sigma: f32) -> Vec<f32> {
+    if sigma <= 0.0 || values.is_empty() {
+        return values.to_vec();
+    }
+
+    let radius = (3.0 * sigma).ceil() as isize;
+    let mut weights: Vec<f32> = (-radius..=radius)
+        .map(|offset| (-(offset * offset) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    weights.iter_mut().for_each(|weight| *weight /= total);
+
+    let convolve = |source: &[f32], horizontal: bool| -> Vec<f32> {
+        let mut output = vec![0.0; source.len()];
+        for y in 0..height {
+            for x in 0..width {
+                output[y * width + x] = weights
+                    .iter()
+                    .enumerate()
+                    .map(|(k, weight)| {
+                        let offset = k as isize - radius;
+                        let index = if horizontal {
+                            y * width + (x as isize + offset).clamp(0, width as isize - 1) as usize
+                        } else {
+                            (y as isize + offset).clamp(0, height as isize - 1) as usize * width + x
+                        };
+                        weight * source[index]
+                    })
+                    .sum();
+            }
+        }
+        output
+    };
+
+    convolve(&convolve(values, true), false)
+}
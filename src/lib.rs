@@ -0,0 +1,19 @@
+//! Ordered (Bayer) and error-diffusion dithering of [`image`] buffers.
+//!
+//! The `ditherer` command-line tool is a thin wrapper around this library.
+//!
+//! Everything except the [`raw`] module needs the default `std` feature. Without it the crate is
+//! `no_std`, and [`raw`] dithers plain 8-bit buffers on targets without `std` or an allocator.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+mod imaging;
+pub mod raw;
+#[cfg(feature = "simd")]
+mod simd;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+#[cfg(feature = "std")]
+pub use imaging::*;
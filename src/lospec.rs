@@ -0,0 +1,36 @@
+//! Palette downloads from lospec.com for `--palette lospec:<slug>`, enabled with the `lospec`
+//! feature.
+//!
+//! Palettes are fetched in Lospec's `.hex` export format, one hex color per line, which the
+//! palette file parser already reads, so the CLI caches and parses them like any palette file.
+
+use std::error::Error;
+use std::io::Read;
+use std::time::Duration;
+
+const PALETTE_URL: &str = "https://lospec.com/palette-list";
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest `.hex` export accepted; 256 colors take under 2 KB.
+const MAX_BYTES: u64 = 64 * 1024;
+
+/// Downloads the `.hex` export of the Lospec palette `slug`.
+pub fn download(slug: &str) -> Result<String, Box<dyn Error>> {
+    let response = ureq::AgentBuilder::new()
+        .timeout(TIMEOUT)
+        .build()
+        .get(&format!("{}/{}.hex", PALETTE_URL, slug))
+        .call()
+        .map_err(|error| match error {
+            ureq::Error::Status(404, _) => format!("no Lospec palette named '{}'", slug),
+            error => format!("downloading Lospec palette '{}': {}", slug, error),
+        })?;
+
+    let mut text = String::new();
+    response
+        .into_reader()
+        .take(MAX_BYTES)
+        .read_to_string(&mut text)?;
+    Ok(text)
+}
@@ -1,237 +1,5459 @@
-use clap::{arg, command, Parser};
+#[cfg(feature = "clipboard")]
+mod clipboard;
+#[cfg(feature = "lospec")]
+mod lospec;
+#[cfg(feature = "plugins")]
+mod plugins;
+
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use ditherer::{
+    adjust_tone, apply_adaptive_offset, apply_bayer_dithering_color,
+    apply_bayer_dithering_grayscale, apply_bayer_dithering_levels, apply_bayer_dithering_rgb,
+    apply_custom_dithering, apply_duotone, apply_duotone_color, apply_error_diffusion_color,
+    apply_error_diffusion_grayscale, apply_error_diffusion_rgb, apply_fm_screening, apply_mask,
+    apply_palette_dithering, apply_palette_error_diffusion, apply_pattern_dithering,
+    apply_yliluoma1_dithering, apply_yliluoma2_dithering, auto_levels_per_channel,
+    build_tone_curve, cmyk_separations, composite_separations, compute_luminance,
+    convert_to_grayscale, convert_to_srgb, count_colors, diff_bilevel, flatten_alpha,
+    luma_to_rgba8, quality_metrics, restore_alpha, srgb_to_linear, swap_channels, tone_map,
+    Algorithm, AlgorithmPair, AlphaPolicy, BayerMatrixOption, ChannelSwap, ColorSpace, DotGain,
+    DotShape, GradientDirection, GrayscaleMethod, IccProfile, InterferenceBlend, MatrixPair,
+    Palette, PreserveOrder, ThresholdChannel, ThresholdMatrix, ToneMap, CMYK_SCREEN_ANGLES,
+};
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::codecs::ico::{IcoEncoder, IcoFrame};
+use image::codecs::pnm::{PnmEncoder, PnmSubtype, SampleEncoding};
+use image::codecs::webp::WebPEncoder;
+use image::imageops::{self, FilterType};
 use image::{
-    DynamicImage, GenericImageView, GrayImage, ImageBuffer, ImageEncoder, ImageReader, Luma, Rgba,
+    AnimationDecoder, DynamicImage, Frame, GenericImageView, GrayImage, ImageBuffer, ImageDecoder,
+    ImageEncoder, ImageError, ImageFormat, ImageReader, Limits, Luma, Rgba,
 };
+use png::text_metadata::{ITXtChunk, TEXtChunk, ZTXtChunk};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::error::Error;
+use std::ffi::OsString;
 use std::fmt;
-use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::io::{BufRead, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Parser, Clone, Debug)]
+#[command(version, about, long_about = None, args_override_self = true)]
+struct DithererArgs {
+    #[arg(short = 'i', long, value_name = "INPUT_IMG")]
+    input: Option<PathBuf>,
+
+    #[arg(short = 'o', long, value_name = "OUTPUT_IMG")]
+    output: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["input", "input_dir", "frames", "watch", "tile_height", "keep_metadata"],
+        help = "Read the input image from the system clipboard"
+    )]
+    clipboard_in: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "output", "output_dir", "format", "profile", "frames", "watch",
+            "tile_height", "report_colors", "export_threshold_map"
+        ],
+        help = "Copy the dithered image to the system clipboard as PNG instead of writing it"
+    )]
+    clipboard_out: bool,
+
+    #[arg(short, long, value_name = "MATRIX_SIZE", default_value = "m4")]
+    matrix_size: BayerMatrixOption,
+
+    #[arg(
+        short,
+        long,
+        help = "Preserve colors using brightness channel dithering"
+    )]
+    color: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["color", "diff", "palette", "colors"],
+        help = "Dither the R, G and B channels independently for an 8-color result"
+    )]
+    rgb: bool,
+
+    #[arg(
+        short,
+        long,
+        value_name = "PRESERVE_ORDER",
+        help = "Preserve order in 'dark' or 'light' pixels, for --color and for --levels ties"
+    )]
+    preserve_order: Option<PreserveOrder>,
+
+    #[arg(
+        long,
+        value_name = "CHANNEL",
+        default_value = "luminance",
+        requires = "color",
+        conflicts_with_all = ["rgb", "palette", "colors", "diff"],
+        help = "Channel thresholded in color mode: luminance, lightness, value, saturation"
+    )]
+    channel: ThresholdChannel,
+
+    #[arg(
+        long,
+        value_name = "HEX",
+        value_parser = parse_color,
+        conflicts_with_all = ["rgb", "palette", "colors"],
+        help = "Color for black (or, with --color, opaque) output pixels, e.g. '#222034'"
+    )]
+    fg: Option<[u8; 3]>,
+
+    #[arg(
+        long,
+        value_name = "HEX",
+        value_parser = parse_color,
+        conflicts_with_all = ["rgb", "palette", "colors"],
+        help = "Color for white (or, with --color, transparent) output pixels, e.g. '#f4f0e8'"
+    )]
+    bg: Option<[u8; 3]>,
+
+    #[arg(
+        long,
+        value_name = "A:B",
+        help = "Output an XOR image of where two algorithms or matrix sizes disagree, e.g. 'bayer:floyd-steinberg' or 'm2:m8'"
+    )]
+    diff: Option<AlgorithmPair>,
+
+    #[arg(
+        long,
+        help = "Stretch the R, G and B channels independently to the full range before dithering"
+    )]
+    auto_levels_per_channel: bool,
+
+    #[arg(
+        long,
+        requires = "auto_levels_per_channel",
+        help = "Keep each pixel's original luminance when stretching channels"
+    )]
+    preserve_luminance: bool,
+
+    #[arg(
+        long,
+        value_name = "STRENGTH",
+        default_value_t = 0.0,
+        value_parser = parse_contrast_curve,
+        help = "Apply a sigmoid tone curve of the given strength before thresholding (0 = off)"
+    )]
+    contrast_curve: f64,
+
+    #[arg(
+        long,
+        value_name = "PERCENT|FILE",
+        value_parser = parse_dot_gain,
+        help = "Compensate for printed dots spreading: the gain of a 50% tint in percent, or a CSV of nominal,printed coverage pairs"
+    )]
+    dot_gain: Option<DotGain>,
+
+    #[arg(
+        long,
+        value_name = "WxH",
+        conflicts_with = "scale",
+        help = "Resize the input before dithering as --preserve-aspect says; leave out W or H to keep the aspect ratio"
+    )]
+    resize: Option<ResizeTarget>,
+
+    #[arg(
+        long,
+        value_name = "FACTOR",
+        value_parser = parse_scale,
+        help = "Scale the input by a factor before dithering"
+    )]
+    scale: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Dither at 1/N resolution and upscale with nearest-neighbor, so every dot becomes an NxN block"
+    )]
+    pixelate: u32,
+
+    #[arg(
+        long,
+        value_name = "AMOUNT",
+        default_value_t = 0.0,
+        allow_negative_numbers = true,
+        value_parser = parse_brightness,
+        help = "Brighten (> 0) or darken (< 0) the input by a fraction of full range, -1.0 to 1.0"
+    )]
+    brightness: f32,
+
+    #[arg(
+        long,
+        value_name = "FACTOR",
+        default_value_t = 1.0,
+        value_parser = parse_contrast,
+        help = "Scale the input contrast around mid gray (1 = unchanged)"
+    )]
+    contrast: f32,
+
+    #[arg(
+        long,
+        value_name = "GAMMA",
+        default_value_t = 1.0,
+        value_parser = parse_gamma,
+        help = "Apply a gamma adjustment to the input (> 1 brightens midtones, < 1 darkens)"
+    )]
+    gamma: f32,
+
+    #[arg(
+        long,
+        value_name = "OPERATOR",
+        help = "Tone-map the input to 8 bits before dithering: reinhard, aces or clamp (default: reinhard for floating-point input, none otherwise)"
+    )]
+    tonemap: Option<ToneMap>,
+
+    #[arg(
+        long,
+        value_name = "STRENGTH",
+        default_value_t = 1.0,
+        value_parser = parse_strength,
+        help = "Blend between plain thresholding (0) and full dithering (1)"
+    )]
+    strength: f32,
+
+    #[arg(
+        long,
+        help = "Threshold the gamma-encoded sRGB values instead of linear light"
+    )]
+    no_gamma: bool,
+
+    #[arg(
+        long,
+        value_name = "METHOD",
+        default_value = "luma",
+        help = "RGB to gray conversion for grayscale dithering: luma, luminosity, lightness, average, desaturate (alias max), single-channel:r|g|b"
+    )]
+    grayscale_method: GrayscaleMethod,
+
+    #[arg(
+        long,
+        conflicts_with = "color",
+        help = "Threshold each pixel relative to the mean of its neighborhood (for uneven lighting)"
+    )]
+    adaptive: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 15,
+        requires = "adaptive",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Side length in pixels of the neighborhood used by --adaptive"
+    )]
+    window: u32,
+
+    #[arg(
+        long,
+        value_name = "ALGORITHM",
+        default_value = "bayer",
+        help = "Dithering algorithm: bayer, interference, blue-noise, halftone, random, ign, floyd-steinberg, atkinson, sierra, sierra-lite, stucki, burkes, jjn, riemersma, ostromoukhov, knoll, yliluoma1, yliluoma2, fm"
+    )]
+    algorithm: Algorithm,
+
+    #[cfg(feature = "plugins")]
+    #[arg(
+        long,
+        value_name = "NAME",
+        value_parser = parse_plugin,
+        conflicts_with_all = [
+            "algorithm", "color", "rgb", "palette", "colors", "cmyk", "diff", "levels", "threshold_map",
+            "jitter", "pattern_scale", "pattern_angle"
+        ],
+        help = "Dither with a registered third-party algorithm instead of --algorithm"
+    )]
+    plugin: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "MATRIX_SIZE",
+        required_if_eq("algorithm", "interference"),
+        help = "First matrix combined by the interference algorithm"
+    )]
+    matrix_a: Option<BayerMatrixOption>,
+
+    #[arg(
+        long,
+        value_name = "MATRIX_SIZE",
+        required_if_eq("algorithm", "interference"),
+        help = "Second matrix combined by the interference algorithm"
+    )]
+    matrix_b: Option<BayerMatrixOption>,
+
+    #[arg(
+        long,
+        value_name = "BLEND",
+        default_value = "average",
+        help = "How the interference algorithm mixes its two matrices: average, difference"
+    )]
+    interference_blend: InterferenceBlend,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 64,
+        value_parser = clap::value_parser!(u32).range(2..=128),
+        help = "Side length of the texture generated for --algorithm blue-noise"
+    )]
+    noise_size: u32,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 8,
+        value_parser = clap::value_parser!(u32).range(2..=32),
+        help = "Distance between dot centers for --algorithm halftone"
+    )]
+    dot_size: u32,
+
+    #[arg(
+        long,
+        value_name = "SHAPE",
+        default_value = "round",
+        help = "Shape of the dots grown by --algorithm halftone and --cmyk: round, square, diamond or line"
+    )]
+    dot_shape: DotShape,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 8,
+        value_parser = clap::value_parser!(u32).range(1..=64),
+        help = "Size of the cells over which --algorithm fm measures local intensity"
+    )]
+    cell_size: u32,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 0,
+        help = "Seed for --algorithm random and --jitter; the same seed always gives the same pattern"
+    )]
+    seed: u64,
+
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Restart --algorithm random every SIZE pixels from a seed derived from the tile position"
+    )]
+    seed_grid: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "F",
+        default_value_t = 0.0,
+        value_parser = parse_jitter,
+        help = "Perturb each ordered threshold by seeded random noise of this amplitude (0.0 to 1.0)"
+    )]
+    jitter: f32,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 2,
+        value_parser = clap::value_parser!(u32).range(2..=256),
+        conflicts_with_all = ["color", "rgb", "diff", "palette", "colors"],
+        help = "Number of gray levels for ordered grayscale dithering (2 = black and white)"
+    )]
+    levels: u32,
+
+    #[arg(
+        long,
+        help = "Alternate the scan direction on every row for error-diffusion algorithms"
+    )]
+    serpentine: bool,
+
+    #[arg(
+        long,
+        value_name = "F",
+        default_value_t = 0.0,
+        value_parser = parse_edge_enhance,
+        help = "Sharpen edges during error diffusion by moving each threshold against the pixel's own input (Eschbach-Knox)"
+    )]
+    edge_enhance: f32,
+
+    #[arg(
+        long,
+        value_name = "DEG",
+        default_value_t = 45.0,
+        allow_negative_numbers = true,
+        value_parser = parse_angle,
+        help = "Screen angle in degrees for --algorithm halftone"
+    )]
+    angle: f64,
+
+    #[arg(
+        long,
+        value_name = "FACTOR",
+        default_value_t = 1.0,
+        value_parser = parse_scale,
+        help = "Enlarge the ordered threshold pattern by this factor, interpolating between cells"
+    )]
+    pattern_scale: f64,
+
+    #[arg(
+        long,
+        value_name = "DEG",
+        default_value_t = 0.0,
+        allow_negative_numbers = true,
+        value_parser = parse_angle,
+        help = "Rotate the ordered threshold pattern by this many degrees"
+    )]
+    pattern_angle: f64,
+
+    #[arg(
+        long,
+        help = "Print the number of unique colors in the input and the most frequent ones, then exit"
+    )]
+    report_colors: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "output", "input_dir", "frames", "watch", "tile_height", "report_colors",
+            "export_threshold_map", "separations", "profile"
+        ],
+        help = "Time dithering the input with the given options and print the throughput in MP/s, then exit"
+    )]
+    bench: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 10,
+        requires = "report_colors",
+        help = "Number of most frequent colors listed by --report-colors"
+    )]
+    top_n: usize,
+
+    #[arg(
+        long,
+        requires = "report_colors",
+        help = "Print the --report-colors output as JSON"
+    )]
+    json: bool,
+
+    #[arg(
+        long,
+        value_name = "A:B",
+        conflicts_with = "algorithm",
+        help = "Blend smoothly from matrix A to matrix B across the image, e.g. 'm2:m8'"
+    )]
+    matrix_gradient: Option<MatrixPair>,
+
+    #[arg(
+        long,
+        value_name = "DIRECTION",
+        default_value = "vertical",
+        requires = "matrix_gradient",
+        help = "Direction of --matrix-gradient: horizontal, vertical, radial"
+    )]
+    gradient_direction: GradientDirection,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["algorithm", "matrix_gradient"],
+        help = "Load a threshold matrix from a text/CSV file of values between 0.0 and 1.0, or an image"
+    )]
+    threshold_map: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Only dither where this grayscale image is white; other pixels pass through unchanged"
+    )]
+    mask: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "ALGORITHM",
+        help = "Describe an algorithm's speed, artifacts and best use, then exit"
+    )]
+    explain: Option<Algorithm>,
+
+    #[arg(long, help = "List the built-in --palette presets, then exit")]
+    list_palettes: bool,
+
+    #[arg(
+        long,
+        alias = "output-format",
+        value_name = "FORMAT",
+        help = "Output format: png, bmp, pnm, qoi, webp, jpeg, gif, ico, p5js, ansi, ascii, svg, pbm, xbm, raw, c-array, data-uri[:FORMAT] (default: from the output extension, otherwise png)"
+    )]
+    format: Option<OutputFormat>,
+
+    #[arg(
+        long,
+        value_name = "SIZES",
+        value_delimiter = ',',
+        default_value = "16,32,48",
+        value_parser = clap::value_parser!(u32).range(1..=256),
+        help = "Comma-separated icon sizes dithered into an ico output"
+    )]
+    ico_sizes: Vec<u32>,
+
+    #[arg(
+        long,
+        value_name = "CHARS",
+        default_value = "@%#*+=-:. ",
+        value_parser = parse_charset,
+        help = "Character ramp for --format ascii, from darkest to lightest"
+    )]
+    charset: String,
+
+    #[arg(
+        long,
+        value_name = "ORDER",
+        default_value = "msb",
+        help = "Order of the pixels within each byte of --format raw: msb (first pixel in the high bit) or lsb"
+    )]
+    bit_order: BitOrder,
+
+    #[arg(
+        long,
+        value_name = "LAYOUT",
+        default_value = "horizontal",
+        help = "Byte orientation of c-array output: horizontal (Adafruit GFX) or vertical (SSD1306 pages, u8g2 tiles)"
+    )]
+    c_array_layout: ByteLayout,
+
+    #[arg(
+        long,
+        value_name = "SHAPE",
+        default_value = "square",
+        help = "Shape drawn for each on pixel in --format svg: square or circle"
+    )]
+    svg_dot: SvgDot,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["input", "output", "report_colors"],
+        requires = "output_dir",
+        help = "Dither every image in this directory"
+    )]
+    input_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        requires = "input_dir",
+        help = "Directory receiving the images dithered from --input-dir, with the same file names"
+    )]
+    output_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        requires = "input_dir",
+        help = "Also dither images in subdirectories of --input-dir"
+    )]
+    recursive: bool,
+
+    #[arg(
+        long,
+        value_name = "MODE",
+        default_value = "fit",
+        help = "How resizing keeps the aspect ratio: fit (letterbox), fill (crop), stretch"
+    )]
+    preserve_aspect: AspectMode,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        value_parser = parse_timeout,
+        help = "Abort with exit code 124 if decoding, dithering and encoding take longer than this"
+    )]
+    timeout: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Number of threads for ordered dithering and error diffusion (default: one per CPU core)"
+    )]
+    threads: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "PERMUTATION",
+        help = "Reorder the RGB channels before dithering, e.g. 'rgb->gbr'"
+    )]
+    channel_swap: Option<ChannelSwap>,
+
+    #[arg(
+        long,
+        value_name = "GAMMA",
+        default_value_t = 1.0,
+        value_parser = parse_display_gamma,
+        help = "Warp thresholds so the dither's average brightness matches the input on a display of this gamma"
+    )]
+    display_gamma: f64,
+
+    #[arg(
+        long,
+        help = "Report on stderr whether the output tiles seamlessly and suggest aligned dimensions"
+    )]
+    check_seamless: bool,
+
+    #[arg(
+        long,
+        alias = "output-bits",
+        value_name = "DEPTH",
+        default_value = "auto",
+        help = "PNG pixel format: auto, rgba8, 1 (1-bit grayscale), 8 (8-bit grayscale), indexed"
+    )]
+    output_depth: OutputDepth,
+
+    #[arg(
+        long,
+        value_name = "X,Y",
+        conflicts_with = "diff",
+        help = "Print the input, threshold and decision for one pixel to stderr"
+    )]
+    sample_point: Option<SamplePoint>,
+
+    #[arg(
+        long,
+        conflicts_with = "diff",
+        help = "Print PSNR and SSIM of the output against the source to stderr as JSON"
+    )]
+    metrics: bool,
+
+    #[arg(
+        long,
+        value_name = "SIGMA",
+        default_value_t = 1.5,
+        value_parser = parse_metrics_blur,
+        requires = "metrics",
+        help = "Gaussian blur radius in pixels applied to both images before --metrics compares them"
+    )]
+    metrics_blur: f32,
+
+    #[arg(
+        long,
+        value_name = "MB",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Keep decoding and dithering within this many megabytes, downscaling if needed"
+    )]
+    memory_limit: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "COLORSPACE",
+        default_value = "srgb",
+        help = "Color space tagged on PNG output: srgb, linear, display-p3"
+    )]
+    output_colorspace: OutputColorspace,
+
+    #[arg(
+        long,
+        help = "Copy the input's ICC profile, EXIF and PNG text chunks into PNG output (WebP: ICC only)"
+    )]
+    keep_metadata: bool,
+
+    #[arg(
+        long,
+        visible_alias = "privacy",
+        help = "Guarantee that no input metadata (EXIF, GPS, ICC, text) reaches the output, overriding --keep-metadata, and leave out ditherer:params"
+    )]
+    strip_metadata: bool,
+
+    #[arg(
+        long,
+        help = "Ignore embedded ICC profiles instead of converting tagged input (Display P3, Adobe RGB, ...) to sRGB"
+    )]
+    assume_srgb: bool,
+
+    #[arg(
+        long,
+        value_name = "PNG",
+        help = "Reuse the dithering options recorded in the ditherer:params chunk of a PNG written by ditherer; options given here override them"
+    )]
+    reapply: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE|PRESET|lospec:SLUG",
+        value_parser = parse_palette,
+        conflicts_with_all = ["color", "diff"],
+        help = "Dither to a palette from a hex list or GIMP .gpl file, a preset (see --list-palettes), or lospec:<slug> from lospec.com"
+    )]
+    palette: Option<Palette>,
+
+    #[arg(
+        long,
+        help = "Never access the network: a lospec: palette must already be in the cache"
+    )]
+    offline: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(2..=256),
+        conflicts_with_all = ["color", "diff", "palette"],
+        help = "Dither to an N-color palette derived from the image by median cut"
+    )]
+    colors: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "SPACE",
+        default_value = "rgb",
+        help = "Color space for palette matching and error diffusion: rgb, lab, oklab"
+    )]
+    color_space: ColorSpace,
+
+    #[arg(
+        long,
+        value_name = "ROWS",
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = [
+            "input_dir", "resize", "scale", "adaptive", "auto_levels_per_channel", "diff",
+            "matrix_gradient", "colors", "format", "output_depth", "report_colors",
+            "check_seamless", "sample_point", "memory_limit", "metrics", "mask", "cmyk",
+            "keep_metadata", "pixelate", "pattern_scale", "pattern_angle", "jitter"
+        ],
+        help = "Decode, dither and encode a PNG in strips of this many rows (ordered algorithms only)"
+    )]
+    tile_height: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "POLICY",
+        help = "Handling of transparent input: keep, premultiply, matte[:<hex color>], dither"
+    )]
+    alpha: Option<AlphaPolicy>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["input_dir", "report_colors", "diff", "matrix_gradient", "tile_height"],
+        help = "Write the threshold matrix of the chosen algorithm as a tileable 16-bit grayscale PNG instead of dithering"
+    )]
+    export_threshold_map: bool,
+
+    #[arg(
+        long,
+        value_name = "PROFILE",
+        requires = "output",
+        conflicts_with_all = [
+            "input_dir", "color", "rgb", "palette", "colors", "diff", "levels", "format",
+            "tile_height", "report_colors", "export_threshold_map"
+        ],
+        help = "Fit, rotate and dither for an e-paper panel, writing raw packed pixels plus a preview PNG: eink-1bit, eink-4gray, eink-16gray"
+    )]
+    profile: Option<DeviceProfile>,
+
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "color", "rgb", "palette", "colors", "diff", "levels", "algorithm", "threshold_map",
+            "matrix_gradient", "profile", "fg", "bg"
+        ],
+        help = "Separate into CMYK and halftone each plate with a clustered-dot screen at 15/75/0/45 degrees"
+    )]
+    cmyk: bool,
+
+    #[arg(
+        long,
+        requires_all = ["cmyk", "output"],
+        conflicts_with_all = ["input_dir", "tile_height"],
+        help = "Also write the four --cmyk plates as grayscale PNGs next to the output"
+    )]
+    separations: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "input", "output", "input_dir", "format", "tile_height", "profile",
+            "report_colors", "export_threshold_map"
+        ],
+        help = "Dither a stream of concatenated PNG or binary PPM frames (or raw RGB with --size) from stdin to stdout"
+    )]
+    frames: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["stable", "tile_height"],
+        help = "Cycle the ordered threshold pattern per animation frame, trading static patterning for temporal noise"
+    )]
+    temporal: bool,
+
+    #[arg(
+        long,
+        help = "Keep the previous frame's dithered pixels wherever an animation frame has not changed, to stop shimmering"
+    )]
+    stable: bool,
+
+    #[arg(
+        long,
+        value_name = "WxH",
+        requires = "frames",
+        value_parser = parse_frame_size,
+        help = "Read --frames as raw RGB24 frames of this size"
+    )]
+    size: Option<(u32, u32)>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["frames", "report_colors", "export_threshold_map", "timeout"],
+        help = "Keep running and re-dither whenever the input file or --input-dir changes"
+    )]
+    watch: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Read default options from this TOML file (default: ditherer.toml in the current directory, if present)"
+    )]
+    config: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Also apply the options in the [preset.NAME] table of the config file"
+    )]
+    preset: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        conflicts_with = "quiet",
+        help = "Print the time taken for each image or frame to stderr"
+    )]
+    verbose: bool,
+
+    #[arg(
+        short,
+        long,
+        help = "Suppress warnings and the --diff statistics on stderr; errors are still printed"
+    )]
+    quiet: bool,
+
+    #[arg(
+        long,
+        help = "Draw a progress bar on stderr for directories, animations and frame streams"
+    )]
+    progress: bool,
+
+    #[arg(
+        long,
+        help = "Print one JSON object per finished image or frame to stderr, and a summary at the end"
+    )]
+    progress_json: bool,
+
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value = "text",
+        help = "How errors are printed on stderr: text, or json for one {\"kind\",\"message\",\"exit_code\"} object"
+    )]
+    error_format: ErrorFormat,
+
+    #[command(subcommand)]
+    command: Option<DithererCommand>,
+
+    /// The `REAPPLIED_PARAMS` options that were set, as `--name[=value]` arguments.
+    #[arg(skip)]
+    params: Vec<String>,
+}
+
+#[derive(Subcommand, Clone, Debug)]
+enum DithererCommand {
+    /// Render the input with several algorithms and matrix sizes into one labeled grid image
+    Compare(CompareArgs),
+    /// Run an HTTP server that dithers images POSTed to /dither and returns PNGs
+    Serve(ServeArgs),
+}
+
+#[derive(Args, Clone, Debug)]
+struct ServeArgs {
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        default_value = "127.0.0.1:8080",
+        help = "Address and port to listen on"
+    )]
+    bind: String,
+
+    #[arg(
+        long,
+        value_name = "MB",
+        default_value_t = 32,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Reject uploads larger than this many megabytes"
+    )]
+    max_upload: u64,
+}
+
+#[derive(Args, Clone, Debug)]
+struct CompareArgs {
+    #[arg(short = 'i', long, value_name = "INPUT_IMG")]
+    input: PathBuf,
+
+    #[arg(short = 'o', long, value_name = "OUTPUT_IMG")]
+    output: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "ALGORITHMS",
+        value_delimiter = ',',
+        default_value = "bayer,blue-noise,halftone,floyd-steinberg,atkinson,riemersma",
+        help = "Comma-separated algorithms to compare"
+    )]
+    algorithms: Vec<Algorithm>,
+
+    #[arg(
+        long,
+        value_name = "MATRIX_SIZES",
+        value_delimiter = ',',
+        default_value = "m2,m4,m8",
+        help = "Comma-separated matrix sizes, each rendered as its own cell for bayer"
+    )]
+    matrix_sizes: Vec<BayerMatrixOption>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Number of cells per row (default: about square)"
+    )]
+    columns: Option<u32>,
+}
+
+#[derive(Clone, Debug)]
+enum OutputFormat {
+    Png,
+    Bmp,
+    Pnm,
+    Qoi,
+    Webp,
+    Jpeg,
+    Gif,
+    Ico,
+    P5js,
+    Ansi,
+    Ascii,
+    Svg,
+    Pbm,
+    Xbm,
+    Raw,
+    CArray,
+    /// A base64 `data:` URI of the image encoded in the given raster format.
+    DataUri(Box<OutputFormat>),
+}
+
+impl FromStr for OutputFormat {
+    type Err = OutputFormatParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "png" => Ok(OutputFormat::Png),
+            "bmp" => Ok(OutputFormat::Bmp),
+            "pnm" => Ok(OutputFormat::Pnm),
+            "qoi" => Ok(OutputFormat::Qoi),
+            "webp" => Ok(OutputFormat::Webp),
+            "jpeg" | "jpg" => Ok(OutputFormat::Jpeg),
+            "gif" => Ok(OutputFormat::Gif),
+            "ico" => Ok(OutputFormat::Ico),
+            "p5js" => Ok(OutputFormat::P5js),
+            "ansi" => Ok(OutputFormat::Ansi),
+            "ascii" => Ok(OutputFormat::Ascii),
+            "svg" => Ok(OutputFormat::Svg),
+            "pbm" => Ok(OutputFormat::Pbm),
+            "xbm" => Ok(OutputFormat::Xbm),
+            "raw" => Ok(OutputFormat::Raw),
+            "c-array" => Ok(OutputFormat::CArray),
+            "data-uri" => Ok(OutputFormat::DataUri(Box::new(OutputFormat::Png))),
+            input => match input
+                .strip_prefix("data-uri:")
+                .map(str::parse::<OutputFormat>)
+            {
+                Some(Ok(encoding)) if encoding.is_raster() => {
+                    Ok(OutputFormat::DataUri(Box::new(encoding)))
+                }
+                _ => Err(OutputFormatParseError),
+            },
+        }
+    }
+}
+
+impl OutputFormat {
+    /// The format picked for an output file with this extension when `--format` is not given, or
+    /// `None` for extensions left to the `image` crate (`.tiff`, `.tga`, ...).
+    fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "ppm" => Some(OutputFormat::Pnm),
+            "js" => Some(OutputFormat::P5js),
+            "ans" => Some(OutputFormat::Ansi),
+            "txt" => Some(OutputFormat::Ascii),
+            "bin" => Some(OutputFormat::Raw),
+            "h" => Some(OutputFormat::CArray),
+            "p5js" | "ansi" | "ascii" | "raw" | "c-array" | "data-uri" => None,
+            extension => extension.parse().ok(),
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Bmp => "bmp",
+            OutputFormat::Pnm => "ppm",
+            OutputFormat::Qoi => "qoi",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Gif => "gif",
+            OutputFormat::Ico => "ico",
+            OutputFormat::P5js => "js",
+            OutputFormat::Ansi => "ans",
+            OutputFormat::Ascii => "txt",
+            OutputFormat::Svg => "svg",
+            OutputFormat::Pbm => "pbm",
+            OutputFormat::Xbm => "xbm",
+            OutputFormat::Raw => "bin",
+            OutputFormat::CArray => "h",
+            OutputFormat::DataUri(_) => "txt",
+        }
+    }
+
+    /// Whether [`write_raster`] encodes this format itself.
+    fn is_raster(&self) -> bool {
+        matches!(
+            self,
+            OutputFormat::Png
+                | OutputFormat::Bmp
+                | OutputFormat::Pnm
+                | OutputFormat::Qoi
+                | OutputFormat::Webp
+                | OutputFormat::Jpeg
+                | OutputFormat::Gif
+        )
+    }
+
+    /// The format the pixels are encoded in, which for a data URI is the embedded image's.
+    fn encoding(&self) -> &OutputFormat {
+        match self {
+            OutputFormat::DataUri(encoding) => encoding,
+            format => format,
+        }
+    }
+
+    fn mime_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Bmp => "image/bmp",
+            OutputFormat::Pnm => "image/x-portable-pixmap",
+            OutputFormat::Qoi => "image/qoi",
+            OutputFormat::Webp => "image/webp",
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Gif => "image/gif",
+            _ => "image/png",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct OutputFormatParseError;
+
+impl fmt::Display for OutputFormatParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid output format. Choose from: png, bmp, pnm, qoi, webp, jpeg, gif, ico, p5js, ansi, ascii, svg, pbm, xbm, raw, c-array, data-uri[:png|bmp|pnm|qoi|webp|jpeg|gif]."
+        )
+    }
+}
+
+impl Error for OutputFormatParseError {}
+
+#[derive(Clone, Debug)]
+enum BitOrder {
+    Msb,
+    Lsb,
+}
+
+impl FromStr for BitOrder {
+    type Err = BitOrderParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "msb" => Ok(BitOrder::Msb),
+            "lsb" => Ok(BitOrder::Lsb),
+            _ => Err(BitOrderParseError),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct BitOrderParseError;
+
+impl fmt::Display for BitOrderParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid bit order. Choose from: msb, lsb.")
+    }
+}
+
+impl Error for BitOrderParseError {}
+
+#[derive(Clone, Debug)]
+enum ByteLayout {
+    Horizontal,
+    Vertical,
+}
+
+impl FromStr for ByteLayout {
+    type Err = ByteLayoutParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "horizontal" => Ok(ByteLayout::Horizontal),
+            "vertical" => Ok(ByteLayout::Vertical),
+            _ => Err(ByteLayoutParseError),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ByteLayoutParseError;
+
+impl fmt::Display for ByteLayoutParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid byte layout. Choose from: horizontal, vertical.")
+    }
+}
+
+impl Error for ByteLayoutParseError {}
+
+#[derive(Clone, Debug)]
+enum DeviceProfile {
+    Eink1bit,
+    Eink4gray,
+    Eink16gray,
+}
+
+impl FromStr for DeviceProfile {
+    type Err = DeviceProfileParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "eink-1bit" => Ok(DeviceProfile::Eink1bit),
+            "eink-4gray" => Ok(DeviceProfile::Eink4gray),
+            "eink-16gray" => Ok(DeviceProfile::Eink16gray),
+            _ => Err(DeviceProfileParseError),
+        }
+    }
+}
+
+impl DeviceProfile {
+    /// Panel resolution in its native landscape orientation.
+    fn resolution(&self) -> (u32, u32) {
+        match self {
+            DeviceProfile::Eink1bit => (800, 480),
+            DeviceProfile::Eink4gray => (400, 300),
+            DeviceProfile::Eink16gray => (1872, 1404),
+        }
+    }
+
+    fn bits(&self) -> usize {
+        match self {
+            DeviceProfile::Eink1bit => 1,
+            DeviceProfile::Eink4gray => 2,
+            DeviceProfile::Eink16gray => 4,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct DeviceProfileParseError;
+
+impl fmt::Display for DeviceProfileParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid profile. Choose from: eink-1bit, eink-4gray, eink-16gray."
+        )
+    }
+}
+
+impl Error for DeviceProfileParseError {}
+
+#[derive(Clone, Debug)]
+enum SvgDot {
+    Square,
+    Circle,
+}
+
+impl FromStr for SvgDot {
+    type Err = SvgDotParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "square" => Ok(SvgDot::Square),
+            "circle" => Ok(SvgDot::Circle),
+            _ => Err(SvgDotParseError),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SvgDotParseError;
+
+impl fmt::Display for SvgDotParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid SVG dot. Choose from: square, circle.")
+    }
+}
+
+impl Error for SvgDotParseError {}
+
+#[derive(Clone, Debug)]
+enum AspectMode {
+    Fit,
+    Fill,
+    Stretch,
+}
+
+impl FromStr for AspectMode {
+    type Err = AspectModeParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "fit" => Ok(AspectMode::Fit),
+            "fill" => Ok(AspectMode::Fill),
+            "stretch" => Ok(AspectMode::Stretch),
+            _ => Err(AspectModeParseError),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct AspectModeParseError;
+
+impl fmt::Display for AspectModeParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid aspect mode. Choose from: fit, fill, stretch.")
+    }
+}
+
+impl Error for AspectModeParseError {}
+
+#[derive(Clone, Debug)]
+struct ResizeTarget {
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+impl FromStr for ResizeTarget {
+    type Err = ResizeTargetParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (width, height) = input
+            .to_lowercase()
+            .split_once('x')
+            .map(|(width, height)| (width.trim().to_string(), height.trim().to_string()))
+            .ok_or(ResizeTargetParseError)?;
+        let parse = |value: &str| match value {
+            "" => Ok(None),
+            value => match value.parse::<u32>() {
+                Ok(size) if size > 0 => Ok(Some(size)),
+                _ => Err(ResizeTargetParseError),
+            },
+        };
+
+        let target = ResizeTarget {
+            width: parse(&width)?,
+            height: parse(&height)?,
+        };
+        if target.width.is_none() && target.height.is_none() {
+            return Err(ResizeTargetParseError);
+        }
+        Ok(target)
+    }
+}
+
+#[derive(Debug)]
+struct ResizeTargetParseError;
+
+impl fmt::Display for ResizeTargetParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid size. Expected WIDTHxHEIGHT, WIDTHx or xHEIGHT, e.g. 640x480 or 640x."
+        )
+    }
+}
+
+impl Error for ResizeTargetParseError {}
+
+#[derive(Clone, Debug)]
+struct SamplePoint {
+    x: u32,
+    y: u32,
+}
+
+impl FromStr for SamplePoint {
+    type Err = SamplePointParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (x, y) = input.split_once(',').ok_or(SamplePointParseError)?;
+        Ok(SamplePoint {
+            x: x.trim().parse().map_err(|_| SamplePointParseError)?,
+            y: y.trim().parse().map_err(|_| SamplePointParseError)?,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct SamplePointParseError;
+
+impl fmt::Display for SamplePointParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid sample point. Expected pixel coordinates separated by ',', e.g. 10,20."
+        )
+    }
+}
+
+impl Error for SamplePointParseError {}
+
+#[derive(Clone, Debug)]
+enum OutputColorspace {
+    Srgb,
+    Linear,
+    DisplayP3,
+}
+
+impl FromStr for OutputColorspace {
+    type Err = OutputColorspaceParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "srgb" => Ok(OutputColorspace::Srgb),
+            "linear" => Ok(OutputColorspace::Linear),
+            "display-p3" => Ok(OutputColorspace::DisplayP3),
+            _ => Err(OutputColorspaceParseError),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum OutputDepth {
+    Auto,
+    Rgba8,
+    Gray1,
+    Gray8,
+    Indexed,
+}
+
+impl FromStr for OutputDepth {
+    type Err = OutputDepthParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "auto" => Ok(OutputDepth::Auto),
+            "rgba8" => Ok(OutputDepth::Rgba8),
+            "1" => Ok(OutputDepth::Gray1),
+            "8" => Ok(OutputDepth::Gray8),
+            "indexed" => Ok(OutputDepth::Indexed),
+            _ => Err(OutputDepthParseError),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct OutputDepthParseError;
+
+impl fmt::Display for OutputDepthParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid output depth. Choose from: auto, rgba8, 1, 8, indexed."
+        )
+    }
+}
+
+impl Error for OutputDepthParseError {}
+
+#[derive(Debug)]
+struct OutputColorspaceParseError;
+
+impl fmt::Display for OutputColorspaceParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid output colorspace. Choose from: srgb, linear, display-p3."
+        )
+    }
+}
+
+impl Error for OutputColorspaceParseError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum ErrorFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = ErrorFormatParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "text" => Ok(ErrorFormat::Text),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(ErrorFormatParseError),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ErrorFormatParseError;
+
+impl fmt::Display for ErrorFormatParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid error format. Choose from: text, json.")
+    }
+}
+
+impl Error for ErrorFormatParseError {}
+
+/// A failure of the CLI, sorted into a kind with its own exit code so scripts can tell them apart.
+#[derive(Debug)]
+enum CliError {
+    /// An option value is invalid or options cannot be combined.
+    InvalidOptions(String),
+    /// The input is not a valid image or frame.
+    Decode(String),
+    /// The input or output format or a feature of it is not supported.
+    UnsupportedFormat(String),
+    /// Reading or writing a file, stream or connection failed.
+    Io(String),
+    /// `--timeout` expired.
+    Timeout(f64),
+    Other(String),
+}
+
+impl CliError {
+    fn kind(&self) -> &'static str {
+        match self {
+            CliError::InvalidOptions(_) => "invalid_options",
+            CliError::Decode(_) => "decode",
+            CliError::UnsupportedFormat(_) => "unsupported_format",
+            CliError::Io(_) => "io",
+            CliError::Timeout(_) => "timeout",
+            CliError::Other(_) => "other",
+        }
+    }
+
+    fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Other(_) => 1,
+            CliError::InvalidOptions(_) => 2,
+            CliError::Decode(_) => 3,
+            CliError::UnsupportedFormat(_) => 4,
+            CliError::Io(_) => 5,
+            CliError::Timeout(_) => TIMEOUT_EXIT_CODE,
+        }
+    }
+
+    /// Sorts an error returned by the CLI or the image, PNG and GIF codecs into a kind.
+    fn classify(error: Box<dyn Error>) -> Self {
+        let message = error.to_string();
+        if let Some(error) = error.downcast_ref::<CliError>() {
+            return match error {
+                CliError::InvalidOptions(_) => CliError::InvalidOptions(message),
+                CliError::Decode(_) => CliError::Decode(message),
+                CliError::UnsupportedFormat(_) => CliError::UnsupportedFormat(message),
+                CliError::Io(_) => CliError::Io(message),
+                CliError::Timeout(timeout) => CliError::Timeout(*timeout),
+                CliError::Other(_) => CliError::Other(message),
+            };
+        }
+        if let Some(error) = error.downcast_ref::<ImageError>() {
+            return match error {
+                ImageError::Decoding(_) | ImageError::Limits(_) => CliError::Decode(message),
+                ImageError::Unsupported(_) => CliError::UnsupportedFormat(message),
+                ImageError::IoError(_) => CliError::Io(message),
+                ImageError::Encoding(_) | ImageError::Parameter(_) => CliError::Other(message),
+            };
+        }
+        if let Some(error) = error.downcast_ref::<png::DecodingError>() {
+            return match error {
+                png::DecodingError::IoError(_) => CliError::Io(message),
+                _ => CliError::Decode(message),
+            };
+        }
+        if let Some(error) = error.downcast_ref::<gif::DecodingError>() {
+            return match error {
+                gif::DecodingError::Io(_) => CliError::Io(message),
+                _ => CliError::Decode(message),
+            };
+        }
+        if error.is::<std::io::Error>() {
+            return CliError::Io(message);
+        }
+        CliError::Other(message)
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Timeout(timeout) => {
+                write!(f, "operation timed out after {} seconds", timeout)
+            }
+            CliError::InvalidOptions(message)
+            | CliError::Decode(message)
+            | CliError::UnsupportedFormat(message)
+            | CliError::Io(message)
+            | CliError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for CliError {}
+
+fn parse_contrast_curve(input: &str) -> Result<f64, String> {
+    match input.parse::<f64>() {
+        Ok(strength) if strength >= 0.0 && strength.is_finite() => Ok(strength),
+        _ => Err("Contrast curve strength must be a finite number >= 0.".to_string()),
+    }
+}
+
+fn parse_dot_gain(input: &str) -> Result<DotGain, String> {
+    if let Ok(percent) = input.trim_end_matches('%').parse::<f32>() {
+        return if (0.0..50.0).contains(&percent) {
+            Ok(DotGain::percent(percent))
+        } else {
+            Err("Dot gain must be a percentage >= 0 and below 50.".to_string())
+        };
+    }
+
+    let text = std::fs::read_to_string(input)
+        .map_err(|error| format!("Cannot read dot gain curve '{}': {}", input, error))?;
+    DotGain::from_text(&text).map_err(|error| error.to_string())
+}
+
+fn parse_edge_enhance(input: &str) -> Result<f32, String> {
+    match input.parse::<f32>() {
+        Ok(edge_enhance) if edge_enhance >= 0.0 && edge_enhance.is_finite() => Ok(edge_enhance),
+        _ => Err("Edge enhancement must be a finite number >= 0.".to_string()),
+    }
+}
+
+fn parse_charset(input: &str) -> Result<String, String> {
+    if input.chars().count() >= 2 {
+        Ok(input.to_string())
+    } else {
+        Err("Charset must contain at least two characters.".to_string())
+    }
+}
+
+fn parse_scale(input: &str) -> Result<f64, String> {
+    match input.parse::<f64>() {
+        Ok(scale) if scale > 0.0 && scale.is_finite() => Ok(scale),
+        _ => Err("Scale must be a finite number > 0.".to_string()),
+    }
+}
+
+fn parse_brightness(input: &str) -> Result<f32, String> {
+    match input.parse::<f32>() {
+        Ok(brightness) if (-1.0..=1.0).contains(&brightness) => Ok(brightness),
+        _ => Err("Brightness must be a number between -1.0 and 1.0.".to_string()),
+    }
+}
+
+fn parse_contrast(input: &str) -> Result<f32, String> {
+    match input.parse::<f32>() {
+        Ok(contrast) if contrast >= 0.0 && contrast.is_finite() => Ok(contrast),
+        _ => Err("Contrast must be a finite number >= 0.".to_string()),
+    }
+}
+
+fn parse_gamma(input: &str) -> Result<f32, String> {
+    match input.parse::<f32>() {
+        Ok(gamma) if gamma > 0.0 && gamma.is_finite() => Ok(gamma),
+        _ => Err("Gamma must be a finite number > 0.".to_string()),
+    }
+}
+
+fn parse_strength(input: &str) -> Result<f32, String> {
+    match input.parse::<f32>() {
+        Ok(strength) if (0.0..=1.0).contains(&strength) => Ok(strength),
+        _ => Err("Strength must be a number between 0.0 and 1.0.".to_string()),
+    }
+}
+
+#[cfg(feature = "plugins")]
+fn parse_plugin(input: &str) -> Result<String, String> {
+    if ditherer::registered_algorithm(input).is_some() {
+        return Ok(input.to_string());
+    }
+    let names: Vec<String> = ditherer::registered_algorithms()
+        .iter()
+        .map(|algorithm| algorithm.name().to_string())
+        .collect();
+    Err(format!(
+        "Unknown plugin. Choose from: {}.",
+        names.join(", ")
+    ))
+}
+
+fn parse_jitter(input: &str) -> Result<f32, String> {
+    match input.parse::<f32>() {
+        Ok(jitter) if (0.0..=1.0).contains(&jitter) => Ok(jitter),
+        _ => Err("Jitter must be a number between 0.0 and 1.0.".to_string()),
+    }
+}
+
+fn parse_angle(input: &str) -> Result<f64, String> {
+    match input.parse::<f64>() {
+        Ok(angle) if angle.is_finite() => Ok(angle),
+        _ => Err("Angle must be a finite number of degrees.".to_string()),
+    }
+}
+
+fn parse_display_gamma(input: &str) -> Result<f64, String> {
+    match input.parse::<f64>() {
+        Ok(gamma) if gamma > 0.0 && gamma.is_finite() => Ok(gamma),
+        _ => Err("Display gamma must be a finite number > 0.".to_string()),
+    }
+}
+
+fn parse_metrics_blur(input: &str) -> Result<f32, String> {
+    match input.parse::<f32>() {
+        Ok(sigma) if sigma >= 0.0 && sigma.is_finite() => Ok(sigma),
+        _ => Err("Metrics blur must be a finite number >= 0.".to_string()),
+    }
+}
+
+fn parse_color(input: &str) -> Result<[u8; 3], String> {
+    let hex = input.trim_start_matches('#');
+    match u32::from_str_radix(hex, 16) {
+        Ok(value) if hex.len() == 6 && hex.is_ascii() => {
+            Ok([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+        }
+        _ => Err("Color must be a 6-digit hex value, e.g. '#f4f0e8'.".to_string()),
+    }
+}
+
+fn parse_palette(input: &str) -> Result<Palette, String> {
+    if let Some(palette) = Palette::preset(input) {
+        return Ok(palette);
+    }
+    if let Some(slug) = input.strip_prefix("lospec:") {
+        let text = lospec_palette(slug)?;
+        return Palette::from_text(&text)
+            .map_err(|error| format!("Lospec palette '{}': {}", slug, error));
+    }
+
+    let text = std::fs::read_to_string(input)
+        .map_err(|error| format!("Cannot read palette file '{}': {}", input, error))?;
+    Palette::from_text(&text).map_err(|error| error.to_string())
+}
+
+/// Returns the `.hex` export of a Lospec palette from the cache, downloading and caching it on a
+/// miss unless `--offline` is set.
+fn lospec_palette(slug: &str) -> Result<String, String> {
+    if slug.is_empty()
+        || !slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err(format!(
+            "Invalid Lospec palette '{}': a slug is lowercase letters, digits and '-', as in lospec:pico-8",
+            slug
+        ));
+    }
+
+    let cache_path = cache_dir().map(|dir| dir.join("lospec").join(format!("{}.hex", slug)));
+    if let Some(text) = cache_path
+        .as_ref()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+    {
+        return Ok(text);
+    }
+    if OFFLINE.load(Ordering::Relaxed) {
+        return Err(format!(
+            "Lospec palette '{}' is not cached and --offline forbids downloading it",
+            slug
+        ));
+    }
+
+    #[cfg(feature = "lospec")]
+    {
+        let text = lospec::download(slug).map_err(|error| error.to_string())?;
+        if let Some(path) = &cache_path {
+            let cached = path
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|()| std::fs::write(path, &text));
+            if let Err(error) = cached {
+                warn(format_args!("cannot cache {}: {}", path.display(), error));
+            }
+        }
+        Ok(text)
+    }
+    #[cfg(not(feature = "lospec"))]
+    Err(format!(
+        "Lospec palette '{}' is not cached, and ditherer was built without the lospec feature to download it",
+        slug
+    ))
+}
+
+/// The per-user cache directory: `$XDG_CACHE_HOME/ditherer`, `%LOCALAPPDATA%\ditherer` or
+/// `~/.cache/ditherer`.
+fn cache_dir() -> Option<PathBuf> {
+    let non_empty = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty());
+    non_empty("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| non_empty("LOCALAPPDATA").map(PathBuf::from))
+        .or_else(|| non_empty("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .map(|dir| dir.join("ditherer"))
+}
+
+fn parse_frame_size(input: &str) -> Result<(u32, u32), String> {
+    match input.parse::<ResizeTarget>() {
+        Ok(ResizeTarget {
+            width: Some(width),
+            height: Some(height),
+        }) => Ok((width, height)),
+        _ => Err("Frame size must be WIDTHxHEIGHT, e.g. 640x480.".to_string()),
+    }
+}
+
+fn parse_timeout(input: &str) -> Result<f64, String> {
+    match input.parse::<f64>() {
+        Ok(seconds) if seconds > 0.0 && seconds.is_finite() => Ok(seconds),
+        _ => Err("Timeout must be a finite number of seconds > 0.".to_string()),
+    }
+}
+
+const P5JS_MAX_SIZE: u32 = 128;
+
+const P5JS_PIXEL_SIZE: u32 = 4;
+
+const DEFAULT_TERMINAL_COLUMNS: u32 = 80;
+
+const ASCII_CELL_WIDTH: u32 = 2;
+
+const ASCII_CELL_HEIGHT: u32 = 4;
+
+const DITHER_BYTES_PER_PIXEL: u64 = 9;
+
+/// Linear sRGB to linear Display P3; both share the D65 white point, so every row sums to 1.
+const SRGB_TO_DISPLAY_P3: [[f32; 3]; 3] = [
+    [0.822_462, 0.177_538, 0.0],
+    [0.033_194, 0.966_806, 0.0],
+    [0.017_083, 0.072_397, 0.910_520],
+];
+
+const TIMEOUT_EXIT_CODE: u8 = 124;
+const PNG_PALETTE_SIZE: usize = 256;
+
+const EXIF_ORIENTATION_TAG: u16 = 0x0112;
+const EXIF_SHORT: u16 = 3;
+
+const EXPORTED_NOISE_SIZE: u32 = 256;
+
+const COMPARE_GAP: u32 = 4;
+
+const LABEL_SCALE: u32 = 2;
+
+/// 3x5 pixel glyphs for contact sheet labels, one row per byte with the leftmost pixel in bit 2.
+const LABEL_FONT: [(char, [u8; 5]); 39] = [
+    ('a', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('b', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('c', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('d', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('e', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('f', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('g', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('h', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('i', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('j', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('k', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('l', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('m', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('n', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('o', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('p', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('r', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('s', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('t', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('u', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('v', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('w', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('x', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b110, 0b001, 0b010, 0b100, 0b111]),
+    ('3', [0b110, 0b001, 0b010, 0b001, 0b110]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b110, 0b001, 0b110]),
+    ('6', [0b011, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b110]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    (' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+];
+
+const DEFAULT_CONFIG_FILE: &str = "ditherer.toml";
+
+/// Keyword of the PNG text chunk that records the dithering options for `--reapply`.
+const PARAMS_KEYWORD: &str = "ditherer:params";
+
+/// Options that shape the dithered pixels and are recorded for `--reapply`. Input, output,
+/// geometry and reporting options are left out, as they belong to each run.
+const REAPPLIED_PARAMS: [&str; 46] = [
+    "matrix_size",
+    "color",
+    "rgb",
+    "preserve_order",
+    "channel",
+    "fg",
+    "bg",
+    "auto_levels_per_channel",
+    "preserve_luminance",
+    "contrast_curve",
+    "dot_gain",
+    "pixelate",
+    "brightness",
+    "contrast",
+    "gamma",
+    "tonemap",
+    "strength",
+    "no_gamma",
+    "grayscale_method",
+    "adaptive",
+    "window",
+    "algorithm",
+    "plugin",
+    "matrix_a",
+    "matrix_b",
+    "interference_blend",
+    "noise_size",
+    "dot_size",
+    "dot_shape",
+    "cell_size",
+    "seed",
+    "seed_grid",
+    "jitter",
+    "levels",
+    "serpentine",
+    "edge_enhance",
+    "angle",
+    "pattern_scale",
+    "pattern_angle",
+    "matrix_gradient",
+    "gradient_direction",
+    "threshold_map",
+    "channel_swap",
+    "display_gamma",
+    "palette",
+    "alpha",
+];
+
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+const PROGRESS_BAR_WIDTH: usize = 30;
+
+const SERVE_MAX_HEADER_BYTES: usize = 16 * 1024;
+
+const SERVE_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest per-channel change between animation frames that `--stable` treats as unchanged.
+const STABLE_TOLERANCE: u8 = 3;
+
+/// `--bench` dithers the input at least this many times, and then until `BENCH_MIN_TIME` passed.
+const BENCH_MIN_RUNS: u32 = 3;
+const BENCH_MIN_TIME: Duration = Duration::from_secs(1);
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// `--offline`, found before the arguments are parsed so that `--palette lospec:` can honor it.
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+fn main() -> ExitCode {
+    #[cfg(feature = "plugins")]
+    plugins::register();
+    let command_line: Vec<OsString> = std::env::args_os().collect();
+    let mut error_format = requested_error_format(&command_line);
+    let result = with_reapplied_args(command_line)
+        .and_then(|command_line| {
+            with_config_args(command_line)
+                .map_err(|error| CliError::InvalidOptions(error.to_string()))
+        })
+        .and_then(|command_line| {
+            error_format = requested_error_format(&command_line);
+            OFFLINE.store(
+                command_line.iter().skip(1).any(|arg| arg == "--offline"),
+                Ordering::Relaxed,
+            );
+            match parse_args(command_line) {
+                Ok(args) => execute(args),
+                Err(error) if error_format == ErrorFormat::Json && error.use_stderr() => {
+                    let rendered = error.render().to_string();
+                    let message = rendered.lines().next().unwrap_or_default();
+                    Err(CliError::InvalidOptions(
+                        message.trim_start_matches("error: ").to_string(),
+                    ))
+                }
+                Err(error) => error.exit(),
+            }
+        });
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            match error_format {
+                ErrorFormat::Text => eprintln!("Error: {}", error),
+                ErrorFormat::Json => eprintln!(
+                    "{{\"kind\":{},\"message\":{},\"exit_code\":{}}}",
+                    json_string(error.kind()),
+                    json_string(&error.to_string()),
+                    error.exit_code()
+                ),
+            }
+            ExitCode::from(error.exit_code())
+        }
+    }
+}
+
+/// Finds `--error-format` before the arguments are parsed, so that parse errors can honor it.
+fn requested_error_format(command_line: &[OsString]) -> ErrorFormat {
+    let mut error_format = ErrorFormat::Text;
+    let mut values = command_line.iter().skip(1).filter_map(|arg| arg.to_str());
+    while let Some(arg) = values.next() {
+        let value = match arg.strip_prefix("--error-format=") {
+            Some(value) => Some(value),
+            None if arg == "--error-format" => values.next(),
+            None => None,
+        };
+        if let Some(format) = value.and_then(|value| value.parse().ok()) {
+            error_format = format;
+        }
+    }
+    error_format
+}
+
+/// Parses the command line and records the `REAPPLIED_PARAMS` it sets, for `ditherer:params`.
+fn parse_args(command_line: Vec<OsString>) -> Result<DithererArgs, clap::Error> {
+    let command = DithererArgs::command();
+    let matches = command.clone().try_get_matches_from(command_line)?;
+    let mut args = DithererArgs::from_arg_matches(&matches)?;
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        if !REAPPLIED_PARAMS.contains(&id)
+            || matches.value_source(id) != Some(clap::parser::ValueSource::CommandLine)
+        {
+            continue;
+        }
+        let name = arg.get_long().unwrap_or(id);
+        if !arg.get_action().takes_values() {
+            args.params.push(format!("--{}", name));
+            continue;
+        }
+        for value in matches.get_raw(id).into_iter().flatten() {
+            args.params
+                .push(format!("--{}={}", name, value.to_string_lossy()));
+        }
+    }
+    Ok(args)
+}
+
+fn execute(args: DithererArgs) -> Result<(), CliError> {
+    QUIET.store(args.quiet, Ordering::Relaxed);
+
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads as usize)
+            .build_global()
+            .map_err(|error| CliError::Other(error.to_string()))?;
+    }
+
+    let Some(timeout) = args.timeout else {
+        return run(args).map_err(CliError::classify);
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(run(args).map_err(CliError::classify));
+    });
+
+    match receiver.recv_timeout(Duration::from_secs_f64(timeout)) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => Err(CliError::Timeout(timeout)),
+        Err(RecvTimeoutError::Disconnected) => {
+            Err(CliError::Other("dithering thread panicked".to_string()))
+        }
+    }
+}
+
+fn warn(message: impl fmt::Display) {
+    if !QUIET.load(Ordering::Relaxed) {
+        eprintln!("Warning: {}", message);
+    }
+}
+
+/// Reports finished images or frames on stderr as configured by `--verbose`, `--progress` and
+/// `--progress-json`. The total is unknown for frame streams.
+struct Progress {
+    total: Option<usize>,
+    done: usize,
+    failed: usize,
+    started: Instant,
+    verbose: bool,
+    bar: bool,
+    json: bool,
+}
+
+impl Progress {
+    fn new(total: Option<usize>, args: &DithererArgs) -> Self {
+        Progress {
+            total,
+            done: 0,
+            failed: 0,
+            started: Instant::now(),
+            verbose: args.verbose,
+            bar: args.progress,
+            json: args.progress_json,
+        }
+    }
+
+    fn item(&mut self, name: &str, started: Instant, succeeded: bool) {
+        self.done += 1;
+        if !succeeded {
+            self.failed += 1;
+        }
+        let elapsed_ms = started.elapsed().as_millis();
+
+        if self.bar {
+            // Clear the bar so that other messages start on an empty line.
+            eprint!("\r\x1b[2K");
+        }
+        if self.verbose {
+            eprintln!("{} in {} ms", name, elapsed_ms);
+        }
+        if self.json {
+            eprintln!(
+                "{{\"event\":\"item\",\"name\":{},\"index\":{},\"total\":{},\"ok\":{},\"elapsed_ms\":{}}}",
+                json_string(name),
+                self.done,
+                self.total.map_or("null".to_string(), |total| total.to_string()),
+                succeeded,
+                elapsed_ms
+            );
+        }
+        if self.bar {
+            match self.total {
+                Some(total) => {
+                    let filled = PROGRESS_BAR_WIDTH * self.done / total.max(1);
+                    eprint!(
+                        "[{}{}] {}/{}",
+                        "#".repeat(filled),
+                        " ".repeat(PROGRESS_BAR_WIDTH - filled),
+                        self.done,
+                        total
+                    );
+                }
+                None => eprint!("{} frames", self.done),
+            }
+        }
+    }
+
+    fn finish(&self) {
+        let elapsed_ms = self.started.elapsed().as_millis();
+        if self.bar {
+            eprintln!();
+        }
+        if self.verbose && self.done > 1 {
+            eprintln!(
+                "{} done, {} failed, in {} ms",
+                self.done, self.failed, elapsed_ms
+            );
+        }
+        if self.json {
+            eprintln!(
+                "{{\"event\":\"done\",\"completed\":{},\"failed\":{},\"elapsed_ms\":{}}}",
+                self.done, self.failed, elapsed_ms
+            );
+        }
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Inserts the options from the config file before the command line ones, so that flags given
+/// on the command line override the file (clap keeps the last occurrence of each option).
+/// Finds the value of option `name` before the arguments are parsed.
+fn option_value(command_line: &[OsString], name: &str) -> Option<String> {
+    let mut values = command_line.iter().skip(1).filter_map(|arg| arg.to_str());
+    let prefix = format!("{}=", name);
+    while let Some(arg) = values.next() {
+        if arg == name {
+            return values.next().map(str::to_string);
+        }
+        if let Some(value) = arg.strip_prefix(&prefix) {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+/// Inserts the options recorded in the `ditherer:params` chunk of the `--reapply` image before the
+/// given arguments, so that options on the command line override them.
+fn with_reapplied_args(command_line: Vec<OsString>) -> Result<Vec<OsString>, CliError> {
+    let Some(path) = option_value(&command_line, "--reapply") else {
+        return Ok(command_line);
+    };
+
+    let file = std::fs::File::open(&path)
+        .map_err(|error| CliError::Io(format!("--reapply {}: {}", path, error)))?;
+    let reader = png::Decoder::new(BufReader::new(file))
+        .read_info()
+        .map_err(|error| CliError::Decode(format!("--reapply {}: {}", path, error)))?;
+    let info = reader.info();
+    let params = info
+        .uncompressed_latin1_text
+        .iter()
+        .find(|chunk| chunk.keyword == PARAMS_KEYWORD)
+        .map(|chunk| chunk.text.clone())
+        .or_else(|| {
+            info.utf8_text
+                .iter()
+                .find(|chunk| chunk.keyword == PARAMS_KEYWORD)
+                .and_then(|chunk| chunk.get_text().ok())
+        })
+        .ok_or_else(|| {
+            CliError::Decode(format!(
+                "--reapply {}: no {} chunk, the image was not written by ditherer",
+                path, PARAMS_KEYWORD
+            ))
+        })?;
+
+    let mut recorded_args = Vec::new();
+    for line in params.lines() {
+        let name = line
+            .strip_prefix("--")
+            .map(|arg| match arg.split_once('=') {
+                Some((name, _)) => name,
+                None => arg,
+            });
+        if !name.is_some_and(|name| REAPPLIED_PARAMS.contains(&name.replace('-', "_").as_str())) {
+            return Err(CliError::Decode(format!(
+                "--reapply {}: unrecognized {} entry '{}'",
+                path, PARAMS_KEYWORD, line
+            )));
+        }
+        recorded_args.push(OsString::from(line));
+    }
+
+    let mut args = command_line;
+    args.splice(1..1, recorded_args);
+    Ok(args)
+}
+
+fn with_config_args(command_line: Vec<OsString>) -> Result<Vec<OsString>, Box<dyn Error>> {
+    let preset = option_value(&command_line, "--preset");
+    let config_path = match option_value(&command_line, "--config") {
+        Some(path) => PathBuf::from(path),
+        None if Path::new(DEFAULT_CONFIG_FILE).is_file() => PathBuf::from(DEFAULT_CONFIG_FILE),
+        None if preset.is_some() => {
+            return Err(format!("--preset needs a {} or --config file", DEFAULT_CONFIG_FILE).into())
+        }
+        None => return Ok(command_line),
+    };
+
+    let text = std::fs::read_to_string(&config_path)
+        .map_err(|error| format!("{}: {}", config_path.display(), error))?;
+    let entries =
+        parse_config(&text).map_err(|error| format!("{}:{}", config_path.display(), error))?;
+
+    let preset_table = preset.as_ref().map(|name| format!("preset.{}", name));
+    if let Some(table) = &preset_table {
+        if !entries
+            .iter()
+            .any(|(entry_table, _, _)| entry_table == table)
+        {
+            return Err(format!("{}: no [{}] table", config_path.display(), table).into());
+        }
+    }
+
+    let mut args = command_line.clone();
+    let config_args = entries
+        .into_iter()
+        .filter(|(table, key, _)| {
+            (table.is_empty() || Some(table) == preset_table.as_ref())
+                && key != "config"
+                && key != "preset"
+        })
+        .filter_map(|(_, key, value)| match value {
+            ConfigValue::Text(value) => Some(format!("--{}={}", key, value)),
+            ConfigValue::Flag(true) => Some(format!("--{}", key)),
+            ConfigValue::Flag(false) => None,
+        })
+        .map(OsString::from);
+    args.splice(1..1, config_args);
+    Ok(args)
+}
+
+enum ConfigValue {
+    Text(String),
+    Flag(bool),
+}
+
+/// Parses the subset of TOML used by config files: `key = value` pairs of strings, numbers,
+/// booleans and flat arrays, grouped under `[table]` headers. Returns `(table, key, value)`
+/// with the table empty for top-level keys and `_` in keys turned into `-`.
+fn parse_config(text: &str) -> Result<Vec<(String, String, ConfigValue)>, String> {
+    let mut entries = Vec::new();
+    let mut table = String::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line = strip_config_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+        {
+            table = name.trim().replace('"', "");
+            continue;
+        }
+
+        let invalid = |message: &str| format!("{}: {}", index + 1, message);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| invalid("expected key = value"))?;
+        let key = key.trim().trim_matches('"').replace('_', "-");
+        let value = value.trim();
+
+        let value = if let Some(text) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+            ConfigValue::Text(text.to_string())
+        } else if let Some(items) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            let items: Vec<&str> = items
+                .split(',')
+                .map(|item| item.trim().trim_matches('"'))
+                .filter(|item| !item.is_empty())
+                .collect();
+            ConfigValue::Text(items.join(","))
+        } else if value == "true" || value == "false" {
+            ConfigValue::Flag(value == "true")
+        } else if !value.is_empty() && value.parse::<f64>().is_ok() {
+            ConfigValue::Text(value.to_string())
+        } else {
+            return Err(invalid("expected a string, number, boolean or array"));
+        };
+
+        entries.push((table.clone(), key, value));
+    }
+
+    Ok(entries)
+}
+
+fn strip_config_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn run(args: DithererArgs) -> Result<(), Box<dyn Error>> {
+    match &args.command {
+        Some(DithererCommand::Compare(compare_args)) => return compare(compare_args, &args),
+        Some(DithererCommand::Serve(serve_args)) => return serve(serve_args, &args),
+        None => {}
+    }
+
+    if let Some(algorithm) = &args.explain {
+        println!("{}", algorithm.description());
+        return Ok(());
+    }
+
+    if args.list_palettes {
+        print_palette_presets();
+        return Ok(());
+    }
+
+    if args.watch {
+        return watch(args);
+    }
+
+    if args.export_threshold_map {
+        return export_threshold_map(args.output.as_deref(), &args);
+    }
+
+    if args.frames {
+        return dither_frames(&args);
+    }
+
+    if let (Some(input_dir), Some(output_dir)) = (&args.input_dir, &args.output_dir) {
+        return run_batch(input_dir, output_dir, &args);
+    }
+
+    if let Some(tile_height) = args.tile_height {
+        return dither_tiled(
+            args.input.as_deref(),
+            args.output.as_deref(),
+            tile_height,
+            &args,
+        );
+    }
+
+    if let (Some(memory_limit), Some(input)) = (args.memory_limit, &args.input) {
+        let output = args.output.as_deref();
+        if let Some(tile_height) = memory_limit_strips(input, output, memory_limit, &args)? {
+            return dither_tiled(Some(input), output, tile_height, &args);
+        }
+    }
+
+    if let (Some(input), Some(output)) = (&args.input, &args.output) {
+        if is_gif(input) && is_gif(output) && !args.report_colors {
+            return dither_animation(input, output, &args);
+        }
+    }
+
+    let started = Instant::now();
+    let image = read_input(args.input.as_deref(), &args)?;
+
+    if args.report_colors {
+        print_color_report(&image, args.top_n, args.json);
+        return Ok(());
+    }
+
+    if args.bench {
+        return print_benchmark(&image, &args);
+    }
+
+    if let (true, Some(output)) = (args.separations, &args.output) {
+        write_separations(&image, output, &args)?;
+    }
+
+    let result = match (&args.profile, &args.output) {
+        (Some(profile), Some(output)) => dither_for_profile(&image, output, profile, &args),
+        _ => {
+            let metadata = output_metadata(args.input.as_deref(), &args)?;
+            write_output(&image, args.output.as_deref(), &args, metadata.as_ref())
+        }
+    };
+    let name = match &args.input {
+        Some(input) => input.display().to_string(),
+        None if args.clipboard_in => "clipboard".to_string(),
+        None => "stdin".to_string(),
+    };
+    let mut progress = Progress::new(Some(1), &args);
+    progress.item(&name, started, result.is_ok());
+    progress.finish();
+    result
+}
+
+fn watch(args: DithererArgs) -> Result<(), Box<dyn Error>> {
+    let watched = args
+        .input_dir
+        .clone()
+        .or_else(|| args.input.clone())
+        .ok_or_else(|| {
+            CliError::InvalidOptions("--watch needs an input file or --input-dir".to_string())
+        })?;
+    let args = DithererArgs {
+        watch: false,
+        ..args
+    };
+
+    let mut last_snapshot = None;
+    loop {
+        // Editors often replace files by renaming, so a missing file is retried, not fatal.
+        if let Ok(snapshot) = modification_times(&watched, args.recursive) {
+            if last_snapshot.as_ref() != Some(&snapshot) {
+                match run(args.clone()) {
+                    Ok(()) if !args.quiet => eprintln!("Dithered {}", watched.display()),
+                    Ok(()) => {}
+                    Err(error) => eprintln!("Error: {}", error),
+                }
+                last_snapshot = Some(snapshot);
+            }
+        }
+        thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+fn modification_times(
+    path: &Path,
+    recursive: bool,
+) -> Result<Vec<(PathBuf, std::time::SystemTime)>, Box<dyn Error>> {
+    let mut paths = Vec::new();
+    if path.is_dir() {
+        collect_images(path, recursive, &mut paths)?;
+    } else {
+        paths.push(path.to_path_buf());
+    }
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let modified = std::fs::metadata(&path)?.modified()?;
+            Ok((path, modified))
+        })
+        .collect()
+}
+
+fn compare(compare_args: &CompareArgs, args: &DithererArgs) -> Result<(), Box<dyn Error>> {
+    let image = read_input(Some(&compare_args.input), args)?;
+
+    let mut variants = Vec::new();
+    for algorithm in &compare_args.algorithms {
+        if let Algorithm::Bayer = algorithm {
+            for matrix_size in &compare_args.matrix_sizes {
+                variants.push((algorithm.clone(), matrix_size.clone()));
+            }
+        } else {
+            variants.push((algorithm.clone(), args.matrix_size.clone()));
+        }
+    }
+
+    let mut cells = Vec::new();
+    let mut labels = Vec::new();
+    for (algorithm, matrix_size) in variants {
+        let label = match algorithm {
+            Algorithm::Bayer => format!("{} {}", algorithm, matrix_size),
+            _ => algorithm.to_string(),
+        };
+        let variant_args = DithererArgs {
+            algorithm,
+            matrix_size,
+            ..args.clone()
+        };
+        cells.push(dither(&image, &variant_args)?);
+        labels.push(label);
+    }
+
+    let columns = compare_args
+        .columns
+        .unwrap_or_else(|| (cells.len() as f64).sqrt().ceil() as u32)
+        .clamp(1, cells.len().max(1) as u32);
+    let sheet = contact_sheet(&cells, &labels, image.width(), image.height(), columns);
+
+    if let Some(output_path) = &compare_args.output {
+        write_png(
+            std::fs::File::create(output_path)?,
+            &sheet,
+            &args.output_depth,
+            None,
+            &args.output_colorspace,
+            None,
+        )
+    } else {
+        let mut stdout = std::io::stdout();
+        write_png(
+            &mut stdout,
+            &sheet,
+            &args.output_depth,
+            None,
+            &args.output_colorspace,
+            None,
+        )?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+fn serve(serve_args: &ServeArgs, args: &DithererArgs) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(&serve_args.bind)
+        .map_err(|error| format!("cannot listen on {}: {}", serve_args.bind, error))?;
+    eprintln!("Listening on http://{}/dither", listener.local_addr()?);
+
+    let max_upload = serve_args.max_upload * 1024 * 1024;
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                warn(format_args!("cannot accept connection: {}", error));
+                continue;
+            }
+        };
+        let args = args.clone();
+        thread::spawn(move || {
+            let response = handle_request(&mut stream, max_upload, &args);
+            let (status, content_type, body) =
+                response.unwrap_or_else(|error| error.into_response());
+            let header = format!(
+                "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                content_type,
+                body.len()
+            );
+            let written = stream
+                .write_all(header.as_bytes())
+                .and_then(|_| stream.write_all(&body))
+                .and_then(|_| stream.flush());
+            if let Err(error) = written {
+                warn(format_args!("cannot send response: {}", error));
+            }
+        });
+    }
+    Ok(())
+}
+
+/// An HTTP error status with a plain text message.
+struct HttpError {
+    status: &'static str,
+    message: String,
+}
+
+impl HttpError {
+    fn new(status: &'static str, message: impl fmt::Display) -> Self {
+        HttpError {
+            status,
+            message: message.to_string(),
+        }
+    }
+
+    fn into_response(self) -> (&'static str, &'static str, Vec<u8>) {
+        (
+            self.status,
+            "text/plain; charset=utf-8",
+            format!("{}\n", self.message).into_bytes(),
+        )
+    }
+}
+
+/// Reads one request and returns the status, content type and body of the response.
+fn handle_request(
+    stream: &mut TcpStream,
+    max_upload: u64,
+    args: &DithererArgs,
+) -> Result<(&'static str, &'static str, Vec<u8>), HttpError> {
+    let bad_request = |message: &dyn fmt::Display| HttpError::new("400 Bad Request", message);
+    stream
+        .set_read_timeout(Some(SERVE_READ_TIMEOUT))
+        .map_err(|error| bad_request(&error))?;
+
+    let mut buffer = Vec::new();
+    let head_end = loop {
+        if let Some(position) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position;
+        }
+        if buffer.len() > SERVE_MAX_HEADER_BYTES {
+            return Err(HttpError::new(
+                "431 Request Header Fields Too Large",
+                "request headers are too large",
+            ));
+        }
+        let mut chunk = [0; 8192];
+        match stream.read(&mut chunk) {
+            Ok(0) => return Err(bad_request(&"connection closed before the request ended")),
+            Ok(length) => buffer.extend_from_slice(&chunk[..length]),
+            Err(error) => return Err(bad_request(&error)),
+        }
+    };
+    let head = String::from_utf8_lossy(&buffer[..head_end]).into_owned();
+    let mut body = buffer.split_off(head_end + 4);
+
+    let mut lines = head.lines();
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+    );
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let headers: Vec<(String, &str)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim()))
+        .collect();
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(header_name, _)| header_name == name)
+            .map(|(_, value)| *value)
+    };
+
+    if path != "/dither" {
+        return Err(HttpError::new("404 Not Found", "POST images to /dither"));
+    }
+    if method != "POST" {
+        return Err(HttpError::new(
+            "405 Method Not Allowed",
+            "POST images to /dither",
+        ));
+    }
+
+    let mut args = args.clone();
+    apply_query(&mut args, query).map_err(|message| bad_request(&message))?;
+
+    let content_length = header("content-length")
+        .ok_or_else(|| {
+            HttpError::new(
+                "411 Length Required",
+                "send the image with a Content-Length",
+            )
+        })?
+        .parse::<u64>()
+        .map_err(|_| bad_request(&"invalid Content-Length"))?;
+    if content_length > max_upload {
+        return Err(HttpError::new(
+            "413 Payload Too Large",
+            format_args!("uploads are limited to {} MB", max_upload / 1024 / 1024),
+        ));
+    }
+    if header("expect").is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue")) {
+        stream
+            .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+            .map_err(|error| bad_request(&error))?;
+    }
+    let remaining = (content_length as usize).saturating_sub(body.len());
+    let mut rest = vec![0; remaining];
+    stream
+        .read_exact(&mut rest)
+        .map_err(|error| bad_request(&error))?;
+    body.extend(rest);
+    body.truncate(content_length as usize);
+
+    let upload = match header("content-type").and_then(multipart_boundary) {
+        Some(boundary) => multipart_file(&body, boundary)
+            .ok_or_else(|| bad_request(&"no file in the multipart form"))?
+            .to_vec(),
+        None => body,
+    };
+
+    let dithered_png = || -> Result<Vec<u8>, Box<dyn Error>> {
+        let image = read_input_bytes(upload, &args)?;
+        let dithered_image = dither(&image, &args)?;
+        let mut png_bytes = Vec::new();
+        write_png(
+            &mut png_bytes,
+            &dithered_image,
+            &args.output_depth,
+            args.palette.as_ref(),
+            &args.output_colorspace,
+            None,
+        )?;
+        Ok(png_bytes)
+    };
+    let png_bytes = dithered_png().map_err(|error| bad_request(&error))?;
+    Ok(("200 OK", "image/png", png_bytes))
+}
+
+/// Overrides the server's settings with the query parameters of one request. Only options that
+/// cannot reach the server's filesystem are accepted, so palettes are presets or hex lists.
+fn apply_query(args: &mut DithererArgs, query: &str) -> Result<(), String> {
+    let flag = |value: &str| match value {
+        "" | "1" | "true" | "yes" => Ok(true),
+        "0" | "false" | "no" => Ok(false),
+        _ => Err(format!("expected true or false, got '{}'", value)),
+    };
+
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = percent_decode(value)?;
+        match percent_decode(key)?.as_str() {
+            "algorithm" => {
+                args.algorithm = value
+                    .parse::<Algorithm>()
+                    .map_err(|error| error.to_string())?
+            }
+            "matrix" | "matrix-size" => {
+                args.matrix_size = value
+                    .parse::<BayerMatrixOption>()
+                    .map_err(|error| error.to_string())?
+            }
+            "palette" => {
+                args.palette = Some(match Palette::preset(&value) {
+                    Some(palette) => palette,
+                    None => Palette::from_text(&value.replace(',', "\n"))
+                        .map_err(|error| error.to_string())?,
+                })
+            }
+            "color" => args.color = flag(&value)?,
+            "serpentine" => args.serpentine = flag(&value)?,
+            "strength" => args.strength = parse_strength(&value)?,
+            "pixelate" => {
+                args.pixelate = value
+                    .parse()
+                    .ok()
+                    .filter(|&pixelate| pixelate >= 1)
+                    .ok_or("pixelate must be a whole number >= 1")?
+            }
+            key => return Err(format!("unsupported query parameter '{}'", key)),
+        }
+    }
+    Ok(())
+}
+
+fn percent_decode(input: &str) -> Result<String, String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' => {
+                let byte = input
+                    .get(index + 1..index + 3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| format!("invalid escape in '{}'", input))?;
+                decoded.push(byte);
+                index += 3;
+            }
+            b'+' => {
+                decoded.push(b' ');
+                index += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| format!("'{}' is not UTF-8", input))
+}
+
+fn multipart_boundary(content_type: &str) -> Option<&str> {
+    let (media_type, parameters) = content_type.split_once(';')?;
+    if !media_type
+        .trim()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return None;
+    }
+    parameters
+        .split(';')
+        .filter_map(|parameter| parameter.trim().split_once('='))
+        .find(|(name, _)| name.eq_ignore_ascii_case("boundary"))
+        .map(|(_, boundary)| boundary.trim_matches('"'))
+}
+
+/// Returns the contents of the first uploaded file in a `multipart/form-data` body, or of the
+/// first part if none has a file name.
+fn multipart_file<'a>(body: &'a [u8], boundary: &str) -> Option<&'a [u8]> {
+    let delimiter = format!("--{}", boundary);
+    let find = |haystack: &[u8], needle: &[u8]| {
+        haystack
+            .windows(needle.len())
+            .position(|window| window == needle)
+    };
+
+    let mut parts = Vec::new();
+    let mut rest = &body[find(body, delimiter.as_bytes())? + delimiter.len()..];
+    while let Some(headers_end) = find(rest, b"\r\n\r\n") {
+        let headers = &rest[..headers_end];
+        let content = &rest[headers_end + 4..];
+        let content_end = find(content, format!("\r\n{}", delimiter).as_bytes())?;
+        parts.push((headers, &content[..content_end]));
+        rest = &content[content_end + 2 + delimiter.len()..];
+    }
+
+    parts
+        .iter()
+        .find(|(headers, _)| find(headers, b"filename=").is_some())
+        .or_else(|| parts.first())
+        .map(|(_, content)| *content)
+}
+
+fn contact_sheet(
+    cells: &[ImageBuffer<Rgba<u8>, Vec<u8>>],
+    labels: &[String],
+    width: u32,
+    height: u32,
+    columns: u32,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let label_height = 7 * LABEL_SCALE;
+    let rows = (cells.len() as u32).div_ceil(columns);
+    let cell_width = width + COMPARE_GAP;
+    let cell_height = height + label_height + COMPARE_GAP;
+    let mut sheet = ImageBuffer::from_pixel(
+        columns * cell_width + COMPARE_GAP,
+        rows * cell_height + COMPARE_GAP,
+        Rgba([255, 255, 255, 255]),
+    );
+
+    for (index, (cell, label)) in cells.iter().zip(labels).enumerate() {
+        let x = COMPARE_GAP + (index as u32 % columns) * cell_width;
+        let y = COMPARE_GAP + (index as u32 / columns) * cell_height;
+        imageops::overlay(&mut sheet, cell, x as i64, y as i64);
+        draw_label(&mut sheet, label, x, y + height + LABEL_SCALE, width);
+    }
+
+    sheet
+}
+
+fn draw_label(
+    image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    label: &str,
+    x: u32,
+    y: u32,
+    max_width: u32,
+) {
+    let advance = 4 * LABEL_SCALE;
+    for (index, c) in label.to_lowercase().chars().enumerate() {
+        let glyph_x = x + index as u32 * advance;
+        if glyph_x + 3 * LABEL_SCALE > x + max_width {
+            break;
+        }
+        let Some((_, rows)) = LABEL_FONT.iter().find(|(glyph, _)| *glyph == c) else {
+            continue;
+        };
+
+        for (row, bits) in rows.iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+                for dy in 0..LABEL_SCALE {
+                    for dx in 0..LABEL_SCALE {
+                        image.put_pixel(
+                            glyph_x + column * LABEL_SCALE + dx,
+                            y + row as u32 * LABEL_SCALE + dy,
+                            Rgba([0, 0, 0, 255]),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn run_batch(
+    input_dir: &Path,
+    output_dir: &Path,
+    args: &DithererArgs,
+) -> Result<(), Box<dyn Error>> {
+    let mut input_paths = Vec::new();
+    collect_images(input_dir, args.recursive, &mut input_paths)?;
+
+    let mut progress = Progress::new(Some(input_paths.len()), args);
+    for input_path in &input_paths {
+        let started = Instant::now();
+        let mut output_path = output_dir.join(input_path.strip_prefix(input_dir)?);
+        if let Some(format) = &args.format {
+            output_path.set_extension(format.extension());
+        }
+
+        let result = output_path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|()| {
+                if is_gif(input_path) && is_gif(&output_path) {
+                    dither_animation(input_path, &output_path, args)
+                } else {
+                    let metadata = output_metadata(Some(input_path), args)?;
+                    let image = read_input(Some(input_path), args)?;
+                    write_output(&image, Some(&output_path), args, metadata.as_ref())
+                }
+            });
+        if let Err(error) = &result {
+            eprintln!("Error: {}: {}", input_path.display(), error);
+        }
+        progress.item(&input_path.display().to_string(), started, result.is_ok());
+    }
+    progress.finish();
+
+    if progress.failed > 0 {
+        return Err(format!("{} of {} images failed", progress.failed, input_paths.len()).into());
+    }
+    Ok(())
+}
+
+fn collect_images(
+    dir: &Path,
+    recursive: bool,
+    paths: &mut Vec<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    let mut entries = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            if recursive {
+                collect_images(&path, recursive, paths)?;
+            }
+        } else if ImageFormat::from_path(&path).is_ok() {
+            paths.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn read_input(input: Option<&Path>, args: &DithererArgs) -> Result<DynamicImage, Box<dyn Error>> {
+    let Some(input_path) = input else {
+        let buffer = if args.clipboard_in {
+            paste_image()?
+        } else {
+            let mut buffer = Vec::new();
+            std::io::stdin().lock().read_to_end(&mut buffer)?;
+            buffer
+        };
+        return read_input_bytes(buffer, args);
+    };
+
+    let reader = ImageReader::open(input_path)
+        .map_err(|error| CliError::Io(format!("{}: {}", input_path.display(), error)))?;
+    let image = decode(reader, args)?;
+    Ok(preprocess(tone_map_input(image, args), args))
+}
+
+/// Decodes an encoded image held in memory, such as stdin or an HTTP upload.
+fn read_input_bytes(bytes: Vec<u8>, args: &DithererArgs) -> Result<DynamicImage, Box<dyn Error>> {
+    let image = decode(
+        ImageReader::new(Cursor::new(bytes)).with_guessed_format()?,
+        args,
+    )?;
+    Ok(preprocess(tone_map_input(image, args), args))
+}
+
+/// Compresses high-dynamic-range input with `--tonemap`. Floating-point images, which hold linear
+/// light that may go far beyond white, are Reinhard-mapped unless another operator is chosen.
+fn tone_map_input(image: DynamicImage, args: &DithererArgs) -> DynamicImage {
+    let operator = match (&args.tonemap, &image) {
+        (Some(operator), _) => operator,
+        (None, DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)) => &ToneMap::Reinhard,
+        (None, _) => return image,
+    };
+    DynamicImage::ImageRgba8(tone_map(&image, operator))
+}
+
+fn preprocess(image: DynamicImage, args: &DithererArgs) -> DynamicImage {
+    let (width, height) = image.dimensions();
+    // Only a full WxH target can distort the image; the others keep the aspect ratio by design.
+    let target = match (&args.resize, args.scale) {
+        (
+            Some(ResizeTarget {
+                width: Some(w),
+                height: Some(h),
+            }),
+            _,
+        ) => Some((*w, *h, &args.preserve_aspect)),
+        (
+            Some(ResizeTarget {
+                width: Some(w),
+                height: None,
+            }),
+            _,
+        ) => Some((
+            *w,
+            (height as f64 * *w as f64 / width as f64).round() as u32,
+            &AspectMode::Stretch,
+        )),
+        (
+            Some(ResizeTarget {
+                width: None,
+                height: Some(h),
+            }),
+            _,
+        ) => Some((
+            (width as f64 * *h as f64 / height as f64).round() as u32,
+            *h,
+            &AspectMode::Stretch,
+        )),
+        (_, Some(scale)) => Some((
+            (width as f64 * scale).round() as u32,
+            (height as f64 * scale).round() as u32,
+            &AspectMode::Stretch,
+        )),
+        _ => None,
+    };
+    let image = match target {
+        Some((target_width, target_height, mode)) => {
+            resize_to_aspect(&image, target_width.max(1), target_height.max(1), mode)
+        }
+        None => image,
+    };
+
+    if args.brightness == 0.0 && args.contrast == 1.0 && args.gamma == 1.0 {
+        image
+    } else {
+        DynamicImage::ImageRgba8(adjust_tone(
+            &image,
+            args.brightness,
+            args.contrast,
+            args.gamma,
+        ))
+    }
+}
+
+fn write_output(
+    image: &DynamicImage,
+    output: Option<&Path>,
+    args: &DithererArgs,
+    metadata: Option<&Metadata>,
+) -> Result<(), Box<dyn Error>> {
+    // `None` for extensions we have no format for (`.tiff`, `.tga`, ...), left to the `image` crate.
+    let format = match (&args.format, output) {
+        (Some(format), _) => Some(format.clone()),
+        (None, Some(output_path)) if output_path.extension().is_some() => {
+            OutputFormat::from_path(output_path)
+        }
+        (None, _) => Some(OutputFormat::Png),
+    };
+
+    match (
+        &args.output_depth,
+        format.as_ref().map(OutputFormat::encoding),
+    ) {
+        (OutputDepth::Auto | OutputDepth::Rgba8, _)
+        | (_, Some(OutputFormat::Png | OutputFormat::Gif))
+        | (
+            OutputDepth::Gray1,
+            Some(
+                OutputFormat::Bmp
+                | OutputFormat::Pnm
+                | OutputFormat::Pbm
+                | OutputFormat::Xbm
+                | OutputFormat::Raw
+                | OutputFormat::CArray,
+            ),
+        ) => {}
+        (OutputDepth::Gray1, _) => {
+            warn("the output format has no 1-bit mode, writing 8 bits per channel instead")
+        }
+        _ => warn("--output-depth only applies to PNG output, writing RGBA instead"),
+    }
+    let format = match (format, output) {
+        (Some(format), _) => format,
+        (None, Some(output_path)) => {
+            dither(image, args)?.save(output_path)?;
+            return Ok(());
+        }
+        (None, None) => OutputFormat::Png,
+    };
+
+    if let OutputFormat::Ico = format {
+        let mut frames = Vec::new();
+        for &size in &args.ico_sizes {
+            let dithered_image = dither_resized(image, size, size, args)?;
+            frames.push(IcoFrame::as_png(
+                &dithered_image,
+                size,
+                size,
+                image::ExtendedColorType::Rgba8,
+            )?);
+        }
+
+        if let Some(output_path) = output {
+            IcoEncoder::new(std::fs::File::create(output_path)?).encode_images(&frames)?;
+        } else {
+            let mut stdout = std::io::stdout();
+            IcoEncoder::new(&mut stdout).encode_images(&frames)?;
+            stdout.flush()?;
+        }
+        return Ok(());
+    }
+
+    if let OutputFormat::P5js = format {
+        let (width, height) = image.dimensions();
+        let dithered_image = if width > P5JS_MAX_SIZE || height > P5JS_MAX_SIZE {
+            dither(
+                &image.resize(P5JS_MAX_SIZE, P5JS_MAX_SIZE, FilterType::Lanczos3),
+                args,
+            )?
+        } else {
+            dither(image, args)?
+        };
+        let sketch = p5js_sketch(&dithered_image);
+
+        if let Some(output_path) = output {
+            std::fs::write(output_path, sketch)?;
+        } else {
+            print!("{}", sketch);
+        }
+        return Ok(());
+    }
+
+    if let OutputFormat::Ansi = format {
+        let columns = terminal_columns();
+        let dithered_image = if image.width() > columns {
+            dither(&image.resize(columns, u32::MAX, FilterType::Lanczos3), args)?
+        } else {
+            dither(image, args)?
+        };
+        let preview = ansi_preview(&dithered_image);
+
+        if let Some(output_path) = output {
+            std::fs::write(output_path, preview)?;
+        } else {
+            print!("{}", preview);
+        }
+        return Ok(());
+    }
+
+    if let OutputFormat::Ascii = format {
+        let max_width = terminal_columns() * ASCII_CELL_WIDTH;
+        let dithered_image = if image.width() > max_width {
+            dither(
+                &image.resize(max_width, u32::MAX, FilterType::Lanczos3),
+                args,
+            )?
+        } else {
+            dither(image, args)?
+        };
+        let art = ascii_art(&dithered_image, &args.charset);
+
+        if let Some(output_path) = output {
+            std::fs::write(output_path, art)?;
+        } else {
+            print!("{}", art);
+        }
+        return Ok(());
+    }
+
+    if let OutputFormat::Svg = format {
+        let svg = svg_document(&dither(image, args)?, &args.svg_dot);
+
+        if let Some(output_path) = output {
+            std::fs::write(output_path, svg)?;
+        } else {
+            print!("{}", svg);
+        }
+        return Ok(());
+    }
+
+    if let OutputFormat::Pbm | OutputFormat::Xbm | OutputFormat::Raw | OutputFormat::CArray = format
+    {
+        let dithered_image = dither(image, args)?;
+        let name = output
+            .and_then(|path| path.file_stem())
+            .map_or("image".into(), |stem| stem.to_string_lossy());
+        let bytes = match format {
+            OutputFormat::Pbm => pbm_bytes(&dithered_image),
+            OutputFormat::Xbm => xbm_source(&dithered_image, &name).into_bytes(),
+            OutputFormat::CArray => {
+                c_array_source(&dithered_image, &name, &args.c_array_layout).into_bytes()
+            }
+            _ => pack_pixels(
+                &white_pixels(&dithered_image),
+                dithered_image.width(),
+                1,
+                &args.bit_order,
+            ),
+        };
+
+        if let Some(output_path) = output {
+            std::fs::write(output_path, bytes)?;
+        } else {
+            let mut stdout = std::io::stdout();
+            stdout.write_all(&bytes)?;
+            stdout.flush()?;
+        }
+        return Ok(());
+    }
+
+    let dithered_image = dither(image, args)?;
+
+    if args.clipboard_out {
+        let mut png_bytes = Vec::new();
+        write_png(
+            &mut png_bytes,
+            &dithered_image,
+            &args.output_depth,
+            args.palette.as_ref(),
+            &args.output_colorspace,
+            metadata,
+        )?;
+        copy_image(&png_bytes)?;
+    } else if let OutputFormat::DataUri(encoding) = &format {
+        let mut bytes = Vec::new();
+        write_raster(&mut bytes, &dithered_image, encoding, args, metadata)?;
+        let data_uri = format!(
+            "data:{};base64,{}",
+            encoding.mime_type(),
+            encode_base64(&bytes)
+        );
+
+        if let Some(output_path) = output {
+            std::fs::write(output_path, data_uri)?;
+        } else {
+            println!("{}", data_uri);
+        }
+    } else if let Some(output_path) = output {
+        write_raster(
+            BufWriter::new(std::fs::File::create(output_path)?),
+            &dithered_image,
+            &format,
+            args,
+            metadata,
+        )?;
+    } else {
+        let mut stdout = std::io::stdout();
+        write_raster(&mut stdout, &dithered_image, &format, args, metadata)?;
+        stdout.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Returns the image on the system clipboard, encoded as PNG.
+fn paste_image() -> Result<Vec<u8>, Box<dyn Error>> {
+    #[cfg(feature = "clipboard-test-hook")]
+    if let Some(command) = clipboard_command("DITHERER_CLIPBOARD_PASTE")? {
+        return paste_with_command(command);
+    }
+    #[cfg(feature = "clipboard")]
+    {
+        clipboard::paste()
+    }
+    #[cfg(not(feature = "clipboard"))]
+    Err(clipboard_unsupported())
+}
+
+/// Puts PNG bytes on the system clipboard.
+fn copy_image(png_bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+    #[cfg(feature = "clipboard-test-hook")]
+    if let Some(command) = clipboard_command("DITHERER_CLIPBOARD_COPY")? {
+        return copy_with_command(command, png_bytes);
+    }
+    #[cfg(feature = "clipboard")]
+    {
+        clipboard::copy(png_bytes)
+    }
+    #[cfg(not(feature = "clipboard"))]
+    {
+        let _ = png_bytes;
+        Err(clipboard_unsupported())
+    }
+}
+
+/// The command in `variable` that stands in for the system clipboard, if set. Only builds with
+/// the `clipboard-test-hook` feature read it, so the tests can exchange PNG bytes through files
+/// instead of a clipboard they don't have.
+#[cfg(feature = "clipboard-test-hook")]
+fn clipboard_command(variable: &str) -> Result<Option<std::process::Command>, Box<dyn Error>> {
+    let Ok(command) = std::env::var(variable) else {
+        return Ok(None);
+    };
+    let words: Vec<&str> = command.split_whitespace().collect();
+    let (program, arguments) = words
+        .split_first()
+        .ok_or_else(|| format!("{} is empty", variable))?;
+    let mut command = std::process::Command::new(program);
+    command.args(arguments);
+    Ok(Some(command))
+}
+
+#[cfg(feature = "clipboard-test-hook")]
+fn paste_with_command(mut command: std::process::Command) -> Result<Vec<u8>, Box<dyn Error>> {
+    let output = command
+        .stdin(std::process::Stdio::null())
+        .stderr(std::process::Stdio::inherit())
+        .output()?;
+    if !output.status.success() || output.stdout.is_empty() {
+        return Err(CliError::Io("the clipboard does not hold an image".to_string()).into());
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(feature = "clipboard-test-hook")]
+fn copy_with_command(
+    mut command: std::process::Command,
+    png_bytes: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    let mut child = command
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::inherit())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .ok_or("cannot write to the clipboard command")?
+        .write_all(png_bytes)?;
+    if !child.wait()?.success() {
+        return Err(CliError::Io("copying to the clipboard failed".to_string()).into());
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn clipboard_unsupported() -> Box<dyn Error> {
+    CliError::Io(
+        "the clipboard is not available: ditherer was built without the clipboard feature"
+            .to_string(),
+    )
+    .into()
+}
+
+fn dither_for_profile(
+    image: &DynamicImage,
+    output: &Path,
+    profile: &DeviceProfile,
+    args: &DithererArgs,
+) -> Result<(), Box<dyn Error>> {
+    let (width, height) = profile.resolution();
+    let rotated_image;
+    let image = if image.height() > image.width() && width > height {
+        rotated_image = image.rotate90();
+        &rotated_image
+    } else {
+        image
+    };
+
+    let levels = 1u32 << profile.bits();
+    let args = DithererArgs {
+        levels,
+        ..args.clone()
+    };
+    let dithered_image = dither_resized(image, width, height, &args)?;
+    let values: Vec<u8> = dithered_image
+        .pixels()
+        .map(|pixel| ((composite_over_white(pixel) as u32 * (levels - 1) + 127) / 255) as u8)
+        .collect();
+
+    let preview_path = output.with_extension("png");
+    let raw_path = if preview_path == output {
+        output.with_extension("bin")
+    } else {
+        output.to_path_buf()
+    };
+    std::fs::write(
+        raw_path,
+        pack_pixels(&values, width, profile.bits(), &BitOrder::Msb),
+    )?;
+    write_png(
+        std::fs::File::create(preview_path)?,
+        &dithered_image,
+        &args.output_depth,
+        None,
+        &args.output_colorspace,
+        None,
+    )
+}
+
+fn is_gif(path: &Path) -> bool {
+    ImageFormat::from_path(path).ok() == Some(ImageFormat::Gif)
+}
+
+fn dither_animation(
+    input: &Path,
+    output: &Path,
+    args: &DithererArgs,
+) -> Result<(), Box<dyn Error>> {
+    let bytes = std::fs::read(input)?;
+    let repeat = match gif::DecodeOptions::new()
+        .read_info(Cursor::new(&bytes))?
+        .repeat()
+    {
+        gif::Repeat::Finite(count) => Repeat::Finite(count),
+        gif::Repeat::Infinite => Repeat::Infinite,
+    };
+
+    let decoded_frames = GifDecoder::new(Cursor::new(&bytes))?
+        .into_frames()
+        .collect_frames()?;
+    let mut progress = Progress::new(Some(decoded_frames.len()), args);
+    let mut frames = Vec::new();
+    let mut sequence = FrameSequence::default();
+    for (index, frame) in decoded_frames.into_iter().enumerate() {
+        let started = Instant::now();
+        let delay = frame.delay();
+        let frame_image = preprocess(DynamicImage::ImageRgba8(frame.into_buffer()), args);
+        let dithered_image = sequence.dither(&frame_image, args)?;
+        frames.push(Frame::from_parts(dithered_image, 0, 0, delay));
+        progress.item(&format!("frame {}", index + 1), started, true);
+    }
+    progress.finish();
+
+    let mut encoder = GifEncoder::new(std::fs::File::create(output)?);
+    encoder.set_repeat(repeat)?;
+    encoder.encode_frames(frames)?;
+    Ok(())
+}
+
+fn export_threshold_map(output: Option<&Path>, args: &DithererArgs) -> Result<(), Box<dyn Error>> {
+    if args.algorithm.diffusion_kernel().is_some() && args.threshold_map.is_none() {
+        return Err(CliError::InvalidOptions(
+            "--export-threshold-map only supports ordered algorithms: bayer, interference, blue-noise, halftone"
+                .to_string(),
+        )
+        .into());
+    }
+
+    let threshold_image = threshold_matrix(args, EXPORTED_NOISE_SIZE, EXPORTED_NOISE_SIZE)?
+        .with_strength(args.strength)
+        .with_display_gamma(args.display_gamma)
+        .to_image();
+    let mut png_bytes = Vec::new();
+    threshold_image.write_to(&mut Cursor::new(&mut png_bytes), ImageFormat::Png)?;
+
+    if let Some(output_path) = output {
+        std::fs::write(output_path, png_bytes)?;
+    } else {
+        let mut stdout = std::io::stdout();
+        stdout.write_all(&png_bytes)?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+/// Why `--tile-height` cannot dither with `algorithm`, if it cannot.
+fn unsupported_in_strips(algorithm: &Algorithm) -> Option<&'static str> {
+    if algorithm.diffusion_kernel().is_some() {
+        return Some(
+            "--tile-height only supports ordered algorithms: bayer, interference, blue-noise, halftone",
+        );
+    }
+    if let Algorithm::Random | Algorithm::Ign = algorithm {
+        return Some("--tile-height does not support the image-sized random and ign patterns");
+    }
+    None
+}
+
+/// Builds an image from rows that the `png` crate decoded with `normalize_to_color8`.
+fn png_rows_image(
+    color_type: png::ColorType,
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+) -> Result<DynamicImage, CliError> {
+    match color_type {
+        png::ColorType::Grayscale => {
+            ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8)
+        }
+        png::ColorType::GrayscaleAlpha => {
+            ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8)
+        }
+        png::ColorType::Rgb => {
+            ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8)
+        }
+        png::ColorType::Rgba | png::ColorType::Indexed => {
+            ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8)
+        }
+    }
+    .ok_or_else(|| CliError::Decode("PNG row has an unexpected length".to_string()))
+}
+
+fn dither_tiled(
+    input: Option<&Path>,
+    output: Option<&Path>,
+    tile_height: u32,
+    args: &DithererArgs,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(message) = unsupported_in_strips(&args.algorithm) {
+        return Err(CliError::InvalidOptions(message.to_string()).into());
+    }
+    if let Some(output_path) = output {
+        if ImageFormat::from_path(output_path).ok() != Some(ImageFormat::Png) {
+            return Err(CliError::UnsupportedFormat(
+                "--tile-height only writes PNG output".to_string(),
+            )
+            .into());
+        }
+    }
+
+    let input_reader: Box<dyn Read> = match input {
+        Some(input_path) => Box::new(BufReader::new(std::fs::File::open(input_path)?)),
+        None => Box::new(std::io::stdin().lock()),
+    };
+    let mut decoder = png::Decoder::new(input_reader);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    if reader.info().interlaced {
+        return Err(CliError::UnsupportedFormat(
+            "--tile-height does not support interlaced PNG input".to_string(),
+        )
+        .into());
+    }
+    let (width, height) = (reader.info().width, reader.info().height);
+    let (color_type, _) = reader.output_color_type();
+
+    let output_writer: Box<dyn Write> = match output {
+        Some(output_path) => Box::new(BufWriter::new(std::fs::File::create(output_path)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let metadata = output_metadata(None, args)?;
+    let mut encoder = png_encoder(
+        output_writer,
+        width,
+        height,
+        &args.output_colorspace,
+        metadata.as_ref(),
+    )?;
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut stream = encoder.write_header()?.into_stream_writer()?;
+
+    let mut first_row = 0;
+    while first_row < height {
+        let rows = tile_height.min(height - first_row);
+        let mut strip = Vec::new();
+        for _ in 0..rows {
+            let row = reader
+                .next_row()?
+                .ok_or_else(|| CliError::Decode("PNG input ended early".to_string()))?;
+            strip.extend_from_slice(row.data());
+        }
+
+        let strip_image = png_rows_image(color_type, width, rows, strip)?;
+        let dithered_strip = dither_strip(&preprocess(strip_image, args), args, first_row, 0)?;
+        stream.write_all(&to_output_colorspace(
+            &dithered_strip,
+            &args.output_colorspace,
+        ))?;
+        first_row += rows;
+    }
+
+    stream.finish()?;
+    Ok(())
+}
+
+fn dither_frames(args: &DithererArgs) -> Result<(), Box<dyn Error>> {
+    let mut reader = std::io::stdin().lock();
+    let mut stdout = std::io::stdout().lock();
+    let mut progress = Progress::new(None, args);
+    let mut sequence = FrameSequence::default();
+
+    while let Some(&first_byte) = reader.fill_buf()?.first() {
+        let started = Instant::now();
+        if let Some((width, height)) = args.size {
+            let mut pixels = vec![0; width as usize * height as usize * 3];
+            reader.read_exact(&mut pixels)?;
+            let frame = ImageBuffer::from_raw(width, height, pixels)
+                .map(DynamicImage::ImageRgb8)
+                .ok_or_else(|| {
+                    CliError::Decode("raw frame has an unexpected length".to_string())
+                })?;
+            let dithered_frame = sequence.dither(&preprocess(frame, args), args)?;
+            stdout.write_all(&DynamicImage::ImageRgba8(dithered_frame).to_rgb8())?;
+        } else if first_byte == PNG_SIGNATURE[0] {
+            let frame = image::load_from_memory_with_format(
+                &read_png_frame(&mut reader)?,
+                ImageFormat::Png,
+            )?;
+            let dithered_frame = sequence.dither(&preprocess(frame, args), args)?;
+            write_png(
+                &mut stdout,
+                &dithered_frame,
+                &args.output_depth,
+                args.palette.as_ref(),
+                &args.output_colorspace,
+                None,
+            )?;
+        } else if first_byte == b'P' {
+            let frame = read_ppm_frame(&mut reader)?;
+            let dithered_frame = sequence.dither(&preprocess(frame, args), args)?;
+            let (width, height) = dithered_frame.dimensions();
+            write!(stdout, "P6\n{} {}\n255\n", width, height)?;
+            stdout.write_all(&DynamicImage::ImageRgba8(dithered_frame).to_rgb8())?;
+        } else {
+            return Err(CliError::Decode(
+                "unrecognized frame: expected PNG or binary PPM (P6), or raw RGB with --size"
+                    .to_string(),
+            )
+            .into());
+        }
+
+        stdout.flush()?;
+        progress.item(&format!("frame {}", progress.done + 1), started, true);
+    }
+    progress.finish();
+
+    Ok(())
+}
+
+fn read_png_frame<R: BufRead>(reader: &mut R) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut bytes = vec![0; PNG_SIGNATURE.len()];
+    reader.read_exact(&mut bytes)?;
+    if bytes != PNG_SIGNATURE {
+        return Err(CliError::Decode("invalid PNG signature in frame stream".to_string()).into());
+    }
+
+    loop {
+        let mut header = [0; 8];
+        reader.read_exact(&mut header)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        bytes.extend_from_slice(&header);
+
+        let start = bytes.len();
+        bytes.resize(start + length + 4, 0);
+        reader.read_exact(&mut bytes[start..])?;
+
+        if &header[4..] == b"IEND" {
+            return Ok(bytes);
+        }
+    }
+}
+
+fn read_ppm_frame<R: BufRead>(reader: &mut R) -> Result<DynamicImage, Box<dyn Error>> {
+    let mut fields = Vec::new();
+    let mut byte = [0];
+    while fields.len() < 4 {
+        let mut field = String::new();
+        loop {
+            reader.read_exact(&mut byte)?;
+            match byte[0] {
+                b'#' => {
+                    reader.read_until(b'\n', &mut Vec::new())?;
+                }
+                value if value.is_ascii_whitespace() => {
+                    if !field.is_empty() {
+                        break;
+                    }
+                }
+                value => field.push(value as char),
+            }
+        }
+        fields.push(field);
+    }
+
+    let invalid_header = || format!("invalid PPM frame header: {}", fields.join(" "));
+    if fields[0] != "P6" || fields[3] != "255" {
+        return Err(CliError::UnsupportedFormat(format!(
+            "{} (only 8-bit binary P6 is supported)",
+            invalid_header()
+        ))
+        .into());
+    }
+    let width: u32 = fields[1].parse().map_err(|_| invalid_header())?;
+    let height: u32 = fields[2].parse().map_err(|_| invalid_header())?;
+
+    let mut pixels = vec![0; width as usize * height as usize * 3];
+    reader.read_exact(&mut pixels)?;
+    Ok(DynamicImage::ImageRgb8(
+        ImageBuffer::from_raw(width, height, pixels).ok_or_else(invalid_header)?,
+    ))
+}
+
+/// Decodes `reader`, keeping within `--memory-limit`. The cost is estimated from the header: an
+/// input too large to decode and dither in full is downscaled while decoding if it is a PNG, and
+/// decoded in full and then downscaled if the decoded input alone fits.
+fn decode<R: BufRead + Seek>(
+    reader: ImageReader<R>,
+    args: &DithererArgs,
+) -> Result<DynamicImage, Box<dyn Error>> {
+    let Some(memory_limit) = args.memory_limit.map(|megabytes| megabytes * 1024 * 1024) else {
+        return decode_full(reader, None, args.assume_srgb);
+    };
+
+    let format = reader.format();
+    let mut input = reader.into_inner();
+    let start = input.stream_position()?;
+    let header = match format {
+        Some(format) => ImageReader::with_format(&mut input, format),
+        None => ImageReader::new(&mut input),
+    }
+    .into_decoder()?;
+    let (width, height) = header.dimensions();
+    let input_bytes_per_pixel = header.color_type().bytes_per_pixel() as u64;
+    drop(header);
+    input.seek(SeekFrom::Start(start))?;
+    let reader = match format {
+        Some(format) => ImageReader::with_format(input, format),
+        None => ImageReader::new(input),
+    };
+
+    let pixels = width as u64 * height as u64;
+    let bytes_per_pixel = input_bytes_per_pixel + DITHER_BYTES_PER_PIXEL;
+    if pixels * bytes_per_pixel <= memory_limit {
+        return decode_full(reader, Some(memory_limit), args.assume_srgb);
+    }
+
+    let streamed = format == Some(ImageFormat::Png);
+    let input_bytes = if streamed {
+        0
+    } else {
+        pixels * input_bytes_per_pixel
+    };
+    let budget_pixels = memory_limit.saturating_sub(input_bytes) / bytes_per_pixel;
+    if budget_pixels == 0 {
+        return Err(CliError::Decode(format!(
+            "The decoded input alone needs {} MB, more than the --memory-limit; only PNG input is downscaled while decoding",
+            input_bytes.div_ceil(1024 * 1024)
+        ))
+        .into());
+    }
+
+    let scale = (budget_pixels as f64 / pixels as f64).sqrt();
+    let target_width = ((width as f64 * scale) as u32).max(1);
+    let target_height = ((height as f64 * scale) as u32).max(1);
+    if args.verbose {
+        eprintln!(
+            "Downscaling {}x{} to {}x{} {} to stay within the memory limit",
+            width,
+            height,
+            target_width,
+            target_height,
+            if streamed {
+                "while decoding"
+            } else {
+                "after decoding"
+            }
+        );
+    }
+
+    if streamed {
+        decode_png_downscaled(
+            reader.into_inner(),
+            target_width,
+            target_height,
+            args.assume_srgb,
+        )
+    } else {
+        let image = decode_full(reader, Some(memory_limit), args.assume_srgb)?;
+        Ok(image.resize_exact(target_width, target_height, FilterType::Triangle))
+    }
+}
+
+fn decode_full<R: BufRead + Seek>(
+    mut reader: ImageReader<R>,
+    memory_limit: Option<u64>,
+    assume_srgb: bool,
+) -> Result<DynamicImage, Box<dyn Error>> {
+    if let Some(memory_limit) = memory_limit {
+        let mut limits = Limits::default();
+        limits.max_alloc = Some(memory_limit);
+        reader.limits(limits);
+    }
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let icc_profile = if assume_srgb {
+        None
+    } else {
+        decoder.icc_profile()?
+    };
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok(convert_input_profile(image, icc_profile.as_deref()))
+}
+
+/// Converts `image` from its embedded ICC profile, if any, to sRGB.
+fn convert_input_profile(image: DynamicImage, icc_profile: Option<&[u8]>) -> DynamicImage {
+    match icc_profile.map(IccProfile::parse) {
+        Some(Ok(profile)) if !profile.is_srgb() => {
+            DynamicImage::ImageRgba8(convert_to_srgb(&image, &profile))
+        }
+        Some(Err(error)) => {
+            warn(format_args!("{} Treating the input as sRGB.", error));
+            image
+        }
+        _ => image,
+    }
+}
+
+/// Input metadata that `--keep-metadata` carries over to the output.
+#[derive(Debug, Default)]
+struct Metadata {
+    icc_profile: Option<Vec<u8>>,
+    exif: Option<Vec<u8>>,
+    png_text: Vec<TEXtChunk>,
+    png_compressed_text: Vec<ZTXtChunk>,
+    png_international_text: Vec<ITXtChunk>,
+}
+
+fn keep_metadata(
+    input: Option<&Path>,
+    args: &DithererArgs,
+) -> Result<Option<Metadata>, Box<dyn Error>> {
+    if args.strip_metadata {
+        return Ok(None);
+    }
+
+    match input {
+        Some(input) if args.keep_metadata => {
+            let mut metadata = read_metadata(input)?;
+            // Converted pixels are sRGB now, so the source profile no longer describes them.
+            let converted = metadata
+                .icc_profile
+                .as_deref()
+                .is_some_and(|icc_profile| IccProfile::parse(icc_profile).is_ok());
+            if converted && !args.assume_srgb {
+                metadata.icc_profile = None;
+            }
+            Ok(Some(metadata))
+        }
+        None if args.keep_metadata => {
+            warn("--keep-metadata needs an input file, no metadata is copied from stdin");
+            Ok(None)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// The metadata written to PNG output: what `--keep-metadata` copies from `input`, and the
+/// `ditherer:params` chunk for `--reapply`. `--strip-metadata` leaves out both.
+fn output_metadata(
+    input: Option<&Path>,
+    args: &DithererArgs,
+) -> Result<Option<Metadata>, Box<dyn Error>> {
+    if args.strip_metadata {
+        return Ok(None);
+    }
+
+    let mut metadata = keep_metadata(input, args)?.unwrap_or_default();
+    metadata
+        .png_text
+        .retain(|chunk| chunk.keyword != PARAMS_KEYWORD);
+    metadata
+        .png_international_text
+        .retain(|chunk| chunk.keyword != PARAMS_KEYWORD);
+    let params = args.params.join("\n");
+    // tEXt only holds Latin-1, so a path or color name outside it goes into an iTXt chunk.
+    if params.chars().all(|c| (c as u32) < 256) {
+        metadata
+            .png_text
+            .push(TEXtChunk::new(PARAMS_KEYWORD, params));
+    } else {
+        metadata
+            .png_international_text
+            .push(ITXtChunk::new(PARAMS_KEYWORD, params));
+    }
+    Ok(Some(metadata))
+}
+
+fn read_metadata(input: &Path) -> Result<Metadata, Box<dyn Error>> {
+    let mut decoder = ImageReader::open(input)?
+        .with_guessed_format()?
+        .into_decoder()?;
+    let mut metadata = Metadata {
+        icc_profile: decoder.icc_profile()?,
+        exif: decoder.exif_metadata()?,
+        ..Metadata::default()
+    };
+    // The pixels were already rotated upright on load, so viewers must not rotate them again.
+    if let Some(exif) = &mut metadata.exif {
+        reset_exif_orientation(exif);
+    }
+
+    if ImageFormat::from_path(input).ok() == Some(ImageFormat::Png) {
+        let reader = png::Decoder::new(BufReader::new(std::fs::File::open(input)?)).read_info()?;
+        let info = reader.info();
+        metadata.png_text = info.uncompressed_latin1_text.clone();
+        metadata.png_compressed_text = info.compressed_latin1_text.clone();
+        metadata.png_international_text = info.utf8_text.clone();
+    }
+
+    Ok(metadata)
+}
+
+/// Sets the orientation tag of a raw EXIF (TIFF) block to 1, "upright", if it has one.
+fn reset_exif_orientation(exif: &mut [u8]) {
+    let read_u16 = |exif: &[u8], offset: usize, big_endian: bool| -> Option<u16> {
+        let bytes = [*exif.get(offset)?, *exif.get(offset + 1)?];
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let big_endian = match exif.get(..4) {
+        Some([b'I', b'I', 42, 0]) => false,
+        Some([b'M', b'M', 0, 42]) => true,
+        _ => return,
+    };
+    let Some(ifd_bytes) = exif.get(4..8) else {
+        return;
+    };
+    let ifd_bytes = [ifd_bytes[0], ifd_bytes[1], ifd_bytes[2], ifd_bytes[3]];
+    let ifd = if big_endian {
+        u32::from_be_bytes(ifd_bytes)
+    } else {
+        u32::from_le_bytes(ifd_bytes)
+    } as usize;
+    let Some(entries) = read_u16(exif, ifd, big_endian) else {
+        return;
+    };
+
+    for entry in 0..entries as usize {
+        let offset = ifd + 2 + entry * 12;
+        if read_u16(exif, offset, big_endian) == Some(EXIF_ORIENTATION_TAG)
+            && read_u16(exif, offset + 2, big_endian) == Some(EXIF_SHORT)
+        {
+            let upright = if big_endian {
+                1u16.to_be_bytes()
+            } else {
+                1u16.to_le_bytes()
+            };
+            if let Some(value) = exif.get_mut(offset + 8..offset + 10) {
+                value.copy_from_slice(&upright);
+            }
+            return;
+        }
+    }
+}
+
+/// Decodes a PNG row by row into a `width`x`height` box-filtered copy, so that the full-size
+/// image is never held in memory.
+fn decode_png_downscaled<R: BufRead + Seek>(
+    input: R,
+    width: u32,
+    height: u32,
+    assume_srgb: bool,
+) -> Result<DynamicImage, Box<dyn Error>> {
+    let mut decoder = png::Decoder::new(input);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    if reader.info().interlaced {
+        return Err(CliError::UnsupportedFormat(
+            "--memory-limit cannot downscale interlaced PNG input while decoding".to_string(),
+        )
+        .into());
+    }
+    let (source_width, source_height) = (reader.info().width, reader.info().height);
+    let (color_type, _) = reader.output_color_type();
+    let channels = color_type.samples();
+
+    // Source column x and row y fall into output column x * width / source_width and row
+    // y * height / source_height; each output pixel averages the source pixels that fall into it.
+    let column = |x: u32| (x as u64 * width as u64 / source_width as u64) as usize;
+    let mut columns = vec![0u32; width as usize];
+    for x in 0..source_width {
+        columns[column(x)] += 1;
+    }
+
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * channels);
+    let mut sums = vec![0u32; width as usize * channels];
+    let mut rows = 0;
+    for y in 0..source_height {
+        let row = reader
+            .next_row()?
+            .ok_or_else(|| CliError::Decode("PNG input ended early".to_string()))?;
+        for (x, pixel) in row.data().chunks_exact(channels).enumerate() {
+            let start = column(x as u32) * channels;
+            for (sum, &value) in sums[start..start + channels].iter_mut().zip(pixel) {
+                *sum += value as u32;
+            }
+        }
+        rows += 1;
+
+        let output_row = y as u64 * height as u64 / source_height as u64;
+        let next_row = (y + 1) as u64 * height as u64 / source_height as u64;
+        if next_row != output_row || y + 1 == source_height {
+            for (index, sum) in sums.iter_mut().enumerate() {
+                let count = columns[index / channels] * rows;
+                pixels.push(((*sum + count / 2) / count) as u8);
+                *sum = 0;
+            }
+            rows = 0;
+        }
+    }
+
+    let icc_profile = if assume_srgb {
+        None
+    } else {
+        reader.info().icc_profile.clone()
+    };
+    let image = png_rows_image(color_type, width, height, pixels)?;
+    Ok(convert_input_profile(image, icc_profile.as_deref()))
+}
+
+/// Picks the `--tile-height` strip path for `--memory-limit` when dithering `input` in full would
+/// exceed the budget and strips can handle it: PNG in and out and an ordered algorithm. Returns
+/// the number of rows per strip that fits the budget.
+fn memory_limit_strips(
+    input: &Path,
+    output: Option<&Path>,
+    memory_limit: u64,
+    args: &DithererArgs,
+) -> Result<Option<u32>, Box<dyn Error>> {
+    let strips_supported = unsupported_in_strips(&args.algorithm).is_none()
+        && output
+            .is_none_or(|output| ImageFormat::from_path(output).ok() == Some(ImageFormat::Png))
+        && !(args.resize.is_some()
+            || args.scale.is_some()
+            || args.adaptive
+            || args.auto_levels_per_channel
+            || args.diff.is_some()
+            || args.matrix_gradient.is_some()
+            || args.colors.is_some()
+            || args.format.is_some()
+            || args.output_depth != OutputDepth::Auto
+            || args.report_colors
+            || args.check_seamless
+            || args.sample_point.is_some()
+            || args.metrics
+            || args.mask.is_some()
+            || args.cmyk
+            || args.keep_metadata
+            || args.clipboard_in
+            || args.clipboard_out
+            || args.bench
+            || args.profile.is_some()
+            || args.pixelate > 1
+            || args.pattern_scale != 1.0
+            || args.pattern_angle != 0.0
+            || args.jitter > 0.0);
+    if !strips_supported
+        || ImageReader::open(input)?.with_guessed_format()?.format() != Some(ImageFormat::Png)
+    {
+        return Ok(None);
+    }
+
+    let decoder = png::Decoder::new(BufReader::new(std::fs::File::open(input)?));
+    let reader = decoder.read_info()?;
+    let (width, height) = (reader.info().width as u64, reader.info().height as u64);
+    let memory_limit = memory_limit * 1024 * 1024;
+    // Rows are decoded to at most 4 bytes per pixel by `normalize_to_color8`.
+    let row_bytes = width * (4 + DITHER_BYTES_PER_PIXEL);
+    let rows = memory_limit / row_bytes.max(1);
+    if reader.info().interlaced || height * row_bytes <= memory_limit || rows == 0 {
+        return Ok(None);
+    }
+
+    let tile_height = rows.min(height) as u32;
+    if args.verbose {
+        eprintln!(
+            "Dithering {}x{} in strips of {} rows to stay within the memory limit",
+            width, height, tile_height
+        );
+    }
+    Ok(Some(tile_height))
+}
+
+fn dither(
+    image: &DynamicImage,
+    args: &DithererArgs,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, Box<dyn Error>> {
+    dither_strip(image, args, 0, 0)
+}
+
+/// Dithers the frames of an animation in order, applying `--temporal` and `--stable`.
+#[derive(Default)]
+struct FrameSequence {
+    index: u64,
+    /// The input each pixel of `previous_output` was dithered from.
+    previous_input: Option<ImageBuffer<Rgba<u8>, Vec<u8>>>,
+    previous_output: Option<ImageBuffer<Rgba<u8>, Vec<u8>>>,
+}
+
+impl FrameSequence {
+    fn dither(
+        &mut self,
+        image: &DynamicImage,
+        args: &DithererArgs,
+    ) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, Box<dyn Error>> {
+        let frame = if args.temporal { self.index } else { 0 };
+        let mut dithered_image = dither_strip(image, args, 0, frame)?;
+        self.index += 1;
+        if !args.stable {
+            return Ok(dithered_image);
+        }
+
+        // Unchanged pixels keep the input they were dithered from, so slow drifts still update.
+        let mut input_image = image.to_rgba8();
+        if let (Some(previous_input), Some(previous_output)) =
+            (&self.previous_input, &self.previous_output)
+        {
+            if previous_input.dimensions() == input_image.dimensions()
+                && previous_output.dimensions() == dithered_image.dimensions()
+            {
+                for (x, y, pixel) in dithered_image.enumerate_pixels_mut() {
+                    let previous_pixel = previous_input.get_pixel(x, y);
+                    let unchanged = input_image
+                        .get_pixel(x, y)
+                        .0
+                        .iter()
+                        .zip(previous_pixel.0)
+                        .all(|(&current, previous)| current.abs_diff(previous) <= STABLE_TOLERANCE);
+                    if unchanged {
+                        *pixel = *previous_output.get_pixel(x, y);
+                        input_image.put_pixel(x, y, *previous_pixel);
+                    }
+                }
+            }
+        }
+        self.previous_input = Some(input_image);
+        self.previous_output = Some(dithered_image.clone());
+        Ok(dithered_image)
+    }
+}
+
+fn dither_strip(
+    image: &DynamicImage,
+    args: &DithererArgs,
+    first_row: u32,
+    frame: u64,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, Box<dyn Error>> {
+    if args.pixelate > 1 {
+        return dither_pixelated(image, args, frame);
+    }
+
+    let input_image = image;
+    let alpha_policy = args.alpha.as_ref().filter(|_| image.color().has_alpha());
+
+    let flattened_image;
+    let image = if let Some(alpha_policy) = alpha_policy {
+        flattened_image = DynamicImage::ImageRgba8(flatten_alpha(image, alpha_policy));
+        &flattened_image
+    } else {
+        image
+    };
+
+    let swapped_image;
+    let image = if let Some(channel_swap) = &args.channel_swap {
+        swapped_image = DynamicImage::ImageRgba8(swap_channels(image, channel_swap));
+        &swapped_image
+    } else {
+        image
+    };
+
+    let leveled_image;
+    let image = if args.auto_levels_per_channel {
+        leveled_image =
+            DynamicImage::ImageRgba8(auto_levels_per_channel(image, args.preserve_luminance));
+        &leveled_image
+    } else {
+        image
+    };
+
+    // Saturation is not a light level, so it is never decoded from sRGB to linear.
+    let linear = !args.no_gamma && !matches!(args.channel, ThresholdChannel::Saturation);
+    let tone_curve = tone_curve(args, linear);
+    let mut threshold_matrix = threshold_matrix(args, image.width(), image.height())?;
+    if args.pattern_scale != 1.0 || args.pattern_angle != 0.0 {
+        threshold_matrix = threshold_matrix.transformed(
+            args.pattern_scale,
+            args.pattern_angle,
+            image.width(),
+            image.height(),
+        );
+    }
+    if args.jitter > 0.0 {
+        threshold_matrix =
+            threshold_matrix.with_jitter(args.jitter, args.seed, image.width(), image.height());
+    }
+    let threshold_matrix = threshold_matrix
+        .with_frame_offset(frame)
+        .with_strength(args.strength)
+        .with_display_gamma(args.display_gamma)
+        .with_row_offset(first_row);
+    let diffusion_kernel = args.algorithm.diffusion_kernel().map(|kernel| {
+        kernel
+            .with_strength(args.strength)
+            .with_serpentine(args.serpentine)
+            .with_edge_enhance(args.edge_enhance)
+    });
+    if args.edge_enhance != 0.0 && diffusion_kernel.is_none() {
+        return Err(CliError::InvalidOptions(
+            "--edge-enhance only applies to error-diffusion algorithms".to_string(),
+        )
+        .into());
+    }
+    if args.levels > 2 && diffusion_kernel.is_some() {
+        return Err(CliError::InvalidOptions(
+            "--levels only applies to ordered algorithms".to_string(),
+        )
+        .into());
+    }
+    if (args.pattern_scale != 1.0 || args.pattern_angle != 0.0) && diffusion_kernel.is_some() {
+        return Err(CliError::InvalidOptions(
+            "--pattern-scale and --pattern-angle only apply to ordered algorithms".to_string(),
+        )
+        .into());
+    }
+    if args.jitter > 0.0 && diffusion_kernel.is_some() {
+        return Err(CliError::InvalidOptions(
+            "--jitter only applies to ordered algorithms".to_string(),
+        )
+        .into());
+    }
+    if args.temporal && diffusion_kernel.is_some() {
+        return Err(CliError::InvalidOptions(
+            "--temporal only applies to ordered algorithms".to_string(),
+        )
+        .into());
+    }
+    let gray_image = || {
+        let color = image.color();
+        if color.bytes_per_pixel() > color.channel_count()
+            && matches!(args.grayscale_method, GrayscaleMethod::Luma)
+            && !args.adaptive
+        {
+            // Kept at 16 bits so the grayscale paths threshold at the source's precision.
+            return DynamicImage::ImageLuma16(image.to_luma16());
+        }
+        let gray_image = convert_to_grayscale(image, &args.grayscale_method);
+        if args.adaptive {
+            DynamicImage::ImageLuma8(apply_adaptive_offset(&gray_image, args.window))
+        } else {
+            DynamicImage::ImageLuma8(gray_image)
+        }
+    };
 
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct DithererArgs {
-    #[arg(short = 'i', long, value_name = "INPUT_IMG")]
-    input: Option<PathBuf>,
+    #[cfg(feature = "plugins")]
+    let plugin = args
+        .plugin
+        .as_deref()
+        .and_then(ditherer::registered_algorithm);
+    #[cfg(not(feature = "plugins"))]
+    let plugin: Option<std::sync::Arc<dyn ditherer::DitherAlgorithm>> = None;
 
-    #[arg(short = 'o', long, value_name = "OUTPUT_IMG")]
-    output: Option<PathBuf>,
+    let mut dithered_image = if let Some(plugin) = plugin {
+        luma_to_rgba8(&apply_custom_dithering(
+            &gray_image(),
+            plugin.as_ref(),
+            &tone_curve,
+        ))
+    } else if args.cmyk {
+        composite_separations(&cmyk_plates(image, args, &tone_curve))
+    } else if let Some(diff) = &args.diff {
+        let dither_side = |(algorithm, matrix_size): &(Algorithm, Option<BayerMatrixOption>)| {
+            // The colors, mask and reports below apply to the difference, not to each side.
+            let mut side_args = args.clone();
+            side_args.diff = None;
+            side_args.fg = None;
+            side_args.bg = None;
+            side_args.mask = None;
+            side_args.check_seamless = false;
+            side_args.algorithm = algorithm.clone();
+            side_args.matrix_size = matrix_size.clone().unwrap_or(args.matrix_size.clone());
+            dither_strip(input_image, &side_args, first_row, frame)
+                .map(|dithered_image| DynamicImage::ImageRgba8(dithered_image).to_luma8())
+        };
+        let (diff_image, differing) = diff_bilevel(&dither_side(&diff.a)?, &dither_side(&diff.b)?);
+        let total = (diff_image.width() as u64 * diff_image.height() as u64).max(1);
+        if !args.quiet {
+            eprintln!(
+                "{} of {} pixels differ ({:.2}%)",
+                differing,
+                total,
+                differing as f64 * 100.0 / total as f64
+            );
+        }
+        luma_to_rgba8(&diff_image)
+    } else if let Some(palette) = args.palette.clone().or_else(|| {
+        args.colors
+            .map(|count| Palette::median_cut(image, count as usize))
+    }) {
+        match &diffusion_kernel {
+            Some(kernel) => apply_palette_error_diffusion(
+                image,
+                &palette,
+                kernel,
+                &tone_curve,
+                !args.no_gamma,
+                &args.color_space,
+            ),
+            None if matches!(args.algorithm, Algorithm::Knoll) => apply_pattern_dithering(
+                image,
+                &palette,
+                &threshold_matrix,
+                &tone_curve,
+                !args.no_gamma,
+                &args.color_space,
+            ),
+            None if matches!(args.algorithm, Algorithm::Yliluoma1) => apply_yliluoma1_dithering(
+                image,
+                &palette,
+                &threshold_matrix,
+                &tone_curve,
+                !args.no_gamma,
+                &args.color_space,
+            ),
+            None if matches!(args.algorithm, Algorithm::Yliluoma2) => apply_yliluoma2_dithering(
+                image,
+                &palette,
+                &threshold_matrix,
+                &tone_curve,
+                !args.no_gamma,
+                &args.color_space,
+            ),
+            None => apply_palette_dithering(
+                image,
+                &palette,
+                &threshold_matrix,
+                &tone_curve,
+                !args.no_gamma,
+                &args.color_space,
+            ),
+        }
+    } else if args.rgb {
+        match &diffusion_kernel {
+            Some(kernel) => apply_error_diffusion_rgb(image, kernel, &tone_curve),
+            None => apply_bayer_dithering_rgb(image, &threshold_matrix, &tone_curve),
+        }
+    } else if let Some(kernel) = &diffusion_kernel {
+        if args.color {
+            let preserve_order = args.preserve_order.clone().unwrap_or(PreserveOrder::Dark);
+            apply_error_diffusion_color(image, kernel, preserve_order, &args.channel, &tone_curve)
+        } else {
+            luma_to_rgba8(&apply_error_diffusion_grayscale(
+                &gray_image(),
+                kernel,
+                &tone_curve,
+            ))
+        }
+    } else if args.color {
+        let preserve_order = args.preserve_order.clone().unwrap_or(PreserveOrder::Dark);
+        apply_bayer_dithering_color(
+            image,
+            &threshold_matrix,
+            preserve_order,
+            &args.channel,
+            &tone_curve,
+        )
+    } else if args.levels > 2 {
+        let preserve_order = args.preserve_order.clone().unwrap_or(PreserveOrder::Dark);
+        luma_to_rgba8(&apply_bayer_dithering_levels(
+            &gray_image(),
+            &threshold_matrix,
+            preserve_order,
+            &tone_curve,
+            args.levels,
+            !args.no_gamma,
+        ))
+    } else if let Algorithm::Fm = args.algorithm {
+        luma_to_rgba8(&apply_fm_screening(
+            &gray_image(),
+            &threshold_matrix,
+            args.cell_size,
+            &tone_curve,
+        ))
+    } else {
+        luma_to_rgba8(&apply_bayer_dithering_grayscale(
+            &gray_image(),
+            &threshold_matrix,
+            &tone_curve,
+        ))
+    };
 
-    #[arg(short, long, value_name = "MATRIX_SIZE")]
-    matrix_size: BayerMatrixOption,
+    if let Some(alpha_policy) = alpha_policy {
+        restore_alpha(
+            &mut dithered_image,
+            input_image,
+            alpha_policy,
+            &threshold_matrix,
+            diffusion_kernel.as_ref(),
+        );
+    }
 
-    #[arg(
-        short,
-        long,
-        help = "Preserve colors using brightness channel dithering"
-    )]
-    color: bool,
+    if args.fg.is_some() || args.bg.is_some() {
+        if args.color {
+            apply_duotone_color(&mut dithered_image, args.fg, args.bg);
+        } else {
+            apply_duotone(
+                &mut dithered_image,
+                args.fg.unwrap_or([0, 0, 0]),
+                args.bg.unwrap_or([255, 255, 255]),
+            );
+        }
+    }
 
-    #[arg(
-        short,
-        long,
-        value_name = "PRESERVE_ORDER",
-        help = "Preserve order in 'dark' or 'light' pixels"
-    )]
-    preserve_order: Option<PreserveOrder>,
-}
+    if let Some(mask) = &args.mask {
+        apply_mask(
+            &mut dithered_image,
+            input_image,
+            &image::open(mask)?.to_luma8(),
+        );
+    }
 
-#[derive(Clone, Debug)]
-enum PreserveOrder {
-    Dark,
-    Light,
-}
+    if args.check_seamless {
+        report_seamless(&dithered_image, &threshold_matrix);
+    }
 
-impl FromStr for PreserveOrder {
-    type Err = PreserveOrderParseError;
+    if let Some(sample_point) = &args.sample_point {
+        let gray_image = (!args.color).then(|| gray_image().to_luma8());
+        report_sample_point(
+            sample_point,
+            image,
+            gray_image.as_ref(),
+            &args.channel,
+            &threshold_matrix,
+            &tone_curve,
+            &dithered_image,
+        );
+    }
 
-    fn from_str(input: &str) -> Result<Self, Self::Err> {
-        match input.to_lowercase().as_str() {
-            "dark" => Ok(PreserveOrder::Dark),
-            "light" => Ok(PreserveOrder::Light),
-            _ => Err(PreserveOrderParseError),
-        }
+    if args.metrics {
+        report_metrics(image, &dithered_image, args.metrics_blur);
     }
+
+    Ok(dithered_image)
 }
-#[derive(Debug)]
-struct PreserveOrderParseError;
 
-impl fmt::Display for PreserveOrderParseError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "Invalid preserve order option. Choose from: dark, light."
-        )
-    }
+/// Dithers `image` at 1/`--pixelate` of its size and scales the result back up with
+/// nearest-neighbor, cropping the partial blocks at the right and bottom edges.
+fn dither_pixelated(
+    image: &DynamicImage,
+    args: &DithererArgs,
+    frame: u64,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, Box<dyn Error>> {
+    let (width, height) = image.dimensions();
+    let block = args.pixelate;
+    let small_image = image.resize_exact(
+        width.div_ceil(block),
+        height.div_ceil(block),
+        FilterType::Triangle,
+    );
+    let block_args = DithererArgs {
+        pixelate: 1,
+        ..args.clone()
+    };
+    let dithered_image = dither_strip(&small_image, &block_args, 0, frame)?;
+    Ok(ImageBuffer::from_fn(width, height, |x, y| {
+        *dithered_image.get_pixel(x / block, y / block)
+    }))
 }
 
-impl Error for PreserveOrderParseError {}
+fn tone_curve(args: &DithererArgs, linear: bool) -> [f32; 256] {
+    let mut tone_curve = build_tone_curve(args.contrast_curve, linear);
+    if let Some(dot_gain) = &args.dot_gain {
+        dot_gain.apply_to_tone_curve(&mut tone_curve);
+    }
+    tone_curve
+}
 
-#[derive(Clone, Debug)]
-enum BayerMatrixOption {
-    M2,
-    M4,
-    M8,
+fn cmyk_plates(
+    image: &DynamicImage,
+    args: &DithererArgs,
+    tone_curve: &[f32; 256],
+) -> [GrayImage; 4] {
+    let screens = CMYK_SCREEN_ANGLES.map(|angle| {
+        ThresholdMatrix::halftone_with_shape(args.dot_size, angle, &args.dot_shape)
+            .with_strength(args.strength)
+            .with_display_gamma(args.display_gamma)
+    });
+    cmyk_separations(image, &screens, tone_curve)
 }
 
-impl FromStr for BayerMatrixOption {
-    type Err = BayerMatrixParseError;
+fn write_separations(
+    image: &DynamicImage,
+    output: &Path,
+    args: &DithererArgs,
+) -> Result<(), Box<dyn Error>> {
+    let tone_curve = tone_curve(args, !args.no_gamma);
+    let stem = output
+        .file_stem()
+        .ok_or_else(|| {
+            CliError::InvalidOptions("--separations needs an output file name".to_string())
+        })?
+        .to_string_lossy();
 
-    fn from_str(input: &str) -> Result<Self, Self::Err> {
-        match input.to_lowercase().as_str() {
-            "m2" => Ok(BayerMatrixOption::M2),
-            "m4" => Ok(BayerMatrixOption::M4),
-            "m8" => Ok(BayerMatrixOption::M8),
-            _ => Err(BayerMatrixParseError),
-        }
+    for (plate, name) in cmyk_plates(image, args, &tone_curve)
+        .iter()
+        .zip(["c", "m", "y", "k"])
+    {
+        plate.save(output.with_file_name(format!("{}-{}.png", stem, name)))?;
     }
+    Ok(())
 }
 
-#[derive(Debug)]
-struct BayerMatrixParseError;
+fn threshold_matrix(
+    args: &DithererArgs,
+    width: u32,
+    height: u32,
+) -> Result<ThresholdMatrix, Box<dyn Error>> {
+    Ok(if let Some(threshold_map) = &args.threshold_map {
+        if ImageFormat::from_path(threshold_map).is_ok() {
+            ThresholdMatrix::from_image(&image::open(threshold_map)?)
+        } else {
+            ThresholdMatrix::from_text(&std::fs::read_to_string(threshold_map)?)?
+        }
+    } else if let Some(matrix_pair) = &args.matrix_gradient {
+        ThresholdMatrix::gradient(
+            &ThresholdMatrix::bayer(&matrix_pair.a),
+            &ThresholdMatrix::bayer(&matrix_pair.b),
+            &args.gradient_direction,
+            width,
+            height,
+        )
+    } else {
+        match args.algorithm {
+            Algorithm::Bayer | Algorithm::Knoll | Algorithm::Yliluoma1 | Algorithm::Yliluoma2 => {
+                ThresholdMatrix::bayer(&args.matrix_size)
+            }
+            Algorithm::Interference => ThresholdMatrix::interference(
+                &ThresholdMatrix::bayer(args.matrix_a.as_ref().unwrap_or(&args.matrix_size)),
+                &ThresholdMatrix::bayer(args.matrix_b.as_ref().unwrap_or(&args.matrix_size)),
+                &args.interference_blend,
+            ),
+            Algorithm::BlueNoise | Algorithm::Fm => ThresholdMatrix::blue_noise(args.noise_size),
+            Algorithm::Halftone => {
+                ThresholdMatrix::halftone_with_shape(args.dot_size, args.angle, &args.dot_shape)
+            }
+            Algorithm::Random => match args.seed_grid {
+                Some(grid) => ThresholdMatrix::white_noise_grid(width, height, args.seed, grid),
+                None => ThresholdMatrix::white_noise(width, height, args.seed),
+            },
+            Algorithm::Ign => ThresholdMatrix::interleaved_gradient_noise(width, height),
+            Algorithm::FloydSteinberg
+            | Algorithm::Atkinson
+            | Algorithm::Sierra
+            | Algorithm::SierraLite
+            | Algorithm::Stucki
+            | Algorithm::Burkes
+            | Algorithm::Jjn
+            | Algorithm::Riemersma
+            | Algorithm::Ostromoukhov => ThresholdMatrix::uniform(0.5),
+        }
+    })
+}
 
-impl fmt::Display for BayerMatrixParseError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Invalid Bayer Matrix option. Choose from: m2, m4, m8.")
+fn dither_resized(
+    image: &DynamicImage,
+    width: u32,
+    height: u32,
+    args: &DithererArgs,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, Box<dyn Error>> {
+    let dithered_image = dither(
+        &resize_to_aspect(image, width, height, &args.preserve_aspect),
+        args,
+    )?;
+    if dithered_image.dimensions() == (width, height) {
+        return Ok(dithered_image);
     }
+
+    let mut letterboxed = ImageBuffer::new(width, height);
+    imageops::overlay(
+        &mut letterboxed,
+        &dithered_image,
+        ((width - dithered_image.width()) / 2) as i64,
+        ((height - dithered_image.height()) / 2) as i64,
+    );
+    Ok(letterboxed)
 }
 
-impl Error for BayerMatrixParseError {}
+/// Resizes `image` to `width`x`height` as `--preserve-aspect` asks. `Fit` keeps the aspect ratio
+/// and may come out smaller on one axis; callers that need the exact size letterbox the result.
+fn resize_to_aspect(
+    image: &DynamicImage,
+    width: u32,
+    height: u32,
+    mode: &AspectMode,
+) -> DynamicImage {
+    match mode {
+        AspectMode::Fit => image.resize(width, height, FilterType::Lanczos3),
+        AspectMode::Fill => image.resize_to_fill(width, height, FilterType::Lanczos3),
+        AspectMode::Stretch => image.resize_exact(width, height, FilterType::Lanczos3),
+    }
+}
 
-const BAYER_MATRIX_2X2: [u8; 4] = [0, 2, 3, 1];
-const BAYER_MATRIX_4X4: [u8; 16] = [
-    0, 128, 32, 160, 192, 64, 224, 96, 48, 176, 16, 144, 240, 112, 208, 80,
-];
-const BAYER_MATRIX_8X8: [u8; 64] = [
-    0, 128, 32, 160, 48, 176, 16, 144, 192, 64, 224, 96, 240, 112, 208, 80, 32, 160, 48, 176, 16,
-    144, 32, 160, 160, 96, 224, 64, 240, 80, 192, 128, 48, 176, 16, 144, 32, 160, 48, 176, 176,
-    224, 96, 64, 240, 80, 192, 128, 16, 144, 32, 160, 48, 176, 16, 144, 144, 80, 208, 128, 192,
-    128, 160, 96,
-];
+fn report_seamless(image: &ImageBuffer<Rgba<u8>, Vec<u8>>, threshold_matrix: &ThresholdMatrix) {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return;
+    }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args = DithererArgs::parse();
+    let matching_rows = (0..height)
+        .filter(|&y| image.get_pixel(0, y) == image.get_pixel(width - 1, y))
+        .count();
+    let matching_columns = (0..width)
+        .filter(|&x| image.get_pixel(x, 0) == image.get_pixel(x, height - 1))
+        .count();
+    eprintln!(
+        "Left/right edges match on {} of {} rows, top/bottom edges on {} of {} columns",
+        matching_rows, height, matching_columns, width
+    );
 
-    let image = if let Some(input_path) = args.input {
-        ImageReader::open(input_path)?.decode()?
+    let aligned_width = width % threshold_matrix.width() == 0;
+    let aligned_height = height % threshold_matrix.height() == 0;
+    if aligned_width && aligned_height {
+        eprintln!(
+            "Output tiles seamlessly: {}x{} is a multiple of the {}x{} matrix",
+            width,
+            height,
+            threshold_matrix.width(),
+            threshold_matrix.height()
+        );
     } else {
-        let mut buffer = Vec::new();
-        std::io::stdin().lock().read_to_end(&mut buffer)?;
-        image::load_from_memory(&buffer)?
+        warn(format_args!(
+            "{}x{} is not a multiple of the {}x{} matrix, the dither pattern will break at tile {}",
+            width,
+            height,
+            threshold_matrix.width(),
+            threshold_matrix.height(),
+            match (aligned_width, aligned_height) {
+                (false, false) => "edges",
+                (false, true) => "left/right edges",
+                _ => "top/bottom edges",
+            }
+        ));
+        eprintln!(
+            "Nearest seamless dimensions: {}x{}",
+            nearest_multiple(width, threshold_matrix.width()),
+            nearest_multiple(height, threshold_matrix.height())
+        );
+    }
+}
+
+fn report_metrics(
+    image: &DynamicImage,
+    dithered_image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    blur_sigma: f32,
+) {
+    // Compare what would be seen on a white page, so transparent "off" pixels count as paper.
+    let over_white = |image: &ImageBuffer<Rgba<u8>, Vec<u8>>| {
+        GrayImage::from_fn(image.width(), image.height(), |x, y| {
+            Luma([composite_over_white(image.get_pixel(x, y))])
+        })
     };
+    let metrics = quality_metrics(
+        &over_white(&image.to_rgba8()),
+        &over_white(dithered_image),
+        blur_sigma,
+    );
 
-    let dithered_image = if args.color {
-        let preserve_order = args.preserve_order.unwrap_or(PreserveOrder::Dark);
-        apply_bayer_dithering_color(&image, args.matrix_size, preserve_order)
+    // JSON has no infinity, so identical images report a null PSNR.
+    let psnr = if metrics.psnr.is_finite() {
+        format!("{:.4}", metrics.psnr)
     } else {
-        luma_to_rgba8(&apply_bayer_dithering_grayscale(&image, args.matrix_size))
+        "null".to_string()
     };
+    eprintln!(
+        "{{\"psnr\":{},\"ssim\":{:.6},\"blur\":{}}}",
+        psnr, metrics.ssim, blur_sigma
+    );
+}
 
-    if let Some(output_path) = args.output {
-        dithered_image.save(output_path)?;
-    } else {
-        let mut stdout = std::io::stdout();
-        let encoder = image::codecs::png::PngEncoder::new(&mut stdout);
-        encoder.write_image(
-            &dithered_image,
-            dithered_image.width(),
-            dithered_image.height(),
-            image::ExtendedColorType::Rgba8,
-        )?;
-        stdout.flush()?;
+fn report_sample_point(
+    sample_point: &SamplePoint,
+    image: &DynamicImage,
+    gray_image: Option<&GrayImage>,
+    channel: &ThresholdChannel,
+    threshold_matrix: &ThresholdMatrix,
+    tone_curve: &[f32; 256],
+    dithered_image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+) {
+    let SamplePoint { x, y } = *sample_point;
+    if !image.in_bounds(x, y) {
+        warn(format_args!(
+            "sample point {},{} is outside the {}x{} image",
+            x,
+            y,
+            image.width(),
+            image.height()
+        ));
+        return;
+    }
+
+    let [r, g, b, _] = image.get_pixel(x, y).0;
+    let luminance = match gray_image {
+        Some(gray_image) => gray_image.get_pixel(x, y)[0],
+        None => channel.extract(&[r, g, b]),
+    };
+    let intensity = tone_curve[luminance as usize];
+    let threshold = threshold_matrix.threshold(x, y);
+    let output = dithered_image.get_pixel(x, y);
+    let decision = match gray_image {
+        Some(_) if output[0] == 255 => "white",
+        Some(_) => "black",
+        None if output[3] == 255 => "opaque",
+        None => "transparent",
+    };
+
+    eprintln!("Sample point {},{}:", x, y);
+    eprintln!("  input RGB: {}, {}, {}", r, g, b);
+    eprintln!(
+        "  luminance: {} ({:.4} after tone curve)",
+        luminance, intensity
+    );
+    eprintln!("  threshold: {:.4}", threshold);
+    eprintln!(
+        "  decision:  {} ({:.4} {} {:.4})",
+        decision,
+        intensity,
+        if intensity > threshold { ">" } else { "<=" },
+        threshold
+    );
+}
+
+fn nearest_multiple(value: u32, step: u32) -> u32 {
+    ((value + step / 2) / step).max(1) * step
+}
+
+/// Encodes a dithered image in one of the raster formats; the others are written as PNG.
+fn write_raster<W: Write>(
+    mut writer: W,
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    format: &OutputFormat,
+    args: &DithererArgs,
+    metadata: Option<&Metadata>,
+) -> Result<(), Box<dyn Error>> {
+    let rgb_image = || DynamicImage::ImageRgba8(image.clone()).to_rgb8();
+    let mut bytes = Cursor::new(Vec::new());
+    match format {
+        OutputFormat::Bmp => match requested_bilevel(image, &args.output_depth) {
+            Some(bilevel_image) => write_bmp_1bit(&mut bytes, &bilevel_image)?,
+            None => image.write_to(&mut bytes, ImageFormat::Bmp)?,
+        },
+        OutputFormat::Pnm => match requested_bilevel(image, &args.output_depth) {
+            Some(_) => bytes.write_all(&pbm_bytes(image))?,
+            None => rgb_image().write_with_encoder(
+                PnmEncoder::new(&mut bytes)
+                    .with_subtype(PnmSubtype::Pixmap(SampleEncoding::Binary)),
+            )?,
+        },
+        OutputFormat::Qoi => image.write_to(&mut bytes, ImageFormat::Qoi)?,
+        OutputFormat::Webp => match metadata {
+            Some(metadata) => write_webp(&mut bytes, image, metadata)?,
+            None => image.write_to(&mut bytes, ImageFormat::WebP)?,
+        },
+        OutputFormat::Jpeg => rgb_image().write_to(&mut bytes, ImageFormat::Jpeg)?,
+        OutputFormat::Gif => write_gif(&mut bytes, image, args.palette.as_ref())?,
+        _ => {
+            return write_png(
+                writer,
+                image,
+                &args.output_depth,
+                args.palette.as_ref(),
+                &args.output_colorspace,
+                metadata,
+            );
+        }
+    }
+    writer.write_all(bytes.get_ref())?;
+    Ok(())
+}
+
+fn write_png<W: Write>(
+    writer: W,
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    output_depth: &OutputDepth,
+    palette: Option<&Palette>,
+    colorspace: &OutputColorspace,
+    metadata: Option<&Metadata>,
+) -> Result<(), Box<dyn Error>> {
+    // A kept ICC profile replaces the colorspace tags, so the pixels stay as they are.
+    let image = match metadata.and_then(|metadata| metadata.icc_profile.as_ref()) {
+        Some(_) => Cow::Borrowed(image),
+        None => to_output_colorspace(image, colorspace),
+    };
+    let image = image.as_ref();
+    match output_depth {
+        OutputDepth::Rgba8 => {}
+        OutputDepth::Auto => {
+            if let Some(bilevel_image) = to_bilevel(image) {
+                return write_png_1bit(writer, &bilevel_image, colorspace, metadata);
+            }
+            if let Some((palette, indices)) = to_indexed(image, palette) {
+                return write_png_indexed(writer, image, &palette, &indices, colorspace, metadata);
+            }
+        }
+        OutputDepth::Gray1 => {
+            if let Some(bilevel_image) = to_bilevel(image) {
+                return write_png_1bit(writer, &bilevel_image, colorspace, metadata);
+            }
+            warn("output is not pure black and white, writing RGBA PNG instead");
+        }
+        OutputDepth::Gray8 => {
+            if let Some(gray_pixels) = to_opaque_gray(image) {
+                let mut encoder =
+                    png_encoder(writer, image.width(), image.height(), colorspace, metadata)?;
+                encoder.set_color(png::ColorType::Grayscale);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.write_header()?.write_image_data(&gray_pixels)?;
+                return Ok(());
+            }
+            warn("output is not opaque grayscale, writing RGBA PNG instead");
+        }
+        OutputDepth::Indexed => {
+            if let Some((palette, indices)) = to_indexed(image, palette) {
+                return write_png_indexed(writer, image, &palette, &indices, colorspace, metadata);
+            }
+            warn(format_args!(
+                "output has more than {} colors, writing RGBA PNG instead",
+                PNG_PALETTE_SIZE
+            ));
+        }
     }
 
+    let mut encoder = png_encoder(writer, image.width(), image.height(), colorspace, metadata)?;
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(image)?;
     Ok(())
 }
-fn luma_to_rgba8(luma_img: &ImageBuffer<Luma<u8>, Vec<u8>>) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
-    let (width, height) = luma_img.dimensions();
-    let mut rgba_img = ImageBuffer::new(width, height);
 
-    for (x, y, luma_pixel) in luma_img.enumerate_pixels() {
-        let luma_value = luma_pixel.0[0];
-        rgba_img.put_pixel(x, y, Rgba([luma_value, luma_value, luma_value, 255]));
+/// Converts the sRGB pixels of `image` to the values `colorspace` tags them with: linear light for
+/// `linear`, and the Display P3 primaries with a 2.2 gamma for `display-p3`. Black, white and,
+/// for `linear`, every fully saturated primary come out unchanged.
+fn to_output_colorspace<'a>(
+    image: &'a ImageBuffer<Rgba<u8>, Vec<u8>>,
+    colorspace: &OutputColorspace,
+) -> Cow<'a, ImageBuffer<Rgba<u8>, Vec<u8>>> {
+    if let OutputColorspace::Srgb = colorspace {
+        return Cow::Borrowed(image);
+    }
+
+    let linear: [f32; 256] = std::array::from_fn(|value| srgb_to_linear(value as f32 / 255.0));
+    let encode = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    let mut converted = image.clone();
+    for pixel in converted.pixels_mut() {
+        let [r, g, b] = [0, 1, 2].map(|channel| linear[pixel[channel] as usize]);
+        if let OutputColorspace::DisplayP3 = colorspace {
+            for (channel, row) in pixel.0[..3].iter_mut().zip(SRGB_TO_DISPLAY_P3) {
+                *channel = encode((row[0] * r + row[1] * g + row[2] * b).powf(1.0 / 2.2));
+            }
+        } else {
+            pixel.0[..3].copy_from_slice(&[r, g, b].map(encode));
+        }
+    }
+    Cow::Owned(converted)
+}
+
+fn png_encoder<W: Write>(
+    writer: W,
+    width: u32,
+    height: u32,
+    colorspace: &OutputColorspace,
+    metadata: Option<&Metadata>,
+) -> Result<png::Encoder<'static, W>, png::EncodingError> {
+    let mut info = png::Info::with_size(width, height);
+    if let Some(metadata) = metadata {
+        info.icc_profile = metadata.icc_profile.clone().map(Cow::Owned);
+        info.exif_metadata = metadata.exif.clone().map(Cow::Owned);
+        info.uncompressed_latin1_text = metadata.png_text.clone();
+        info.compressed_latin1_text = metadata.png_compressed_text.clone();
+        info.utf8_text = metadata.png_international_text.clone();
+    }
+    // A kept ICC profile describes the color space by itself, and PNG drops iCCP next to sRGB.
+    let has_icc_profile = info.icc_profile.is_some();
+    let mut encoder = png::Encoder::with_info(writer, info)?;
+    if has_icc_profile {
+        return Ok(encoder);
+    }
+
+    match colorspace {
+        OutputColorspace::Srgb => {
+            encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+        }
+        OutputColorspace::Linear => {
+            encoder.set_source_gamma(png::ScaledFloat::new(1.0));
+            encoder.set_source_chromaticities(png::SourceChromaticities::new(
+                (0.3127, 0.3290),
+                (0.64, 0.33),
+                (0.30, 0.60),
+                (0.15, 0.06),
+            ));
+        }
+        OutputColorspace::DisplayP3 => {
+            encoder.set_source_gamma(png::ScaledFloat::new(1.0 / 2.2));
+            encoder.set_source_chromaticities(png::SourceChromaticities::new(
+                (0.3127, 0.3290),
+                (0.680, 0.320),
+                (0.265, 0.690),
+                (0.150, 0.060),
+            ));
+        }
     }
 
-    rgba_img
+    Ok(encoder)
 }
-fn compute_luminance(pixel: &[u8; 3]) -> u8 {
-    (0.299 * pixel[0] as f64 + 0.587 * pixel[1] as f64 + 0.114 * pixel[2] as f64).clamp(0.0, 255.0)
-        as u8
+
+fn write_webp<W: Write>(
+    writer: W,
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    metadata: &Metadata,
+) -> Result<(), Box<dyn Error>> {
+    let mut encoder = WebPEncoder::new_lossless(writer);
+    if let Some(icc_profile) = &metadata.icc_profile {
+        encoder.set_icc_profile(icc_profile.clone())?;
+    }
+    if metadata.exif.is_some() {
+        warn("EXIF metadata cannot be written to WebP output and was dropped");
+    }
+    image.write_with_encoder(encoder)?;
+    Ok(())
 }
 
-fn apply_bayer_dithering_grayscale(
-    image: &DynamicImage,
-    bayer_option: BayerMatrixOption,
-) -> ImageBuffer<Luma<u8>, Vec<u8>> {
-    let gray_image = image.to_luma8();
-    let (width, height) = gray_image.dimensions();
+fn p5js_sketch(image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> String {
+    let (width, height) = image.dimensions();
+    let mut sketch = String::new();
 
-    let (bayer_matrix, matrix_size): (&[u8], u32) = match bayer_option {
-        BayerMatrixOption::M2 => (&BAYER_MATRIX_2X2, 2),
-        BayerMatrixOption::M4 => (&BAYER_MATRIX_4X4, 4),
-        BayerMatrixOption::M8 => (&BAYER_MATRIX_8X8, 8),
-    };
+    sketch.push_str(&format!("const PIXEL_SIZE = {};\n\n", P5JS_PIXEL_SIZE));
+    sketch.push_str("function setup() {\n");
+    sketch.push_str(&format!(
+        "  createCanvas({} * PIXEL_SIZE, {} * PIXEL_SIZE);\n",
+        width, height
+    ));
+    sketch.push_str("  noStroke();\n  noLoop();\n}\n\n");
+    sketch.push_str("function draw() {\n  background(255);\n  scale(PIXEL_SIZE);\n");
+
+    let mut current_fill = None;
+
+    for (x, y, run, [r, g, b]) in on_runs(image) {
+        if current_fill != Some([r, g, b]) {
+            sketch.push_str(&format!("  fill({}, {}, {});\n", r, g, b));
+            current_fill = Some([r, g, b]);
+        }
+        sketch.push_str(&format!("  rect({}, {}, {}, 1);\n", x, y, run));
+    }
+
+    sketch.push_str("}\n");
+    sketch
+}
+
+fn svg_document(image: &ImageBuffer<Rgba<u8>, Vec<u8>>, svg_dot: &SvgDot) -> String {
+    let (width, height) = image.dimensions();
+    let mut svg = String::new();
+
+    svg.push_str(&format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\n",
+        width, height
+    ));
+    svg.push_str("<rect width=\"100%\" height=\"100%\" fill=\"#ffffff\"/>\n");
+
+    for (x, y, run, [r, g, b]) in on_runs(image) {
+        let fill = format!("#{:02x}{:02x}{:02x}", r, g, b);
+        match svg_dot {
+            SvgDot::Square => svg.push_str(&format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"1\" fill=\"{}\"/>\n",
+                x, y, run, fill
+            )),
+            SvgDot::Circle => {
+                for dot_x in x..x + run {
+                    svg.push_str(&format!(
+                        "<circle cx=\"{}.5\" cy=\"{}.5\" r=\"0.5\" fill=\"{}\"/>\n",
+                        dot_x, y, fill
+                    ));
+                }
+            }
+        }
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
 
-    let mut output_image = GrayImage::new(width, height);
+/// Finds horizontal runs of identical "on" (opaque, non-white) pixels as `(x, y, length, color)`.
+fn on_runs(image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> Vec<(u32, u32, u32, [u8; 3])> {
+    let (width, height) = image.dimensions();
+    let is_on = |pixel: &Rgba<u8>| pixel[3] > 0 && pixel.0[..3] != [255, 255, 255];
+    let mut runs = Vec::new();
 
     for y in 0..height {
-        for x in 0..width {
-            let pixel = gray_image.get_pixel(x, y);
-            let intensity = pixel[0];
+        let mut x = 0;
+        while x < width {
+            let pixel = image.get_pixel(x, y);
+            if !is_on(pixel) {
+                x += 1;
+                continue;
+            }
 
-            let index = ((y % matrix_size) * matrix_size + (x % matrix_size)) as usize;
-            let threshold = bayer_matrix[index];
+            let mut run = 1;
+            while x + run < width && image.get_pixel(x + run, y) == pixel {
+                run += 1;
+            }
 
-            let new_intensity = if intensity > threshold { 255 } else { 0 };
-            output_image.put_pixel(x, y, Luma([new_intensity]));
+            let [r, g, b, _] = pixel.0;
+            runs.push((x, y, run, [r, g, b]));
+            x += run;
         }
     }
 
-    output_image
+    runs
 }
 
-fn apply_bayer_dithering_color(
-    image: &DynamicImage,
-    bayer_option: BayerMatrixOption,
-    preserve_order: PreserveOrder,
-) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+fn terminal_columns() -> u32 {
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .filter(|&columns| columns > 0)
+        .unwrap_or(DEFAULT_TERMINAL_COLUMNS)
+}
+
+fn ascii_art(image: &ImageBuffer<Rgba<u8>, Vec<u8>>, charset: &str) -> String {
     let (width, height) = image.dimensions();
+    let ramp: Vec<char> = charset.chars().collect();
+    let mut art = String::new();
+
+    for cell_y in (0..height).step_by(ASCII_CELL_HEIGHT as usize) {
+        for cell_x in (0..width).step_by(ASCII_CELL_WIDTH as usize) {
+            let mut lightness = 0.0;
+            let mut count = 0;
+            for y in cell_y..(cell_y + ASCII_CELL_HEIGHT).min(height) {
+                for x in cell_x..(cell_x + ASCII_CELL_WIDTH).min(width) {
+                    lightness += composite_over_white(image.get_pixel(x, y)) as f32 / 255.0;
+                    count += 1;
+                }
+            }
+            let index = (lightness / count as f32 * (ramp.len() - 1) as f32).round() as usize;
+            art.push(ramp[index]);
+        }
+        art.push('\n');
+    }
+
+    art
+}
+
+fn white_pixels(image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> Vec<u8> {
+    image
+        .pixels()
+        .map(|pixel| (composite_over_white(pixel) >= 128) as u8)
+        .collect()
+}
+
+fn pbm_bytes(image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> Vec<u8> {
+    let black_pixels: Vec<u8> = white_pixels(image).iter().map(|&white| white ^ 1).collect();
+    let mut bytes = format!("P4\n{} {}\n", image.width(), image.height()).into_bytes();
+    bytes.extend(pack_pixels(&black_pixels, image.width(), 1, &BitOrder::Msb));
+    bytes
+}
+
+fn c_identifier(name: &str) -> String {
+    let mut identifier: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !identifier.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        identifier.insert(0, '_');
+    }
+    identifier
+}
 
-    let (bayer_matrix, matrix_size): (&[u8], u32) = match bayer_option {
-        BayerMatrixOption::M2 => (&BAYER_MATRIX_2X2, 2),
-        BayerMatrixOption::M4 => (&BAYER_MATRIX_4X4, 4),
-        BayerMatrixOption::M8 => (&BAYER_MATRIX_8X8, 8),
+fn c_byte_lines(bytes: &[u8]) -> String {
+    let lines: Vec<String> = bytes
+        .chunks(12)
+        .map(|line| {
+            let values: Vec<String> = line.iter().map(|byte| format!("0x{:02x}", byte)).collect();
+            format!("   {}", values.join(", "))
+        })
+        .collect();
+    lines.join(",\n")
+}
+
+fn xbm_source(image: &ImageBuffer<Rgba<u8>, Vec<u8>>, name: &str) -> String {
+    let identifier = c_identifier(name);
+    let black_pixels: Vec<u8> = white_pixels(image).iter().map(|&white| white ^ 1).collect();
+    let bytes = pack_pixels(&black_pixels, image.width(), 1, &BitOrder::Lsb);
+    let mut source = format!(
+        "#define {0}_width {1}\n#define {0}_height {2}\nstatic unsigned char {0}_bits[] = {{\n",
+        identifier,
+        image.width(),
+        image.height()
+    );
+    source.push_str(&c_byte_lines(&bytes));
+    source.push_str(" };\n");
+    source
+}
+
+fn c_array_source(
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    name: &str,
+    layout: &ByteLayout,
+) -> String {
+    let identifier = c_identifier(name);
+    let (width, height) = image.dimensions();
+    let black_pixels: Vec<u8> = white_pixels(image).iter().map(|&white| white ^ 1).collect();
+    let (bytes, description) = match layout {
+        ByteLayout::Horizontal => (
+            pack_pixels(&black_pixels, width, 1, &BitOrder::Msb),
+            "horizontal bytes, most significant bit leftmost",
+        ),
+        ByteLayout::Vertical => (
+            pack_pages(&black_pixels, width, height),
+            "vertical bytes in 8-row pages, least significant bit on top",
+        ),
     };
 
-    let mut output_image = ImageBuffer::new(width, height);
+    format!(
+        "// {}x{} pixels, 1 bit per pixel ({}), 1 = black\n\
+         #include <stdint.h>\n\n\
+         #define {}_WIDTH {}\n\
+         #define {}_HEIGHT {}\n\n\
+         const uint8_t {}[] = {{\n{}\n}};\n",
+        width,
+        height,
+        description,
+        identifier.to_uppercase(),
+        width,
+        identifier.to_uppercase(),
+        height,
+        identifier,
+        c_byte_lines(&bytes)
+    )
+}
+
+fn pack_pages(values: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let mut packed = vec![0u8; width * height.div_ceil(8)];
 
-    for y in 0..height {
+    for (i, &value) in values.iter().enumerate() {
+        let (y, x) = (i / width, i % width);
+        packed[(y / 8) * width + x] |= value << (y % 8);
+    }
+
+    packed
+}
+
+fn composite_over_white(pixel: &Rgba<u8>) -> u8 {
+    let [r, g, b, a] = pixel.0;
+    let [r, g, b] =
+        [r, g, b].map(|value| ((value as u32 * a as u32 + 255 * (255 - a as u32)) / 255) as u8);
+    compute_luminance(&[r, g, b])
+}
+
+fn ansi_preview(image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> String {
+    let (width, height) = image.dimensions();
+    let mut preview = String::new();
+
+    // Transparent pixels are "off" in color mode, so show them over a white page.
+    let color = |x: u32, y: u32| {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        [r, g, b].map(|value| ((value as u32 * a as u32 + 255 * (255 - a as u32)) / 255) as u8)
+    };
+
+    for y in (0..height).step_by(2) {
+        let mut current = None;
         for x in 0..width {
-            let pixel = image.get_pixel(x, y).0;
-            let intensity = compute_luminance(&[pixel[0], pixel[1], pixel[2]]);
-
-            let index = ((y % matrix_size) * matrix_size + (x % matrix_size)) as usize;
-            let threshold = bayer_matrix[index];
-            let new_intensity = match preserve_order {
-                PreserveOrder::Light => {
-                    if intensity > threshold {
-                        255
-                    } else {
-                        0
-                    }
-                }
-                PreserveOrder::Dark => {
-                    if intensity > threshold {
-                        0
-                    } else {
-                        255
-                    }
+            let cell = (color(x, y), (y + 1 < height).then(|| color(x, y + 1)));
+            if current != Some(cell) {
+                let ([r, g, b], bottom) = cell;
+                preview.push_str(&format!("\x1b[38;2;{};{};{}m", r, g, b));
+                match bottom {
+                    Some([r, g, b]) => preview.push_str(&format!("\x1b[48;2;{};{};{}m", r, g, b)),
+                    None => preview.push_str("\x1b[49m"),
                 }
-            };
+                current = Some(cell);
+            }
+            preview.push('\u{2580}');
+        }
+        preview.push_str("\x1b[0m\n");
+    }
+
+    preview
+}
+
+fn to_bilevel(image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> Option<GrayImage> {
+    let mut bilevel_image = GrayImage::new(image.width(), image.height());
+
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let value = match pixel.0 {
+            [0, 0, 0, 255] => 0,
+            [255, 255, 255, 255] => 255,
+            _ => return None,
+        };
+        bilevel_image.put_pixel(x, y, Luma([value]));
+    }
+
+    Some(bilevel_image)
+}
+
+/// Returns the black and white pixels of `image` when `--output-depth 1` asks for them, warning
+/// if the image is not black and white.
+fn requested_bilevel(
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    output_depth: &OutputDepth,
+) -> Option<GrayImage> {
+    if *output_depth != OutputDepth::Gray1 {
+        return None;
+    }
+    let bilevel_image = to_bilevel(image);
+    if bilevel_image.is_none() {
+        warn("output is not pure black and white, writing 8 bits per channel instead");
+    }
+    bilevel_image
+}
+
+/// Writes a 1-bit BMP: a black and white palette and rows padded to 4 bytes, bottom row first.
+fn write_bmp_1bit<W: Write>(mut writer: W, image: &GrayImage) -> std::io::Result<()> {
+    const HEADER_SIZE: u32 = 14 + 40 + 2 * 4;
+    let (width, height) = image.dimensions();
+    let row_bytes = width.div_ceil(32) * 4;
+    let pixel_bytes = row_bytes * height;
+
+    let mut bytes = Vec::with_capacity((HEADER_SIZE + pixel_bytes) as usize);
+    bytes.extend_from_slice(b"BM");
+    for value in [HEADER_SIZE + pixel_bytes, 0, HEADER_SIZE, 40, width, height] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    // One plane at 1 bit per pixel, uncompressed, 72 DPI, and a two-color palette.
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    for value in [0, pixel_bytes, 2835, 2835, 2, 0] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes.extend_from_slice(&[0, 0, 0, 0, 255, 255, 255, 0]);
+
+    for row in image.rows().rev() {
+        let bits: Vec<u8> = row.map(|pixel| (pixel[0] == 255) as u8).collect();
+        let mut packed = pack_pixels(&bits, width, 1, &BitOrder::Msb);
+        packed.resize(row_bytes as usize, 0);
+        bytes.extend_from_slice(&packed);
+    }
+    writer.write_all(&bytes)
+}
+
+fn write_png_1bit<W: Write>(
+    writer: W,
+    image: &GrayImage,
+    colorspace: &OutputColorspace,
+    metadata: Option<&Metadata>,
+) -> Result<(), Box<dyn Error>> {
+    let (width, height) = image.dimensions();
+    let bits: Vec<u8> = image
+        .pixels()
+        .map(|pixel| (pixel[0] == 255) as u8)
+        .collect();
+    let packed = pack_pixels(&bits, width, 1, &BitOrder::Msb);
+
+    let mut encoder = png_encoder(writer, width, height, colorspace, metadata)?;
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::One);
+    encoder.write_header()?.write_image_data(&packed)?;
+    Ok(())
+}
+
+fn to_opaque_gray(image: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> Option<Vec<u8>> {
+    image
+        .pixels()
+        .map(|pixel| match pixel.0 {
+            [r, g, b, 255] if r == g && g == b => Some(r),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Splits `image` into a palette of at most 256 colors and one index per pixel. The palette starts
+/// with the colors of `dither_palette` in their order, so palette output keeps its indices.
+fn to_indexed(
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    dither_palette: Option<&Palette>,
+) -> Option<(Vec<[u8; 4]>, Vec<u8>)> {
+    let mut palette: Vec<[u8; 4]> = dither_palette
+        .filter(|palette| palette.colors().len() <= PNG_PALETTE_SIZE)
+        .map_or_else(Vec::new, |palette| {
+            palette
+                .colors()
+                .iter()
+                .map(|&[r, g, b]| [r, g, b, 255])
+                .collect()
+        });
+    let mut lookup: HashMap<[u8; 4], u8> = HashMap::new();
+    for (index, &color) in palette.iter().enumerate().rev() {
+        lookup.insert(color, index as u8);
+    }
+    let mut indices = Vec::with_capacity(image.len() / 4);
+
+    for pixel in image.pixels() {
+        let index = match lookup.get(&pixel.0) {
+            Some(&index) => index,
+            None if palette.len() < PNG_PALETTE_SIZE => {
+                palette.push(pixel.0);
+                lookup.insert(pixel.0, (palette.len() - 1) as u8);
+                (palette.len() - 1) as u8
+            }
+            None => return None,
+        };
+        indices.push(index);
+    }
+
+    Some((palette, indices))
+}
+
+fn write_gif<W: Write>(
+    writer: W,
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    dither_palette: Option<&Palette>,
+) -> Result<(), Box<dyn Error>> {
+    let Some((palette, indices)) = to_indexed(image, dither_palette) else {
+        warn(format_args!(
+            "output has more than {} colors, quantizing the GIF palette",
+            PNG_PALETTE_SIZE
+        ));
+        GifEncoder::new(writer).encode_frame(Frame::new(image.clone()))?;
+        return Ok(());
+    };
+    let (Ok(width), Ok(height)) = (u16::try_from(image.width()), u16::try_from(image.height()))
+    else {
+        return Err(CliError::UnsupportedFormat(
+            "GIF output is limited to 65535x65535 pixels".to_string(),
+        )
+        .into());
+    };
+
+    let global_palette: Vec<u8> = palette
+        .iter()
+        .flat_map(|color| [color[0], color[1], color[2]])
+        .collect();
+    let mut encoder = gif::Encoder::new(writer, width, height, &global_palette)?;
+    let frame = gif::Frame {
+        width,
+        height,
+        // GIF has a single fully transparent index and no partial transparency.
+        transparent: palette
+            .iter()
+            .position(|color| color[3] == 0)
+            .map(|index| index as u8),
+        buffer: Cow::Owned(indices),
+        ..gif::Frame::default()
+    };
+    encoder.write_frame(&frame)?;
+    Ok(())
+}
 
-            output_image.put_pixel(x, y, Rgba([pixel[0], pixel[1], pixel[2], new_intensity]));
+fn write_png_indexed<W: Write>(
+    writer: W,
+    image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    palette: &[[u8; 4]],
+    indices: &[u8],
+    colorspace: &OutputColorspace,
+    metadata: Option<&Metadata>,
+) -> Result<(), Box<dyn Error>> {
+    let (width, height) = image.dimensions();
+    let (bits, depth) = match palette.len() {
+        0..=2 => (1, png::BitDepth::One),
+        3..=4 => (2, png::BitDepth::Two),
+        5..=16 => (4, png::BitDepth::Four),
+        _ => (8, png::BitDepth::Eight),
+    };
+
+    let mut encoder = png_encoder(writer, width, height, colorspace, metadata)?;
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(depth);
+    encoder.set_palette(
+        palette
+            .iter()
+            .flat_map(|color| [color[0], color[1], color[2]])
+            .collect::<Vec<_>>(),
+    );
+    if palette.iter().any(|color| color[3] != 255) {
+        encoder.set_trns(palette.iter().map(|color| color[3]).collect::<Vec<_>>());
+    }
+    encoder
+        .write_header()?
+        .write_image_data(&pack_pixels(indices, width, bits, &BitOrder::Msb))?;
+    Ok(())
+}
+
+fn pack_pixels(values: &[u8], width: u32, bits: usize, bit_order: &BitOrder) -> Vec<u8> {
+    if bits == 8 {
+        return values.to_vec();
+    }
+
+    let width = width as usize;
+    let per_byte = 8 / bits;
+    let row_bytes = width.div_ceil(per_byte);
+    let mut packed = vec![0u8; row_bytes * values.len().div_ceil(width.max(1))];
+
+    for (i, &value) in values.iter().enumerate() {
+        let (y, x) = (i / width, i % width);
+        let shift = match bit_order {
+            BitOrder::Msb => 8 - bits * (x % per_byte + 1),
+            BitOrder::Lsb => bits * (x % per_byte),
+        };
+        packed[y * row_bytes + x / per_byte] |= value << shift;
+    }
+
+    packed
+}
+
+fn encode_base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let group = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
         }
     }
 
-    output_image
+    encoded
+}
+
+fn print_palette_presets() {
+    for (name, description) in Palette::presets() {
+        let colors = Palette::preset(name).map_or(0, |palette| palette.colors().len());
+        println!("{:<8} {:>3} colors  {}", name, colors, description);
+    }
+}
+
+/// Dithers `image` repeatedly and prints the fastest run as time and megapixels per second.
+fn print_benchmark(image: &DynamicImage, args: &DithererArgs) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    let mut runs = 0;
+    let mut fastest = Duration::MAX;
+    while runs < BENCH_MIN_RUNS || started.elapsed() < BENCH_MIN_TIME {
+        let run = Instant::now();
+        dither(image, args)?;
+        fastest = fastest.min(run.elapsed());
+        runs += 1;
+    }
+
+    #[cfg(feature = "plugins")]
+    let name = args
+        .plugin
+        .clone()
+        .unwrap_or_else(|| args.algorithm.to_string());
+    #[cfg(not(feature = "plugins"))]
+    let name = args.algorithm.to_string();
+    let (width, height) = image.dimensions();
+    println!(
+        "{}: {}x{}, {:.2} ms, {:.2} MP/s (fastest of {} runs)",
+        name,
+        width,
+        height,
+        fastest.as_secs_f64() * 1000.0,
+        (width as f64 * height as f64) / fastest.as_secs_f64() / 1e6,
+        runs
+    );
+    Ok(())
+}
+
+fn print_color_report(image: &DynamicImage, top_n: usize, json: bool) {
+    let counts = count_colors(image);
+    let top = &counts[..top_n.min(counts.len())];
+    let hex = |[r, g, b]: [u8; 3]| format!("#{:02x}{:02x}{:02x}", r, g, b);
+
+    if json {
+        let entries: Vec<String> = top
+            .iter()
+            .map(|&(color, count)| format!("{{\"color\":\"{}\",\"count\":{}}}", hex(color), count))
+            .collect();
+        println!(
+            "{{\"unique_colors\":{},\"top\":[{}]}}",
+            counts.len(),
+            entries.join(",")
+        );
+    } else {
+        println!("{} unique colors", counts.len());
+        for &(color, count) in top {
+            println!("{}  {}", hex(color), count);
+        }
+    }
 }
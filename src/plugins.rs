@@ -0,0 +1,33 @@
+//! Third-party dithering algorithms compiled into the CLI, enabled with the `plugins` feature.
+//!
+//! Every [`DitherAlgorithm`] registered in [`register`] can be selected with `--plugin NAME`. To
+//! add one from another crate, add the crate as a dependency and register its algorithm here.
+
+use ditherer::{register_algorithm, DitherAlgorithm};
+use std::sync::Arc;
+
+/// Period in pixels of the [`LineScreen`].
+const LINE_SCREEN_PERIOD: u32 = 6;
+
+pub fn register() {
+    register_algorithm(Arc::new(LineScreen));
+}
+
+/// An engraving-style screen of horizontal lines that thicken as the image darkens.
+struct LineScreen;
+
+impl DitherAlgorithm for LineScreen {
+    fn name(&self) -> &str {
+        "lines"
+    }
+
+    fn description(&self) -> &str {
+        "Horizontal line screen that thickens in the shadows, like an engraving."
+    }
+
+    fn threshold(&self, _x: u32, y: u32) -> Option<f32> {
+        // Lines grow outwards from the middle row of each period.
+        let distance = (y % LINE_SCREEN_PERIOD) as f32 + 0.5 - LINE_SCREEN_PERIOD as f32 / 2.0;
+        Some(1.0 - distance.abs() * 2.0 / LINE_SCREEN_PERIOD as f32)
+    }
+}
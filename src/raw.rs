@@ -0,0 +1,94 @@
+//! Ordered dithering of raw 8-bit grayscale buffers with explicit dimensions.
+//!
+//! This module only uses `core`, so it is the part of the library that is left when the crate is
+//! built without its default `std` feature, e.g. for firmware. It needs no allocator either: the
+//! caller passes in both buffers.
+
+/// The 2x2 Bayer matrix, thresholds evenly spaced over 0-255 in row-major order.
+pub const BAYER_MATRIX_2X2: [u8; 4] = bayer_matrix(2);
+/// The 4x4 Bayer matrix, thresholds evenly spaced over 0-255 in row-major order.
+pub const BAYER_MATRIX_4X4: [u8; 16] = bayer_matrix(4);
+/// The 8x8 Bayer matrix, thresholds evenly spaced over 0-255 in row-major order.
+pub const BAYER_MATRIX_8X8: [u8; 64] = bayer_matrix(8);
+
+/// Builds a `size`x`size` Bayer matrix at compile time, its thresholds evenly spaced over 0-255.
+/// Each bit of the coordinates, lowest first, picks the next base-4 digit of the cell's rank from
+/// the 2x2 pattern: the same recursion `ThresholdMatrix::generated_bayer` runs for m16 and up.
+const fn bayer_matrix<const LEN: usize>(size: usize) -> [u8; LEN] {
+    assert!(size * size == LEN && LEN <= 64);
+
+    let mut values = [0; LEN];
+    let mut index = 0;
+    while index < LEN {
+        let (x, y) = (index % size, index / size);
+        let mut rank = 0;
+        let mut bit = 1;
+        while bit < size {
+            rank = rank * 4
+                + match (x & bit != 0, y & bit != 0) {
+                    (false, false) => 0,
+                    (true, false) => 2,
+                    (false, true) => 3,
+                    (true, true) => 1,
+                };
+            bit *= 2;
+        }
+        values[index] = (rank * (256 / LEN)) as u8;
+        index += 1;
+    }
+    values
+}
+
+/// Dithers the `width`x`height` grayscale `input` into `output`, one byte per pixel in row-major
+/// order. `matrix` holds `matrix_width` thresholds per row and is tiled across the image; a pixel
+/// comes out 255 when it is above its threshold and 0 otherwise.
+///
+/// This is what [`apply_bayer_dithering_grayscale`](crate::apply_bayer_dithering_grayscale) does
+/// with a plain `build_tone_curve(0.0, false)`, without the tone curve or gamma correction.
+///
+/// # Panics
+///
+/// Panics if `input` or `output` is not `width * height` bytes long, or if `matrix` is empty or
+/// not a whole number of `matrix_width` rows.
+pub fn dither_ordered(
+    input: &[u8],
+    output: &mut [u8],
+    width: usize,
+    height: usize,
+    matrix: &[u8],
+    matrix_width: usize,
+) {
+    assert_eq!(
+        input.len(),
+        width * height,
+        "input is not width * height bytes"
+    );
+    assert_eq!(
+        output.len(),
+        width * height,
+        "output is not width * height bytes"
+    );
+    assert!(
+        matrix_width > 0 && !matrix.is_empty() && matrix.len().is_multiple_of(matrix_width),
+        "matrix is not a whole number of rows"
+    );
+    if width == 0 {
+        return;
+    }
+
+    let matrix_height = matrix.len() / matrix_width;
+    for (y, (input_row, output_row)) in input
+        .chunks_exact(width)
+        .zip(output.chunks_exact_mut(width))
+        .enumerate()
+    {
+        let thresholds = &matrix[(y % matrix_height) * matrix_width..][..matrix_width];
+        for ((&input, output), &threshold) in input_row
+            .iter()
+            .zip(output_row)
+            .zip(thresholds.iter().cycle())
+        {
+            *output = if input > threshold { 255 } else { 0 };
+        }
+    }
+}
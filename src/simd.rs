@@ -0,0 +1,110 @@
+//! Explicit SIMD kernels for ordered dithering, enabled with the `simd` feature.
+//!
+//! Instead of looking up the tone curve and comparing floats for every pixel, each matrix cell is
+//! turned into the smallest 8-bit input that comes out white. A row is then dithered with one
+//! unsigned byte comparison per pixel, 32 pixels at a time with AVX2 or 16 with SSE2, chosen at
+//! runtime.
+
+use crate::ThresholdMatrix;
+
+/// The smallest input level that is dithered white, per threshold matrix cell.
+pub(crate) struct Cutoffs {
+    values: Vec<u8>,
+    width: usize,
+    height: usize,
+}
+
+impl Cutoffs {
+    /// Returns `None` when the tone curve is not monotonic or some cell stays black even for
+    /// white input, as a single byte comparison cannot express those.
+    pub(crate) fn new(threshold_matrix: &ThresholdMatrix, tone_curve: &[f32; 256]) -> Option<Self> {
+        if tone_curve.windows(2).any(|pair| pair[0] > pair[1]) {
+            return None;
+        }
+
+        let (width, height) = (threshold_matrix.width(), threshold_matrix.height());
+        let mut values = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let threshold = threshold_matrix.threshold(x, y);
+                let cutoff = tone_curve.partition_point(|&intensity| intensity <= threshold);
+                values.push(u8::try_from(cutoff).ok()?);
+            }
+        }
+
+        Some(Cutoffs {
+            values,
+            width: width as usize,
+            height: height as usize,
+        })
+    }
+
+    /// Tiles the cutoffs of matrix row `y` across `length` pixels.
+    pub(crate) fn row(&self, y: usize, length: usize) -> Vec<u8> {
+        let start = (y % self.height) * self.width;
+        let pattern = &self.values[start..start + self.width];
+        pattern.iter().copied().cycle().take(length).collect()
+    }
+}
+
+/// Writes 255 where `input` reaches the cutoff and 0 elsewhere.
+pub(crate) fn threshold_row(input: &[u8], cutoffs: &[u8], output: &mut [u8]) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: AVX2 support was just detected.
+            return unsafe { x86_64::threshold_row_avx2(input, cutoffs, output) };
+        }
+        // SAFETY: SSE2 is part of the x86_64 baseline.
+        unsafe { x86_64::threshold_row_sse2(input, cutoffs, output) }
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    threshold_row_scalar(input, cutoffs, output);
+}
+
+fn threshold_row_scalar(input: &[u8], cutoffs: &[u8], output: &mut [u8]) {
+    for ((output, &input), &cutoff) in output.iter_mut().zip(input).zip(cutoffs) {
+        *output = if input >= cutoff { 255 } else { 0 };
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86_64 {
+    use std::arch::x86_64::*;
+
+    // `max(input, cutoff) == input` is an unsigned `input >= cutoff`, and the comparison already
+    // yields the 0x00/0xFF output bytes.
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn threshold_row_avx2(input: &[u8], cutoffs: &[u8], output: &mut [u8]) {
+        let length = input.len().min(cutoffs.len()).min(output.len());
+        let mut offset = 0;
+        while offset + 32 <= length {
+            let pixels = _mm256_loadu_si256(input.as_ptr().add(offset) as *const __m256i);
+            let cutoff = _mm256_loadu_si256(cutoffs.as_ptr().add(offset) as *const __m256i);
+            let white = _mm256_cmpeq_epi8(_mm256_max_epu8(pixels, cutoff), pixels);
+            _mm256_storeu_si256(output.as_mut_ptr().add(offset) as *mut __m256i, white);
+            offset += 32;
+        }
+        threshold_row_sse2(&input[offset..], &cutoffs[offset..], &mut output[offset..]);
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub(super) unsafe fn threshold_row_sse2(input: &[u8], cutoffs: &[u8], output: &mut [u8]) {
+        let length = input.len().min(cutoffs.len()).min(output.len());
+        let mut offset = 0;
+        while offset + 16 <= length {
+            let pixels = _mm_loadu_si128(input.as_ptr().add(offset) as *const __m128i);
+            let cutoff = _mm_loadu_si128(cutoffs.as_ptr().add(offset) as *const __m128i);
+            let white = _mm_cmpeq_epi8(_mm_max_epu8(pixels, cutoff), pixels);
+            _mm_storeu_si128(output.as_mut_ptr().add(offset) as *mut __m128i, white);
+            offset += 16;
+        }
+        super::threshold_row_scalar(
+            &input[offset..length],
+            &cutoffs[offset..length],
+            &mut output[offset..length],
+        );
+    }
+}
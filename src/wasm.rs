@@ -0,0 +1,168 @@
+//! WebAssembly bindings, enabled with the `wasm` feature.
+//!
+//! Everything works on in-memory buffers, so no filesystem or stdin access is needed.
+
+use crate::{Algorithm, BayerMatrixOption, ColorSpace, Palette};
+use image::{DynamicImage, ImageBuffer, ImageFormat, Rgba};
+use std::io::Cursor;
+use wasm_bindgen::prelude::*;
+
+/// Settings for [`dither`] and [`dither_rgba`], a subset of the CLI flags with the same defaults.
+#[wasm_bindgen]
+pub struct DitherOptions {
+    algorithm: Algorithm,
+    matrix_size: BayerMatrixOption,
+    matrix_b: BayerMatrixOption,
+    color: bool,
+    palette: Option<Palette>,
+    color_space: ColorSpace,
+    strength: f32,
+    contrast_curve: f64,
+    linear: bool,
+    serpentine: bool,
+    edge_enhance: f32,
+}
+
+#[wasm_bindgen]
+impl DitherOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        DitherOptions {
+            algorithm: Algorithm::Bayer,
+            matrix_size: BayerMatrixOption::M4,
+            matrix_b: BayerMatrixOption::M8,
+            color: false,
+            palette: None,
+            color_space: ColorSpace::Rgb,
+            strength: 1.0,
+            contrast_curve: 0.0,
+            linear: true,
+            serpentine: false,
+            edge_enhance: 0.0,
+        }
+    }
+
+    pub fn set_algorithm(&mut self, algorithm: &str) -> Result<(), JsError> {
+        self.algorithm = algorithm.parse()?;
+        Ok(())
+    }
+
+    pub fn set_matrix_size(&mut self, matrix_size: &str) -> Result<(), JsError> {
+        self.matrix_size = matrix_size.parse()?;
+        Ok(())
+    }
+
+    /// Sets the second matrix blended in by the `interference` algorithm.
+    pub fn set_matrix_b(&mut self, matrix_size: &str) -> Result<(), JsError> {
+        self.matrix_b = matrix_size.parse()?;
+        Ok(())
+    }
+
+    pub fn set_color(&mut self, color: bool) {
+        self.color = color;
+    }
+
+    /// Sets the palette from a preset name (`bw`, `gameboy`, ...) or the contents of a palette file.
+    pub fn set_palette(&mut self, palette: &str) -> Result<(), JsError> {
+        self.palette = Some(match Palette::preset(palette) {
+            Some(palette) => palette,
+            None => Palette::from_text(palette)?,
+        });
+        Ok(())
+    }
+
+    pub fn clear_palette(&mut self) {
+        self.palette = None;
+    }
+
+    pub fn set_color_space(&mut self, color_space: &str) -> Result<(), JsError> {
+        self.color_space = color_space.parse()?;
+        Ok(())
+    }
+
+    pub fn set_strength(&mut self, strength: f32) -> Result<(), JsError> {
+        if !(0.0..=1.0).contains(&strength) {
+            return Err(JsError::new(
+                "Strength must be a number between 0.0 and 1.0.",
+            ));
+        }
+        self.strength = strength;
+        Ok(())
+    }
+
+    pub fn set_contrast_curve(&mut self, contrast_curve: f64) -> Result<(), JsError> {
+        if !(contrast_curve >= 0.0 && contrast_curve.is_finite()) {
+            return Err(JsError::new(
+                "Contrast curve strength must be a finite number >= 0.",
+            ));
+        }
+        self.contrast_curve = contrast_curve;
+        Ok(())
+    }
+
+    /// Thresholds in linear light (the default) or, with `false`, on sRGB-encoded values.
+    pub fn set_linear(&mut self, linear: bool) {
+        self.linear = linear;
+    }
+
+    pub fn set_serpentine(&mut self, serpentine: bool) {
+        self.serpentine = serpentine;
+    }
+
+    /// Sharpens edges during error diffusion; 0 (the default) turns it off.
+    pub fn set_edge_enhance(&mut self, edge_enhance: f32) -> Result<(), JsError> {
+        if !(edge_enhance >= 0.0 && edge_enhance.is_finite()) {
+            return Err(JsError::new(
+                "Edge enhancement must be a finite number >= 0.",
+            ));
+        }
+        self.edge_enhance = edge_enhance;
+        Ok(())
+    }
+}
+
+impl Default for DitherOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Decodes an encoded image (PNG, JPEG, GIF, ...), dithers it and returns it encoded as PNG.
+#[wasm_bindgen]
+pub fn dither(bytes: &[u8], options: &DitherOptions) -> Result<Vec<u8>, JsError> {
+    let image = image::load_from_memory(bytes)?;
+    let mut png_bytes = Vec::new();
+    dither_image(&image, options).write_to(&mut Cursor::new(&mut png_bytes), ImageFormat::Png)?;
+    Ok(png_bytes)
+}
+
+/// Dithers raw RGBA pixels, e.g. from `ImageData.data`, and returns raw RGBA pixels.
+#[wasm_bindgen]
+pub fn dither_rgba(
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+    options: &DitherOptions,
+) -> Result<Vec<u8>, JsError> {
+    let image = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, pixels.to_vec())
+        .ok_or_else(|| JsError::new("Pixel buffer does not match width * height * 4."))?;
+    Ok(dither_image(&DynamicImage::ImageRgba8(image), options).into_raw())
+}
+
+fn dither_image(image: &DynamicImage, options: &DitherOptions) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let mut library_options = crate::DitherOptions::new()
+        .algorithm(options.algorithm.clone())
+        .matrix_size(options.matrix_size.clone())
+        .matrix_b(options.matrix_b.clone())
+        .color(options.color)
+        .color_space(options.color_space.clone())
+        .strength(options.strength)
+        .contrast_curve(options.contrast_curve)
+        .linear(options.linear)
+        .serpentine(options.serpentine)
+        .edge_enhance(options.edge_enhance);
+    if let Some(palette) = &options.palette {
+        library_options = library_options.palette(palette.clone());
+    }
+    crate::dither(image, &library_options).to_rgba8()
+}
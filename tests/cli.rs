@@ -0,0 +1,3421 @@
+use assert_cmd::Command;
+use image::{GenericImageView, ImageBuffer, Luma, Rgb, Rgba};
+use predicates::prelude::*;
+use std::path::PathBuf;
+
+const FIXTURE_WIDTH: u32 = 32;
+const FIXTURE_HEIGHT: u32 = 24;
+
+fn temp_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name)
+}
+
+fn fixture(name: &str) -> PathBuf {
+    let path = temp_path(name);
+    let image = ImageBuffer::from_fn(FIXTURE_WIDTH, FIXTURE_HEIGHT, |x, y| {
+        Rgb([(x * 8) as u8, (y * 10) as u8, 128])
+    });
+    image.save(&path).unwrap();
+    path
+}
+
+fn ditherer() -> Command {
+    Command::cargo_bin("ditherer").unwrap()
+}
+
+#[test]
+fn dithers_file_to_file() {
+    let input = fixture("file_to_file.png");
+    let output = temp_path("file_to_file_out.png");
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("-m")
+        .arg("m4")
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    let dithered = image::open(&output).unwrap();
+    assert_eq!(dithered.dimensions(), (FIXTURE_WIDTH, FIXTURE_HEIGHT));
+}
+
+#[test]
+fn pipes_stdin_to_stdout() {
+    let input = std::fs::read(fixture("stdin_to_stdout.png")).unwrap();
+
+    let assert = ditherer()
+        .arg("-m")
+        .arg("m2")
+        .write_stdin(input)
+        .assert()
+        .success();
+
+    let dithered = image::load_from_memory(&assert.get_output().stdout).unwrap();
+    assert_eq!(dithered.dimensions(), (FIXTURE_WIDTH, FIXTURE_HEIGHT));
+}
+
+#[test]
+fn diffs_two_algorithms() {
+    let input = fixture("diff.png");
+    let dithered = |flags: &[&str]| {
+        let output = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .args(flags)
+            .assert()
+            .success();
+        image::load_from_memory(&output.get_output().stdout)
+            .unwrap()
+            .to_luma8()
+    };
+
+    let bayer = dithered(&["-m", "m2"]);
+    let floyd_steinberg = dithered(&["--algorithm", "floyd-steinberg"]);
+    let diff = dithered(&["--diff", "bayer:m2:floyd-steinberg"]);
+    for ((a, b), difference) in bayer
+        .pixels()
+        .zip(floyd_steinberg.pixels())
+        .zip(diff.pixels())
+    {
+        assert_eq!(difference[0], a[0] ^ b[0]);
+    }
+    assert!(diff.pixels().any(|pixel| pixel[0] == 255));
+
+    assert_eq!(
+        dithered(&["--diff", "m2:m8"]),
+        dithered(&["--diff", "bayer:m2:bayer:m8"])
+    );
+    assert!(dithered(&["--diff", "atkinson:atkinson"])
+        .pixels()
+        .all(|pixel| pixel[0] == 0));
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--diff")
+        .arg("m2:m8")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("pixels differ"));
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--diff")
+        .arg("m2:m8")
+        .arg("--quiet")
+        .assert()
+        .success()
+        .stderr(predicate::str::is_empty());
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--diff")
+        .arg("bayer:atkinson:sierra")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid algorithm pair"));
+}
+
+#[test]
+fn rejects_invalid_matrix_size() {
+    let input = fixture("invalid_matrix.png");
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("-m")
+        .arg("m3")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Invalid Bayer Matrix option. Choose from: m2, m4, m8, m16, m32, m64, m128, m256.",
+        ));
+}
+
+#[test]
+fn dithers_in_color_mode() {
+    let input = fixture("color.png");
+    let output = temp_path("color_out.png");
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("-m")
+        .arg("m8")
+        .arg("--color")
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    let dithered = image::open(&output).unwrap();
+    assert_eq!(dithered.dimensions(), (FIXTURE_WIDTH, FIXTURE_HEIGHT));
+    assert!(dithered.color().has_alpha());
+}
+
+#[test]
+fn applies_float_threshold_map() {
+    let input = fixture("threshold_map.png");
+    let threshold_map = temp_path("threshold_map.txt");
+    let output = temp_path("threshold_map_out.png");
+    std::fs::write(&threshold_map, "0.999, 0.999\n0.999 0.999\n").unwrap();
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--threshold-map")
+        .arg(&threshold_map)
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    let dithered = image::open(&output).unwrap().to_luma8();
+    assert!(dithered.pixels().all(|pixel| pixel[0] == 0));
+}
+
+#[test]
+fn applies_image_threshold_map() {
+    let input = temp_path("threshold_texture_in.png");
+    let threshold_map = temp_path("threshold_texture.png");
+    ImageBuffer::from_pixel(8, 8, Rgb([128u8, 128, 128]))
+        .save(&input)
+        .unwrap();
+    ImageBuffer::from_fn(2, 1, |x, _| Luma([if x == 0 { 0u8 } else { 255 }]))
+        .save(&threshold_map)
+        .unwrap();
+
+    let output = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--threshold-map")
+        .arg(&threshold_map)
+        .assert()
+        .success();
+    let dithered = image::load_from_memory(&output.get_output().stdout)
+        .unwrap()
+        .to_luma8();
+
+    assert!(dithered
+        .enumerate_pixels()
+        .all(|(x, _, pixel)| pixel[0] == if x % 2 == 0 { 255 } else { 0 }));
+}
+
+#[test]
+fn rejects_out_of_range_threshold_map() {
+    let input = fixture("threshold_map_invalid.png");
+    let threshold_map = temp_path("threshold_map_invalid.txt");
+    std::fs::write(&threshold_map, "0.5 1.5\n").unwrap();
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--threshold-map")
+        .arg(&threshold_map)
+        .assert()
+        .failure();
+}
+
+#[test]
+fn explains_algorithm_without_input() {
+    ditherer()
+        .arg("--explain")
+        .arg("bayer")
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("bayer:"));
+}
+
+#[test]
+fn lists_palette_presets_without_input() {
+    ditherer()
+        .arg("--list-palettes")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("gameboy"))
+        .stdout(predicate::str::contains("pico8     16 colors"));
+}
+
+#[test]
+fn dithers_to_retro_palette_preset() {
+    let input = fixture("gameboy.png");
+
+    let assert = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--palette")
+        .arg("GameBoy")
+        .assert()
+        .success();
+
+    let gameboy = [[15, 56, 15], [48, 98, 48], [139, 172, 15], [155, 188, 15]];
+    let dithered = image::load_from_memory(&assert.get_output().stdout)
+        .unwrap()
+        .to_rgb8();
+    assert!(dithered.pixels().all(|pixel| gameboy.contains(&pixel.0)));
+}
+
+#[test]
+fn reads_lospec_palettes_from_the_cache_when_offline() {
+    let input = fixture("lospec.png");
+    let cache = temp_path("lospec_cache");
+    std::fs::create_dir_all(cache.join("ditherer/lospec")).unwrap();
+    std::fs::write(
+        cache.join("ditherer/lospec/test-palette.hex"),
+        "1a1c2c\n5d275d\nb13e53\nf4f4f4\n",
+    )
+    .unwrap();
+    let lospec = |palette: &str| {
+        let mut command = ditherer();
+        command
+            .env("XDG_CACHE_HOME", &cache)
+            .arg("-i")
+            .arg(&input)
+            .arg("--palette")
+            .arg(palette)
+            .arg("--offline");
+        command
+    };
+
+    let assert = lospec("lospec:test-palette").assert().success();
+    let palette = [[26, 28, 44], [93, 39, 93], [177, 62, 83], [244, 244, 244]];
+    let dithered = image::load_from_memory(&assert.get_output().stdout)
+        .unwrap()
+        .to_rgb8();
+    assert!(dithered.pixels().all(|pixel| palette.contains(&pixel.0)));
+
+    lospec("lospec:not-cached")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("--offline forbids downloading"));
+    lospec("lospec:../test-palette")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("Invalid Lospec palette"));
+}
+
+#[test]
+fn pixelate_dithers_in_blocks_at_full_size() {
+    let input = temp_path("pixelate.png");
+    ImageBuffer::from_fn(30, 18, |x, _| Luma([(x * 255 / 29) as u8]))
+        .save(&input)
+        .unwrap();
+
+    let assert = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--pixelate")
+        .arg("4")
+        .assert()
+        .success();
+
+    let dithered = image::load_from_memory(&assert.get_output().stdout)
+        .unwrap()
+        .to_luma8();
+    assert_eq!(dithered.dimensions(), (30, 18));
+    for (x, y, pixel) in dithered.enumerate_pixels() {
+        assert_eq!(pixel, dithered.get_pixel(x / 4 * 4, y / 4 * 4));
+    }
+    assert!(dithered.pixels().any(|pixel| pixel[0] == 0));
+    assert!(dithered.pixels().any(|pixel| pixel[0] == 255));
+}
+
+#[test]
+fn fm_screening_matches_each_cells_coverage() {
+    let input = temp_path("fm.png");
+    ImageBuffer::from_fn(64, 32, |x, _| Luma([if x < 32 { 188u8 } else { 100 }]))
+        .save(&input)
+        .unwrap();
+
+    let assert = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--algorithm")
+        .arg("fm")
+        .arg("--cell-size")
+        .arg("8")
+        .assert()
+        .success();
+
+    let dithered = image::load_from_memory(&assert.get_output().stdout)
+        .unwrap()
+        .to_luma8();
+    for cell_y in 0..4 {
+        for cell_x in 0..8 {
+            let white = (0..8)
+                .flat_map(|dy| (0..8).map(move |dx| (cell_x * 8 + dx, cell_y * 8 + dy)))
+                .filter(|&(x, y)| dithered.get_pixel(x, y)[0] == 255)
+                .count();
+            // 188 and 100 are 50.3% and 12.7% of white in linear light.
+            assert_eq!(white, if cell_x < 4 { 32 } else { 8 });
+        }
+    }
+}
+
+#[test]
+fn rotates_and_scales_the_threshold_pattern() {
+    let input = temp_path("pattern_transform.png");
+    ImageBuffer::from_pixel(32, 32, Luma([188u8]))
+        .save(&input)
+        .unwrap();
+    let dither = |options: &[&str]| {
+        let assert = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .args(options)
+            .assert()
+            .success();
+        image::load_from_memory(&assert.get_output().stdout)
+            .unwrap()
+            .to_luma8()
+    };
+    let transitions = |image: &image::GrayImage| {
+        image
+            .rows()
+            .map(|row| {
+                let row: Vec<u8> = row.map(|pixel| pixel[0]).collect();
+                row.windows(2).filter(|pair| pair[0] != pair[1]).count()
+            })
+            .sum::<usize>()
+    };
+
+    let plain = dither(&[]);
+    let rotated = dither(&["--pattern-angle", "90"]);
+    for (x, y, pixel) in rotated.enumerate_pixels() {
+        assert_eq!(pixel, plain.get_pixel(y, (31 - x) % 32));
+    }
+
+    let enlarged = dither(&["--pattern-scale", "4"]);
+    assert!(transitions(&enlarged) * 2 < transitions(&plain));
+    assert_ne!(dither(&["--pattern-angle", "45"]), plain);
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--pattern-angle")
+        .arg("45")
+        .arg("--algorithm")
+        .arg("atkinson")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains(
+            "--pattern-scale and --pattern-angle only apply to ordered algorithms",
+        ));
+}
+
+#[test]
+fn edge_enhance_keeps_thin_lines_dark() {
+    let input = temp_path("edge_enhance.png");
+    ImageBuffer::from_fn(32, 32, |x, _| Luma([if x % 8 == 3 { 200u8 } else { 235 }]))
+        .save(&input)
+        .unwrap();
+    let dither = |options: &[&str]| {
+        let assert = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--algorithm")
+            .arg("floyd-steinberg")
+            .args(options)
+            .assert()
+            .success();
+        image::load_from_memory(&assert.get_output().stdout)
+            .unwrap()
+            .to_luma8()
+    };
+    let black_on_lines = |image: &image::GrayImage| {
+        image
+            .enumerate_pixels()
+            .filter(|(x, _, pixel)| x % 8 == 3 && pixel[0] == 0)
+            .count()
+    };
+
+    let plain = dither(&[]);
+    let enhanced = dither(&["--edge-enhance", "2"]);
+    assert!(black_on_lines(&enhanced) > black_on_lines(&plain));
+    assert_eq!(dither(&["--edge-enhance", "0"]), plain);
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--edge-enhance")
+        .arg("2")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains(
+            "--edge-enhance only applies to error-diffusion algorithms",
+        ));
+}
+
+#[test]
+fn tone_maps_high_dynamic_range_input() {
+    let input = temp_path("tonemap.hdr");
+    image::Rgb32FImage::from_fn(32, 32, |x, _| {
+        let radiance = 2.0 + x as f32 / 2.0;
+        image::Rgb([radiance; 3])
+    })
+    .save(&input)
+    .unwrap();
+    let white_fraction = |options: &[&str]| {
+        let assert = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .args(options)
+            .assert()
+            .success();
+        let image = image::load_from_memory(&assert.get_output().stdout)
+            .unwrap()
+            .to_luma8();
+        image.pixels().filter(|pixel| pixel[0] == 255).count() as f64 / 1024.0
+    };
+
+    assert_eq!(white_fraction(&["--tonemap", "clamp"]), 1.0);
+    let reinhard = white_fraction(&[]);
+    assert_eq!(white_fraction(&["--tonemap", "reinhard"]), reinhard);
+    assert!(reinhard > 0.1 && reinhard < 0.9);
+    let aces = white_fraction(&["--tonemap", "aces"]);
+    assert!(aces > 0.1 && aces < 0.9);
+
+    let deep = temp_path("tonemap16.png");
+    ImageBuffer::from_pixel(8, 8, Luma([40000u16]))
+        .save(&deep)
+        .unwrap();
+    ditherer()
+        .arg("-i")
+        .arg(&deep)
+        .arg("--tonemap")
+        .arg("aces")
+        .assert()
+        .success();
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--tonemap")
+        .arg("filmic")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Invalid tone map. Choose from: clamp, reinhard, aces.",
+        ));
+}
+
+#[test]
+fn thresholds_16_bit_input_at_full_precision() {
+    let dither = |name: &str, value: u16, options: &[&str]| {
+        let input = temp_path(name);
+        ImageBuffer::from_pixel(64, 64, Luma([value]))
+            .save(&input)
+            .unwrap();
+        let assert = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .args(options)
+            .assert()
+            .success();
+        image::load_from_memory(&assert.get_output().stdout)
+            .unwrap()
+            .to_luma8()
+    };
+    let white = |image: &image::GrayImage| image.pixels().filter(|pixel| pixel[0] == 255).count();
+
+    // Both levels round to 8-bit 128, but lie on either side of it in 16 bits.
+    let diffusion = ["--algorithm", "floyd-steinberg"];
+    let darker = dither("deep_darker.png", 32800, &diffusion);
+    let lighter = dither("deep_lighter.png", 32950, &diffusion);
+    assert!(white(&darker) < white(&lighter));
+
+    // 16-bit levels that are exact 8-bit levels dither the same as the 8-bit image.
+    let input = temp_path("deep_reference.png");
+    ImageBuffer::from_pixel(64, 64, Luma([90u8]))
+        .save(&input)
+        .unwrap();
+    let assert = ditherer().arg("-i").arg(&input).assert().success();
+    let reference = image::load_from_memory(&assert.get_output().stdout)
+        .unwrap()
+        .to_luma8();
+    assert_eq!(dither("deep_exact.png", 90 * 257, &[]), reference);
+}
+
+#[test]
+fn jitter_tears_the_bayer_pattern_reproducibly() {
+    let input = temp_path("jitter.png");
+    ImageBuffer::from_pixel(32, 32, Luma([160u8]))
+        .save(&input)
+        .unwrap();
+    let dither = |options: &[&str]| {
+        let assert = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .args(options)
+            .assert()
+            .success();
+        image::load_from_memory(&assert.get_output().stdout)
+            .unwrap()
+            .to_luma8()
+    };
+    let white = |image: &image::GrayImage| image.pixels().filter(|pixel| pixel[0] == 255).count();
+
+    let plain = dither(&[]);
+    let torn = dither(&["--jitter", "0.5", "--seed", "7"]);
+    assert_ne!(torn, plain);
+    assert_eq!(dither(&["--jitter", "0.5", "--seed", "7"]), torn);
+    assert_ne!(dither(&["--jitter", "0.5", "--seed", "8"]), torn);
+    assert_eq!(dither(&["--jitter", "0"]), plain);
+    assert!(white(&torn).abs_diff(white(&plain)) < 64);
+
+    // Strength scales the jitter along with the matrix.
+    assert_eq!(
+        dither(&["--jitter", "1", "--strength", "0"]),
+        dither(&["--strength", "0"])
+    );
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--jitter")
+        .arg("0.5")
+        .arg("--algorithm")
+        .arg("atkinson")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains(
+            "--jitter only applies to ordered algorithms",
+        ));
+}
+
+#[cfg(feature = "plugins")]
+#[test]
+fn dithers_with_a_registered_plugin() {
+    let input = temp_path("plugin.png");
+    ImageBuffer::from_pixel(12, 12, Luma([128u8]))
+        .save(&input)
+        .unwrap();
+    let assert = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--plugin")
+        .arg("lines")
+        .assert()
+        .success();
+    let image = image::load_from_memory(&assert.get_output().stdout)
+        .unwrap()
+        .to_luma8();
+
+    // The line screen varies by row only.
+    for row in image.rows() {
+        let row: Vec<u8> = row.map(|pixel| pixel[0]).collect();
+        assert!(row.iter().all(|&value| value == row[0]));
+    }
+    assert_ne!(image.get_pixel(0, 0), image.get_pixel(0, 3));
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--plugin")
+        .arg("engraving")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Unknown plugin. Choose from: lines.",
+        ));
+}
+
+#[test]
+fn bench_prints_throughput_instead_of_writing_output() {
+    let input = fixture("bench.png");
+    let assert = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--algorithm")
+        .arg("atkinson")
+        .arg("--bench")
+        .assert()
+        .success();
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    assert!(stdout.starts_with(&format!("atkinson: {}x{}, ", FIXTURE_WIDTH, FIXTURE_HEIGHT)));
+    assert!(stdout.contains(" MP/s (fastest of "));
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(temp_path("bench_out.png"))
+        .arg("--bench")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn zero_contrast_curve_leaves_output_unchanged() {
+    let input = fixture("contrast_curve_zero.png");
+    let output = |flags: &[&str]| {
+        ditherer()
+            .arg("-i")
+            .arg(&input)
+            .args(flags)
+            // The recorded options differ, so leave them out to compare the encoded pixels.
+            .arg("--strip-metadata")
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone()
+    };
+
+    for algorithm in ["bayer", "floyd-steinberg"] {
+        assert_eq!(
+            output(&["--algorithm", algorithm, "--contrast-curve", "0"]),
+            output(&["--algorithm", algorithm])
+        );
+    }
+    assert_ne!(output(&["--contrast-curve", "4"]), output(&[]));
+}
+
+#[test]
+fn dot_gain_lightens_midtones_to_compensate_for_ink_spread() {
+    let input = temp_path("dot_gain.png");
+    ImageBuffer::from_pixel(16, 16, Luma([128u8]))
+        .save(&input)
+        .unwrap();
+    let curve = temp_path("dot_gain.csv");
+    std::fs::write(&curve, "# nominal,printed\n25,35\n50,70\n75,88\n").unwrap();
+
+    let white_fraction = |dot_gain: Option<&std::ffi::OsStr>| {
+        let mut command = ditherer();
+        command.arg("-i").arg(&input).arg("--no-gamma");
+        if let Some(dot_gain) = dot_gain {
+            command.arg("--dot-gain").arg(dot_gain);
+        }
+        let assert = command.assert().success();
+        let dithered = image::load_from_memory(&assert.get_output().stdout)
+            .unwrap()
+            .to_luma8();
+        dithered.pixels().filter(|pixel| pixel[0] == 255).count() as f64 / 256.0
+    };
+
+    assert!((white_fraction(None) - 0.5).abs() < 0.05);
+    assert!(white_fraction(Some("20%".as_ref())) > 0.6);
+    assert!(white_fraction(Some(curve.as_os_str())) > 0.6);
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--dot-gain")
+        .arg("60")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("below 50"));
+}
+
+#[test]
+#[cfg(all(unix, feature = "clipboard-test-hook"))]
+fn dithers_between_clipboard_commands() {
+    let pasted = temp_path("clipboard_in.png");
+    let copied = temp_path("clipboard_out.png");
+    let _ = std::fs::remove_file(&copied);
+    ImageBuffer::from_pixel(6, 4, Luma([255u8]))
+        .save(&pasted)
+        .unwrap();
+
+    ditherer()
+        .arg("--clipboard-in")
+        .arg("--clipboard-out")
+        .env(
+            "DITHERER_CLIPBOARD_PASTE",
+            format!("cat {}", pasted.display()),
+        )
+        .env(
+            "DITHERER_CLIPBOARD_COPY",
+            format!("tee {}", copied.display()),
+        )
+        .assert()
+        .success()
+        .stdout("");
+
+    let dithered = image::open(&copied).unwrap().to_luma8();
+    assert_eq!(dithered.dimensions(), (6, 4));
+    assert!(dithered.pixels().all(|pixel| pixel[0] == 255));
+
+    ditherer()
+        .arg("--clipboard-in")
+        .env("DITHERER_CLIPBOARD_PASTE", "true")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "the clipboard does not hold an image",
+        ));
+}
+
+#[test]
+#[cfg(not(feature = "clipboard"))]
+fn clipboard_needs_the_clipboard_feature() {
+    let mut command = ditherer();
+    command.arg("--clipboard-in");
+    // Without the test hook the variable is ignored, so no command can stand in for the clipboard.
+    #[cfg(feature = "clipboard-test-hook")]
+    command.env_remove("DITHERER_CLIPBOARD_PASTE");
+    #[cfg(not(feature = "clipboard-test-hook"))]
+    command.env("DITHERER_CLIPBOARD_PASTE", "echo not an image");
+    command
+        .assert()
+        .code(5)
+        .stderr(predicate::str::contains("without the clipboard feature"));
+}
+
+#[test]
+fn writes_multi_size_ico() {
+    let input = fixture("ico.png");
+    let output = temp_path("ico_out.ico");
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--ico-sizes")
+        .arg("16,32")
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    let bytes = std::fs::read(&output).unwrap();
+    assert_eq!(&bytes[..6], &[0, 0, 1, 0, 2, 0]);
+    let icon = image::load_from_memory_with_format(&bytes, image::ImageFormat::Ico).unwrap();
+    assert_eq!(icon.dimensions(), (32, 32));
+}
+
+#[test]
+fn letterboxes_ico_frames_by_default() {
+    let input = fixture("ico_fit.png");
+    let output = temp_path("ico_fit_out.ico");
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--ico-sizes")
+        .arg("32")
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    let icon = image::open(&output).unwrap().to_rgba8();
+    assert_eq!(icon.get_pixel(0, 0)[3], 0);
+    assert_eq!(icon.get_pixel(0, 16)[3], 255);
+}
+
+#[test]
+fn stretches_ico_frames_on_request() {
+    let input = fixture("ico_stretch.png");
+    let output = temp_path("ico_stretch_out.ico");
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--ico-sizes")
+        .arg("32")
+        .arg("--preserve-aspect")
+        .arg("stretch")
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    let icon = image::open(&output).unwrap().to_rgba8();
+    assert!(icon.pixels().all(|pixel| pixel[3] == 255));
+}
+
+#[test]
+fn writes_p5js_sketch() {
+    let input = fixture("p5js.png");
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--format")
+        .arg("p5js")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "createCanvas(32 * PIXEL_SIZE, 24 * PIXEL_SIZE);",
+        ))
+        .stdout(predicate::str::contains("rect("));
+}
+
+#[test]
+fn exits_with_timeout_code_when_too_slow() {
+    let input = temp_path("timeout.png");
+    ImageBuffer::from_fn(1024, 1024, |x, y| Rgb([(x ^ y) as u8, x as u8, y as u8]))
+        .save(&input)
+        .unwrap();
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--timeout")
+        .arg("0.000000001")
+        .assert()
+        .code(124)
+        .stderr(predicate::str::contains("timed out"));
+}
+
+#[test]
+fn finishes_within_generous_timeout() {
+    let input = fixture("timeout_ok.png");
+    let output = temp_path("timeout_ok_out.png");
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--timeout")
+        .arg("60")
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    assert!(image::open(&output).is_ok());
+}
+
+#[test]
+fn swaps_channels_before_color_dithering() {
+    let input = fixture("channel_swap.png");
+    let output = temp_path("channel_swap_out.png");
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--color")
+        .arg("--channel-swap")
+        .arg("rgb->bgr")
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    let dithered = image::open(&output).unwrap().to_rgba8();
+    let pixel = dithered.get_pixel(FIXTURE_WIDTH - 1, 0);
+    assert_eq!(&pixel.0[..3], &[128, 0, ((FIXTURE_WIDTH - 1) * 8) as u8]);
+}
+
+#[test]
+fn rejects_invalid_channel_swap() {
+    ditherer()
+        .arg("--channel-swap")
+        .arg("rgb->rrg")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid channel swap."));
+}
+
+#[test]
+fn display_gamma_darkens_midtones() {
+    let input = temp_path("display_gamma.png");
+    ImageBuffer::from_pixel(16, 16, Rgb([128u8, 128, 128]))
+        .save(&input)
+        .unwrap();
+
+    let white_pixels = |gamma: &str| {
+        let assert = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--display-gamma")
+            .arg(gamma)
+            .assert()
+            .success();
+        let dithered = image::load_from_memory(&assert.get_output().stdout)
+            .unwrap()
+            .to_luma8();
+        dithered.pixels().filter(|pixel| pixel[0] == 255).count()
+    };
+
+    assert!(white_pixels("2.2") < white_pixels("1"));
+}
+
+#[test]
+fn suggests_seamless_dimensions() {
+    let input = fixture("seamless.png");
+    let threshold_map = temp_path("seamless.txt");
+    std::fs::write(&threshold_map, "0.1 0.5 0.3\n0.7 0.2 0.8\n0.4 0.9 0.6\n").unwrap();
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--threshold-map")
+        .arg(&threshold_map)
+        .arg("--check-seamless")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Nearest seamless dimensions: 33x24",
+        ));
+}
+
+#[test]
+fn writes_one_bit_png() {
+    let input = fixture("one_bit.png");
+    let output = temp_path("one_bit_out.png");
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--output-bits")
+        .arg("1")
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    let decoder = png::Decoder::new(std::fs::File::open(&output).unwrap());
+    let reader = decoder.read_info().unwrap();
+    assert_eq!(reader.info().bit_depth, png::BitDepth::One);
+    assert_eq!(reader.info().color_type, png::ColorType::Grayscale);
+
+    let reference = ditherer().arg("-i").arg(&input).assert().success();
+    let reference = image::load_from_memory(&reference.get_output().stdout)
+        .unwrap()
+        .to_luma8();
+    assert_eq!(image::open(&output).unwrap().to_luma8(), reference);
+}
+
+#[test]
+fn writes_one_bit_bmp_and_pbm_with_output_bits() {
+    let input = fixture("one_bit_formats.png");
+    let encode = |format: &str| {
+        ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--output-bits")
+            .arg("1")
+            .arg("--format")
+            .arg(format)
+            .assert()
+            .success()
+            .get_output()
+            .clone()
+    };
+    let reference = ditherer().arg("-i").arg(&input).assert().success();
+    let reference = image::load_from_memory(&reference.get_output().stdout)
+        .unwrap()
+        .to_luma8();
+
+    let bmp = encode("bmp").stdout;
+    assert_eq!(&bmp[..2], b"BM");
+    assert_eq!(u16::from_le_bytes([bmp[28], bmp[29]]), 1, "bits per pixel");
+    assert_eq!(image::load_from_memory(&bmp).unwrap().to_luma8(), reference);
+
+    let pnm = encode("pnm").stdout;
+    assert!(pnm.starts_with(b"P4"));
+    assert_eq!(image::load_from_memory(&pnm).unwrap().to_luma8(), reference);
+
+    let webp = encode("webp");
+    assert_eq!(&webp.stdout[8..12], b"WEBP");
+    assert!(String::from_utf8_lossy(&webp.stderr).contains("no 1-bit mode"));
+}
+
+#[test]
+fn prints_sample_point_decision() {
+    let input = fixture("sample_point.png");
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--sample-point")
+        .arg("3,2")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Sample point 3,2:"))
+        .stderr(predicate::str::contains("input RGB: 24, 20, 128"))
+        .stderr(predicate::str::contains("decision:"));
+}
+
+#[test]
+fn downscales_to_memory_limit() {
+    let input = temp_path("memory_limit.png");
+    ImageBuffer::from_fn(512, 512, |x, y| Rgb([x as u8, y as u8, 128]))
+        .save(&input)
+        .unwrap();
+
+    let assert = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--algorithm")
+        .arg("floyd-steinberg")
+        .arg("--memory-limit")
+        .arg("1")
+        .arg("--verbose")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Downscaling 512x512 to 295x295 while decoding",
+        ));
+
+    let dithered = image::load_from_memory(&assert.get_output().stdout).unwrap();
+    assert_eq!((dithered.width(), dithered.height()), (295, 295));
+
+    // Without --verbose the strategy is not reported.
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--algorithm")
+        .arg("floyd-steinberg")
+        .arg("--memory-limit")
+        .arg("1")
+        .assert()
+        .success()
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn dithers_inputs_over_the_memory_limit() {
+    // 12 MB of decoded RGB, more than the 8 MB budget before any dithering buffer.
+    let input = temp_path("memory_limit_large.png");
+    ImageBuffer::from_fn(2000, 2000, |x, y| Rgb([(x / 8) as u8, (y / 8) as u8, 128]))
+        .save(&input)
+        .unwrap();
+
+    let dither = |algorithm: &str| {
+        let assert = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--algorithm")
+            .arg(algorithm)
+            .arg("--memory-limit")
+            .arg("8")
+            .arg("--verbose")
+            .assert()
+            .success();
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        let dithered = image::load_from_memory(&assert.get_output().stdout).unwrap();
+        (stderr, dithered.width(), dithered.height())
+    };
+
+    // Ordered algorithms keep the full size by dithering in strips.
+    let (stderr, width, height) = dither("bayer");
+    assert!(stderr.contains("Dithering 2000x2000 in strips of 322 rows"));
+    assert_eq!((width, height), (2000, 2000));
+
+    let (stderr, width, height) = dither("atkinson");
+    assert!(stderr.contains("Downscaling 2000x2000 to 836x836 while decoding"));
+    assert_eq!((width, height), (836, 836));
+}
+
+#[test]
+#[cfg(not(feature = "clipboard"))]
+fn memory_limit_keeps_clipboard_output_off_the_strip_path() {
+    let input = temp_path("memory_limit_clipboard.png");
+    ImageBuffer::from_fn(2000, 2000, |x, y| Rgb([(x / 8) as u8, (y / 8) as u8, 128]))
+        .save(&input)
+        .unwrap();
+
+    // The strips would be written to stdout; the clipboard is the only place the PNG may go.
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--clipboard-out")
+        .arg("--memory-limit")
+        .arg("8")
+        .env_remove("DITHERER_CLIPBOARD_COPY")
+        .assert()
+        .code(5)
+        .stdout("")
+        .stderr(predicate::str::contains("without the clipboard feature"));
+}
+
+#[test]
+fn tags_output_colorspace() {
+    let input = fixture("colorspace.png");
+
+    let png_info = |colorspace: &str| {
+        let assert = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--output-colorspace")
+            .arg(colorspace)
+            .assert()
+            .success();
+        let decoder = png::Decoder::new(std::io::Cursor::new(assert.get_output().stdout.clone()));
+        decoder.read_info().unwrap().info().clone()
+    };
+
+    assert!(png_info("srgb").srgb.is_some());
+    let linear = png_info("linear");
+    assert!(linear.srgb.is_none());
+    assert_eq!(linear.source_gamma.unwrap().into_scaled(), 100000);
+}
+
+#[test]
+fn converts_pixels_to_output_colorspace() {
+    let input = temp_path("output_colorspace_values.png");
+    ImageBuffer::from_fn(8, 8, |x, _| {
+        if x < 4 {
+            Rgb([128u8, 128, 128])
+        } else {
+            Rgb([255, 0, 0])
+        }
+    })
+    .save(&input)
+    .unwrap();
+    let palette = temp_path("output_colorspace_values.hex");
+    std::fs::write(&palette, "000000\n808080\nffffff\nff0000\n").unwrap();
+
+    // Mid-gray dithers to mid-gray and black on the left half; red stays red on the right.
+    let colors = |colorspace: &str| {
+        let output = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--palette")
+            .arg(&palette)
+            .arg("--output-colorspace")
+            .arg(colorspace)
+            .assert()
+            .success();
+        let image = image::load_from_memory(&output.get_output().stdout)
+            .unwrap()
+            .to_rgb8();
+        let mut colors: Vec<[u8; 3]> = image.pixels().map(|pixel| pixel.0).collect();
+        colors.sort();
+        colors.dedup();
+        colors
+    };
+
+    assert_eq!(colors("srgb"), [[0, 0, 0], [128, 128, 128], [255, 0, 0]]);
+    assert_eq!(colors("linear"), [[0, 0, 0], [55, 55, 55], [255, 0, 0]]);
+    assert_eq!(
+        colors("display-p3"),
+        [[0, 0, 0], [127, 127, 127], [233, 54, 40]]
+    );
+}
+
+#[test]
+fn floyd_steinberg_preserves_average_tone() {
+    let input = temp_path("floyd_steinberg.png");
+    ImageBuffer::from_pixel(32, 32, Rgb([64u8, 64, 64]))
+        .save(&input)
+        .unwrap();
+
+    let assert = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--algorithm")
+        .arg("floyd-steinberg")
+        .arg("--no-gamma")
+        .assert()
+        .success();
+
+    let dithered = image::load_from_memory(&assert.get_output().stdout)
+        .unwrap()
+        .to_luma8();
+    let white = dithered.pixels().filter(|pixel| pixel[0] == 255).count();
+    assert!((240..=272).contains(&white), "{} white pixels", white);
+}
+
+#[test]
+fn dithers_with_every_diffusion_kernel() {
+    let input = fixture("kernels.png");
+
+    for algorithm in [
+        "atkinson",
+        "sierra",
+        "sierra-lite",
+        "stucki",
+        "burkes",
+        "jjn",
+    ] {
+        let assert = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--algorithm")
+            .arg(algorithm)
+            .assert()
+            .success();
+
+        let dithered = image::load_from_memory(&assert.get_output().stdout)
+            .unwrap()
+            .to_luma8();
+        assert!(dithered
+            .pixels()
+            .all(|pixel| pixel[0] == 0 || pixel[0] == 255));
+    }
+}
+
+#[test]
+fn dithers_to_hex_palette() {
+    let input = fixture("palette.png");
+    let palette = temp_path("palette.hex");
+    std::fs::write(&palette, "; red and blue\n#ff0000, 0000ff\n").unwrap();
+
+    for algorithm in ["bayer", "floyd-steinberg"] {
+        let assert = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--palette")
+            .arg(&palette)
+            .arg("--algorithm")
+            .arg(algorithm)
+            .assert()
+            .success();
+
+        let dithered = image::load_from_memory(&assert.get_output().stdout)
+            .unwrap()
+            .to_rgb8();
+        assert!(dithered
+            .pixels()
+            .all(|pixel| pixel.0 == [255, 0, 0] || pixel.0 == [0, 0, 255]));
+    }
+}
+
+#[test]
+fn reads_gimp_palette() {
+    let input = fixture("palette_gpl.png");
+    let palette = temp_path("palette.gpl");
+    std::fs::write(
+        &palette,
+        "GIMP Palette\nName: Test\nColumns: 2\n#\n  0 128   0 Green\n255 255 255 White\n",
+    )
+    .unwrap();
+
+    let assert = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--palette")
+        .arg(&palette)
+        .assert()
+        .success();
+
+    let dithered = image::load_from_memory(&assert.get_output().stdout)
+        .unwrap()
+        .to_rgb8();
+    assert!(dithered
+        .pixels()
+        .all(|pixel| pixel.0 == [0, 128, 0] || pixel.0 == [255, 255, 255]));
+}
+
+#[test]
+fn blue_noise_reproduces_flat_tone() {
+    let input = temp_path("blue_noise.png");
+    ImageBuffer::from_pixel(32, 32, Rgb([63u8, 63, 63]))
+        .save(&input)
+        .unwrap();
+
+    let assert = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--algorithm")
+        .arg("blue-noise")
+        .arg("--noise-size")
+        .arg("16")
+        .arg("--no-gamma")
+        .assert()
+        .success();
+
+    let dithered = image::load_from_memory(&assert.get_output().stdout)
+        .unwrap()
+        .to_luma8();
+    let white = dithered.pixels().filter(|pixel| pixel[0] == 255).count();
+    assert_eq!(white, 256);
+}
+
+#[test]
+fn dithers_rgb_channels_independently() {
+    let input = fixture("rgb.png");
+
+    let assert = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--rgb")
+        .assert()
+        .success();
+
+    let dithered = image::load_from_memory(&assert.get_output().stdout)
+        .unwrap()
+        .to_rgba8();
+    assert!(dithered
+        .pixels()
+        .all(|pixel| pixel.0.iter().all(|&value| value == 0 || value == 255)));
+    assert!(dithered
+        .pixels()
+        .any(|pixel| pixel[0] != pixel[1] || pixel[1] != pixel[2]));
+}
+
+#[test]
+fn zero_strength_is_plain_threshold() {
+    let input = fixture("strength.png");
+
+    for algorithm in ["bayer", "floyd-steinberg"] {
+        let assert = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--algorithm")
+            .arg(algorithm)
+            .arg("--strength")
+            .arg("0")
+            .arg("--no-gamma")
+            .assert()
+            .success();
+
+        let dithered = image::load_from_memory(&assert.get_output().stdout)
+            .unwrap()
+            .to_luma8();
+        let source = image::open(&input).unwrap().to_luma8();
+        for (dithered, source) in dithered.pixels().zip(source.pixels()) {
+            assert_eq!(dithered[0] == 255, source[0] as f32 / 255.0 > 0.5);
+        }
+    }
+}
+
+#[test]
+fn thresholds_in_linear_light_by_default() {
+    let input = temp_path("linear_light.png");
+    ImageBuffer::from_pixel(32, 32, Rgb([188u8, 188, 188]))
+        .save(&input)
+        .unwrap();
+
+    let white_pixels = |gamma_flag: Option<&str>| {
+        let mut command = ditherer();
+        command
+            .arg("-i")
+            .arg(&input)
+            .arg("--algorithm")
+            .arg("floyd-steinberg");
+        if let Some(flag) = gamma_flag {
+            command.arg(flag);
+        }
+        let assert = command.assert().success();
+        let dithered = image::load_from_memory(&assert.get_output().stdout)
+            .unwrap()
+            .to_luma8();
+        dithered.pixels().filter(|pixel| pixel[0] == 255).count()
+    };
+
+    assert!((496..=528).contains(&white_pixels(None)));
+    assert!(white_pixels(Some("--no-gamma")) > 700);
+}
+
+#[test]
+fn dithers_directory_recursively() {
+    let input_dir = temp_path("batch_in");
+    let output_dir = temp_path("batch_out");
+    let _ = std::fs::remove_dir_all(&input_dir);
+    let _ = std::fs::remove_dir_all(&output_dir);
+    std::fs::create_dir_all(input_dir.join("nested")).unwrap();
+    for name in ["a.png", "nested/b.png"] {
+        std::fs::copy(fixture("batch.png"), input_dir.join(name)).unwrap();
+    }
+    std::fs::write(input_dir.join("notes.txt"), "not an image").unwrap();
+
+    ditherer()
+        .arg("--input-dir")
+        .arg(&input_dir)
+        .arg("--output-dir")
+        .arg(&output_dir)
+        .arg("--recursive")
+        .assert()
+        .success();
+
+    for name in ["a.png", "nested/b.png"] {
+        let dithered = image::open(output_dir.join(name)).unwrap();
+        assert_eq!(dithered.dimensions(), (FIXTURE_WIDTH, FIXTURE_HEIGHT));
+    }
+    assert!(!output_dir.join("notes.txt").exists());
+}
+
+#[test]
+fn single_thread_matches_parallel_output() {
+    let input = fixture("threads.png");
+    let dither = |threads: Option<&str>, name: &str| {
+        let output = temp_path(name);
+        let mut command = ditherer();
+        command
+            .arg("-i")
+            .arg(&input)
+            .arg("-o")
+            .arg(&output)
+            .arg("--color");
+        if let Some(threads) = threads {
+            command.arg("--threads").arg(threads);
+        }
+        command.assert().success();
+        image::open(&output).unwrap().to_rgba8()
+    };
+
+    assert_eq!(
+        dither(Some("1"), "threads_single.png"),
+        dither(None, "threads_parallel.png")
+    );
+}
+
+#[test]
+fn parallel_error_diffusion_matches_single_thread() {
+    let input = fixture("threads_diffusion.png");
+    let dither = |algorithm: &str, threads: &str| {
+        let output = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--algorithm")
+            .arg(algorithm)
+            .arg("--threads")
+            .arg(threads)
+            .assert()
+            .success();
+        image::load_from_memory(&output.get_output().stdout)
+            .unwrap()
+            .to_rgba8()
+    };
+
+    for algorithm in ["floyd-steinberg", "jjn", "ostromoukhov"] {
+        assert_eq!(
+            dither(algorithm, "1"),
+            dither(algorithm, "4"),
+            "{}",
+            algorithm
+        );
+    }
+}
+
+#[test]
+fn dithers_every_gif_frame() {
+    use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+    use image::{AnimationDecoder, Delay, Frame, Rgba};
+
+    let input = temp_path("animation.gif");
+    let output = temp_path("animation_out.gif");
+    let mut encoder = GifEncoder::new(std::fs::File::create(&input).unwrap());
+    encoder.set_repeat(Repeat::Finite(3)).unwrap();
+    encoder
+        .encode_frames((1..=3).map(|shade| {
+            let buffer = ImageBuffer::from_pixel(16, 16, Rgba([shade * 60, shade * 60, 0, 255]));
+            Frame::from_parts(
+                buffer,
+                0,
+                0,
+                Delay::from_numer_denom_ms(shade as u32 * 100, 1),
+            )
+        }))
+        .unwrap();
+    drop(encoder);
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    let bytes = std::fs::read(&output).unwrap();
+    let repeat = gif::DecodeOptions::new()
+        .read_info(std::io::Cursor::new(&bytes))
+        .unwrap()
+        .repeat();
+    assert_eq!(repeat, gif::Repeat::Finite(3));
+
+    let frames = GifDecoder::new(std::io::Cursor::new(&bytes))
+        .unwrap()
+        .into_frames()
+        .collect_frames()
+        .unwrap();
+    let delays: Vec<_> = frames
+        .iter()
+        .map(|frame| frame.delay().numer_denom_ms())
+        .collect();
+    assert_eq!(delays, [(100, 1), (200, 1), (300, 1)]);
+    for frame in &frames {
+        assert!(frame
+            .buffer()
+            .pixels()
+            .all(|pixel| pixel[0] == 0 || pixel[0] == 255));
+    }
+}
+
+#[test]
+fn writes_indexed_png_for_palette_output() {
+    let input = fixture("indexed.png");
+    let output = temp_path("indexed_out.png");
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--palette")
+        .arg("rgb8")
+        .arg("--output-depth")
+        .arg("indexed")
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    let decoder = png::Decoder::new(std::fs::File::open(&output).unwrap());
+    let reader = decoder.read_info().unwrap();
+    assert_eq!(reader.info().color_type, png::ColorType::Indexed);
+    assert!(reader.info().palette.as_ref().unwrap().len() <= 8 * 3);
+
+    let reference = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--palette")
+        .arg("rgb8")
+        .assert()
+        .success();
+    let reference = image::load_from_memory(&reference.get_output().stdout)
+        .unwrap()
+        .to_rgba8();
+    assert_eq!(image::open(&output).unwrap().to_rgba8(), reference);
+}
+
+#[test]
+fn writes_palette_output_as_indexed_png_and_gif_by_default() {
+    let input = fixture("indexed_auto.png");
+    let palette = temp_path("indexed_auto.hex");
+    // The last color is never used, but is still embedded at its index.
+    std::fs::write(&palette, "#ffffff #000000 #ff0000 #00ff00\n").unwrap();
+    let expected_palette = [255, 255, 255, 0, 0, 0, 255, 0, 0, 0, 255, 0];
+
+    let dither_to = |name: &str| {
+        let output = temp_path(name);
+        ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--palette")
+            .arg(&palette)
+            .arg("-o")
+            .arg(&output)
+            .assert()
+            .success();
+        output
+    };
+
+    let png_output = dither_to("indexed_auto_out.png");
+    let reader = png::Decoder::new(std::fs::File::open(&png_output).unwrap())
+        .read_info()
+        .unwrap();
+    assert_eq!(reader.info().color_type, png::ColorType::Indexed);
+    assert_eq!(
+        reader.info().palette.as_deref(),
+        Some(&expected_palette[..])
+    );
+
+    let gif_output = dither_to("indexed_auto_out.gif");
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options
+        .read_info(std::fs::File::open(&gif_output).unwrap())
+        .unwrap();
+    assert_eq!(
+        &decoder.global_palette().unwrap()[..expected_palette.len()],
+        &expected_palette[..]
+    );
+    let frame = decoder.read_next_frame().unwrap().unwrap().clone();
+    assert!(frame.buffer.iter().all(|&index| index < 4));
+
+    let reference = image::open(&png_output).unwrap().to_rgba8();
+    assert_eq!(image::open(&gif_output).unwrap().to_rgba8(), reference);
+}
+
+#[test]
+fn writes_black_and_white_output_as_1bit_png_by_default() {
+    let input = fixture("gray1_auto.png");
+    let output = temp_path("gray1_auto_out.png");
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    let reader = png::Decoder::new(std::fs::File::open(&output).unwrap())
+        .read_info()
+        .unwrap();
+    assert_eq!(reader.info().color_type, png::ColorType::Grayscale);
+    assert_eq!(reader.info().bit_depth, png::BitDepth::One);
+}
+
+#[test]
+fn writes_8bit_grayscale_png() {
+    let input = fixture("gray8.png");
+    let output = temp_path("gray8_out.png");
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--output-depth")
+        .arg("8")
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    let decoder = png::Decoder::new(std::fs::File::open(&output).unwrap());
+    let reader = decoder.read_info().unwrap();
+    assert_eq!(reader.info().color_type, png::ColorType::Grayscale);
+    assert_eq!(reader.info().bit_depth, png::BitDepth::Eight);
+}
+
+#[test]
+fn generated_bayer_matrix_has_distinct_thresholds() {
+    let input = temp_path("m16.png");
+    ImageBuffer::from_pixel(16, 16, Rgb([127u8, 127, 127]))
+        .save(&input)
+        .unwrap();
+
+    let output = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("-m")
+        .arg("m16")
+        .arg("--no-gamma")
+        .assert()
+        .success();
+    let dithered = image::load_from_memory(&output.get_output().stdout)
+        .unwrap()
+        .to_luma8();
+
+    assert_eq!(
+        dithered.pixels().filter(|pixel| pixel[0] == 255).count(),
+        128
+    );
+}
+
+#[test]
+fn halftone_grows_dots_from_cell_centers() {
+    let input = temp_path("halftone.png");
+    ImageBuffer::from_pixel(16, 16, Rgb([127u8, 127, 127]))
+        .save(&input)
+        .unwrap();
+
+    let output = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--algorithm")
+        .arg("halftone")
+        .arg("--dot-size")
+        .arg("8")
+        .arg("--angle")
+        .arg("0")
+        .arg("--no-gamma")
+        .assert()
+        .success();
+    let dithered = image::load_from_memory(&output.get_output().stdout)
+        .unwrap()
+        .to_luma8();
+
+    for (cell_x, cell_y) in [(0, 0), (8, 0), (0, 8), (8, 8)] {
+        assert_eq!(dithered.get_pixel(cell_x + 4, cell_y + 4)[0], 0);
+        assert_eq!(dithered.get_pixel(cell_x, cell_y)[0], 255);
+    }
+    assert_eq!(dithered.pixels().filter(|pixel| pixel[0] == 0).count(), 128);
+
+    let output = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--algorithm")
+        .arg("halftone")
+        .arg("--dot-size")
+        .arg("8")
+        .arg("--angle")
+        .arg("0")
+        .arg("--dot-shape")
+        .arg("line")
+        .arg("--no-gamma")
+        .assert()
+        .success();
+    let lines = image::load_from_memory(&output.get_output().stdout)
+        .unwrap()
+        .to_luma8();
+    for (x, y, pixel) in lines.enumerate_pixels() {
+        match y % 8 {
+            0 | 7 => assert_eq!(pixel[0], 255, "({}, {})", x, y),
+            2..=5 => assert_eq!(pixel[0], 0, "({}, {})", x, y),
+            _ => {}
+        }
+    }
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--dot-shape")
+        .arg("star")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid dot shape"));
+}
+
+#[test]
+fn serpentine_scan_reverses_odd_rows() {
+    let input = fixture("serpentine.png");
+    let dither = |serpentine: bool| {
+        let mut command = ditherer();
+        command
+            .arg("-i")
+            .arg(&input)
+            .arg("--algorithm")
+            .arg("floyd-steinberg");
+        if serpentine {
+            command.arg("--serpentine");
+        }
+        let output = command.assert().success();
+        image::load_from_memory(&output.get_output().stdout)
+            .unwrap()
+            .to_luma8()
+    };
+
+    let raster = dither(false);
+    let serpentine = dither(true);
+    let first_row = |image: &image::GrayImage| {
+        (0..FIXTURE_WIDTH)
+            .map(|x| image.get_pixel(x, 0)[0])
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(first_row(&raster), first_row(&serpentine));
+    assert_ne!(raster, serpentine);
+}
+
+#[test]
+fn preprocesses_size_and_tone() {
+    let input = fixture("preprocess.png");
+    let dithered = |flags: &[&str]| {
+        let output = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .args(flags)
+            .assert()
+            .success();
+        image::load_from_memory(&output.get_output().stdout)
+            .unwrap()
+            .to_luma8()
+    };
+
+    assert_eq!(dithered(&["--resize", "16x"]).dimensions(), (16, 12));
+    assert_eq!(dithered(&["--resize", "8x8"]).dimensions(), (8, 6));
+    for mode in ["fill", "stretch"] {
+        assert_eq!(
+            dithered(&["--resize", "8x8", "--preserve-aspect", mode]).dimensions(),
+            (8, 8)
+        );
+    }
+    assert_eq!(dithered(&["--scale", "0.5"]).dimensions(), (16, 12));
+    assert!(dithered(&["--brightness", "1"])
+        .pixels()
+        .all(|pixel| pixel[0] == 255));
+    assert!(dithered(&["--brightness", "-1"])
+        .pixels()
+        .all(|pixel| pixel[0] == 0));
+    assert!(dithered(&["--contrast", "0"])
+        .pixels()
+        .any(|pixel| pixel[0] == 255));
+}
+
+#[test]
+fn renders_ansi_preview() {
+    let input = fixture("ansi.png");
+
+    let output = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--format")
+        .arg("ansi")
+        .env("COLUMNS", "16")
+        .assert()
+        .success();
+    let preview = String::from_utf8(output.get_output().stdout.clone()).unwrap();
+
+    let lines: Vec<&str> = preview.lines().collect();
+    assert_eq!(lines.len(), 6);
+    for line in lines {
+        assert_eq!(line.matches('\u{2580}').count(), 16);
+        assert!(line.ends_with("\x1b[0m"));
+    }
+}
+
+#[test]
+fn renders_ascii_art() {
+    let input = fixture("ascii.png");
+
+    let output = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--format")
+        .arg("ascii")
+        .arg("--charset")
+        .arg("#. ")
+        .env("COLUMNS", "8")
+        .assert()
+        .success();
+    let art = String::from_utf8(output.get_output().stdout.clone()).unwrap();
+
+    let lines: Vec<&str> = art.lines().collect();
+    assert_eq!(lines.len(), 3);
+    for line in lines {
+        assert_eq!(line.chars().count(), 8);
+        assert!(line.chars().all(|c| "#. ".contains(c)));
+    }
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--format")
+        .arg("ascii")
+        .arg("--charset")
+        .arg("#")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn writes_svg_runs_and_circles() {
+    let input = temp_path("svg.png");
+    ImageBuffer::from_fn(4, 2, |x, y| {
+        if y == 0 && x < 3 {
+            Rgb([0u8, 0, 0])
+        } else {
+            Rgb([255, 255, 255])
+        }
+    })
+    .save(&input)
+    .unwrap();
+
+    let svg = |shape: &str| {
+        let output = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--format")
+            .arg("svg")
+            .arg("--svg-dot")
+            .arg(shape)
+            .assert()
+            .success();
+        String::from_utf8(output.get_output().stdout.clone()).unwrap()
+    };
+
+    let squares = svg("square");
+    assert!(squares.starts_with("<svg"));
+    assert!(squares.contains("<rect x=\"0\" y=\"0\" width=\"3\" height=\"1\" fill=\"#000000\"/>"));
+    assert_eq!(svg("circle").matches("<circle").count(), 3);
+}
+
+#[test]
+fn riemersma_preserves_average_tone() {
+    let input = temp_path("riemersma.png");
+    ImageBuffer::from_pixel(40, 30, Rgb([64u8, 64, 64]))
+        .save(&input)
+        .unwrap();
+
+    let output = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--algorithm")
+        .arg("riemersma")
+        .arg("--no-gamma")
+        .assert()
+        .success();
+    let dithered = image::load_from_memory(&output.get_output().stdout)
+        .unwrap()
+        .to_luma8();
+
+    assert_eq!(dithered.dimensions(), (40, 30));
+    let white = dithered.pixels().filter(|pixel| pixel[0] == 255).count();
+    assert!((270..=330).contains(&white), "{} white pixels", white);
+}
+
+#[test]
+fn ostromoukhov_preserves_highlight_and_shadow_tones() {
+    for shade in [16u8, 240] {
+        let input = temp_path(&format!("ostromoukhov_{}.png", shade));
+        ImageBuffer::from_pixel(40, 30, Rgb([shade, shade, shade]))
+            .save(&input)
+            .unwrap();
+
+        let output = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--algorithm")
+            .arg("ostromoukhov")
+            .arg("--no-gamma")
+            .assert()
+            .success();
+        let dithered = image::load_from_memory(&output.get_output().stdout)
+            .unwrap()
+            .to_luma8();
+
+        let white = dithered.pixels().filter(|pixel| pixel[0] == 255).count() as i32;
+        let expected = 40 * 30 * shade as i32 / 255;
+        assert!((white - expected).abs() <= 20, "{} white pixels", white);
+    }
+}
+
+#[test]
+fn derives_palette_with_median_cut() {
+    let input = fixture("median_cut.png");
+
+    let output = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--colors")
+        .arg("4")
+        .arg("--algorithm")
+        .arg("floyd-steinberg")
+        .assert()
+        .success();
+    let dithered = image::load_from_memory(&output.get_output().stdout)
+        .unwrap()
+        .to_rgb8();
+
+    let mut colors: Vec<[u8; 3]> = dithered.pixels().map(|pixel| pixel.0).collect();
+    colors.sort_unstable();
+    colors.dedup();
+    assert!(colors.len() <= 4 && colors.len() > 1, "{:?}", colors);
+    assert!(colors.iter().all(|color| color[2] == 128));
+}
+
+#[test]
+fn matches_palette_colors_in_lab() {
+    let input = temp_path("color_space.png");
+    let palette = temp_path("color_space.hex");
+    ImageBuffer::from_pixel(4, 4, Rgb([0u8, 0, 255]))
+        .save(&input)
+        .unwrap();
+    std::fs::write(&palette, "#8080ff #000080\n").unwrap();
+
+    let nearest = |color_space: &str| {
+        let output = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--palette")
+            .arg(&palette)
+            .arg("--strength")
+            .arg("0")
+            .arg("--color-space")
+            .arg(color_space)
+            .assert()
+            .success();
+        let dithered = image::load_from_memory(&output.get_output().stdout)
+            .unwrap()
+            .to_rgb8();
+        dithered.get_pixel(0, 0).0
+    };
+
+    assert_eq!(nearest("rgb"), [128, 128, 255]);
+    assert_eq!(nearest("lab"), [0, 0, 128]);
+}
+
+#[test]
+fn pattern_algorithms_mix_palette_colors_to_average_the_input() {
+    let input = temp_path("knoll.png");
+    let palette = temp_path("knoll.hex");
+    ImageBuffer::from_pixel(8, 8, Rgb([255u8, 128, 128]))
+        .save(&input)
+        .unwrap();
+    std::fs::write(&palette, "#000000 #ffffff #ff0000\n").unwrap();
+
+    for algorithm in ["knoll", "yliluoma1", "yliluoma2"] {
+        let assert = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--palette")
+            .arg(&palette)
+            .arg("--algorithm")
+            .arg(algorithm)
+            .arg("--no-gamma")
+            .assert()
+            .success();
+
+        let dithered = image::load_from_memory(&assert.get_output().stdout)
+            .unwrap()
+            .to_rgb8();
+        let count = |color: [u8; 3]| dithered.pixels().filter(|pixel| pixel.0 == color).count();
+        assert_eq!(count([255, 0, 0]), 32, "{}", algorithm);
+        assert_eq!(count([255, 255, 255]), 32, "{}", algorithm);
+    }
+}
+
+#[test]
+fn yliluoma_mixes_in_linear_light() {
+    let input = temp_path("yliluoma_gray.png");
+    let palette = temp_path("yliluoma_gray.hex");
+    ImageBuffer::from_pixel(8, 8, Rgb([128u8, 128, 128]))
+        .save(&input)
+        .unwrap();
+    std::fs::write(&palette, "#000000 #ffffff\n").unwrap();
+
+    for algorithm in ["yliluoma1", "yliluoma2"] {
+        let assert = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--palette")
+            .arg(&palette)
+            .arg("--algorithm")
+            .arg(algorithm)
+            .assert()
+            .success();
+
+        // sRGB 128 is about 22% of white's light, so 3 or 4 of every 16 pixels are white.
+        let dithered = image::load_from_memory(&assert.get_output().stdout)
+            .unwrap()
+            .to_luma8();
+        let white = dithered.pixels().filter(|pixel| pixel[0] == 255).count();
+        assert!((12..=16).contains(&white), "{}: {} white", algorithm, white);
+    }
+}
+
+#[test]
+fn tiled_output_matches_whole_image() {
+    let input = fixture("tiled.png");
+    let dither = |tile_height: Option<&str>, name: &str| {
+        let output = temp_path(name);
+        let mut command = ditherer();
+        command
+            .arg("-i")
+            .arg(&input)
+            .arg("-o")
+            .arg(&output)
+            .arg("-m")
+            .arg("m8")
+            .arg("--color");
+        if let Some(tile_height) = tile_height {
+            command.arg("--tile-height").arg(tile_height);
+        }
+        command.assert().success();
+        image::open(&output).unwrap().to_rgba8()
+    };
+
+    assert_eq!(
+        dither(Some("5"), "tiled_strips.png"),
+        dither(None, "tiled_whole.png")
+    );
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--tile-height")
+        .arg("5")
+        .arg("--algorithm")
+        .arg("atkinson")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "--tile-height only supports ordered algorithms",
+        ));
+}
+
+#[test]
+fn writes_data_uri_in_chosen_format() {
+    let input = fixture("data_uri.png");
+    let data_uri = |format: &str| {
+        let output = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--format")
+            .arg(format)
+            .assert()
+            .success();
+        String::from_utf8(output.get_output().stdout.clone()).unwrap()
+    };
+
+    // "iVBORw0KGgo" and "UklG" are the base64 of the PNG and RIFF signatures.
+    assert!(data_uri("data-uri").starts_with("data:image/png;base64,iVBORw0KGgo"));
+    assert!(data_uri("data-uri:webp").starts_with("data:image/webp;base64,UklG"));
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--format")
+        .arg("data-uri:svg")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid output format"));
+}
+
+#[test]
+fn encodes_stdout_and_extensionless_output_in_chosen_format() {
+    let input = fixture("output_format.png");
+    let encode = |format: &str| {
+        ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--output-format")
+            .arg(format)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone()
+    };
+
+    assert!(encode("pnm").starts_with(b"P6"));
+    assert!(encode("qoi").starts_with(b"qoif"));
+    let dithered = image::load_from_memory(&encode("bmp")).unwrap();
+    assert_eq!(dithered.dimensions(), (FIXTURE_WIDTH, FIXTURE_HEIGHT));
+
+    let output = temp_path("output_format_out");
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .arg("--output-format")
+        .arg("webp")
+        .assert()
+        .success();
+    assert_eq!(
+        image::ImageFormat::from_path(&output).ok(),
+        None,
+        "output keeps its extensionless name"
+    );
+    let bytes = std::fs::read(&output).unwrap();
+    assert_eq!(&bytes[8..12], b"WEBP");
+
+    let svg = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--output-format")
+        .arg("svg")
+        .arg("--svg-dot")
+        .arg("circle")
+        .assert()
+        .success();
+    assert!(String::from_utf8_lossy(&svg.get_output().stdout).contains("<circle"));
+
+    let output = temp_path("output_format_out.png");
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .arg("--format")
+        .arg("pbm")
+        .arg("--output-format")
+        .arg("bmp")
+        .assert()
+        .success();
+    assert!(std::fs::read(&output).unwrap().starts_with(b"BM"));
+}
+
+#[test]
+fn applies_alpha_policies() {
+    let input = temp_path("alpha.png");
+    ImageBuffer::from_fn(16, 16, |x, _| {
+        if x < 8 {
+            Rgba([0u8, 0, 0, 0])
+        } else {
+            Rgba([0, 0, 0, 128])
+        }
+    })
+    .save(&input)
+    .unwrap();
+
+    let dither = |policy: &str| {
+        let output = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--alpha")
+            .arg(policy)
+            .assert()
+            .success();
+        image::load_from_memory(&output.get_output().stdout)
+            .unwrap()
+            .to_rgba8()
+    };
+
+    let kept = dither("keep");
+    assert_eq!(kept.get_pixel(0, 0)[3], 0);
+    assert_eq!(kept.get_pixel(15, 15)[3], 128);
+
+    let matted = dither("matte:#ffffff");
+    assert_eq!(matted.get_pixel(0, 0).0, [255, 255, 255, 255]);
+    assert!(matted.pixels().all(|pixel| pixel[3] == 255));
+
+    let dithered = dither("dither");
+    let right_half: Vec<u8> = (8..16)
+        .flat_map(|x| (0..16).map(move |y| (x, y)))
+        .map(|(x, y)| dithered.get_pixel(x, y)[3])
+        .collect();
+    assert!(right_half.iter().all(|&alpha| alpha == 0 || alpha == 255));
+    assert_eq!(right_half.iter().filter(|&&alpha| alpha == 255).count(), 64);
+    assert!((0..8).all(|x| dithered.get_pixel(x, 0)[3] == 0));
+}
+
+#[test]
+fn exports_threshold_map_as_png() {
+    let output = temp_path("exported_m4.png");
+
+    ditherer()
+        .arg("--export-threshold-map")
+        .arg("-m")
+        .arg("m4")
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    let exported = image::open(&output).unwrap().to_luma16();
+    assert_eq!(exported.dimensions(), (4, 4));
+    let mut levels: Vec<u16> = exported.pixels().map(|pixel| pixel[0]).collect();
+    levels.sort_unstable();
+    levels.dedup();
+    assert_eq!(levels.len(), 16);
+
+    ditherer()
+        .arg("--export-threshold-map")
+        .arg("--algorithm")
+        .arg("floyd-steinberg")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "--export-threshold-map only supports ordered algorithms",
+        ));
+}
+
+#[test]
+fn random_thresholds_are_reproducible_per_seed() {
+    let input = fixture("random.png");
+    let dither = |algorithm: &str, seed: &str| {
+        let output = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--algorithm")
+            .arg(algorithm)
+            .arg("--seed")
+            .arg(seed)
+            .assert()
+            .success();
+        image::load_from_memory(&output.get_output().stdout)
+            .unwrap()
+            .to_luma8()
+    };
+
+    assert_eq!(dither("random", "7"), dither("random", "7"));
+    assert_ne!(dither("random", "7"), dither("random", "8"));
+
+    let gray = |width: u32| {
+        let input = temp_path(&format!("seed_grid_{}.png", width));
+        ImageBuffer::from_pixel(width, 8, Rgb([128u8, 128, 128]))
+            .save(&input)
+            .unwrap();
+        let output = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--algorithm")
+            .arg("random")
+            .arg("--seed-grid")
+            .arg("8")
+            .assert()
+            .success();
+        image::load_from_memory(&output.get_output().stdout)
+            .unwrap()
+            .to_luma8()
+    };
+    let (narrow, wide) = (gray(8), gray(24));
+    for (x, y, pixel) in narrow.enumerate_pixels() {
+        assert_eq!(wide.get_pixel(x, y), pixel);
+    }
+    let tile = |x0: u32| {
+        (0..64)
+            .map(|i| wide.get_pixel(x0 + i % 8, i / 8)[0])
+            .collect::<Vec<_>>()
+    };
+    assert_ne!(tile(0), tile(8));
+
+    let ign = dither("ign", "0");
+    assert!(ign.pixels().all(|pixel| pixel[0] == 0 || pixel[0] == 255));
+    assert!(ign.pixels().any(|pixel| pixel[0] == 0));
+    assert!(ign.pixels().any(|pixel| pixel[0] == 255));
+}
+
+#[test]
+fn dithers_to_multiple_gray_levels() {
+    let input = fixture("levels.png");
+    let output = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--levels")
+        .arg("4")
+        .assert()
+        .success();
+    let dithered = image::load_from_memory(&output.get_output().stdout)
+        .unwrap()
+        .to_luma8();
+    let mut values: Vec<u8> = dithered.pixels().map(|pixel| pixel[0]).collect();
+    values.sort_unstable();
+    values.dedup();
+    assert_eq!(values, [0, 85, 170, 255]);
+
+    let flat = temp_path("levels_flat.png");
+    ImageBuffer::from_pixel(8, 8, Luma([85u8]))
+        .save(&flat)
+        .unwrap();
+    let output = ditherer()
+        .arg("-i")
+        .arg(&flat)
+        .arg("--levels")
+        .arg("4")
+        .assert()
+        .success();
+    let dithered = image::load_from_memory(&output.get_output().stdout)
+        .unwrap()
+        .to_luma8();
+    assert!(dithered.pixels().all(|pixel| pixel[0] == 85));
+}
+
+#[test]
+fn writes_packed_pixels_for_eink_profile() {
+    let input = temp_path("eink_portrait.png");
+    ImageBuffer::from_fn(24, 32, |x, y| Luma([(x * 5 + y * 3) as u8]))
+        .save(&input)
+        .unwrap();
+    let output = temp_path("eink_panel.bin");
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .arg("--profile")
+        .arg("eink-4gray")
+        .assert()
+        .success();
+
+    assert_eq!(std::fs::read(&output).unwrap().len(), 400 / 4 * 300);
+    let preview = image::open(temp_path("eink_panel.png")).unwrap().to_luma8();
+    assert_eq!(preview.dimensions(), (400, 300));
+    assert!(preview
+        .pixels()
+        .all(|pixel| [0, 85, 170, 255].contains(&pixel[0])));
+}
+
+#[test]
+fn writes_packed_1bit_formats() {
+    let input = temp_path("packed.png");
+    ImageBuffer::from_fn(10, 2, |x, _| Luma([if x < 2 { 0u8 } else { 255 }]))
+        .save(&input)
+        .unwrap();
+    let encode = |format: &str, bit_order: &str| {
+        ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--format")
+            .arg(format)
+            .arg("--bit-order")
+            .arg(bit_order)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone()
+    };
+
+    let pbm = encode("pbm", "msb");
+    assert_eq!(&pbm[..8], b"P4\n10 2\n");
+    assert_eq!(&pbm[8..], [0xc0, 0x00, 0xc0, 0x00]);
+    let decoded = image::load_from_memory(&pbm).unwrap().to_luma8();
+    assert_eq!(decoded.get_pixel(0, 0)[0], 0);
+    assert_eq!(decoded.get_pixel(9, 1)[0], 255);
+
+    assert_eq!(encode("raw", "msb"), [0x3f, 0xc0, 0x3f, 0xc0]);
+    assert_eq!(encode("raw", "lsb"), [0xfc, 0x03, 0xfc, 0x03]);
+
+    let output = temp_path("packed-icon.xbm");
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+    assert_eq!(
+        std::fs::read_to_string(&output).unwrap(),
+        "#define packed_icon_width 10\n#define packed_icon_height 2\n\
+         static unsigned char packed_icon_bits[] = {\n   0x03, 0x00, 0x03, 0x00 };\n"
+    );
+}
+
+#[test]
+fn writes_c_array_header() {
+    let input = temp_path("c_array.png");
+    ImageBuffer::from_fn(10, 2, |x, _| Luma([if x < 2 { 0u8 } else { 255 }]))
+        .save(&input)
+        .unwrap();
+
+    let output = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--output-format")
+        .arg("c-array")
+        .assert()
+        .success();
+    let header = String::from_utf8(output.get_output().stdout.clone()).unwrap();
+    assert!(header.contains("#define IMAGE_WIDTH 10\n#define IMAGE_HEIGHT 2\n"));
+    assert!(header.contains("const uint8_t image[] = {\n   0xc0, 0x00, 0xc0, 0x00\n};\n"));
+
+    let output = temp_path("logo.h");
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .arg("--c-array-layout")
+        .arg("vertical")
+        .assert()
+        .success();
+    let header = std::fs::read_to_string(&output).unwrap();
+    assert!(header.contains("#define LOGO_WIDTH 10\n"));
+    assert!(header.contains(
+        "const uint8_t logo[] = {\n   0x03, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00\n};\n"
+    ));
+}
+
+#[test]
+fn dithers_frame_streams() {
+    let frame = std::fs::read(fixture("frames.png")).unwrap();
+    let output = ditherer()
+        .arg("--frames")
+        .write_stdin([frame.clone(), frame].concat())
+        .assert()
+        .success();
+    let stdout = &output.get_output().stdout;
+    assert_eq!(
+        stdout.windows(4).filter(|chunk| chunk == b"IEND").count(),
+        2
+    );
+    let first_frame = image::load_from_memory(stdout).unwrap();
+    assert_eq!(first_frame.dimensions(), (FIXTURE_WIDTH, FIXTURE_HEIGHT));
+
+    let ppm_frame = [b"P6\n# comment\n2 1\n255\n".as_slice(), &[200; 6]].concat();
+    let output = ditherer()
+        .arg("--frames")
+        .write_stdin([ppm_frame.clone(), ppm_frame].concat())
+        .assert()
+        .success();
+    let expected_frame = [b"P6\n2 1\n255\n".as_slice(), &[255; 6]].concat();
+    assert_eq!(
+        output.get_output().stdout,
+        [expected_frame.clone(), expected_frame].concat()
+    );
+
+    let output = ditherer()
+        .arg("--frames")
+        .arg("--size")
+        .arg("4x2")
+        .write_stdin(vec![0u8; 4 * 2 * 3 * 3])
+        .assert()
+        .success();
+    assert_eq!(output.get_output().stdout, vec![0u8; 4 * 2 * 3 * 3]);
+}
+
+#[test]
+fn temporal_and_stable_frame_streams() {
+    let gray_frame = vec![128u8; 8 * 8 * 3];
+    let dither_frames = |mode: &str, frames: Vec<u8>| {
+        let mut command = ditherer();
+        command.arg("--frames").arg("--size").arg("8x8");
+        if !mode.is_empty() {
+            command.arg(mode);
+        }
+        let output = command.write_stdin(frames).assert().success();
+        let stdout = output.get_output().stdout.clone();
+        let (first, second) = stdout.split_at(8 * 8 * 3);
+        (first.to_vec(), second.to_vec())
+    };
+
+    let (first, second) = dither_frames("", [gray_frame.clone(), gray_frame.clone()].concat());
+    assert_eq!(first, second);
+    let (first, second) = dither_frames(
+        "--temporal",
+        [gray_frame.clone(), gray_frame.clone()].concat(),
+    );
+    assert_ne!(first, second);
+    assert_eq!(
+        first.iter().filter(|&&value| value == 255).count(),
+        second.iter().filter(|&&value| value == 255).count()
+    );
+
+    // The left half brightens within the tolerance and the right half turns black.
+    let changed_frame: Vec<u8> = (0..8 * 8)
+        .flat_map(|index| if index % 8 < 4 { [130u8; 3] } else { [0; 3] })
+        .collect();
+    let (first, second) = dither_frames("--stable", [gray_frame, changed_frame].concat());
+    for index in 0..8 * 8 {
+        let pixel = index * 3..index * 3 + 3;
+        if index % 8 < 4 {
+            assert_eq!(first[pixel.clone()], second[pixel]);
+        } else {
+            assert_eq!(second[pixel], [0; 3]);
+        }
+    }
+
+    ditherer()
+        .arg("--frames")
+        .arg("--temporal")
+        .arg("--algorithm")
+        .arg("floyd-steinberg")
+        .write_stdin(vec![0u8; 8 * 8 * 3])
+        .arg("--size")
+        .arg("8x8")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "--temporal only applies to ordered algorithms",
+        ));
+}
+
+#[test]
+fn watch_redithers_when_input_changes() {
+    let input = temp_path("watch.png");
+    let output = temp_path("watch_out.png");
+    let _ = std::fs::remove_file(&output);
+    ImageBuffer::from_pixel(4, 4, Luma([0u8]))
+        .save(&input)
+        .unwrap();
+
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("ditherer"))
+        .arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .arg("--watch")
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let wait_for_pixel = |value: u8| {
+        (0..100).any(|_| {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            image::open(&output)
+                .map(|image| image.to_luma8().get_pixel(0, 0)[0] == value)
+                .unwrap_or(false)
+        })
+    };
+
+    let first_run = wait_for_pixel(0);
+    ImageBuffer::from_pixel(4, 4, Luma([255u8]))
+        .save(&input)
+        .unwrap();
+    let second_run = wait_for_pixel(255);
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    assert!(first_run);
+    assert!(second_run);
+}
+
+#[test]
+fn reports_errors_as_json_with_distinct_exit_codes() {
+    let input = temp_path("not_an_image.png");
+    std::fs::write(&input, "not an image").unwrap();
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .assert()
+        .code(3)
+        .stderr(predicate::str::starts_with("Error: "));
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--error-format")
+        .arg("json")
+        .assert()
+        .code(3)
+        .stderr(predicate::str::starts_with(
+            r#"{"kind":"decode","message":"#,
+        ));
+
+    let fixture = fixture("errors.png");
+    ditherer()
+        .arg("-i")
+        .arg(&fixture)
+        .arg("--levels")
+        .arg("4")
+        .arg("--algorithm")
+        .arg("atkinson")
+        .arg("--error-format=json")
+        .assert()
+        .code(2)
+        .stderr(
+            "{\"kind\":\"invalid_options\",\"message\":\"--levels only applies to ordered algorithms\",\"exit_code\":2}\n",
+        );
+    ditherer()
+        .arg("-i")
+        .arg(&fixture)
+        .arg("-o")
+        .arg(temp_path("errors.unknown"))
+        .assert()
+        .code(4);
+    ditherer()
+        .arg("-i")
+        .arg(temp_path("missing.png"))
+        .arg("--error-format")
+        .arg("json")
+        .assert()
+        .code(5)
+        .stderr(
+            predicate::str::contains(r#""kind":"io""#).and(predicate::str::contains("missing.png")),
+        );
+    ditherer()
+        .arg("--no-such-flag")
+        .arg("--error-format")
+        .arg("json")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::starts_with(r#"{"kind":"invalid_options""#));
+}
+
+#[test]
+fn serves_dithered_png_over_http() {
+    use std::io::{BufRead, Read, Write};
+
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("ditherer"))
+        .arg("serve")
+        .arg("--bind")
+        .arg("127.0.0.1:0")
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut banner = String::new();
+    std::io::BufReader::new(child.stderr.take().unwrap())
+        .read_line(&mut banner)
+        .unwrap();
+    let address = banner
+        .trim()
+        .trim_start_matches("Listening on http://")
+        .trim_end_matches("/dither")
+        .to_string();
+
+    let request = |target: &str, body: &[u8]| {
+        let mut stream = std::net::TcpStream::connect(&address).unwrap();
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: image/png\r\nContent-Length: {}\r\n\r\n",
+            target,
+            body.len()
+        )
+        .unwrap();
+        stream.write_all(body).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let split = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .unwrap();
+        let head = String::from_utf8(response[..split].to_vec()).unwrap();
+        (head, response[split + 4..].to_vec())
+    };
+
+    let upload = std::fs::read(fixture("serve.png")).unwrap();
+    let (head, body) = request("/dither?algorithm=atkinson&palette=gameboy", &upload);
+    let (bad_head, bad_body) = request("/dither?algorithm=nope", &upload);
+    let (file_head, _) = request("/dither?palette=%2Fetc%2Fpasswd", &upload);
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    assert!(head.starts_with("HTTP/1.1 200 OK"));
+    assert!(head.contains("Content-Type: image/png"));
+    let dithered = image::load_from_memory(&body).unwrap().to_rgb8();
+    assert_eq!(dithered.dimensions(), (FIXTURE_WIDTH, FIXTURE_HEIGHT));
+    let gameboy = [[15, 56, 15], [48, 98, 48], [139, 172, 15], [155, 188, 15]];
+    assert!(dithered.pixels().all(|pixel| gameboy.contains(&pixel.0)));
+
+    assert!(bad_head.starts_with("HTTP/1.1 400 Bad Request"));
+    assert!(String::from_utf8(bad_body)
+        .unwrap()
+        .contains("Invalid algorithm"));
+    assert!(file_head.starts_with("HTTP/1.1 400 Bad Request"));
+}
+
+#[test]
+fn reads_options_and_presets_from_config_file() {
+    let input = fixture("config.png");
+    let config = temp_path("ditherer_config.toml");
+    std::fs::write(
+        &config,
+        "# shared pipeline settings\n\
+         algorithm = \"random\"\n\
+         seed = 7\n\
+         \n\
+         [preset.other-seed]\n\
+         seed = 8 # overrides the top-level seed\n",
+    )
+    .unwrap();
+    let dither = |args: &[&str]| {
+        let output = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .args(args)
+            .assert()
+            .success();
+        image::load_from_memory(&output.get_output().stdout)
+            .unwrap()
+            .to_luma8()
+    };
+    let config = config.to_str().unwrap();
+
+    let seed_7 = dither(&["--algorithm", "random", "--seed", "7"]);
+    let seed_8 = dither(&["--algorithm", "random", "--seed", "8"]);
+    assert_eq!(dither(&["--config", config]), seed_7);
+    assert_eq!(dither(&["--config", config, "--seed", "8"]), seed_8);
+    assert_eq!(
+        dither(&["--config", config, "--preset", "other-seed"]),
+        seed_8
+    );
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--config")
+        .arg(config)
+        .arg("--preset")
+        .arg("missing")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no [preset.missing] table"));
+}
+
+#[test]
+fn compare_renders_labeled_grid() {
+    let input = fixture("compare.png");
+    let output = ditherer()
+        .arg("compare")
+        .arg("-i")
+        .arg(&input)
+        .arg("--algorithms")
+        .arg("bayer,atkinson")
+        .arg("--matrix-sizes")
+        .arg("m2,m4")
+        .assert()
+        .success();
+    let sheet = image::load_from_memory(&output.get_output().stdout)
+        .unwrap()
+        .to_luma8();
+
+    // Three cells in two columns, each with a 4px gap and a 14px label strip.
+    assert_eq!(
+        sheet.dimensions(),
+        (
+            2 * (FIXTURE_WIDTH + 4) + 4,
+            2 * (FIXTURE_HEIGHT + 14 + 4) + 4
+        )
+    );
+
+    let output = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("-m")
+        .arg("m2")
+        .assert()
+        .success();
+    let bayer_m2 = image::load_from_memory(&output.get_output().stdout)
+        .unwrap()
+        .to_luma8();
+    let first_cell = image::imageops::crop_imm(&sheet, 4, 4, FIXTURE_WIDTH, FIXTURE_HEIGHT);
+    assert_eq!(first_cell.to_image(), bayer_m2);
+
+    let label = image::imageops::crop_imm(&sheet, 4, 4 + FIXTURE_HEIGHT, FIXTURE_WIDTH, 14);
+    assert!(label.pixels().any(|(_, _, pixel)| pixel.0[0] == 0));
+}
+
+#[test]
+fn metrics_reports_psnr_and_ssim_as_json() {
+    let input = fixture("metrics.png");
+    let output = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(temp_path("metrics_out.png"))
+        .arg("--algorithm")
+        .arg("floyd-steinberg")
+        .arg("--metrics")
+        .assert()
+        .success();
+    let stderr = String::from_utf8(output.get_output().stderr.clone()).unwrap();
+    let field = |name: &str| -> f64 {
+        let start = stderr.find(&format!("\"{}\":", name)).unwrap() + name.len() + 3;
+        let end = start + stderr[start..].find([',', '}']).unwrap();
+        stderr[start..end].parse().unwrap()
+    };
+    assert!(field("psnr") > 10.0, "{}", stderr);
+    assert!((0.0..=1.0).contains(&field("ssim")), "{}", stderr);
+    assert_eq!(field("blur"), 1.5);
+
+    let white = temp_path("metrics_white.png");
+    ImageBuffer::from_pixel(8, 8, Luma([255u8]))
+        .save(&white)
+        .unwrap();
+    ditherer()
+        .arg("-i")
+        .arg(&white)
+        .arg("-o")
+        .arg(temp_path("metrics_white_out.png"))
+        .arg("--metrics")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "{\"psnr\":null,\"ssim\":1.000000,\"blur\":1.5}",
+        ));
+}
+
+#[test]
+fn mask_limits_dithering_to_white_areas() {
+    let input = fixture("mask.png");
+    let mask = temp_path("mask_mask.png");
+    ImageBuffer::from_fn(FIXTURE_WIDTH, FIXTURE_HEIGHT, |x, _| {
+        Luma([if x < FIXTURE_WIDTH / 2 { 255u8 } else { 0 }])
+    })
+    .save(&mask)
+    .unwrap();
+
+    let output = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--mask")
+        .arg(&mask)
+        .assert()
+        .success();
+    let dithered = image::load_from_memory(&output.get_output().stdout)
+        .unwrap()
+        .to_rgb8();
+    let original = image::open(&input).unwrap().to_rgb8();
+
+    for (x, y, pixel) in dithered.enumerate_pixels() {
+        if x < FIXTURE_WIDTH / 2 {
+            assert!(pixel.0 == [0, 0, 0] || pixel.0 == [255, 255, 255]);
+        } else {
+            assert_eq!(pixel, original.get_pixel(x, y));
+        }
+    }
+}
+
+#[test]
+fn channel_saturation_thresholds_color_saturation() {
+    let input = temp_path("channel.png");
+    ImageBuffer::from_fn(8, 8, |x, _| {
+        if x < 4 {
+            Rgb([255u8, 0, 0])
+        } else {
+            Rgb([200u8, 200, 200])
+        }
+    })
+    .save(&input)
+    .unwrap();
+
+    let output = ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--color")
+        .arg("--preserve-order")
+        .arg("light")
+        .arg("--channel")
+        .arg("saturation")
+        .assert()
+        .success();
+    let dithered = image::load_from_memory(&output.get_output().stdout)
+        .unwrap()
+        .to_rgba8();
+
+    for (x, _, pixel) in dithered.enumerate_pixels() {
+        assert_eq!(pixel[3], if x < 4 { 255 } else { 0 });
+    }
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--channel")
+        .arg("value")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--color"));
+}
+
+#[test]
+fn fg_and_bg_recolor_output() {
+    let input = fixture("duotone.png");
+    let dither = |args: &[&str]| {
+        let output = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .args(args)
+            .assert()
+            .success();
+        image::load_from_memory(&output.get_output().stdout)
+            .unwrap()
+            .to_rgba8()
+    };
+
+    let grayscale = dither(&["--fg", "#222034", "--bg", "f4f0e8"]);
+    let plain = dither(&[]);
+    for (pixel, plain_pixel) in grayscale.pixels().zip(plain.pixels()) {
+        let expected = if plain_pixel[0] == 0 {
+            [0x22, 0x20, 0x34, 255]
+        } else {
+            [0xf4, 0xf0, 0xe8, 255]
+        };
+        assert_eq!(pixel.0, expected);
+    }
+
+    let color = dither(&["--color", "--bg", "#f4f0e8"]);
+    let plain_color = dither(&["--color"]);
+    for (pixel, plain_pixel) in color.pixels().zip(plain_color.pixels()) {
+        if plain_pixel[3] == 0 {
+            assert_eq!(pixel.0, [0xf4, 0xf0, 0xe8, 255]);
+        } else {
+            assert_eq!(pixel, plain_pixel);
+        }
+    }
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--fg")
+        .arg("#12345")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("6-digit hex"));
+}
+
+#[test]
+fn cmyk_halftones_and_writes_separations() {
+    let input = fixture("cmyk.png");
+    let output = temp_path("cmyk_out.png");
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .arg("--cmyk")
+        .arg("--separations")
+        .assert()
+        .success();
+
+    let composite = image::open(&output).unwrap().to_rgb8();
+    assert_eq!(composite.dimensions(), (FIXTURE_WIDTH, FIXTURE_HEIGHT));
+    assert!(composite
+        .pixels()
+        .all(|pixel| pixel.0.iter().all(|&value| value == 0 || value == 255)));
+
+    for plate in ["c", "m", "y", "k"] {
+        let plate = image::open(temp_path(&format!("cmyk_out-{}.png", plate))).unwrap();
+        assert_eq!(plate.color(), image::ColorType::L8);
+        assert!(plate
+            .to_luma8()
+            .pixels()
+            .all(|pixel| pixel[0] == 0 || pixel[0] == 255));
+    }
+
+    let cyan = temp_path("cmyk_cyan.png");
+    ImageBuffer::from_pixel(16, 16, Rgb([0u8, 255, 255]))
+        .save(&cyan)
+        .unwrap();
+    let output = ditherer()
+        .arg("-i")
+        .arg(&cyan)
+        .arg("--cmyk")
+        .assert()
+        .success();
+    let composite = image::load_from_memory(&output.get_output().stdout)
+        .unwrap()
+        .to_rgb8();
+    assert!(composite.pixels().all(|pixel| pixel.0 == [0, 255, 255]));
+}
+
+#[test]
+fn progress_json_reports_each_image() {
+    let input_dir = temp_path("progress_in");
+    let output_dir = temp_path("progress_out");
+    let _ = std::fs::remove_dir_all(&input_dir);
+    std::fs::create_dir_all(&input_dir).unwrap();
+    for name in ["a.png", "b.png"] {
+        std::fs::copy(fixture("progress.png"), input_dir.join(name)).unwrap();
+    }
+
+    let output = ditherer()
+        .arg("--input-dir")
+        .arg(&input_dir)
+        .arg("--output-dir")
+        .arg(&output_dir)
+        .arg("--progress-json")
+        .assert()
+        .success();
+    let stderr = String::from_utf8(output.get_output().stderr.clone()).unwrap();
+    let lines: Vec<&str> = stderr.lines().collect();
+
+    assert_eq!(lines.len(), 3, "{}", stderr);
+    assert!(lines[0].starts_with("{\"event\":\"item\",\"name\":\""));
+    assert!(lines[0].contains("a.png\",\"index\":1,\"total\":2,\"ok\":true,"));
+    assert!(lines[1].contains("b.png\",\"index\":2,\"total\":2,\"ok\":true,"));
+    assert!(lines[2].starts_with("{\"event\":\"done\",\"completed\":2,\"failed\":0,"));
+}
+
+#[test]
+fn quiet_suppresses_warnings() {
+    let input = fixture("quiet.png");
+    let output = temp_path("quiet_out.png");
+    let dither = |quiet: bool| {
+        let mut command = ditherer();
+        command
+            .arg("-i")
+            .arg(&input)
+            .arg("-o")
+            .arg(&output)
+            .arg("--color")
+            .arg("--output-depth")
+            .arg("1");
+        if quiet {
+            command.arg("-q");
+        }
+        command.assert().success()
+    };
+
+    dither(false).stderr(predicate::str::contains("Warning:"));
+    dither(true).stderr("");
+}
+
+#[test]
+fn keep_metadata_copies_icc_profile_and_text_chunks() {
+    let input = temp_path("keep_metadata.png");
+    let output = temp_path("keep_metadata_out.png");
+    let icc_profile = b"not a real profile, but carried byte for byte".to_vec();
+    let pixels = fixture("keep_metadata_pixels.png");
+    let pixels = image::open(&pixels).unwrap().to_rgb8();
+
+    let mut info = png::Info::with_size(FIXTURE_WIDTH, FIXTURE_HEIGHT);
+    info.icc_profile = Some(icc_profile.clone().into());
+    let mut encoder =
+        png::Encoder::with_info(std::fs::File::create(&input).unwrap(), info).unwrap();
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .add_text_chunk("Author".to_string(), "moonstripe".to_string())
+        .unwrap();
+    encoder
+        .write_header()
+        .unwrap()
+        .write_image_data(&pixels)
+        .unwrap();
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .arg("--keep-metadata")
+        .assert()
+        .success();
+
+    let reader = png::Decoder::new(std::fs::File::open(&output).unwrap())
+        .read_info()
+        .unwrap();
+    let info = reader.info();
+    assert_eq!(info.icc_profile.as_deref(), Some(&icc_profile[..]));
+    assert!(info.srgb.is_none());
+    let keywords: Vec<&str> = info
+        .uncompressed_latin1_text
+        .iter()
+        .map(|chunk| chunk.keyword.as_str())
+        .collect();
+    assert_eq!(keywords, ["Author", "ditherer:params"]);
+    assert_eq!(info.uncompressed_latin1_text[0].text, "moonstripe");
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    let reader = png::Decoder::new(std::fs::File::open(&output).unwrap())
+        .read_info()
+        .unwrap();
+    assert!(reader.info().icc_profile.is_none());
+    let text = &reader.info().uncompressed_latin1_text;
+    assert_eq!(text.len(), 1);
+    assert_eq!(text[0].keyword, "ditherer:params");
+}
+
+#[test]
+fn reapplies_options_recorded_in_a_previous_output() {
+    let first = fixture("reapply_first.png");
+    let recorded = temp_path("reapply_recorded.png");
+    let second = temp_path("reapply_second.png");
+    ImageBuffer::from_fn(FIXTURE_WIDTH, FIXTURE_HEIGHT, |x, y| {
+        Rgb([(y * 10) as u8, 200, (x * 8) as u8])
+    })
+    .save(&second)
+    .unwrap();
+    let flags = [
+        "--algorithm",
+        "atkinson",
+        "--palette",
+        "gameboy",
+        "--gamma",
+        "1.5",
+    ];
+
+    ditherer()
+        .arg("-i")
+        .arg(&first)
+        .args(flags)
+        .arg("-o")
+        .arg(&recorded)
+        .assert()
+        .success();
+    let reader = png::Decoder::new(std::fs::File::open(&recorded).unwrap())
+        .read_info()
+        .unwrap();
+    let params = &reader.info().uncompressed_latin1_text[0];
+    assert_eq!(params.keyword, "ditherer:params");
+    assert_eq!(
+        params.text,
+        "--gamma=1.5\n--algorithm=atkinson\n--palette=gameboy"
+    );
+
+    let dither = |flags: &[&str]| {
+        let output = ditherer()
+            .arg("-i")
+            .arg(&second)
+            .args(flags)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        image::load_from_memory(&output.stdout).unwrap().to_rgba8()
+    };
+    let reapply = ["--reapply", recorded.to_str().unwrap()];
+    assert_eq!(dither(&reapply), dither(&flags));
+    assert_ne!(dither(&reapply), dither(&[]));
+    assert_eq!(
+        dither(&[&reapply[..], &["--palette", "pico8"]].concat()),
+        dither(&[&flags[..], &["--palette", "pico8"]].concat())
+    );
+
+    ditherer()
+        .arg("-i")
+        .arg(&second)
+        .arg("--reapply")
+        .arg(&first)
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("no ditherer:params chunk"));
+}
+
+#[test]
+fn applies_exif_orientation_on_load() {
+    let input = temp_path("exif_orientation.jpg");
+    let output = temp_path("exif_orientation_out.png");
+    let pixels = image::open(fixture("exif_orientation.png")).unwrap();
+    let mut jpeg = Vec::new();
+    pixels
+        .write_to(
+            &mut std::io::Cursor::new(&mut jpeg),
+            image::ImageFormat::Jpeg,
+        )
+        .unwrap();
+
+    // An APP1 segment with a single IFD0 entry: orientation 6, "rotate 90 clockwise".
+    let mut exif = b"Exif\0\0II*\0\x08\0\0\0\x01\0".to_vec();
+    exif.extend_from_slice(&[0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0]);
+    let mut segment = vec![0xff, 0xe1];
+    segment.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+    segment.extend_from_slice(&exif);
+    jpeg.splice(2..2, segment);
+    std::fs::write(&input, jpeg).unwrap();
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .arg("--keep-metadata")
+        .assert()
+        .success();
+
+    let dithered = image::open(&output).unwrap();
+    assert_eq!(dithered.dimensions(), (FIXTURE_HEIGHT, FIXTURE_WIDTH));
+
+    let png_bytes = std::fs::read(&output).unwrap();
+    let exif_chunk = png_bytes
+        .windows(4)
+        .position(|window| window == b"eXIf")
+        .expect("EXIF should be kept");
+    let exif = &png_bytes[exif_chunk + 4..];
+    assert_eq!(&exif[..4], b"II*\0");
+    assert_eq!(exif[18], 1, "orientation should be reset to upright");
+}
+
+#[test]
+fn strips_exif_and_icc_from_tagged_jpeg() {
+    let input = temp_path("strip_metadata.jpg");
+    let pixels = image::open(fixture("strip_metadata.png")).unwrap();
+    let mut jpeg = Vec::new();
+    pixels
+        .write_to(
+            &mut std::io::Cursor::new(&mut jpeg),
+            image::ImageFormat::Jpeg,
+        )
+        .unwrap();
+
+    // An APP1 EXIF segment with an empty IFD0 and an APP2 segment holding a one-part ICC profile.
+    let mut exif = b"Exif\0\0II*\0\x08\0\0\0\0\0\0\0\0\0".to_vec();
+    let mut icc = b"ICC_PROFILE\0\x01\x01".to_vec();
+    icc.extend_from_slice(b"not a real profile, but carried byte for byte");
+    for (marker, payload) in [(0xe2, &mut icc), (0xe1, &mut exif)] {
+        let mut segment = vec![0xff, marker];
+        segment.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+        segment.append(payload);
+        jpeg.splice(2..2, segment);
+    }
+    std::fs::write(&input, jpeg).unwrap();
+
+    let encode = |format: &str, flags: &[&str]| {
+        ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--format")
+            .arg(format)
+            .args(flags)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone()
+    };
+    let contains = |bytes: &[u8], chunk: &[u8]| bytes.windows(4).any(|window| window == chunk);
+
+    let kept = encode("png", &["--keep-metadata"]);
+    assert!(contains(&kept, b"eXIf") && contains(&kept, b"iCCP"));
+
+    for flags in [
+        &[][..],
+        &["--keep-metadata", "--strip-metadata"],
+        &["--privacy"],
+    ] {
+        let png = encode("png", flags);
+        assert!(!contains(&png, b"eXIf") && !contains(&png, b"iCCP"));
+        let webp = encode("webp", flags);
+        assert!(!contains(&webp, b"EXIF") && !contains(&webp, b"ICCP"));
+    }
+}
+
+/// A minimal Display P3 matrix/TRC profile: D50-adapted primaries and the sRGB transfer curve.
+fn display_p3_profile() -> Vec<u8> {
+    let s15_fixed16 = |value: f64| ((value * 65536.0).round() as i32).to_be_bytes();
+    let xyz = |[x, y, z]: [f64; 3]| {
+        let mut tag = b"XYZ \0\0\0\0".to_vec();
+        for value in [x, y, z] {
+            tag.extend_from_slice(&s15_fixed16(value));
+        }
+        tag
+    };
+    let mut curve = b"para\0\0\0\0\0\x03\0\0".to_vec();
+    for value in [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045] {
+        curve.extend_from_slice(&s15_fixed16(value));
+    }
+    let tags: [(&[u8; 4], Vec<u8>); 6] = [
+        (b"rXYZ", xyz([0.515102, 0.241182, -0.001049])),
+        (b"gXYZ", xyz([0.291965, 0.692236, 0.041882])),
+        (b"bXYZ", xyz([0.157153, 0.066582, 0.784378])),
+        (b"rTRC", curve.clone()),
+        (b"gTRC", curve.clone()),
+        (b"bTRC", curve),
+    ];
+
+    let mut header = vec![0u8; 128];
+    header[12..16].copy_from_slice(b"mntr");
+    header[16..20].copy_from_slice(b"RGB ");
+    header[20..24].copy_from_slice(b"XYZ ");
+    header[36..40].copy_from_slice(b"acsp");
+    let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+    let mut data = Vec::new();
+    let data_start = 128 + 4 + tags.len() * 12;
+    for (signature, tag) in &tags {
+        table.extend_from_slice(*signature);
+        table.extend_from_slice(&((data_start + data.len()) as u32).to_be_bytes());
+        table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+        data.extend_from_slice(tag);
+    }
+    let mut profile = [header, table, data].concat();
+    let size = (profile.len() as u32).to_be_bytes();
+    profile[..4].copy_from_slice(&size);
+    profile
+}
+
+#[test]
+fn converts_icc_tagged_input_to_srgb() {
+    let input = temp_path("display_p3.png");
+    let mut info = png::Info::with_size(4, 4);
+    info.icc_profile = Some(display_p3_profile().into());
+    let mut encoder =
+        png::Encoder::with_info(std::fs::File::create(&input).unwrap(), info).unwrap();
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .unwrap()
+        .write_image_data(&[200, 100, 50].repeat(16))
+        .unwrap();
+
+    let top_color = |assume_srgb: bool| {
+        let mut command = ditherer();
+        command
+            .arg("-i")
+            .arg(&input)
+            .arg("--report-colors")
+            .arg("--json");
+        if assume_srgb {
+            command.arg("--assume-srgb");
+        }
+        let stdout =
+            String::from_utf8(command.assert().success().get_output().stdout.clone()).unwrap();
+        let start = stdout.find("\"color\":\"#").unwrap() + 10;
+        let hex = &stdout[start..start + 6];
+        [0, 2, 4].map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap() as i32)
+    };
+
+    // Display P3 (200, 100, 50) is a more saturated orange than the same sRGB values.
+    let converted = top_color(false);
+    for (channel, expected) in converted.iter().zip([215, 93, 31]) {
+        assert!((channel - expected).abs() <= 1, "{:?}", converted);
+    }
+    assert_eq!(top_color(true), [200, 100, 50]);
+}
+
+#[test]
+fn grayscale_method_picks_the_rgb_to_gray_conversion() {
+    let input = temp_path("grayscale_method_red.png");
+    ImageBuffer::from_pixel(8, 8, Rgb([255u8, 0, 0]))
+        .save(&input)
+        .unwrap();
+    let white_pixels = |method: &str| {
+        let output = ditherer()
+            .arg("-i")
+            .arg(&input)
+            .arg("--grayscale-method")
+            .arg(method)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        image::load_from_memory(&output.stdout)
+            .unwrap()
+            .to_luma8()
+            .pixels()
+            .filter(|pixel| pixel[0] == 255)
+            .count()
+    };
+
+    assert_eq!(white_pixels("desaturate"), 64);
+    assert_eq!(white_pixels("max"), 64);
+    assert_eq!(white_pixels("single-channel:g"), 0);
+    let luma = white_pixels("luma");
+    assert!(luma > 0 && luma < 32);
+
+    ditherer()
+        .arg("-i")
+        .arg(&input)
+        .arg("--grayscale-method")
+        .arg("brightest")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("desaturate"));
+}
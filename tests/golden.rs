@@ -0,0 +1,196 @@
+//! Golden-image regression tests: small fixtures are dithered with every algorithm and a set of
+//! option combinations, and the output is compared pixel by pixel against the images checked in
+//! under `tests/golden`. After an intended change in output, regenerate them with:
+//!
+//! ```bash
+//! UPDATE_GOLDEN=1 cargo test --test golden
+//! ```
+//!
+//! and review the changed images before committing them.
+
+use assert_cmd::Command;
+use ditherer::Algorithm;
+use image::{ImageBuffer, Rgb, RgbImage, RgbaImage};
+use std::path::{Path, PathBuf};
+
+const FIXTURE_WIDTH: u32 = 32;
+const FIXTURE_HEIGHT: u32 = 24;
+
+/// Options every algorithm is run with, by the name they appear under in the golden file names.
+const ALGORITHM_VARIANTS: [(&str, &[&str]); 4] = [
+    ("default", &[]),
+    ("color", &["--color"]),
+    ("palette", &["--palette", "gameboy"]),
+    ("strength", &["--strength", "0.5"]),
+];
+
+/// Further option combinations, each run once on its own.
+const OPTION_VARIANTS: [(&str, &[&str]); 16] = [
+    ("m2", &["-m", "m2"]),
+    ("m16", &["-m", "m16"]),
+    ("rgb", &["--rgb"]),
+    ("levels", &["--levels", "4"]),
+    ("no-gamma", &["--no-gamma"]),
+    ("contrast-curve", &["--contrast-curve", "2"]),
+    ("dot-gain", &["--dot-gain", "15"]),
+    ("jitter", &["--jitter", "0.5", "--seed", "3"]),
+    (
+        "pattern",
+        &["--pattern-scale", "2", "--pattern-angle", "30"],
+    ),
+    ("pixelate", &["--pixelate", "3"]),
+    (
+        "serpentine",
+        &["--algorithm", "floyd-steinberg", "--serpentine"],
+    ),
+    (
+        "edge-enhance",
+        &["--algorithm", "floyd-steinberg", "--edge-enhance", "1.5"],
+    ),
+    (
+        "fm-cell",
+        &[
+            "--algorithm",
+            "fm",
+            "--noise-size",
+            "16",
+            "--cell-size",
+            "4",
+        ],
+    ),
+    (
+        "halftone-dot",
+        &[
+            "--algorithm",
+            "halftone",
+            "--dot-size",
+            "6",
+            "--angle",
+            "15",
+        ],
+    ),
+    ("lab", &["--palette", "pico8", "--color-space", "lab"]),
+    ("duotone", &["--fg", "#202060", "--bg", "#f0e0c0"]),
+];
+
+fn fixtures() -> [(&'static str, RgbImage); 2] {
+    let gradient = ImageBuffer::from_fn(FIXTURE_WIDTH, FIXTURE_HEIGHT, |x, y| {
+        Rgb([(x * 8) as u8, (y * 10) as u8, ((x + y) * 4) as u8])
+    });
+    let shapes = ImageBuffer::from_fn(FIXTURE_WIDTH, FIXTURE_HEIGHT, |x, y| {
+        let (dx, dy) = (x as i32 - 12, y as i32 - 12);
+        if dx * dx + dy * dy < 64 {
+            Rgb([220, 60, 40])
+        } else if x % 6 == 0 || y == 20 {
+            Rgb([20, 20, 20])
+        } else {
+            Rgb([200, 210, 190])
+        }
+    });
+    [("gradient", gradient), ("shapes", shapes)]
+}
+
+/// Every test case as its name and CLI arguments.
+fn cases() -> Vec<(String, Vec<String>)> {
+    let mut cases = Vec::new();
+    for algorithm in Algorithm::ALL {
+        for (variant, options) in ALGORITHM_VARIANTS {
+            let arguments = ["--algorithm", &algorithm.to_string()]
+                .into_iter()
+                .chain(required_options(&algorithm).iter().copied())
+                .chain(options.iter().copied())
+                .map(String::from)
+                .collect();
+            cases.push((format!("{}-{}", algorithm, variant), arguments));
+        }
+    }
+    for (variant, options) in OPTION_VARIANTS {
+        let arguments = options.iter().copied().map(String::from).collect();
+        cases.push((variant.to_string(), arguments));
+    }
+    cases
+}
+
+/// Options an algorithm needs to run, or to run fast enough here: generating the default 64x64
+/// blue noise texture takes over a second in debug builds.
+fn required_options(algorithm: &Algorithm) -> &'static [&'static str] {
+    match algorithm {
+        Algorithm::Interference => &["--matrix-a", "m4", "--matrix-b", "m8"],
+        Algorithm::BlueNoise | Algorithm::Fm => &["--noise-size", "16"],
+        _ => &[],
+    }
+}
+
+fn dither(input: &Path, arguments: &[String]) -> RgbaImage {
+    let assert = Command::cargo_bin("ditherer")
+        .unwrap()
+        .arg("-i")
+        .arg(input)
+        .args(arguments)
+        .assert()
+        .success();
+    image::load_from_memory(&assert.get_output().stdout)
+        .unwrap()
+        .to_rgba8()
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.png", name))
+}
+
+#[test]
+fn outputs_match_golden_images() {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let actual_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("golden-actual");
+    std::fs::create_dir_all(&actual_dir).unwrap();
+    if update {
+        std::fs::create_dir_all(golden_path("").parent().unwrap()).unwrap();
+    }
+
+    let mut mismatches = Vec::new();
+    for (fixture_name, fixture) in fixtures() {
+        let input =
+            PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("golden-{}.png", fixture_name));
+        fixture.save(&input).unwrap();
+
+        for (case_name, arguments) in cases() {
+            let name = format!("{}-{}", fixture_name, case_name);
+            let actual = dither(&input, &arguments);
+            let golden_path = golden_path(&name);
+            if update {
+                actual.save(&golden_path).unwrap();
+                continue;
+            }
+
+            let golden = image::open(&golden_path).map(|golden| golden.to_rgba8());
+            let differing = match &golden {
+                Ok(golden) if golden.dimensions() == actual.dimensions() => golden
+                    .pixels()
+                    .zip(actual.pixels())
+                    .filter(|(golden, actual)| golden != actual)
+                    .count(),
+                _ => (actual.width() * actual.height()) as usize,
+            };
+            if differing > 0 {
+                actual
+                    .save(actual_dir.join(format!("{}.png", name)))
+                    .unwrap();
+                mismatches.push(match golden {
+                    Ok(_) => format!("{} ({} pixels differ)", name, differing),
+                    Err(_) => format!("{} (no golden image)", name),
+                });
+            }
+        }
+    }
+
+    assert!(
+        mismatches.is_empty(),
+        "{} outputs differ from tests/golden, actual images written to {}:\n  {}\n\
+         If the change is intended, run `UPDATE_GOLDEN=1 cargo test --test golden`.",
+        mismatches.len(),
+        actual_dir.display(),
+        mismatches.join("\n  ")
+    );
+}
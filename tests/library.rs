@@ -0,0 +1,394 @@
+use ditherer::{
+    apply_bayer_dithering_grayscale, apply_bayer_dithering_levels, apply_custom_dithering,
+    apply_error_diffusion_grayscale, apply_palette_error_diffusion, build_tone_curve,
+    convert_to_grayscale, dither, raw, register_algorithm, registered_algorithm, srgb_to_linear,
+    Algorithm, BayerMatrixOption, ColorSpace, DitherAlgorithm, DitherOptions, Ditherer, DotShape,
+    GrayscaleMethod, Palette, PreserveOrder, ThresholdMatrix, FLOYD_STEINBERG, OSTROMOUKHOV,
+    RIEMERSMA,
+};
+use image::{DynamicImage, GrayImage, ImageBuffer, Rgb};
+use std::sync::Arc;
+
+fn frame(shift: u32) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    ImageBuffer::from_fn(40, 30, |x, y| {
+        Rgb([(x * 6 + shift) as u8, (y * 8) as u8, ((x + y) * 3) as u8])
+    })
+}
+
+#[test]
+fn ditherer_matches_one_shot_functions_across_frames() {
+    let tone_curve = build_tone_curve(0.0, true);
+    let threshold_matrix = ThresholdMatrix::bayer(&BayerMatrixOption::M8);
+    let mut ordered = Ditherer::ordered(threshold_matrix.clone(), tone_curve);
+    let mut diffusion = [FLOYD_STEINBERG, OSTROMOUKHOV, RIEMERSMA].map(|kernel| {
+        (
+            Ditherer::error_diffusion(kernel.clone(), tone_curve),
+            kernel,
+        )
+    });
+    let mut destination = GrayImage::new(0, 0);
+
+    for shift in [0, 40, 80] {
+        let source = frame(shift);
+        let image = DynamicImage::ImageRgb8(source.clone());
+
+        ordered.dither_into(&source, &mut destination);
+        assert_eq!(
+            destination,
+            apply_bayer_dithering_grayscale(&image, &threshold_matrix, &tone_curve)
+        );
+
+        for (ditherer, kernel) in &mut diffusion {
+            ditherer.dither_into(&source, &mut destination);
+            assert_eq!(
+                destination,
+                apply_error_diffusion_grayscale(&image, kernel, &tone_curve)
+            );
+        }
+    }
+}
+
+#[test]
+fn bayer_matrices_are_permutations_of_evenly_spaced_thresholds() {
+    for (option, size) in [
+        (BayerMatrixOption::M2, 2u32),
+        (BayerMatrixOption::M4, 4),
+        (BayerMatrixOption::M8, 8),
+        (BayerMatrixOption::Generated(16), 16),
+    ] {
+        let threshold_matrix = ThresholdMatrix::bayer(&option);
+        let count = size * size;
+        // Gray level k sits halfway between the k-th and (k + 1)-th evenly spaced threshold.
+        let mut tone_curve = [1.0; 256];
+        for (level, intensity) in tone_curve.iter_mut().enumerate().take(count as usize) {
+            *intensity = (level as f32 + 0.5) / count as f32;
+        }
+
+        let mut previous = GrayImage::new(size, size);
+        for level in 0..count {
+            let image =
+                DynamicImage::ImageLuma8(GrayImage::from_pixel(size, size, [level as u8].into()));
+            let dithered = apply_bayer_dithering_grayscale(&image, &threshold_matrix, &tone_curve);
+
+            assert_eq!(
+                dithered.pixels().filter(|pixel| pixel[0] == 255).count(),
+                level as usize + 1,
+                "{} at level {}",
+                option,
+                level
+            );
+            assert!(previous
+                .pixels()
+                .zip(dithered.pixels())
+                .all(|(previous, pixel)| previous[0] <= pixel[0]));
+            previous = dithered;
+        }
+    }
+}
+
+#[test]
+fn halftone_dot_shapes_grow_from_the_cell_center() {
+    let shapes = [
+        DotShape::Round,
+        DotShape::Square,
+        DotShape::Diamond,
+        DotShape::Line,
+    ]
+    .map(|shape| ThresholdMatrix::halftone_with_shape(8, 0.0, &shape));
+    let thresholds = |screen: &ThresholdMatrix| {
+        (0..64 * 64)
+            .map(|index| screen.threshold(index % 64, index / 64))
+            .collect::<Vec<_>>()
+    };
+    for (index, screen) in shapes.iter().enumerate() {
+        // The cell center is the last pixel to turn white, the corner the first.
+        assert!(screen.threshold(4, 4) > 0.9);
+        assert!(screen.threshold(0, 0) < 0.1);
+        for other in &shapes[index + 1..] {
+            assert_ne!(thresholds(screen), thresholds(other));
+        }
+    }
+
+    // At 0° a line screen splits each cell into the outer and inner half of its rows.
+    let line = &shapes[3];
+    for y in 0..64 {
+        let outer = matches!(y % 8, 0 | 1 | 6 | 7);
+        for x in 0..64 {
+            assert_eq!(line.threshold(x, y) < 0.5, outer, "({}, {})", x, y);
+        }
+    }
+}
+
+#[test]
+fn seed_grid_tiles_match_at_their_edges() {
+    let (seed, grid) = (7, 16);
+    let whole = ThresholdMatrix::white_noise_grid(40, 40, seed, grid);
+    let tiles: Vec<Vec<ThresholdMatrix>> = (0..3)
+        .map(|tile_y| {
+            (0..3)
+                .map(|tile_x| ThresholdMatrix::white_noise_tile(grid, seed, tile_x, tile_y))
+                .collect()
+        })
+        .collect();
+
+    for y in 0..40 {
+        for x in 0..40 {
+            let tile = &tiles[(y / grid) as usize][(x / grid) as usize];
+            assert_eq!(
+                whole.threshold(x, y),
+                tile.threshold(x % grid, y % grid),
+                "({}, {})",
+                x,
+                y
+            );
+        }
+    }
+
+    // On both sides of every boundary between neighboring tiles, the whole render continues with
+    // the edge column or row of the tile that owns it.
+    for boundary in [grid, 2 * grid] {
+        let (before, after) = ((boundary / grid - 1) as usize, (boundary / grid) as usize);
+        for along in 0..40 {
+            let row = &tiles[(along / grid) as usize];
+            assert_eq!(
+                whole.threshold(boundary - 1, along),
+                row[before].threshold(grid - 1, along % grid)
+            );
+            assert_eq!(
+                whole.threshold(boundary, along),
+                row[after].threshold(0, along % grid)
+            );
+
+            let column = (along / grid) as usize;
+            assert_eq!(
+                whole.threshold(along, boundary - 1),
+                tiles[before][column].threshold(along % grid, grid - 1)
+            );
+            assert_eq!(
+                whole.threshold(along, boundary),
+                tiles[after][column].threshold(along % grid, 0)
+            );
+        }
+    }
+
+    // A smaller render keeps the same tiles, and neighboring tiles get their own noise.
+    let smaller = ThresholdMatrix::white_noise_grid(24, 24, seed, grid);
+    for y in 0..24 {
+        for x in 0..24 {
+            assert_eq!(smaller.threshold(x, y), whole.threshold(x, y));
+        }
+    }
+    assert!((0..grid).any(|x| tiles[0][0].threshold(x, 0) != tiles[0][1].threshold(x, 0)));
+}
+
+#[test]
+fn white_noise_of_an_empty_image_has_thresholds() {
+    for (width, height) in [(0, 0), (0, 5), (5, 0)] {
+        let grid = ThresholdMatrix::white_noise_grid(width, height, 7, 16);
+        let noise = ThresholdMatrix::white_noise(width, height, 7);
+        for (x, y) in [(0, 0), (3, 9)] {
+            assert!((0.0..1.0).contains(&grid.threshold(x, y)));
+            assert!((0.0..1.0).contains(&noise.threshold(x, y)));
+        }
+    }
+}
+
+/// Dithers flat gray at `fraction` of the way from 0 to 0.5 to three levels (0, 0.5 and 1) over
+/// a 2x2 Bayer matrix, returning the output levels from the lowest threshold to the highest.
+fn three_levels(fraction: f32, preserve_order: PreserveOrder) -> Vec<u8> {
+    let threshold_matrix = ThresholdMatrix::bayer(&BayerMatrixOption::M2);
+    let mut tone_curve = [0.0; 256];
+    tone_curve[1] = fraction / 2.0;
+    let image = DynamicImage::ImageLuma8(GrayImage::from_pixel(2, 2, [1].into()));
+    let dithered = apply_bayer_dithering_levels(
+        &image,
+        &threshold_matrix,
+        preserve_order,
+        &tone_curve,
+        3,
+        false,
+    );
+    let mut pixels: Vec<(f32, u8)> = dithered
+        .enumerate_pixels()
+        .map(|(x, y, pixel)| (threshold_matrix.threshold(x, y), pixel[0]))
+        .collect();
+    pixels.sort_by(|a, b| a.0.total_cmp(&b.0));
+    pixels.into_iter().map(|(_, level)| level).collect()
+}
+
+/// The third-lowest threshold of the 2x2 Bayer matrix, which a pixel can land on exactly.
+const TIE: f32 = 128.0 / 255.0;
+
+#[test]
+fn levels_preserving_dark_order_lighten_low_thresholds_and_round_ties_down() {
+    assert_eq!(three_levels(TIE, PreserveOrder::Dark), [128, 128, 0, 0]);
+    assert_eq!(three_levels(0.2, PreserveOrder::Dark), [128, 0, 0, 0]);
+}
+
+#[test]
+fn levels_preserving_light_order_darken_low_thresholds_and_round_ties_up() {
+    // The distance below the lighter level, 1 - fraction, is what lands on the threshold here.
+    assert_eq!(
+        three_levels(1.0 - TIE, PreserveOrder::Light),
+        [0, 0, 128, 128]
+    );
+    assert_eq!(three_levels(0.3, PreserveOrder::Light), [0, 0, 0, 128]);
+}
+
+#[test]
+fn raw_ordered_dithering_matches_a_plain_tone_curve() {
+    let image = DynamicImage::ImageRgb8(frame(0));
+    let gray = image.to_luma8();
+    let (width, height) = gray.dimensions();
+    let mut output = vec![0; gray.len()];
+    raw::dither_ordered(
+        &gray,
+        &mut output,
+        width as usize,
+        height as usize,
+        &raw::BAYER_MATRIX_8X8,
+        8,
+    );
+
+    let expected = apply_bayer_dithering_grayscale(
+        &image,
+        &ThresholdMatrix::bayer(&BayerMatrixOption::M8),
+        &build_tone_curve(0.0, false),
+    );
+    assert_eq!(output, expected.into_raw());
+}
+
+#[test]
+fn grayscale_luma_matches_image_and_desaturate_takes_the_brightest_channel() {
+    let image = DynamicImage::ImageRgb8(ImageBuffer::from_fn(64, 64, |x, y| {
+        Rgb([(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8])
+    }));
+    let luma = convert_to_grayscale(&image, &GrayscaleMethod::Luma);
+    assert_eq!(luma, image.to_luma8());
+
+    let desaturate = convert_to_grayscale(&image, &"desaturate".parse().unwrap());
+    for (x, y, pixel) in image.to_rgb8().enumerate_pixels() {
+        assert_eq!(
+            desaturate.get_pixel(x, y)[0],
+            *pixel.0.iter().max().unwrap()
+        );
+    }
+    let max = convert_to_grayscale(&image, &"max".parse().unwrap());
+    assert_eq!(max, desaturate);
+}
+
+#[test]
+fn zero_contrast_curve_is_the_identity() {
+    let plain = build_tone_curve(0.0, false);
+    let linear = build_tone_curve(0.0, true);
+    for value in 0..=255u8 {
+        let intensity = value as f32 / 255.0;
+        assert_eq!(plain[value as usize], intensity);
+        assert_eq!(linear[value as usize], srgb_to_linear(intensity));
+    }
+}
+
+#[test]
+fn dither_options_match_the_underlying_functions() {
+    let image = DynamicImage::ImageRgb8(frame(0));
+    let tone_curve = build_tone_curve(0.0, true);
+
+    let ordered = dither(
+        &image,
+        &DitherOptions::new()
+            .algorithm(Algorithm::Bayer)
+            .matrix_size(BayerMatrixOption::M8)
+            .strength(0.8),
+    );
+    let threshold_matrix = ThresholdMatrix::bayer(&BayerMatrixOption::M8).with_strength(0.8);
+    assert_eq!(
+        ordered,
+        DynamicImage::ImageLuma8(apply_bayer_dithering_grayscale(
+            &image,
+            &threshold_matrix,
+            &tone_curve
+        ))
+    );
+
+    let palette = Palette::preset("gameboy").unwrap();
+    let diffused = dither(
+        &image,
+        &DitherOptions::new()
+            .algorithm(Algorithm::FloydSteinberg)
+            .palette(palette.clone()),
+    );
+    assert_eq!(
+        diffused,
+        DynamicImage::ImageRgba8(apply_palette_error_diffusion(
+            &image,
+            &palette,
+            &FLOYD_STEINBERG,
+            &tone_curve,
+            true,
+            &ColorSpace::Rgb
+        ))
+    );
+
+    let color = dither(&image, &DitherOptions::default().color(true));
+    assert!(matches!(color, DynamicImage::ImageRgba8(_)));
+}
+
+struct CustomBayer(ThresholdMatrix);
+
+impl DitherAlgorithm for CustomBayer {
+    fn name(&self) -> &str {
+        "custom-bayer"
+    }
+
+    fn threshold(&self, x: u32, y: u32) -> Option<f32> {
+        Some(self.0.threshold(x, y))
+    }
+}
+
+struct CustomFloydSteinberg;
+
+impl DitherAlgorithm for CustomFloydSteinberg {
+    fn name(&self) -> &str {
+        "custom-floyd-steinberg"
+    }
+
+    fn diffuse_row(&self, _y: u32, row: &mut [f32], below: &mut [&mut [f32]], output: &mut [bool]) {
+        for x in 0..row.len() {
+            output[x] = row[x] > 0.5;
+            let error = row[x] - if output[x] { 1.0 } else { 0.0 };
+            if x + 1 < row.len() {
+                row[x + 1] += error * (7.0 / 16.0);
+            }
+            if let Some(next) = below.first_mut() {
+                if x > 0 {
+                    next[x - 1] += error * (3.0 / 16.0);
+                }
+                next[x] += error * (5.0 / 16.0);
+                if x + 1 < next.len() {
+                    next[x + 1] += error * (1.0 / 16.0);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn custom_algorithms_match_the_built_in_ones() {
+    let image = DynamicImage::ImageRgb8(frame(0));
+    let tone_curve = build_tone_curve(0.0, true);
+    let threshold_matrix = ThresholdMatrix::bayer(&BayerMatrixOption::M4);
+
+    assert_eq!(
+        apply_custom_dithering(&image, &CustomBayer(threshold_matrix.clone()), &tone_curve),
+        apply_bayer_dithering_grayscale(&image, &threshold_matrix, &tone_curve)
+    );
+    assert_eq!(
+        apply_custom_dithering(&image, &CustomFloydSteinberg, &tone_curve),
+        apply_error_diffusion_grayscale(&image, &FLOYD_STEINBERG, &tone_curve)
+    );
+
+    register_algorithm(Arc::new(CustomFloydSteinberg));
+    let registered = registered_algorithm("custom-floyd-steinberg").unwrap();
+    assert_eq!(registered.name(), "custom-floyd-steinberg");
+    assert!(registered_algorithm("custom-atkinson").is_none());
+}
@@ -0,0 +1,21 @@
+//! Uses the [`ditherer::raw`] module from a `#![no_std]` crate, so this only compiles as long as
+//! that module needs nothing from `std`. Run it with `cargo test --no-default-features --test
+//! no_std`. The library itself builds without `std` on the host and for targets that lack it, e.g.
+//! `cargo build --lib --no-default-features --target thumbv7em-none-eabihf`; CI checks both.
+
+#![no_std]
+
+use ditherer::raw::{dither_ordered, BAYER_MATRIX_2X2};
+
+#[test]
+fn dithers_raw_buffers_without_std() {
+    // Four rows of a 3-pixel-wide ramp, tiled with the 2x2 matrix (thresholds 0, 128, 192, 64).
+    let input = [0, 100, 255, 0, 100, 255, 150, 150, 150, 200, 200, 200];
+    let mut output = [0; 12];
+    dither_ordered(&input, &mut output, 3, 4, &BAYER_MATRIX_2X2, 2);
+
+    assert_eq!(
+        output,
+        [0, 0, 255, 0, 255, 255, 255, 255, 255, 255, 255, 255]
+    );
+}
@@ -0,0 +1,6 @@
+//! The WebAssembly module that `wasm-pack` packages for JavaScript.
+//!
+//! The bindings live in [`ditherer::wasm`] behind the `wasm` feature. Only this crate is a
+//! `cdylib`, so native and `no_std` builds of the library don't link one.
+
+pub use ditherer::wasm::*;